@@ -33,7 +33,7 @@ struct CountRow {
     count: u64,
 }
 
-fn build_smtp_transport(cfg: &SmtpConfig) -> Option<AsyncSmtpTransport<Tokio1Executor>> {
+pub fn build_smtp_transport(cfg: &SmtpConfig) -> Option<AsyncSmtpTransport<Tokio1Executor>> {
     let host = cfg.host.as_deref()?;
     let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(host).ok()?;
     builder = builder.port(cfg.port);
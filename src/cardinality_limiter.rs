@@ -0,0 +1,620 @@
+//! Ingest-time per-metric cardinality limiter.
+//!
+//! Runs in `ChWriter::write` right after the metric firewall, so it sees series
+//! exactly as they will be stored (post-relabel). For every (tenant, metric)
+//! that has a limit configured it tracks the set of active series over a
+//! sliding window: a bounded map of series hash → last-seen second. Entries not
+//! seen for `window_secs` are pruned, so a series only counts against the limit
+//! while it is still being written.
+//!
+//! When a datapoint would add a NEW series beyond `max_series`, the rule's
+//! action decides what happens:
+//!   - **drop**: the datapoint is discarded (existing series keep flowing), or
+//!   - **strip_labels**: the configured labels — or, if none are configured,
+//!     the label with the most distinct values seen in the window — are removed
+//!     and the datapoint is kept under the collapsed series. Collapsed series
+//!     may exceed the limit by up to `max_series` again (hard memory bound);
+//!     beyond that they are dropped too.
+//!
+//! Limits are resolved per tenant: a per-metric override wins over the
+//! tenant's default (stored under metric `*`). Tenants/metrics without a limit
+//! pay one HashMap probe per datapoint and are never tracked.
+//!
+//! Every limited batch emits a `LimitEvent`; `spawn_notifier` turns those into
+//! usage events and (throttled) notifications on the rule's channels.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+
+use crate::metric_firewall::MetricRow;
+
+/// Metric name under which a tenant's default limit is stored.
+pub const TENANT_DEFAULT_METRIC: &str = "*";
+/// Default sliding window when a rule doesn't set one.
+pub const DEFAULT_WINDOW_SECS: u64 = 3600;
+/// Distinct values tracked per label (for picking the offending label).
+const LABEL_VALUE_CAP: usize = 10_000;
+/// Minimum spacing between expiry scans of one tracker.
+const PRUNE_EVERY_SECS: u64 = 10;
+/// Minimum spacing between notifications for one (tenant, metric).
+const NOTIFY_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitAction {
+    Drop,
+    StripLabels,
+}
+
+impl LimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitAction::Drop => "drop",
+            LimitAction::StripLabels => "strip_labels",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LimitRule {
+    pub max_series: u64,
+    pub action: LimitAction,
+    /// Labels removed by `strip_labels` (empty = auto-pick the worst label).
+    pub strip_labels: Vec<String>,
+    pub window_secs: u64,
+    pub channel_ids: Vec<String>,
+}
+
+/// Raw limit fields as stored in `config_cardinality_limits`.
+pub struct RawLimit {
+    pub tenant_id: String,
+    pub metric_name: String,
+    pub max_series: u64,
+    pub action: String, // "drop" | "strip_labels"
+    pub strip_labels: String, // comma-separated
+    pub window_secs: u64,
+    pub notification_channel_ids: String, // JSON array
+}
+
+#[derive(Default)]
+struct TenantLimits {
+    default: Option<LimitRule>,
+    metrics: HashMap<String, LimitRule>,
+}
+
+/// Compiled limits for every tenant, hot-swapped like the metric firewall.
+#[derive(Default)]
+pub struct LimitConfig {
+    tenants: HashMap<String, TenantLimits>,
+}
+
+impl LimitConfig {
+    /// Compile raw limits; rows with `max_series == 0` are ignored (0 = no limit).
+    pub fn compile(raw: &[RawLimit]) -> Self {
+        let mut cfg = LimitConfig::default();
+        for r in raw {
+            if r.max_series == 0 {
+                continue;
+            }
+            let rule = LimitRule {
+                max_series: r.max_series,
+                action: if r.action == "strip_labels" { LimitAction::StripLabels } else { LimitAction::Drop },
+                strip_labels: r.strip_labels.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect(),
+                window_secs: if r.window_secs == 0 { DEFAULT_WINDOW_SECS } else { r.window_secs },
+                channel_ids: serde_json::from_str(&r.notification_channel_ids).unwrap_or_default(),
+            };
+            let t = cfg.tenants.entry(r.tenant_id.clone()).or_default();
+            if r.metric_name == TENANT_DEFAULT_METRIC {
+                t.default = Some(rule);
+            } else {
+                t.metrics.insert(r.metric_name.clone(), rule);
+            }
+        }
+        cfg
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    fn rule_for(&self, tenant: &str, metric: &str) -> Option<&LimitRule> {
+        let t = self.tenants.get(tenant)?;
+        t.metrics.get(metric).or(t.default.as_ref())
+    }
+}
+
+/// Active-series state for one (tenant, metric).
+#[derive(Default)]
+struct SeriesTracker {
+    /// Series hash → last-seen unix second.
+    series: HashMap<u64, u64>,
+    /// Label key → distinct value hashes seen since `label_epoch`.
+    label_values: HashMap<String, HashSet<u64>>,
+    label_epoch: u64,
+    last_prune: u64,
+}
+
+impl SeriesTracker {
+    fn prune(&mut self, now: u64, window: u64) {
+        if now.saturating_sub(self.last_prune) < PRUNE_EVERY_SECS {
+            return;
+        }
+        self.last_prune = now;
+        self.series.retain(|_, seen| now.saturating_sub(*seen) < window);
+        if now.saturating_sub(self.label_epoch) >= window {
+            self.label_values.clear();
+            self.label_epoch = now;
+        }
+    }
+
+    fn observe_labels(&mut self, attrs: &[(String, String)]) {
+        for (k, v) in attrs {
+            let set = match self.label_values.get_mut(k) {
+                Some(s) => s,
+                None => self.label_values.entry(k.clone()).or_default(),
+            };
+            if set.len() < LABEL_VALUE_CAP {
+                set.insert(hash_one(v));
+            }
+        }
+    }
+
+    /// The label with the most distinct values in the window, if any.
+    fn worst_label(&self) -> Option<String> {
+        self.label_values
+            .iter()
+            .max_by_key(|(_, v)| v.len())
+            .filter(|(_, v)| v.len() > 1)
+            .map(|(k, _)| k.clone())
+    }
+}
+
+fn hash_one<T: Hash + ?Sized>(v: &T) -> u64 {
+    let mut h = DefaultHasher::new();
+    v.hash(&mut h);
+    h.finish()
+}
+
+/// Order-independent series identity: metric name + attribute set. The
+/// (key, value) pairs are sorted by reference (rows aren't copied) and fed
+/// through one hasher, so distinct label sets don't collide the way a
+/// commutative combination of per-pair hashes can.
+fn series_hash(name: &str, attrs: &[(String, String)]) -> u64 {
+    let mut pairs: Vec<&(String, String)> = attrs.iter().collect();
+    pairs.sort_unstable();
+    let mut h = DefaultHasher::new();
+    name.hash(&mut h);
+    pairs.hash(&mut h);
+    h.finish()
+}
+
+/// Emitted once per limited (tenant, metric) per batch.
+#[derive(Debug, Clone)]
+pub struct LimitEvent {
+    pub tenant_id: String,
+    pub metric_name: String,
+    pub max_series: u64,
+    pub active_series: u64,
+    pub action: LimitAction,
+    pub dropped: u64,
+    pub stripped: u64,
+    pub stripped_labels: Vec<String>,
+    pub channel_ids: Vec<String>,
+}
+
+/// Per-batch limiter outcome (for logging).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LimitOutcome {
+    pub dropped: usize,
+    pub stripped: usize,
+}
+
+pub struct CardinalityLimiter {
+    /// Hot-swappable compiled limits (refreshed by a background task and on
+    /// config change, like `ChWriter::firewall`).
+    pub config: RwLock<Arc<LimitConfig>>,
+    /// Tenant → metric → tracker; nested so the hot path can probe with
+    /// borrowed names and only allocate keys for a new tenant or metric.
+    trackers: DashMap<String, HashMap<String, SeriesTracker>>,
+    events_tx: mpsc::UnboundedSender<LimitEvent>,
+    events_rx: Mutex<Option<mpsc::UnboundedReceiver<LimitEvent>>>,
+}
+
+impl Default for CardinalityLimiter {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        CardinalityLimiter {
+            config: RwLock::new(Arc::new(LimitConfig::default())),
+            trackers: DashMap::new(),
+            events_tx: tx,
+            events_rx: Mutex::new(Some(rx)),
+        }
+    }
+}
+
+impl CardinalityLimiter {
+    pub fn set_config(&self, cfg: LimitConfig) {
+        if let Ok(mut g) = self.config.write() {
+            *g = Arc::new(cfg);
+        }
+    }
+
+    /// Active series currently tracked for each limited metric of a tenant.
+    pub fn active_series(&self, tenant: &str) -> HashMap<String, u64> {
+        self.trackers
+            .get(tenant)
+            .map(|metrics| metrics.iter().map(|(m, t)| (m.clone(), t.series.len() as u64)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Drop trackers for metrics that no longer have a limit and prune expired
+    /// series everywhere else. Called periodically by the config refresher.
+    pub fn gc(&self, now: u64) {
+        let cfg = match self.config.read() {
+            Ok(g) => g.clone(),
+            Err(_) => return,
+        };
+        self.trackers.retain(|tenant, metrics| {
+            metrics.retain(|metric, t| match cfg.rule_for(tenant, metric) {
+                Some(rule) => {
+                    t.prune(now, rule.window_secs);
+                    !t.series.is_empty()
+                }
+                None => false,
+            });
+            !metrics.is_empty()
+        });
+    }
+
+    /// Apply limits to a batch of metric rows in place, at unix second `now`.
+    pub fn apply<T: MetricRow>(&self, rows: &mut Vec<T>, now: u64) -> LimitOutcome {
+        let cfg = match self.config.read() {
+            Ok(g) => g.clone(), // cheap Arc clone; guard released immediately
+            Err(_) => return LimitOutcome::default(),
+        };
+        if cfg.is_empty() {
+            return LimitOutcome::default();
+        }
+        let mut outcome = LimitOutcome::default();
+        let mut events: HashMap<(String, String), LimitEvent> = HashMap::new();
+
+        rows.retain_mut(|row| {
+            let Some(rule) = cfg.rule_for(row.fw_tenant_id(), row.fw_metric_name()) else {
+                return true;
+            };
+            let mut metrics = match self.trackers.get_mut(row.fw_tenant_id()) {
+                Some(m) => m,
+                None => self.trackers.entry(row.fw_tenant_id().to_string()).or_default(),
+            };
+            let tracker = match metrics.get_mut(row.fw_metric_name()) {
+                Some(t) => t,
+                None => metrics.entry(row.fw_metric_name().to_string()).or_default(),
+            };
+            let h = series_hash(row.fw_metric_name(), row.fw_attributes());
+            if let Some(seen) = tracker.series.get_mut(&h) {
+                *seen = now;
+                return true;
+            }
+            if tracker.series.len() as u64 >= rule.max_series {
+                tracker.prune(now, rule.window_secs);
+            }
+            if (tracker.series.len() as u64) < rule.max_series {
+                tracker.series.insert(h, now);
+                tracker.observe_labels(row.fw_attributes());
+                return true;
+            }
+
+            // Over the limit with a new series.
+            let key = (row.fw_tenant_id().to_string(), row.fw_metric_name().to_string());
+            let ev = events.entry(key).or_insert_with(|| LimitEvent {
+                tenant_id: row.fw_tenant_id().to_string(),
+                metric_name: row.fw_metric_name().to_string(),
+                max_series: rule.max_series,
+                active_series: 0,
+                action: rule.action,
+                dropped: 0,
+                stripped: 0,
+                stripped_labels: Vec::new(),
+                channel_ids: rule.channel_ids.clone(),
+            });
+            ev.active_series = tracker.series.len() as u64;
+
+            if rule.action == LimitAction::StripLabels {
+                let strip = if rule.strip_labels.is_empty() {
+                    tracker.worst_label().into_iter().collect()
+                } else {
+                    rule.strip_labels.clone()
+                };
+                if !strip.is_empty() {
+                    row.fw_attributes_mut().retain(|(k, _)| !strip.contains(k));
+                    let collapsed = series_hash(row.fw_metric_name(), row.fw_attributes());
+                    let room = (tracker.series.len() as u64) < rule.max_series.saturating_mul(2);
+                    let admitted = match tracker.series.get_mut(&collapsed) {
+                        Some(seen) => {
+                            *seen = now;
+                            true
+                        }
+                        None if room => {
+                            tracker.series.insert(collapsed, now);
+                            true
+                        }
+                        None => false,
+                    };
+                    if admitted {
+                        for l in strip {
+                            if !ev.stripped_labels.contains(&l) {
+                                ev.stripped_labels.push(l);
+                            }
+                        }
+                        ev.stripped += 1;
+                        outcome.stripped += 1;
+                        return true;
+                    }
+                }
+            }
+            ev.dropped += 1;
+            outcome.dropped += 1;
+            false
+        });
+
+        for (_, ev) in events {
+            let _ = self.events_tx.send(ev);
+        }
+        outcome
+    }
+
+    /// Spawn the background task that records usage events for every limited
+    /// batch and notifies the rule's channels, at most once per
+    /// `NOTIFY_COOLDOWN` per (tenant, metric). No-op if already spawned.
+    pub fn spawn_notifier(
+        &self,
+        config_db: Arc<crate::clickhouse_config::ConfigDb>,
+        usage: crate::usage_tracker::UsageTracker,
+        smtp_config: crate::alert_engine::SmtpConfig,
+    ) {
+        let Some(mut rx) = self.events_rx.lock().ok().and_then(|mut g| g.take()) else { return };
+        tokio::spawn(async move {
+            let http_client = reqwest::Client::new();
+            let smtp_transport = crate::alert_engine::build_smtp_transport(&smtp_config);
+            // (tenant, metric) → (last notified, dropped, stripped since then)
+            let mut pending: HashMap<(String, String), (Option<Instant>, u64, u64)> = HashMap::new();
+            while let Some(ev) = rx.recv().await {
+                usage.track(crate::usage_tracker::UsageEvent {
+                    signal_name: ev.metric_name.clone(),
+                    signal_type: "metric".to_string(),
+                    source: "cardinality_limit".to_string(),
+                });
+                let entry = pending.entry((ev.tenant_id.clone(), ev.metric_name.clone())).or_insert((None, 0, 0));
+                entry.1 += ev.dropped;
+                entry.2 += ev.stripped;
+                if matches!(entry.0, Some(at) if at.elapsed() < NOTIFY_COOLDOWN) {
+                    continue;
+                }
+                let (dropped, stripped) = (entry.1, entry.2);
+                *entry = (Some(Instant::now()), 0, 0);
+
+                tracing::warn!(
+                    tenant_id = %ev.tenant_id,
+                    metric = %ev.metric_name,
+                    max_series = ev.max_series,
+                    action = ev.action.as_str(),
+                    dropped = dropped,
+                    stripped = stripped,
+                    "cardinality limit reached"
+                );
+                let mut message = format!(
+                    "Metric `{}` (tenant {}) reached its cardinality limit of {} active series. ",
+                    ev.metric_name, ev.tenant_id, ev.max_series
+                );
+                if dropped > 0 {
+                    message.push_str(&format!("{dropped} datapoints for new series were dropped. "));
+                }
+                if stripped > 0 {
+                    message.push_str(&format!(
+                        "{stripped} datapoints had labels [{}] stripped. ",
+                        ev.stripped_labels.join(", ")
+                    ));
+                }
+                let alert_name = format!("Cardinality limit: {}", ev.metric_name);
+                for channel_id in &ev.channel_ids {
+                    let Ok(Some(channel)) = config_db.get_channel_by_id(channel_id).await else { continue };
+                    if !channel.enabled || channel.tenant_id != ev.tenant_id {
+                        continue;
+                    }
+                    let result = crate::alert_engine::send_channel_notification(
+                        &channel,
+                        &message,
+                        &alert_name,
+                        "limited",
+                        ev.active_series as f64,
+                        ev.max_series as f64,
+                        "metrics",
+                        ">=",
+                        &message,
+                        "",
+                        "",
+                        &http_client,
+                        &smtp_config,
+                        &smtp_transport,
+                    )
+                    .await;
+                    let (status, error_msg) = match &result {
+                        Ok(()) => ("sent", String::new()),
+                        Err(e) => {
+                            tracing::warn!(channel_id = %channel_id, error = %e, "cardinality limit notification failed");
+                            ("failed", e.clone())
+                        }
+                    };
+                    let _ = config_db.create_notification_log(
+                        channel_id,
+                        &ev.tenant_id,
+                        "cardinality_limit",
+                        &alert_name,
+                        "warning",
+                        status,
+                        &error_msg,
+                    ).await;
+                }
+            }
+        });
+    }
+}
+
+/// Current unix second (the limiter's clock).
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Row { tenant: String, name: String, attrs: Vec<(String, String)> }
+    impl MetricRow for Row {
+        fn fw_tenant_id(&self) -> &str { &self.tenant }
+        fn fw_metric_name(&self) -> &str { &self.name }
+        fn fw_set_metric_name(&mut self, name: &str) { self.name = name.into(); }
+        fn fw_attributes(&self) -> &[(String, String)] { &self.attrs }
+        fn fw_attributes_mut(&mut self) -> &mut Vec<(String, String)> { &mut self.attrs }
+    }
+    fn row(tenant: &str, name: &str, attrs: &[(&str, &str)]) -> Row {
+        Row {
+            tenant: tenant.into(),
+            name: name.into(),
+            attrs: attrs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+    fn limit(tenant: &str, metric: &str, max: u64, action: &str, strip: &str) -> RawLimit {
+        RawLimit {
+            tenant_id: tenant.into(),
+            metric_name: metric.into(),
+            max_series: max,
+            action: action.into(),
+            strip_labels: strip.into(),
+            window_secs: 60,
+            notification_channel_ids: "[]".into(),
+        }
+    }
+    fn limiter(raw: &[RawLimit]) -> CardinalityLimiter {
+        let l = CardinalityLimiter::default();
+        l.set_config(LimitConfig::compile(raw));
+        l
+    }
+    fn req_rows(metric: &str, ids: std::ops::Range<u32>) -> Vec<Row> {
+        ids.map(|i| row("t1", metric, &[("route", "/a"), ("request_id", &i.to_string())])).collect()
+    }
+
+    #[test]
+    fn drops_new_series_over_limit_but_keeps_existing() {
+        let l = limiter(&[limit("t1", "http_requests", 3, "drop", "")]);
+        let mut rows = req_rows("http_requests", 0..5);
+        assert_eq!(l.apply(&mut rows, 100), LimitOutcome { dropped: 2, stripped: 0 });
+        assert_eq!(rows.len(), 3);
+        // Already-admitted series keep flowing.
+        let mut again = req_rows("http_requests", 0..3);
+        assert_eq!(l.apply(&mut again, 101).dropped, 0);
+        assert_eq!(l.active_series("t1")["http_requests"], 3);
+    }
+
+    #[test]
+    fn series_identity_ignores_label_order() {
+        let l = limiter(&[limit("t1", "m", 1, "drop", "")]);
+        let mut rows = vec![
+            row("t1", "m", &[("a", "1"), ("b", "2")]),
+            row("t1", "m", &[("b", "2"), ("a", "1")]),
+        ];
+        assert_eq!(l.apply(&mut rows, 0).dropped, 0);
+    }
+
+    #[test]
+    fn swapped_label_values_are_distinct_series() {
+        let l = limiter(&[limit("t1", "m", 1, "drop", "")]);
+        let mut rows = vec![
+            row("t1", "m", &[("a", "x"), ("b", "y")]),
+            row("t1", "m", &[("a", "y"), ("b", "x")]),
+        ];
+        assert_eq!(l.apply(&mut rows, 0).dropped, 1);
+        assert_ne!(
+            series_hash("m", &[("a".into(), "x".into()), ("b".into(), "y".into())]),
+            series_hash("m", &[("a".into(), "y".into()), ("b".into(), "x".into())]),
+        );
+    }
+
+    #[test]
+    fn metric_override_beats_tenant_default_and_other_tenants_unaffected() {
+        let l = limiter(&[
+            limit("t1", TENANT_DEFAULT_METRIC, 1, "drop", ""),
+            limit("t1", "big", 10, "drop", ""),
+        ]);
+        let mut rows = req_rows("big", 0..5);
+        rows.extend(req_rows("small", 0..5));
+        rows.extend((0..5).map(|i| row("t2", "small", &[("id", &i.to_string())])));
+        let out = l.apply(&mut rows, 0);
+        assert_eq!(out.dropped, 4); // only t1/small is over (limit 1)
+        assert_eq!(rows.iter().filter(|r| r.tenant == "t2").count(), 5);
+        assert!(l.active_series("t2").is_empty(), "unlimited tenants are not tracked");
+    }
+
+    #[test]
+    fn sliding_window_frees_expired_series() {
+        let l = limiter(&[limit("t1", "m", 2, "drop", "")]);
+        let mut rows = req_rows("m", 0..2);
+        l.apply(&mut rows, 0);
+        let mut fresh = req_rows("m", 2..4);
+        assert_eq!(l.apply(&mut fresh, 30).dropped, 2, "old series still active");
+        let mut later = req_rows("m", 2..4);
+        assert_eq!(l.apply(&mut later, 61).dropped, 0, "old series expired after window");
+    }
+
+    #[test]
+    fn strip_configured_labels_collapses_series() {
+        let l = limiter(&[limit("t1", "m", 2, "strip_labels", "request_id")]);
+        let mut rows = req_rows("m", 0..6);
+        let out = l.apply(&mut rows, 0);
+        assert_eq!(out, LimitOutcome { dropped: 0, stripped: 4 });
+        assert_eq!(rows.len(), 6);
+        assert!(rows[2..].iter().all(|r| r.attrs == vec![("route".to_string(), "/a".to_string())]));
+        // Two admitted originals + one collapsed series.
+        assert_eq!(l.active_series("t1")["m"], 3);
+    }
+
+    #[test]
+    fn strip_auto_picks_highest_cardinality_label() {
+        let l = limiter(&[limit("t1", "m", 3, "strip_labels", "")]);
+        let mut rows: Vec<Row> = (0..6)
+            .map(|i| row("t1", "m", &[("route", if i % 2 == 0 { "/a" } else { "/b" }), ("request_id", &i.to_string())]))
+            .collect();
+        let out = l.apply(&mut rows, 0);
+        assert_eq!(out.dropped, 0);
+        assert_eq!(out.stripped, 3);
+        assert!(rows[3..].iter().all(|r| r.attrs.iter().all(|(k, _)| k == "route")));
+    }
+
+    #[test]
+    fn limited_batches_emit_one_event_per_metric() {
+        let l = limiter(&[limit("t1", "m", 1, "drop", "")]);
+        let mut rx = l.events_rx.lock().unwrap().take().unwrap();
+        let mut rows = req_rows("m", 0..4);
+        l.apply(&mut rows, 0);
+        let ev = rx.try_recv().expect("event emitted");
+        assert_eq!((ev.tenant_id.as_str(), ev.metric_name.as_str(), ev.dropped), ("t1", "m", 3));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn gc_forgets_metrics_without_limits() {
+        let l = limiter(&[limit("t1", "m", 5, "drop", "")]);
+        let mut rows = req_rows("m", 0..2);
+        l.apply(&mut rows, 0);
+        l.set_config(LimitConfig::default());
+        l.gc(1);
+        assert!(l.active_series("t1").is_empty());
+    }
+}
@@ -7,8 +7,13 @@
 ///               JSON and handed to `Spool::append`.  If the spool is full a
 ///               `WriteError::Backpressure` is returned (→ HTTP 429).
 ///
+/// WAL mode:     with `RUSH_INGEST_DURABILITY=wal`, batched writes are first
+///               appended to a local fsync'd WAL (`crate::wal`) and acked only
+///               then; see `ChWriter::with_wal`.
+///
 /// Replay:       `spawn_replayer` consumes the oldest segment every ~5 s,
-///               retrying with exponential back-off up to 60 s.
+///               retrying with exponential back-off up to 60 s. Records CH
+///               keeps rejecting are moved to the buffer's dead-letter area.
 
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -20,7 +25,8 @@ use tokio::sync::Mutex as AsyncMutex;
 use crate::models::ingest::{ExpHistogramRow, GaugeRow, HistogramRow, LogInsertRow, RumReplayChunk, SumRow, SummaryRow, TraceInsertRow};
 use crate::models::rum::RumRecord;
 use crate::models::trace::WideEvent;
-use crate::spool::{IngestBuffer, SpoolFull};
+use crate::spool::{IngestBuffer, Spool, SpoolFull};
+use crate::wal::{Wal, WalTicket};
 
 // ─── Public error type ───────────────────────────────────────────────────────
 
@@ -92,6 +98,59 @@ impl SpoolBatch {
         }
     }
 
+    /// Distinct tenants with rows in this batch, in first-seen order.
+    pub fn tenants(&self) -> Vec<&str> {
+        fn distinct<R: TenantRow>(rows: &[R]) -> Vec<&str> {
+            let mut out: Vec<&str> = Vec::new();
+            for r in rows {
+                if !out.contains(&r.tenant()) {
+                    out.push(r.tenant());
+                }
+            }
+            out
+        }
+        match self {
+            SpoolBatch::SpansRaw(v) => distinct(v),
+            SpoolBatch::Spans(v) => distinct(v),
+            SpoolBatch::Logs(v) => distinct(v),
+            SpoolBatch::Gauge(v) => distinct(v),
+            SpoolBatch::Sum(v) => distinct(v),
+            SpoolBatch::Rum(v) => distinct(v),
+            SpoolBatch::RumReplay(v) => distinct(v),
+            SpoolBatch::Histogram(v) => distinct(v),
+            SpoolBatch::ExpHistogram(v) => distinct(v),
+            SpoolBatch::Summary(v) => distinct(v),
+        }
+    }
+
+    /// Split into one batch per tenant (row order kept within each), so that
+    /// spooled data is attributed to — and capped against — its owner. The
+    /// batcher coalesces rows across requests, hence across tenants.
+    pub fn split_by_tenant(self) -> Vec<(String, SpoolBatch)> {
+        fn split<R: TenantRow>(rows: Vec<R>, wrap: fn(Vec<R>) -> SpoolBatch) -> Vec<(String, SpoolBatch)> {
+            let mut groups: Vec<(String, Vec<R>)> = Vec::new();
+            for r in rows {
+                match groups.iter_mut().find(|(t, _)| t == r.tenant()) {
+                    Some((_, g)) => g.push(r),
+                    None => groups.push((r.tenant().to_string(), vec![r])),
+                }
+            }
+            groups.into_iter().map(|(t, g)| (t, wrap(g))).collect()
+        }
+        match self {
+            SpoolBatch::SpansRaw(v) => split(v, SpoolBatch::SpansRaw),
+            SpoolBatch::Spans(v) => split(v, SpoolBatch::Spans),
+            SpoolBatch::Logs(v) => split(v, SpoolBatch::Logs),
+            SpoolBatch::Gauge(v) => split(v, SpoolBatch::Gauge),
+            SpoolBatch::Sum(v) => split(v, SpoolBatch::Sum),
+            SpoolBatch::Rum(v) => split(v, SpoolBatch::Rum),
+            SpoolBatch::RumReplay(v) => split(v, SpoolBatch::RumReplay),
+            SpoolBatch::Histogram(v) => split(v, SpoolBatch::Histogram),
+            SpoolBatch::ExpHistogram(v) => split(v, SpoolBatch::ExpHistogram),
+            SpoolBatch::Summary(v) => split(v, SpoolBatch::Summary),
+        }
+    }
+
     /// Stable index for this variant's per-table buffer slot (one slot per
     /// ClickHouse table). Used by the cross-request batcher to coalesce rows of
     /// the same variant from independent requests into one larger insert.
@@ -133,6 +192,24 @@ impl SpoolBatch {
     }
 }
 
+/// Rows that carry a tenant id (every `SpoolBatch` row type).
+trait TenantRow {
+    fn tenant(&self) -> &str;
+}
+
+macro_rules! impl_tenant_row {
+    ($($t:ty),* $(,)?) => {
+        $(impl TenantRow for $t {
+            fn tenant(&self) -> &str { &self.tenant_id }
+        })*
+    };
+}
+
+impl_tenant_row!(
+    TraceInsertRow, WideEvent, LogInsertRow, GaugeRow, SumRow, RumRecord, RumReplayChunk,
+    HistogramRow, ExpHistogramRow, SummaryRow,
+);
+
 // ─── Cross-request insert batching ─────────────────────────────────────────────
 //
 // Each ingest HTTP request previously triggered an immediate typed insert (and a
@@ -161,6 +238,13 @@ impl SpoolBatch {
 //   Operators who require today's synchronous 429 / zero in-memory buffering can
 //   set RUSH_INGEST_BATCH_ROWS=1 or RUSH_INGEST_BATCH_MS=0, which makes `write`
 //   flush immediately (identical to the pre-batching behavior, synchronous 429).
+//
+//   The third option, RUSH_INGEST_DURABILITY=wal, keeps batching but closes the
+//   crash window: each request's rows are group-committed to a local fsync'd WAL
+//   before `write` returns, and every buffered batch carries the `WalTicket`s of
+//   the appends it holds. Tickets are dropped (releasing their WAL segment) once
+//   the flush inserted or spooled the rows; tickets of tenants whose rows the
+//   spool refused are kept, and the WAL replays them on the next startup.
 
 #[derive(Clone, Copy, Debug)]
 pub struct BatchConfig {
@@ -187,7 +271,7 @@ impl BatchConfig {
     }
 
     /// True when batching is effectively disabled (flush every write inline).
-    fn disabled(&self) -> bool {
+    pub fn disabled(&self) -> bool {
         self.max_rows <= 1 || self.max_age.is_zero()
     }
 }
@@ -199,13 +283,21 @@ impl Default for BatchConfig {
 }
 
 /// One per-table buffer slot: the coalesced batch plus the instant its first
-/// row was buffered (for the age-based flush trigger).
+/// row was buffered (for the age-based flush trigger), and in WAL mode the
+/// tickets of the appends whose rows it holds.
 #[derive(Default)]
 struct TableBuffer {
     batch: Option<SpoolBatch>,
     first_row_at: Option<Instant>,
+    wal: Vec<TenantTicket>,
 }
 
+/// A WAL append's ticket and the tenants whose rows it holds.
+type TenantTicket = (Vec<String>, WalTicket);
+
+/// A batch taken from the batcher for flushing, with its WAL tickets.
+type DueBatch = (SpoolBatch, Vec<TenantTicket>);
+
 /// The in-process batching layer: one buffer per ClickHouse table, each behind
 /// its own async mutex so independent ingest tasks contend only with same-table
 /// peers and the flusher.
@@ -226,7 +318,7 @@ impl BatchAccumulator {
     /// the batch to flush NOW if enqueuing pushed the slot to/over `max_rows`
     /// (so the caller flushes inline, bounding latency + memory); otherwise the
     /// rows stay buffered for the background flusher / age trigger.
-    async fn enqueue(&self, batch: SpoolBatch) -> Option<SpoolBatch> {
+    async fn enqueue(&self, batch: SpoolBatch, ticket: Option<TenantTicket>) -> Option<DueBatch> {
         let slot = batch.slot();
         let mut g = self.slots[slot].lock().await;
         match g.batch.as_mut() {
@@ -236,10 +328,10 @@ impl BatchAccumulator {
                 g.batch = Some(batch);
             }
         }
+        g.wal.extend(ticket);
         let len = g.batch.as_ref().map(|b| b.len()).unwrap_or(0);
         if len >= self.cfg.max_rows {
-            g.first_row_at = None;
-            g.batch.take()
+            g.take()
         } else {
             None
         }
@@ -247,31 +339,82 @@ impl BatchAccumulator {
 
     /// Take any slot whose buffer is due to flush by the age trigger. Returns the
     /// drained batch (caller flushes it). Called repeatedly by the flush task.
-    async fn take_aged(&self, slot: usize, now: Instant) -> Option<SpoolBatch> {
+    async fn take_aged(&self, slot: usize, now: Instant) -> Option<DueBatch> {
         let mut g = self.slots[slot].lock().await;
         let due = matches!(g.first_row_at, Some(t) if now.duration_since(t) >= self.cfg.max_age);
         if due {
-            g.first_row_at = None;
-            g.batch.take()
+            g.take()
         } else {
             None
         }
     }
 
     /// Drain every non-empty slot unconditionally (used by graceful shutdown).
-    async fn drain_all(&self) -> Vec<SpoolBatch> {
+    async fn drain_all(&self) -> Vec<DueBatch> {
         let mut out = Vec::new();
         for slot in &self.slots {
-            let mut g = slot.lock().await;
-            g.first_row_at = None;
-            if let Some(b) = g.batch.take() {
-                out.push(b);
+            if let Some(due) = slot.lock().await.take() {
+                out.push(due);
             }
         }
         out
     }
 }
 
+impl TableBuffer {
+    /// Empty the slot, returning its batch (if any) with its WAL tickets.
+    fn take(&mut self) -> Option<DueBatch> {
+        self.first_row_at = None;
+        let batch = self.batch.take()?;
+        Some((batch, std::mem::take(&mut self.wal)))
+    }
+}
+
+/// What the replayer knows about the records of segments it has not committed
+/// yet, keyed by (segment id, record index). A segment is only committed once
+/// every record is handled, so when a later record fails transiently the next
+/// pass must skip the records it already inserted or dead-lettered — otherwise
+/// they would be inserted again or quarantined twice. In memory only: after a
+/// restart a half-replayed segment is replayed from its first record.
+#[derive(Debug, Default)]
+struct ReplayLedger {
+    records: std::collections::HashMap<(String, usize), RecordOutcome>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordOutcome {
+    /// Rejected by ClickHouse this many times; retried until `max_attempts`.
+    Failed(u32),
+    /// Inserted or quarantined.
+    Handled,
+}
+
+impl ReplayLedger {
+    fn is_handled(&self, segment: &str, idx: usize) -> bool {
+        self.records.get(&(segment.to_string(), idx)) == Some(&RecordOutcome::Handled)
+    }
+
+    fn mark_handled(&mut self, segment: &str, idx: usize) {
+        self.records.insert((segment.to_string(), idx), RecordOutcome::Handled);
+    }
+
+    /// Count one more non-transient rejection; returns the total so far.
+    fn record_failure(&mut self, segment: &str, idx: usize) -> u32 {
+        let outcome = self.records.entry((segment.to_string(), idx)).or_insert(RecordOutcome::Failed(0));
+        let n = match *outcome {
+            RecordOutcome::Failed(n) => n + 1,
+            RecordOutcome::Handled => 1,
+        };
+        *outcome = RecordOutcome::Failed(n);
+        n
+    }
+
+    /// Drop everything about a committed segment.
+    fn forget(&mut self, segment: &str) {
+        self.records.retain(|(s, _), _| s != segment);
+    }
+}
+
 // ─── ChWriter ────────────────────────────────────────────────────────────────
 
 /// Cloneable ClickHouse writer with an integrated durable buffer (spool).
@@ -284,9 +427,17 @@ pub struct ChWriter {
     /// Hot-swappable compiled metric firewall (applied to metric batches before
     /// insert/spool). Refreshed by a background task and on config change.
     pub firewall: Arc<std::sync::RwLock<Arc<crate::metric_firewall::MetricFirewall>>>,
+    /// Per-(tenant, metric) active-series limiter, applied after the firewall.
+    pub cardinality: Arc<crate::cardinality_limiter::CardinalityLimiter>,
+    /// Per-series delta → cumulative converter, applied before the firewall.
+    pub temporality: Arc<crate::temporality::DeltaToCumulative>,
+    /// Live-tail subscribers, fed every accepted log / span batch.
+    pub tail: Arc<crate::live_tail::LiveTail>,
     /// Cross-request insert batcher. Rows from multiple ingest requests coalesce
     /// here into fewer, larger ClickHouse inserts (see `BatchAccumulator`).
     batcher: Arc<BatchAccumulator>,
+    /// Write-ahead log for durable acks (`RUSH_INGEST_DURABILITY=wal`).
+    wal: Option<Arc<Wal>>,
 }
 
 impl ChWriter {
@@ -302,7 +453,65 @@ impl ChWriter {
             firewall: Arc::new(std::sync::RwLock::new(Arc::new(
                 crate::metric_firewall::MetricFirewall::default(),
             ))),
+            cardinality: Arc::new(crate::cardinality_limiter::CardinalityLimiter::default()),
+            temporality: Arc::new(crate::temporality::DeltaToCumulative::new(
+                crate::temporality::DeltaConfig::from_env(),
+            )),
+            tail: Arc::new(crate::live_tail::LiveTail::new(crate::live_tail::TailConfig::from_env())),
             batcher: Arc::new(BatchAccumulator::new(cfg)),
+            wal: None,
+        }
+    }
+
+    /// Ack batched writes only once they are in the WAL. Ignored when batching
+    /// is disabled — that path is already synchronous.
+    pub fn with_wal(mut self, wal: Arc<Wal>) -> Self {
+        if !self.batcher.cfg.disabled() {
+            self.wal = Some(wal);
+        }
+        self
+    }
+
+    /// Re-write WAL segments left over from a previous run (see
+    /// `crate::wal::Wal::open`). A segment is deleted once all of its rows were
+    /// inserted or spooled; otherwise it is kept for the next startup.
+    pub async fn replay_wal(&self, segments: Vec<std::path::PathBuf>) {
+        for path in segments {
+            let (records, corrupt) = match Spool::read_segment(&path) {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::error!(error = %e, segment = %path.display(), "wal replay: unreadable segment — keeping it");
+                    continue;
+                }
+            };
+            if corrupt > 0 {
+                tracing::error!(corrupt, segment = %path.display(), "wal replay: skipped corrupt records");
+            }
+            let mut ok = true;
+            for (table, payload) in &records {
+                let batch: SpoolBatch = match serde_json::from_slice(payload) {
+                    Ok(b) => b,
+                    Err(e) => {
+                        tracing::error!(error = %e, table = table, "wal replay: undecodable record — skipping");
+                        continue;
+                    }
+                };
+                match self.write_now(&batch).await {
+                    Ok(refused) if refused.is_empty() => {}
+                    Ok(refused) => {
+                        tracing::warn!(table = table, ?refused, "wal replay: spool full for tenants");
+                        ok = false;
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, table = table, "wal replay: write failed");
+                        ok = false;
+                    }
+                }
+            }
+            if ok {
+                crate::wal::remove_segment(&path);
+            }
+            tracing::info!(records = records.len(), segment = %path.display(), replayed = ok, "wal replay: segment processed");
         }
     }
 
@@ -311,6 +520,19 @@ impl ChWriter {
         self.batcher.cfg
     }
 
+    /// Convert DELTA sums / histograms to CUMULATIVE in place (drops delta
+    /// points that arrive out of order). No-op for other batches.
+    fn apply_temporality(&self, batch: &mut SpoolBatch) {
+        let now = crate::cardinality_limiter::now_secs();
+        let d = &self.temporality;
+        match batch {
+            SpoolBatch::Sum(rows) => d.apply(rows, now),
+            SpoolBatch::Histogram(rows) => d.apply(rows, now),
+            SpoolBatch::ExpHistogram(rows) => d.apply(rows, now),
+            _ => return,
+        };
+    }
+
     /// Apply the metric firewall to a metric batch in place (drops blocked
     /// datapoints, strips dropped labels). No-op for non-metric batches.
     fn apply_firewall(&self, batch: &mut SpoolBatch) {
@@ -334,6 +556,30 @@ impl ChWriter {
         }
     }
 
+    /// Apply per-metric cardinality limits to a metric batch in place (drops or
+    /// collapses datapoints that would create series over the limit). No-op for
+    /// non-metric batches.
+    fn apply_cardinality_limits(&self, batch: &mut SpoolBatch) {
+        let now = crate::cardinality_limiter::now_secs();
+        let limiter = &self.cardinality;
+        let outcome = match batch {
+            SpoolBatch::Gauge(rows) => limiter.apply(rows, now),
+            SpoolBatch::Sum(rows) => limiter.apply(rows, now),
+            SpoolBatch::Histogram(rows) => limiter.apply(rows, now),
+            SpoolBatch::ExpHistogram(rows) => limiter.apply(rows, now),
+            SpoolBatch::Summary(rows) => limiter.apply(rows, now),
+            _ => return,
+        };
+        if outcome.dropped > 0 || outcome.stripped > 0 {
+            tracing::debug!(
+                dropped = outcome.dropped,
+                stripped = outcome.stripped,
+                table = batch.table(),
+                "cardinality limiter limited datapoints"
+            );
+        }
+    }
+
     /// Write a batch to ClickHouse.
     ///
     /// With batching enabled (the default), the firewall is applied here and the
@@ -346,43 +592,99 @@ impl ChWriter {
     /// With batching disabled (`RUSH_INGEST_BATCH_ROWS=1` / `RUSH_INGEST_BATCH_MS=0`)
     /// this behaves exactly as before: a synchronous insert with synchronous
     /// 429 backpressure on spool-full.
+    ///
+    /// Either way the 429 only goes to a request whose own tenant the spool
+    /// refused; an inline flush also carries other tenants' buffered rows.
     pub async fn write(&self, mut batch: SpoolBatch) -> Result<(), WriteError> {
         // Metric firewall runs once here over the request's rows so the firewall
         // semantics are unchanged whether or not batching coalesces afterward
         // (allow→block precedence + label stripping all happen pre-buffer). The
         // spooled/inserted data is therefore already filtered, exactly as before.
+        // Cardinality limits run after the firewall so they count series as
+        // they will be stored (post-relabel). Delta → cumulative runs first,
+        // on the series exactly as the exporter identified them.
+        self.apply_temporality(&mut batch);
         self.apply_firewall(&mut batch);
+        self.apply_cardinality_limits(&mut batch);
         if batch.len() == 0 {
             return Ok(());
         }
+        let tenants: Vec<String> = batch.tenants().into_iter().map(str::to_string).collect();
 
-        if self.batcher.cfg.disabled() {
+        let refused = if self.batcher.cfg.disabled() {
             // Batching off: preserve today's synchronous insert→spool→429 path.
-            return self.write_now(batch).await;
-        }
-
-        // Buffer the rows; flush inline only if this enqueue crossed the row
-        // threshold. Otherwise the background flusher / age trigger drains it.
-        if let Some(due) = self.batcher.enqueue(batch).await {
-            self.write_now(due).await
+            // Live tails see the rows once they are inserted or spooled.
+            let refused = self.write_now(&batch).await?;
+            if refused.is_empty() {
+                self.tail.publish(&batch);
+            } else {
+                for (tenant, part) in batch.split_by_tenant() {
+                    if !refused.contains(&tenant) {
+                        self.tail.publish(&part);
+                    }
+                }
+            }
+            refused
         } else {
-            Ok(())
+            // WAL mode: the rows are durable before the caller is acked.
+            let ticket = match &self.wal {
+                Some(wal) => {
+                    let payload = serde_json::to_vec(&batch)
+                        .map_err(|e| WriteError::Fatal(format!("serde_json serialise: {e}")))?;
+                    let ticket = wal
+                        .append(batch.table(), &payload)
+                        .await
+                        .map_err(|e| WriteError::Fatal(format!("wal append: {e}")))?;
+                    Some((tenants.clone(), ticket))
+                }
+                None => None,
+            };
+
+            // The rows are accepted (buffered, and in the WAL if on): show them
+            // to live tails. Buffer them; flush inline only if this enqueue
+            // crossed the row threshold. Otherwise the background flusher / age
+            // trigger drains it.
+            self.tail.publish(&batch);
+            match self.batcher.enqueue(batch, ticket).await {
+                Some(due) => self.flush(due).await?,
+                None => Vec::new(),
+            }
+        };
+        if refused.iter().any(|t| tenants.contains(t)) { Err(WriteError::Backpressure) } else { Ok(()) }
+    }
+
+    /// Flush a batch taken from the batcher, returning the tenants whose rows
+    /// the spool refused. WAL tickets are released once their rows are inserted
+    /// or spooled; those holding a refused tenant's rows stay in the WAL for the
+    /// next startup.
+    async fn flush(&self, (batch, tickets): DueBatch) -> Result<Vec<String>, WriteError> {
+        let result = self.write_now(&batch).await;
+        for (tenants, ticket) in tickets {
+            let keep = match &result {
+                Ok(refused) => tenants.iter().any(|t| refused.contains(t)),
+                Err(_) => true,
+            };
+            if keep {
+                ticket.keep();
+            }
         }
+        result
     }
 
     /// Perform the actual insert for a (firewall-already-applied) batch, spooling
     /// on CH failure. This is the single durable write path shared by the inline
     /// `write` (batching-disabled), the row-threshold inline flush, the
-    /// background flusher, and graceful shutdown.
-    async fn write_now(&self, batch: SpoolBatch) -> Result<(), WriteError> {
+    /// background flusher, and graceful shutdown. Returns the tenants whose rows
+    /// the spool refused (each tenant is spooled, and capped, separately).
+    async fn write_now(&self, batch: &SpoolBatch) -> Result<Vec<String>, WriteError> {
         if batch.len() == 0 {
-            return Ok(());
+            return Ok(Vec::new());
         }
         let row_count = batch.len();
         let table = batch.table();
 
-        match try_insert(&self.ch, &batch).await {
-            Ok(()) => Ok(()),
+        match try_insert(&self.ch, batch).await {
+            Ok(()) => Ok(Vec::new()),
             Err(e) => {
                 tracing::warn!(
                     error = %e,
@@ -391,14 +693,18 @@ impl ChWriter {
                     "ch insert failed — spooling batch"
                 );
 
-                // Serialise to JSON for the spool.
-                let payload = serde_json::to_vec(&batch)
-                    .map_err(|e| WriteError::Fatal(format!("serde_json serialise: {e}")))?;
-
-                match self.buffer.append(table, payload).await {
-                    Ok(()) => Ok(()),
-                    Err(SpoolFull) => Err(WriteError::Backpressure),
+                // Serialise to JSON for the spool, one record per tenant. A
+                // tenant over its cap is refused without affecting the others.
+                let mut refused = Vec::new();
+                for (tenant, part) in batch.clone().split_by_tenant() {
+                    let payload = serde_json::to_vec(&part)
+                        .map_err(|e| WriteError::Fatal(format!("serde_json serialise: {e}")))?;
+                    if let Err(SpoolFull) = self.buffer.append(&tenant, table, payload).await {
+                        tracing::warn!(tenant = %tenant, table = table, "spool full for tenant — rows refused");
+                        refused.push(tenant);
+                    }
                 }
+                Ok(refused)
             }
         }
     }
@@ -406,13 +712,20 @@ impl ChWriter {
     /// Flush every buffered table batch to ClickHouse (spooling on failure).
     /// Called on graceful shutdown so no buffered rows are silently dropped.
     pub async fn flush_all(&self) {
-        for batch in self.batcher.drain_all().await {
-            let table = batch.table();
-            let rows = batch.len();
-            if let Err(e) = self.write_now(batch).await {
-                tracing::warn!(error = %e, table = table, rows = rows, "flush_all: write failed (spool full?)");
+        for due in self.batcher.drain_all().await {
+            let table = due.0.table();
+            let rows = due.0.len();
+            match self.flush(due).await {
+                Ok(refused) if !refused.is_empty() => {
+                    tracing::warn!(table = table, ?refused, "flush_all: spool full for tenants — rows refused");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!(error = %e, table = table, rows = rows, "flush_all: write failed"),
             }
         }
+        if let Some(wal) = &self.wal {
+            wal.close();
+        }
     }
 
     /// Spawn the background flush task. It wakes on a fixed cadence (a fraction of
@@ -438,11 +751,11 @@ impl ChWriter {
                 interval.tick().await;
                 let now = Instant::now();
                 for slot in 0..SpoolBatch::SLOTS {
-                    if let Some(batch) = me.batcher.take_aged(slot, now).await {
-                        let table = batch.table();
-                        let rows = batch.len();
-                        if let Err(e) = me.write_now(batch).await {
-                            tracing::warn!(error = %e, table = table, rows = rows, "batch flush failed (spool full?)");
+                    if let Some(due) = me.batcher.take_aged(slot, now).await {
+                        let table = due.0.table();
+                        let rows = due.0.len();
+                        if let Err(e) = me.flush(due).await {
+                            tracing::warn!(error = %e, table = table, rows = rows, "batch flush failed");
                         }
                     }
                 }
@@ -461,11 +774,26 @@ impl ChWriter {
     }
 
     /// Spawn a background tokio task that replays spooled segments to CH.
+    ///
+    /// Transient failures (network, timeouts, overload) back off and retry the
+    /// same batch forever. A record ClickHouse rejects outright (schema or value
+    /// errors, see `is_transient_insert_error`) is retried `RUSH_SPOOL_MAX_ATTEMPTS`
+    /// times (default 3, which rides out e.g. a migration that lands between
+    /// attempts) and is then moved to the dead-letter area so it stops blocking
+    /// everything behind it. A record that no longer deserialises is quarantined
+    /// immediately. The rest of the batch is still replayed and committed.
     pub fn spawn_replayer(self) {
+        let max_attempts: u32 = std::env::var("RUSH_SPOOL_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3)
+            .max(1);
         tokio::spawn(async move {
             let mut backoff = Duration::from_secs(5);
             const MAX_BACKOFF: Duration = Duration::from_secs(60);
             const POLL_INTERVAL: Duration = Duration::from_secs(5);
+            // Per-record outcomes of segments that haven't been committed yet.
+            let mut ledger = ReplayLedger::default();
 
             loop {
                 tokio::time::sleep(POLL_INTERVAL).await;
@@ -475,39 +803,68 @@ impl ChWriter {
                         Some(d) => d,
                         None => break, // buffer empty
                     };
+                    let batch_id = drain.id();
 
                     let mut all_ok = true;
-                    for (table, payload) in &drain.records {
+                    for (idx, (table, payload)) in drain.records.iter().enumerate() {
+                        // Inserted or quarantined on an earlier pass of this segment.
+                        if ledger.is_handled(&batch_id, idx) {
+                            continue;
+                        }
                         let batch: SpoolBatch = match serde_json::from_slice(payload) {
                             Ok(b) => b,
                             Err(e) => {
-                                tracing::warn!(
-                                    error = %e,
-                                    table = table,
-                                    "replayer: deserialise failed — skipping record"
-                                );
+                                // Retrying can't make undecodable bytes decode.
+                                let reason = format!("deserialise failed: {e}");
+                                if !self.quarantine_record(&drain.tenant, &batch_id, table, payload, reason, 1).await {
+                                    all_ok = false;
+                                    break;
+                                }
+                                ledger.mark_handled(&batch_id, idx);
                                 continue;
                             }
                         };
 
                         if let Err(e) = try_insert(&self.ch, &batch).await {
-                            tracing::warn!(
-                                error = %e,
-                                table = table,
-                                "replayer: CH insert failed — backing off"
-                            );
-                            all_ok = false;
-                            break;
+                            if is_transient_insert_error(&e) {
+                                tracing::warn!(
+                                    error = %e,
+                                    table = table,
+                                    "replayer: CH insert failed — backing off"
+                                );
+                                all_ok = false;
+                                break;
+                            }
+                            let n = ledger.record_failure(&batch_id, idx);
+                            if n < max_attempts {
+                                tracing::warn!(
+                                    error = %e,
+                                    table = table,
+                                    segment = %batch_id,
+                                    attempt = n,
+                                    max_attempts,
+                                    "replayer: CH rejected record — will retry"
+                                );
+                                all_ok = false;
+                                break;
+                            }
+                            if !self.quarantine_record(&drain.tenant, &batch_id, table, payload, e.to_string(), n).await {
+                                all_ok = false;
+                                break;
+                            }
                         }
+                        ledger.mark_handled(&batch_id, idx);
                     }
 
                     if all_ok {
                         let n = drain.records.len();
                         self.buffer.commit(drain).await;
+                        ledger.forget(&batch_id);
                         tracing::info!(records = n, "replayer: batch replayed and committed");
                         backoff = Duration::from_secs(5); // reset on success
                     } else {
-                        // CH is still down — back off and stop this replay pass.
+                        // CH is still down (or rejected a record that still has
+                        // retries left) — back off and stop this replay pass.
                         // (drain not committed → retried next pass.)
                         tracing::warn!(
                             backoff_secs = backoff.as_secs(),
@@ -521,6 +878,92 @@ impl ChWriter {
             }
         });
     }
+
+    /// Move one spooled record to the dead-letter area. Returns false (and
+    /// logs) if that failed, in which case the batch must not be committed.
+    async fn quarantine_record(
+        &self,
+        tenant: &str,
+        source: &str,
+        table: &str,
+        payload: &[u8],
+        reason: String,
+        attempts: u32,
+    ) -> bool {
+        let meta = crate::spool::DeadLetterMeta {
+            tenant: tenant.to_string(),
+            reason,
+            attempts,
+            source: source.to_string(),
+            quarantined_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        tracing::error!(
+            table = table,
+            segment = source,
+            attempts,
+            reason = %meta.reason,
+            "replayer: quarantining poison record to dead-letter"
+        );
+        match self.buffer.quarantine(table, payload, meta).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::error!(error = %e, segment = source, "replayer: dead-letter write failed");
+                false
+            }
+        }
+    }
+}
+
+/// Whether a failed insert is worth retrying as-is. Connectivity problems,
+/// timeouts and server-side overload/availability errors are transient; errors
+/// about the data itself (row serialisation, or ClickHouse refusing to parse or
+/// convert a value / match the schema) will fail identically on every retry.
+/// Unrecognised server errors default to transient — blocking the drain is
+/// recoverable, quarantining good data is not.
+pub fn is_transient_insert_error(e: &clickhouse::error::Error) -> bool {
+    use clickhouse::error::Error;
+    match e {
+        Error::BadResponse(msg) => {
+            // ClickHouse error codes for rejected input data.
+            const DATA_ERRORS: &[u32] = &[
+                6,   // CANNOT_PARSE_TEXT
+                16,  // NO_SUCH_COLUMN_IN_TABLE
+                26,  // CANNOT_PARSE_QUOTED_STRING
+                27,  // CANNOT_PARSE_INPUT_ASSERTION_FAILED
+                33,  // CANNOT_READ_ALL_DATA (RowBinary / schema mismatch)
+                38,  // CANNOT_PARSE_DATE
+                41,  // CANNOT_PARSE_DATETIME
+                53,  // TYPE_MISMATCH
+                69,  // ARGUMENT_OUT_OF_BOUND
+                70,  // CANNOT_CONVERT_TYPE
+                72,  // CANNOT_PARSE_NUMBER
+                117, // INCORRECT_DATA
+                131, // TOO_LARGE_STRING_SIZE
+            ];
+            match clickhouse_error_code(msg) {
+                Some(code) => !DATA_ERRORS.contains(&code),
+                None => true,
+            }
+        }
+        Error::Custom(_)
+        | Error::SequenceMustHaveLength
+        | Error::NotEnoughData
+        | Error::InvalidUtf8Encoding(_)
+        | Error::InvalidTagEncoding(_)
+        | Error::VariantDiscriminatorIsOutOfBound(_)
+        | Error::Unsupported(_) => false,
+        _ => true,
+    }
+}
+
+/// Extract N from a server error body like `Code: 53. DB::Exception: ...`.
+fn clickhouse_error_code(msg: &str) -> Option<u32> {
+    let rest = &msg[msg.find("Code: ")? + "Code: ".len()..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
 }
 
 // ─── try_insert helper ───────────────────────────────────────────────────────
@@ -649,26 +1092,26 @@ mod batch_tests {
     async fn row_count_threshold_triggers_inline_flush() {
         let acc = BatchAccumulator::new(BatchConfig { max_rows: 10, max_age: Duration::from_secs(60) });
         // Under threshold: buffered, nothing returned.
-        assert!(acc.enqueue(gauge(4)).await.is_none());
-        assert!(acc.enqueue(gauge(3)).await.is_none());
+        assert!(acc.enqueue(gauge(4), None).await.is_none());
+        assert!(acc.enqueue(gauge(3), None).await.is_none());
         // Crossing the threshold returns the coalesced batch (4+3+5 = 12 >= 10).
-        let due = acc.enqueue(gauge(5)).await.expect("should flush at threshold");
-        assert_eq!(due.len(), 12, "all buffered rows coalesce into one flush");
+        let due = acc.enqueue(gauge(5), None).await.expect("should flush at threshold");
+        assert_eq!(due.0.len(), 12, "all buffered rows coalesce into one flush");
         // Slot is now empty again.
-        assert!(acc.enqueue(gauge(1)).await.is_none());
+        assert!(acc.enqueue(gauge(1), None).await.is_none());
     }
 
     #[tokio::test]
     async fn time_threshold_triggers_aged_flush() {
         let acc = BatchAccumulator::new(BatchConfig { max_rows: 1_000_000, max_age: Duration::from_millis(20) });
-        assert!(acc.enqueue(gauge(3)).await.is_none(), "below row threshold → buffered");
+        assert!(acc.enqueue(gauge(3), None).await.is_none(), "below row threshold → buffered");
         let slot = gauge(1).slot();
         // Not yet aged.
         assert!(acc.take_aged(slot, Instant::now()).await.is_none());
         // After max_age elapses, the buffer is due.
         let later = Instant::now() + Duration::from_millis(25);
         let aged = acc.take_aged(slot, later).await.expect("should be due after max_age");
-        assert_eq!(aged.len(), 3);
+        assert_eq!(aged.0.len(), 3);
         // Drained: no longer due.
         assert!(acc.take_aged(slot, later + Duration::from_secs(1)).await.is_none());
     }
@@ -676,27 +1119,186 @@ mod batch_tests {
     #[tokio::test]
     async fn separate_tables_buffer_independently() {
         let acc = BatchAccumulator::new(BatchConfig { max_rows: 5, max_age: Duration::from_secs(60) });
-        assert!(acc.enqueue(gauge(3)).await.is_none());
+        assert!(acc.enqueue(gauge(3), None).await.is_none());
         // Sum rows go to a different slot; gauge's 3 rows don't trip the sum slot.
         let sums = SpoolBatch::Sum(Vec::new());
         // (empty Sum batch just exercises slot independence; 3 gauge rows stay put)
         let _ = sums.slot();
-        assert!(acc.enqueue(gauge(1)).await.is_none(), "gauge still at 4 < 5");
-        let due = acc.enqueue(gauge(1)).await.expect("gauge reaches 5");
-        assert_eq!(due.len(), 5);
+        assert!(acc.enqueue(gauge(1), None).await.is_none(), "gauge still at 4 < 5");
+        let due = acc.enqueue(gauge(1), None).await.expect("gauge reaches 5");
+        assert_eq!(due.0.len(), 5);
     }
 
     #[tokio::test]
     async fn drain_all_empties_every_slot() {
         let acc = BatchAccumulator::new(BatchConfig { max_rows: 1_000_000, max_age: Duration::from_secs(60) });
-        acc.enqueue(gauge(2)).await;
-        acc.enqueue(SpoolBatch::Logs(Vec::new())).await; // empty, still creates a slot entry
-        acc.enqueue(gauge(3)).await; // coalesces with first gauge → 5
+        acc.enqueue(gauge(2), None).await;
+        acc.enqueue(SpoolBatch::Logs(Vec::new()), None).await; // empty, still creates a slot entry
+        acc.enqueue(gauge(3), None).await; // coalesces with first gauge → 5
         let drained = acc.drain_all().await;
-        let total: usize = drained.iter().map(|b| b.len()).sum();
+        let total: usize = drained.iter().map(|(b, _)| b.len()).sum();
         assert_eq!(total, 5, "all buffered rows drained exactly once");
         // Second drain is empty.
         assert!(acc.drain_all().await.is_empty());
     }
-}
 
+    #[tokio::test]
+    async fn wal_tickets_travel_with_the_coalesced_batch() {
+        let ns = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = std::env::temp_dir().join(format!("rush-wal-acc-{}-{ns}", std::process::id()));
+        let cfg = crate::wal::WalConfig { dir: dir.clone(), commit_interval: Duration::ZERO };
+        let (wal, _) = Wal::open(&cfg).unwrap();
+        let acc = BatchAccumulator::new(BatchConfig { max_rows: 5, max_age: Duration::from_secs(60) });
+        let t1 = wal.append("metrics_gauge", b"[]").await.unwrap();
+        let t2 = wal.append("metrics_gauge", b"[]").await.unwrap();
+        assert!(acc.enqueue(gauge(3), Some((vec!["t".into()], t1))).await.is_none());
+        let (batch, tickets) = acc.enqueue(gauge(2), Some((vec!["t".into()], t2))).await.expect("reaches 5");
+        assert_eq!((batch.len(), tickets.len()), (5, 2));
+        // Dropping the tickets (rows durable) unpins the segment.
+        assert_eq!(wal.pinned_segments(), 1);
+        drop(tickets);
+        assert_eq!(wal.pinned_segments(), 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn tenant_gauge(tenant: &str, n: usize) -> SpoolBatch {
+        let SpoolBatch::Gauge(mut rows) = gauge(n) else { unreachable!() };
+        rows.iter_mut().for_each(|r| r.tenant_id = tenant.into());
+        SpoolBatch::Gauge(rows)
+    }
+
+    #[tokio::test]
+    async fn spool_refusals_only_hit_the_owning_tenant() {
+        let ns = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = std::env::temp_dir().join(format!("rush-refuse-{}-{ns}", std::process::id()));
+        let spool = Spool::open(dir.join("spool"), 10_000).unwrap();
+        let buffer = Arc::new(IngestBuffer::Disk(spool));
+        buffer.set_tenant_policy(crate::spool::TenantPolicy { max_pct: 50, weights: Default::default() });
+        // The share only applies while another tenant has data spooled.
+        buffer.append("small", "metrics_gauge", b"[]".to_vec()).await.unwrap();
+        while buffer.append("big", "metrics_gauge", vec![b'x'; 1_000]).await.is_ok() {}
+
+        // Nothing listens on port 1, so every insert fails over to the spool.
+        let ch = Client::default().with_url("http://127.0.0.1:1");
+        let writer = ChWriter::with_batch_config(ch, buffer, BatchConfig { max_rows: 3, max_age: Duration::from_secs(60) });
+        // big's rows sit in the buffer; small's write triggers the inline flush.
+        writer.write(tenant_gauge("big", 2)).await.unwrap();
+        writer.write(tenant_gauge("small", 1)).await.unwrap();
+        writer.write(tenant_gauge("small", 1)).await.unwrap();
+        assert!(matches!(writer.write(tenant_gauge("big", 2)).await, Err(WriteError::Backpressure)));
+
+        // Only the refused tenant's WAL ticket is kept.
+        let wal = |name: &str| {
+            let cfg = crate::wal::WalConfig { dir: dir.join(name), commit_interval: Duration::ZERO };
+            Wal::open(&cfg).unwrap().0
+        };
+        let (wal_big, wal_small) = (wal("wal-big"), wal("wal-small"));
+        let tickets = vec![
+            (vec!["big".to_string()], wal_big.append("metrics_gauge", b"[]").await.unwrap()),
+            (vec!["small".to_string()], wal_small.append("metrics_gauge", b"[]").await.unwrap()),
+        ];
+        let mut batch = tenant_gauge("big", 1);
+        batch.extend_from(tenant_gauge("small", 1));
+        assert_eq!(writer.flush((batch, tickets)).await.unwrap(), ["big"]);
+        assert_eq!((wal_big.pinned_segments(), wal_small.pinned_segments()), (1, 0));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn live_tails_only_see_stored_rows() {
+        use crate::live_tail::{TailEvent, TailFilter, TailSignal};
+        let log = |tenant: &str| crate::models::ingest::LogInsertRow {
+            tenant_id: tenant.into(),
+            timestamp: 1,
+            trace_id: String::new(),
+            span_id: String::new(),
+            trace_flags: 0,
+            severity_text: "INFO".into(),
+            severity_number: 9,
+            body: format!("from {tenant}"),
+            service_name: "api".into(),
+            resource_schema_url: "".into(),
+            resource_attributes: Arc::new(vec![]),
+            scope_schema_url: "".into(),
+            scope_name: "".into(),
+            scope_version: "".into(),
+            scope_attributes: Arc::new(vec![]),
+            log_attributes: vec![],
+            event_name: String::new(),
+        };
+        let ns = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = std::env::temp_dir().join(format!("rush-tail-{}-{ns}", std::process::id()));
+        let buffer = Arc::new(IngestBuffer::Disk(Spool::open(&dir, 10_000).unwrap()));
+        buffer.set_tenant_policy(crate::spool::TenantPolicy { max_pct: 50, weights: Default::default() });
+        // The share only applies while another tenant has data spooled.
+        buffer.append("small", "logs", b"[]".to_vec()).await.unwrap();
+        while buffer.append("big", "logs", vec![b'x'; 1_000]).await.is_ok() {}
+
+        // Batching off and ClickHouse unreachable: rows are stored only if spooled.
+        let ch = Client::default().with_url("http://127.0.0.1:1");
+        let writer = ChWriter::with_batch_config(ch, buffer, BatchConfig { max_rows: 1, max_age: Duration::ZERO });
+        let mut big = writer.tail.subscribe("big", TailSignal::Logs, TailFilter::new(vec![], None)).unwrap();
+        let mut small = writer.tail.subscribe("small", TailSignal::Logs, TailFilter::new(vec![], None)).unwrap();
+        let batch = SpoolBatch::Logs(vec![log("big"), log("small")]);
+        assert!(matches!(writer.write(batch).await, Err(WriteError::Backpressure)));
+
+        let Some(TailEvent::Row(row)) = small.next().await else { panic!("expected small's row") };
+        assert!(row.contains("from small"));
+        assert!(tokio::time::timeout(Duration::from_millis(50), big.next()).await.is_err(), "big's rows were refused");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replay_ledger_skips_handled_records_until_commit() {
+        let mut ledger = ReplayLedger::default();
+        // First pass: record 0 inserted, record 1 quarantined, record 2 rejected.
+        ledger.mark_handled("seg-a", 0);
+        ledger.mark_handled("seg-a", 1);
+        assert_eq!(ledger.record_failure("seg-a", 2), 1);
+        // Next pass re-reads the segment: only record 2 is retried.
+        assert!(ledger.is_handled("seg-a", 0) && ledger.is_handled("seg-a", 1));
+        assert!(!ledger.is_handled("seg-a", 2));
+        assert_eq!(ledger.record_failure("seg-a", 2), 2);
+        assert!(!ledger.is_handled("seg-b", 0));
+
+        ledger.mark_handled("seg-a", 2);
+        ledger.forget("seg-a");
+        assert!(!ledger.is_handled("seg-a", 0));
+        assert!(ledger.records.is_empty());
+    }
+
+    #[test]
+    fn insert_errors_classified_for_dead_lettering() {
+        use clickhouse::error::Error;
+        let bad = |m: &str| Error::BadResponse(m.to_string());
+        assert_eq!(clickhouse_error_code("Code: 53. DB::Exception: Type mismatch"), Some(53));
+        assert_eq!(clickhouse_error_code("no code here"), None);
+        // Data errors are poison; overload / unknown server errors are retried.
+        assert!(!is_transient_insert_error(&bad("Code: 53. DB::Exception: Type mismatch")));
+        assert!(!is_transient_insert_error(&bad("Code: 33. DB::Exception: Cannot read all data")));
+        assert!(is_transient_insert_error(&bad("Code: 252. DB::Exception: Too many parts")));
+        assert!(is_transient_insert_error(&bad("502 Bad Gateway")));
+        assert!(is_transient_insert_error(&Error::TimedOut));
+        assert!(!is_transient_insert_error(&Error::NotEnoughData));
+    }
+
+    #[test]
+    fn split_by_tenant_groups_rows_in_order() {
+        let SpoolBatch::Gauge(mut rows) = gauge(4) else { unreachable!() };
+        for (r, (t, v)) in rows.iter_mut().zip([("a", 1.0), ("b", 2.0), ("a", 3.0), ("c", 4.0)]) {
+            r.tenant_id = t.into();
+            r.value = v;
+        }
+        let batch = SpoolBatch::Gauge(rows);
+        assert_eq!(batch.tenants(), ["a", "b", "c"]);
+        let parts: Vec<(String, Vec<f64>)> = batch
+            .split_by_tenant()
+            .into_iter()
+            .map(|(t, b)| match b {
+                SpoolBatch::Gauge(rows) => (t, rows.iter().map(|r| r.value).collect()),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(parts, [("a".to_string(), vec![1.0, 3.0]), ("b".into(), vec![2.0]), ("c".into(), vec![4.0])]);
+    }
+}
@@ -72,6 +72,52 @@ pub struct AnomalyRuleRow {
     pub created_at: String, pub updated_at: String,
 }
 
+#[derive(clickhouse::Row, serde::Deserialize)]
+pub struct SavedQueryRow {
+    pub id: String, pub tenant_id: String, pub owner_id: String, pub name: String,
+    pub description: String, pub visibility: String, pub signal: String,
+    pub filters: String, pub search: String, pub group_by: String,
+    pub aggregation: String, pub columns: String, pub default_range: String,
+    pub created_at: String, pub updated_at: String,
+}
+
+#[derive(clickhouse::Row, serde::Deserialize)]
+pub struct ScheduledExportRow {
+    pub id: String, pub tenant_id: String, pub owner_id: String, pub name: String,
+    pub saved_query_id: String, pub schedule: String, pub format: String,
+    pub destination: String, pub notification_channels: String, pub enabled: u8,
+    pub last_run_at: String, pub last_status: String, pub last_error: String,
+    pub exported_until: String, pub created_at: String, pub updated_at: String,
+}
+
+#[derive(clickhouse::Row, serde::Deserialize)]
+pub struct ExportRunRow {
+    pub id: String, pub export_id: String, pub tenant_id: String,
+    pub scheduled_at: String, pub started_at: String, pub finished_at: String,
+    pub window_from: String, pub window_to: String, pub status: String,
+    pub rows: u64, pub bytes: u64, pub object_path: String, pub error: String,
+}
+
+#[derive(clickhouse::Row, serde::Deserialize)]
+pub struct QueryLimitsRow {
+    pub tenant_id: String, pub max_concurrent_queries: u32, pub queries_per_minute: u32,
+    pub max_execution_secs: u32, pub max_bytes_to_read: u64, pub max_result_rows: u64,
+    pub enforce_result_rows: u8,
+}
+
+impl From<QueryLimitsRow> for crate::query_limits::QueryLimits {
+    fn from(r: QueryLimitsRow) -> Self {
+        crate::query_limits::QueryLimits {
+            max_concurrent_queries: r.max_concurrent_queries,
+            queries_per_minute: r.queries_per_minute,
+            max_execution_secs: r.max_execution_secs,
+            max_bytes_to_read: r.max_bytes_to_read,
+            max_result_rows: r.max_result_rows,
+            enforce_result_rows: r.enforce_result_rows != 0,
+        }
+    }
+}
+
 #[derive(clickhouse::Row, serde::Deserialize)]
 pub struct MonitorRow {
     pub id: String, pub tenant_id: String, pub name: String, pub monitor_type: String,
@@ -101,7 +147,9 @@ pub struct ConfigDb {
 }
 
 /// A metric firewall rule (storage + API shape). `enabled`/`*_regex` are 0/1.
-/// `action` is "allow", "block" or "drop_label".
+/// `action` is "allow", "block", "drop_label" or one of the relabel actions
+/// ("replace", "labelmap", "labelkeep", "hashmod"). `source_labels` is a
+/// comma-separated list.
 #[derive(Debug, Clone, clickhouse::Row, serde::Deserialize, serde::Serialize)]
 pub struct MetricFirewallRule {
     pub id: String,
@@ -115,9 +163,29 @@ pub struct MetricFirewallRule {
     pub match_label_value_regex: u8,
     pub drop_label_pattern: String,
     pub drop_label_regex: u8,
+    pub source_labels: String,
+    pub separator: String,
+    pub regex: String,
+    pub target_label: String,
+    pub replacement: String,
+    pub modulus: u64,
     pub created_at: String,
 }
 
+/// A per-tenant cardinality limit (storage + API shape). `metric_name` is `*`
+/// for the tenant default; `notification_channel_ids` is a JSON array.
+#[derive(Debug, Clone, clickhouse::Row, serde::Deserialize, serde::Serialize)]
+pub struct CardinalityLimitRow {
+    pub tenant_id: String,
+    pub metric_name: String,
+    pub max_series: u64,
+    pub action: String,
+    pub strip_labels: String,
+    pub window_secs: u64,
+    pub notification_channel_ids: String,
+    pub updated_at: String,
+}
+
 /// Global retention caps. Per-signal values of 0 mean "inherit `default_days`".
 /// These are the maximum retention per signal (logs / metrics / apm), used as
 /// the table-level TTL and as the ceiling for tenant overrides. `apm` covers
@@ -709,6 +777,30 @@ impl ConfigDb {
             ) ENGINE = ReplacingMergeTree(version)
             ORDER BY (tenant_id, signal)",
 
+            // ── Tenant metric naming (OTel vs Prometheus-normalized) ──────────────
+            "CREATE TABLE IF NOT EXISTS config_tenant_metric_naming (
+                tenant_id  String,
+                mode       String,
+                version    UInt64,
+                is_deleted UInt8 DEFAULT 0
+            ) ENGINE = ReplacingMergeTree(version)
+            ORDER BY (tenant_id)",
+
+            // ── Tenant query budgets (see query_limits) ───────────────────────────
+            "CREATE TABLE IF NOT EXISTS config_tenant_query_limits (
+                tenant_id              String,
+                max_concurrent_queries UInt32,
+                queries_per_minute     UInt32,
+                max_execution_secs     UInt32,
+                max_bytes_to_read      UInt64,
+                max_result_rows        UInt64,
+                enforce_result_rows    UInt8 DEFAULT 0,
+                version                UInt64,
+                is_deleted             UInt8 DEFAULT 0
+            ) ENGINE = ReplacingMergeTree(version)
+            ORDER BY (tenant_id)",
+            "ALTER TABLE config_tenant_query_limits ADD COLUMN IF NOT EXISTS enforce_result_rows UInt8 DEFAULT 0",
+
             // ── Global retention (singleton, id='global') ─────────────────────────
             // default_days applies to any signal whose per-signal value is 0 (inherit).
             // These are the MAXIMUM retention per signal — tenant overrides are clamped
@@ -737,7 +829,7 @@ impl ConfigDb {
             ) ENGINE = ReplacingMergeTree(version)
             ORDER BY (id)",
 
-            // ── Metric firewall (ingest-time block / drop-label rules) ────────────
+            // ── Metric firewall (ingest-time block / drop-label / relabel rules) ──
             "CREATE TABLE IF NOT EXISTS config_metric_firewall (
                 id                      String,
                 name                    String,
@@ -750,12 +842,41 @@ impl ConfigDb {
                 match_label_value_regex UInt8 DEFAULT 0,
                 drop_label_pattern      String DEFAULT '',
                 drop_label_regex        UInt8 DEFAULT 0,
+                source_labels           String DEFAULT '',
+                separator               String DEFAULT ';',
+                regex                   String DEFAULT '(.*)',
+                target_label            String DEFAULT '',
+                replacement             String DEFAULT '$1',
+                modulus                 UInt64 DEFAULT 0,
                 created_at              String DEFAULT toString(now()),
                 version                 UInt64,
                 is_deleted              UInt8 DEFAULT 0
             ) ENGINE = ReplacingMergeTree(version)
             ORDER BY (id)",
 
+            // Backfill relabel columns on firewall tables created before relabel support.
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS source_labels String DEFAULT ''",
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS separator String DEFAULT ';'",
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS regex String DEFAULT '(.*)'",
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS target_label String DEFAULT ''",
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS replacement String DEFAULT '$1'",
+            "ALTER TABLE config_metric_firewall ADD COLUMN IF NOT EXISTS modulus UInt64 DEFAULT 0",
+
+            // ── Cardinality limits (per tenant default + per-metric overrides) ────
+            "CREATE TABLE IF NOT EXISTS config_cardinality_limits (
+                tenant_id                String,
+                metric_name              String,
+                max_series               UInt64,
+                action                   String DEFAULT 'drop',
+                strip_labels             String DEFAULT '',
+                window_secs              UInt64 DEFAULT 3600,
+                notification_channel_ids String DEFAULT '[]',
+                updated_at               String DEFAULT toString(now()),
+                version                  UInt64,
+                is_deleted               UInt8 DEFAULT 0
+            ) ENGINE = ReplacingMergeTree(version)
+            ORDER BY (tenant_id, metric_name)",
+
             // ── Trace funnels ─────────────────────────────────────────────────────
             "CREATE TABLE IF NOT EXISTS config_trace_funnels (
                 id         String,
@@ -767,6 +888,71 @@ impl ConfigDb {
                 is_deleted UInt8 DEFAULT 0
             ) ENGINE = ReplacingMergeTree(version)
             ORDER BY (id)",
+
+            // ── Saved queries (named Explore queries) ─────────────────────────────
+            "CREATE TABLE IF NOT EXISTS config_saved_queries (
+                id            String,
+                tenant_id     String DEFAULT 'default',
+                owner_id      String DEFAULT '',
+                name          String,
+                description   String DEFAULT '',
+                visibility    String DEFAULT 'tenant',
+                signal        String DEFAULT 'spans',
+                filters       String DEFAULT '[]',
+                search        String DEFAULT '',
+                group_by      String DEFAULT '[]',
+                aggregation   String DEFAULT 'count',
+                columns       String DEFAULT '[]',
+                default_range String DEFAULT '1h',
+                created_at    String DEFAULT toString(now()),
+                updated_at    String DEFAULT toString(now()),
+                version       UInt64,
+                is_deleted    UInt8 DEFAULT 0
+            ) ENGINE = ReplacingMergeTree(version)
+            ORDER BY (tenant_id, id)",
+
+            // ── Scheduled exports (saved query → object storage, see export_scheduler) ──
+            "CREATE TABLE IF NOT EXISTS config_scheduled_exports (
+                id                    String,
+                tenant_id             String DEFAULT 'default',
+                owner_id              String DEFAULT '',
+                name                  String,
+                saved_query_id        String,
+                schedule              String,
+                format                String DEFAULT 'csv',
+                destination           String DEFAULT '',
+                notification_channels String DEFAULT '[]',
+                enabled               UInt8 DEFAULT 1,
+                last_run_at           String DEFAULT '',
+                last_status           String DEFAULT '',
+                last_error            String DEFAULT '',
+                exported_until        String DEFAULT '',
+                created_at            String DEFAULT toString(now()),
+                updated_at            String DEFAULT toString(now()),
+                version               UInt64,
+                is_deleted            UInt8 DEFAULT 0
+            ) ENGINE = ReplacingMergeTree(version)
+            ORDER BY (tenant_id, id)",
+
+            // ── Scheduled export run history (90 days) ────────────────────────────
+            "CREATE TABLE IF NOT EXISTS config_scheduled_export_runs (
+                id           String,
+                export_id    String,
+                tenant_id    String,
+                scheduled_at String,
+                started_at   String,
+                finished_at  String,
+                window_from  String,
+                window_to    String,
+                status       String,
+                rows         UInt64 DEFAULT 0,
+                bytes        UInt64 DEFAULT 0,
+                object_path  String DEFAULT '',
+                error        String DEFAULT '',
+                created_at   DateTime DEFAULT now()
+            ) ENGINE = MergeTree()
+            ORDER BY (tenant_id, export_id, started_at)
+            TTL created_at + INTERVAL 90 DAY",
         ];
 
         for ddl in ddls {
@@ -1028,6 +1214,102 @@ impl ConfigDb {
         Ok(rows.into_iter().map(|r| (r.tenant_id, r.signal, r.retain_days)).collect())
     }
 
+    // ── Tenant metric naming operations ───────────────────────────────────────
+
+    pub async fn get_tenant_metric_naming(&self, tenant_id: &str) -> anyhow::Result<Option<String>> {
+        #[derive(clickhouse::Row, serde::Deserialize)]
+        struct Row { mode: String }
+        let result = self.client
+            .query("SELECT mode FROM config_tenant_metric_naming FINAL WHERE tenant_id = ? AND is_deleted = 0 LIMIT 1")
+            .bind(tenant_id)
+            .fetch_one::<Row>()
+            .await;
+        match result {
+            Ok(r) => Ok(Some(r.mode)),
+            Err(clickhouse::error::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn set_tenant_metric_naming(&self, tenant_id: &str, mode: &str) -> anyhow::Result<()> {
+        let ver = Self::next_version();
+        self.client
+            .query("INSERT INTO config_tenant_metric_naming (tenant_id, mode, version, is_deleted) VALUES (?, ?, ?, 0)")
+            .bind(tenant_id)
+            .bind(mode)
+            .bind(ver)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// (tenant_id, mode) for every tenant with an explicit naming mode.
+    pub async fn list_tenant_metric_naming(&self) -> anyhow::Result<Vec<(String, String)>> {
+        #[derive(clickhouse::Row, serde::Deserialize)]
+        struct Row { tenant_id: String, mode: String }
+        let rows = self.client
+            .query("SELECT tenant_id, mode FROM config_tenant_metric_naming FINAL WHERE is_deleted = 0")
+            .fetch_all::<Row>()
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.tenant_id, r.mode)).collect())
+    }
+
+    // ── Tenant query limit operations ─────────────────────────────────────────
+
+    pub async fn get_tenant_query_limits(&self, tenant_id: &str) -> anyhow::Result<Option<crate::query_limits::QueryLimits>> {
+        let result = self.client
+            .query("SELECT tenant_id, max_concurrent_queries, queries_per_minute, max_execution_secs, max_bytes_to_read, max_result_rows, enforce_result_rows FROM config_tenant_query_limits FINAL WHERE tenant_id = ? AND is_deleted = 0 LIMIT 1")
+            .bind(tenant_id)
+            .fetch_one::<QueryLimitsRow>()
+            .await;
+        match result {
+            Ok(r) => Ok(Some(r.into())),
+            Err(clickhouse::error::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn set_tenant_query_limits(&self, tenant_id: &str, limits: &crate::query_limits::QueryLimits) -> anyhow::Result<()> {
+        let ver = Self::next_version();
+        self.client
+            .query("INSERT INTO config_tenant_query_limits (tenant_id, max_concurrent_queries, queries_per_minute, max_execution_secs, max_bytes_to_read, max_result_rows, enforce_result_rows, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, 0)")
+            .bind(tenant_id)
+            .bind(limits.max_concurrent_queries)
+            .bind(limits.queries_per_minute)
+            .bind(limits.max_execution_secs)
+            .bind(limits.max_bytes_to_read)
+            .bind(limits.max_result_rows)
+            .bind(limits.enforce_result_rows as u8)
+            .bind(ver)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Drop a tenant's override so it falls back to the defaults.
+    pub async fn delete_tenant_query_limits(&self, tenant_id: &str) -> anyhow::Result<bool> {
+        if self.get_tenant_query_limits(tenant_id).await?.is_none() {
+            return Ok(false);
+        }
+        let ver = Self::next_version();
+        self.client
+            .query("INSERT INTO config_tenant_query_limits (tenant_id, max_concurrent_queries, queries_per_minute, max_execution_secs, max_bytes_to_read, max_result_rows, version, is_deleted) VALUES (?, 0, 0, 0, 0, 0, ?, 1)")
+            .bind(tenant_id)
+            .bind(ver)
+            .execute()
+            .await?;
+        Ok(true)
+    }
+
+    /// (tenant_id, limits) for every tenant with an override.
+    pub async fn list_tenant_query_limits(&self) -> anyhow::Result<Vec<(String, crate::query_limits::QueryLimits)>> {
+        let rows = self.client
+            .query("SELECT tenant_id, max_concurrent_queries, queries_per_minute, max_execution_secs, max_bytes_to_read, max_result_rows, enforce_result_rows FROM config_tenant_query_limits FINAL WHERE is_deleted = 0")
+            .fetch_all::<QueryLimitsRow>()
+            .await?;
+        Ok(rows.into_iter().map(|r| (r.tenant_id.clone(), r.into())).collect())
+    }
+
     // ── Global retention operations ────────────────────────────────────────────
 
     /// Seed the singleton global-retention row if absent: 365d default, all
@@ -1071,7 +1353,7 @@ impl ConfigDb {
 
     pub async fn list_metric_firewall(&self) -> anyhow::Result<Vec<MetricFirewallRule>> {
         let rows = self.client
-            .query("SELECT id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, created_at FROM config_metric_firewall FINAL WHERE is_deleted = 0 ORDER BY created_at")
+            .query("SELECT id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, source_labels, separator, regex, target_label, replacement, modulus, created_at FROM config_metric_firewall FINAL WHERE is_deleted = 0 ORDER BY created_at")
             .fetch_all::<MetricFirewallRule>()
             .await?;
         Ok(rows)
@@ -1082,11 +1364,13 @@ impl ConfigDb {
     pub async fn upsert_metric_firewall(&self, r: &MetricFirewallRule) -> anyhow::Result<()> {
         let ver = Self::next_version();
         self.client
-            .query("INSERT INTO config_metric_firewall (id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, created_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 0)")
+            .query("INSERT INTO config_metric_firewall (id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, source_labels, separator, regex, target_label, replacement, modulus, created_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 0)")
             .bind(&r.id).bind(&r.name).bind(r.enabled).bind(&r.action)
             .bind(&r.metric_pattern).bind(r.metric_regex)
             .bind(&r.match_label_key).bind(&r.match_label_value).bind(r.match_label_value_regex)
             .bind(&r.drop_label_pattern).bind(r.drop_label_regex)
+            .bind(&r.source_labels).bind(&r.separator).bind(&r.regex)
+            .bind(&r.target_label).bind(&r.replacement).bind(r.modulus)
             .bind(&r.created_at).bind(ver)
             .execute()
             .await?;
@@ -1098,11 +1382,13 @@ impl ConfigDb {
         let Some(r) = existing.into_iter().find(|r| r.id == id) else { return Ok(false) };
         let ver = Self::next_version();
         self.client
-            .query("INSERT INTO config_metric_firewall (id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, created_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1)")
+            .query("INSERT INTO config_metric_firewall (id, name, enabled, action, metric_pattern, metric_regex, match_label_key, match_label_value, match_label_value_regex, drop_label_pattern, drop_label_regex, source_labels, separator, regex, target_label, replacement, modulus, created_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, 1)")
             .bind(&r.id).bind(&r.name).bind(r.enabled).bind(&r.action)
             .bind(&r.metric_pattern).bind(r.metric_regex)
             .bind(&r.match_label_key).bind(&r.match_label_value).bind(r.match_label_value_regex)
             .bind(&r.drop_label_pattern).bind(r.drop_label_regex)
+            .bind(&r.source_labels).bind(&r.separator).bind(&r.regex)
+            .bind(&r.target_label).bind(&r.replacement).bind(r.modulus)
             .bind(&r.created_at).bind(ver)
             .execute()
             .await?;
@@ -1122,10 +1408,76 @@ impl ConfigDb {
             match_label_value_regex: r.match_label_value_regex != 0,
             drop_label_pattern: r.drop_label_pattern.clone(),
             drop_label_regex: r.drop_label_regex != 0,
+            source_labels: r.source_labels.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect(),
+            separator: r.separator.clone(),
+            regex: r.regex.clone(),
+            target_label: r.target_label.clone(),
+            replacement: r.replacement.clone(),
+            modulus: r.modulus,
         }).collect();
         Ok(crate::metric_firewall::MetricFirewall::compile(&raw))
     }
 
+    // ── Cardinality limit operations ───────────────────────────────────────────
+
+    /// All limits, or only one tenant's when `tenant_id` is given.
+    pub async fn list_cardinality_limits(&self, tenant_id: Option<&str>) -> anyhow::Result<Vec<CardinalityLimitRow>> {
+        let base = "SELECT tenant_id, metric_name, max_series, action, strip_labels, window_secs, notification_channel_ids, updated_at FROM config_cardinality_limits FINAL WHERE is_deleted = 0";
+        let rows = match tenant_id {
+            Some(t) => self.client
+                .query(&format!("{base} AND tenant_id = ? ORDER BY metric_name"))
+                .bind(t)
+                .fetch_all::<CardinalityLimitRow>()
+                .await?,
+            None => self.client
+                .query(&format!("{base} ORDER BY tenant_id, metric_name"))
+                .fetch_all::<CardinalityLimitRow>()
+                .await?,
+        };
+        Ok(rows)
+    }
+
+    pub async fn upsert_cardinality_limit(&self, r: &CardinalityLimitRow) -> anyhow::Result<()> {
+        let ver = Self::next_version();
+        self.client
+            .query("INSERT INTO config_cardinality_limits (tenant_id, metric_name, max_series, action, strip_labels, window_secs, notification_channel_ids, updated_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 0)")
+            .bind(&r.tenant_id).bind(&r.metric_name).bind(r.max_series).bind(&r.action)
+            .bind(&r.strip_labels).bind(r.window_secs).bind(&r.notification_channel_ids)
+            .bind(&r.updated_at).bind(ver)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_cardinality_limit(&self, tenant_id: &str, metric_name: &str) -> anyhow::Result<bool> {
+        let existing = self.list_cardinality_limits(Some(tenant_id)).await?;
+        if !existing.iter().any(|r| r.metric_name == metric_name) {
+            return Ok(false);
+        }
+        let ver = Self::next_version();
+        self.client
+            .query("INSERT INTO config_cardinality_limits (tenant_id, metric_name, max_series, version, is_deleted) VALUES (?, ?, 0, ?, 1)")
+            .bind(tenant_id).bind(metric_name).bind(ver)
+            .execute()
+            .await?;
+        Ok(true)
+    }
+
+    /// Load + compile every tenant's limits for the ingest hot path.
+    pub async fn compiled_cardinality_limits(&self) -> anyhow::Result<crate::cardinality_limiter::LimitConfig> {
+        let rows = self.list_cardinality_limits(None).await?;
+        let raw: Vec<crate::cardinality_limiter::RawLimit> = rows.into_iter().map(|r| crate::cardinality_limiter::RawLimit {
+            tenant_id: r.tenant_id,
+            metric_name: r.metric_name,
+            max_series: r.max_series,
+            action: r.action,
+            strip_labels: r.strip_labels,
+            window_secs: r.window_secs,
+            notification_channel_ids: r.notification_channel_ids,
+        }).collect();
+        Ok(crate::cardinality_limiter::LimitConfig::compile(&raw))
+    }
+
     // ── User & session operations ──────────────────────────────────────────────
 
     pub async fn ensure_default_admin(&self) -> anyhow::Result<()> {
@@ -4102,4 +4454,241 @@ impl ConfigDb {
             .execute().await?;
         Ok(true)
     }
+
+    // ── Saved queries ──────────────────────────────────────────────────────────
+
+    const SAVED_QUERY_SELECT: &'static str = "SELECT id, tenant_id, owner_id, name, description, visibility, signal, filters, search, group_by, aggregation, columns, default_range, created_at, updated_at FROM config_saved_queries FINAL WHERE is_deleted = 0 AND tenant_id = ? AND (visibility = 'tenant' OR owner_id = ?)";
+
+    fn map_saved_query_row(r: SavedQueryRow) -> crate::models::saved_query::SavedQuery {
+        crate::models::saved_query::SavedQuery {
+            id: r.id, name: r.name, description: r.description,
+            tenant_id: r.tenant_id, owner_id: r.owner_id, visibility: r.visibility,
+            signal: r.signal,
+            filters: serde_json::from_str(&r.filters).unwrap_or(serde_json::json!([])),
+            search: r.search,
+            group_by: serde_json::from_str(&r.group_by).unwrap_or_default(),
+            aggregation: r.aggregation,
+            columns: serde_json::from_str(&r.columns).unwrap_or_default(),
+            default_range: r.default_range,
+            created_at: r.created_at, updated_at: r.updated_at,
+        }
+    }
+
+    /// Saved queries visible to `user_id`: the tenant's shared ones and their own private ones.
+    pub async fn list_saved_queries(&self, tenant_id: &str, user_id: &str) -> anyhow::Result<Vec<crate::models::saved_query::SavedQuery>> {
+        let rows = self.client
+            .query(&format!("{} ORDER BY updated_at DESC", Self::SAVED_QUERY_SELECT))
+            .bind(tenant_id).bind(user_id)
+            .fetch_all::<SavedQueryRow>()
+            .await?;
+        Ok(rows.into_iter().map(Self::map_saved_query_row).collect())
+    }
+
+    pub async fn get_saved_query(&self, id: &str, tenant_id: &str, user_id: &str) -> anyhow::Result<Option<crate::models::saved_query::SavedQuery>> {
+        let result = self.client
+            .query(&format!("{} AND id = ? LIMIT 1", Self::SAVED_QUERY_SELECT))
+            .bind(tenant_id).bind(user_id).bind(id)
+            .fetch_one::<SavedQueryRow>()
+            .await;
+        match result {
+            Ok(r) => Ok(Some(Self::map_saved_query_row(r))),
+            Err(clickhouse::error::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_saved_query(&self, q: &crate::models::saved_query::SavedQuery, is_deleted: u8) -> anyhow::Result<()> {
+        self.client
+            .query("INSERT INTO config_saved_queries (id, tenant_id, owner_id, name, description, visibility, signal, filters, search, group_by, aggregation, columns, default_range, created_at, updated_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&q.id).bind(&q.tenant_id).bind(&q.owner_id).bind(&q.name)
+            .bind(&q.description).bind(&q.visibility).bind(&q.signal)
+            .bind(serde_json::to_string(&q.filters)?).bind(&q.search)
+            .bind(serde_json::to_string(&q.group_by)?).bind(&q.aggregation)
+            .bind(serde_json::to_string(&q.columns)?).bind(&q.default_range)
+            .bind(&q.created_at).bind(&q.updated_at).bind(Self::next_version()).bind(is_deleted)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Insert a new saved query; `created_at` / `updated_at` are set here.
+    pub async fn create_saved_query(&self, q: &crate::models::saved_query::SavedQuery) -> anyhow::Result<()> {
+        let now = Self::now_str();
+        let q = crate::models::saved_query::SavedQuery { created_at: now.clone(), updated_at: now, ..q.clone() };
+        self.write_saved_query(&q, 0).await
+    }
+
+    /// Replace the editable fields of a saved query. Same rule as dashboards:
+    /// the owner, or a tenant admin/editor for shared queries. Returns false
+    /// when the query is not visible or not editable by the caller.
+    pub async fn update_saved_query(&self, update: &crate::models::saved_query::SavedQuery, user_id: &str, user_role: &str) -> anyhow::Result<bool> {
+        let existing = match self.get_saved_query(&update.id, &update.tenant_id, user_id).await? {
+            Some(q) => q,
+            None => return Ok(false),
+        };
+        let can_edit = existing.owner_id == user_id
+            || (existing.visibility == "tenant" && (user_role == "admin" || user_role == "editor"))
+            || existing.owner_id.is_empty();
+        if !can_edit { return Ok(false); }
+        let q = crate::models::saved_query::SavedQuery {
+            owner_id: existing.owner_id,
+            created_at: existing.created_at,
+            updated_at: Self::now_str(),
+            ..update.clone()
+        };
+        self.write_saved_query(&q, 0).await?;
+        Ok(true)
+    }
+
+    pub async fn delete_saved_query(&self, id: &str, tenant_id: &str, user_id: &str, user_role: &str) -> anyhow::Result<bool> {
+        let existing = match self.get_saved_query(id, tenant_id, user_id).await? {
+            Some(q) => q,
+            None => return Ok(false),
+        };
+        let can_delete = existing.owner_id == user_id || user_role == "admin" || existing.owner_id.is_empty();
+        if !can_delete { return Ok(false); }
+        let q = crate::models::saved_query::SavedQuery { updated_at: Self::now_str(), ..existing };
+        self.write_saved_query(&q, 1).await?;
+        Ok(true)
+    }
+
+    // ── Scheduled exports ──────────────────────────────────────────────────────
+
+    const SCHEDULED_EXPORT_SELECT: &'static str = "SELECT id, tenant_id, owner_id, name, saved_query_id, schedule, format, destination, notification_channels, enabled, last_run_at, last_status, last_error, exported_until, created_at, updated_at FROM config_scheduled_exports FINAL WHERE is_deleted = 0";
+
+    fn map_scheduled_export_row(r: ScheduledExportRow) -> crate::models::scheduled_export::ScheduledExport {
+        crate::models::scheduled_export::ScheduledExport {
+            id: r.id, tenant_id: r.tenant_id, owner_id: r.owner_id, name: r.name,
+            saved_query_id: r.saved_query_id, schedule: r.schedule, format: r.format,
+            destination: r.destination,
+            notification_channels: serde_json::from_str(&r.notification_channels).unwrap_or_default(),
+            enabled: r.enabled != 0,
+            last_run_at: r.last_run_at, last_status: r.last_status, last_error: r.last_error,
+            exported_until: r.exported_until,
+            created_at: r.created_at, updated_at: r.updated_at,
+        }
+    }
+
+    pub async fn list_scheduled_exports(&self, tenant_id: &str) -> anyhow::Result<Vec<crate::models::scheduled_export::ScheduledExport>> {
+        let rows = self.client
+            .query(&format!("{} AND tenant_id = ? ORDER BY name", Self::SCHEDULED_EXPORT_SELECT))
+            .bind(tenant_id)
+            .fetch_all::<ScheduledExportRow>()
+            .await?;
+        Ok(rows.into_iter().map(Self::map_scheduled_export_row).collect())
+    }
+
+    /// Enabled scheduled exports across all tenants (for the scheduler).
+    pub async fn list_enabled_scheduled_exports(&self) -> anyhow::Result<Vec<crate::models::scheduled_export::ScheduledExport>> {
+        let rows = self.client
+            .query(&format!("{} AND enabled = 1", Self::SCHEDULED_EXPORT_SELECT))
+            .fetch_all::<ScheduledExportRow>()
+            .await?;
+        Ok(rows.into_iter().map(Self::map_scheduled_export_row).collect())
+    }
+
+    pub async fn get_scheduled_export(&self, id: &str, tenant_id: &str) -> anyhow::Result<Option<crate::models::scheduled_export::ScheduledExport>> {
+        let result = self.client
+            .query(&format!("{} AND tenant_id = ? AND id = ? LIMIT 1", Self::SCHEDULED_EXPORT_SELECT))
+            .bind(tenant_id).bind(id)
+            .fetch_one::<ScheduledExportRow>()
+            .await;
+        match result {
+            Ok(r) => Ok(Some(Self::map_scheduled_export_row(r))),
+            Err(clickhouse::error::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write_scheduled_export(&self, e: &crate::models::scheduled_export::ScheduledExport, is_deleted: u8) -> anyhow::Result<()> {
+        self.client
+            .query("INSERT INTO config_scheduled_exports (id, tenant_id, owner_id, name, saved_query_id, schedule, format, destination, notification_channels, enabled, last_run_at, last_status, last_error, exported_until, created_at, updated_at, version, is_deleted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&e.id).bind(&e.tenant_id).bind(&e.owner_id).bind(&e.name)
+            .bind(&e.saved_query_id).bind(&e.schedule).bind(&e.format).bind(&e.destination)
+            .bind(serde_json::to_string(&e.notification_channels)?).bind(e.enabled as u8)
+            .bind(&e.last_run_at).bind(&e.last_status).bind(&e.last_error).bind(&e.exported_until)
+            .bind(&e.created_at).bind(&e.updated_at).bind(Self::next_version()).bind(is_deleted)
+            .execute()
+            .await?;
+        Ok(())
+    }
+
+    /// Insert a new scheduled export; `created_at` / `updated_at` are set here.
+    pub async fn create_scheduled_export(&self, e: &crate::models::scheduled_export::ScheduledExport) -> anyhow::Result<()> {
+        let now = Self::now_str();
+        let e = crate::models::scheduled_export::ScheduledExport { created_at: now.clone(), updated_at: now, ..e.clone() };
+        self.write_scheduled_export(&e, 0).await
+    }
+
+    /// Replace the editable fields, keeping run state. The owner is replaced
+    /// too: the saved query was checked against the editor, and runs resolve
+    /// it as the owner. Returns false if not found.
+    pub async fn update_scheduled_export(&self, update: &crate::models::scheduled_export::ScheduledExport) -> anyhow::Result<bool> {
+        let Some(existing) = self.get_scheduled_export(&update.id, &update.tenant_id).await? else {
+            return Ok(false);
+        };
+        let e = crate::models::scheduled_export::ScheduledExport {
+            last_run_at: existing.last_run_at,
+            last_status: existing.last_status,
+            last_error: existing.last_error,
+            exported_until: existing.exported_until,
+            created_at: existing.created_at,
+            updated_at: Self::now_str(),
+            ..update.clone()
+        };
+        self.write_scheduled_export(&e, 0).await?;
+        Ok(true)
+    }
+
+    pub async fn delete_scheduled_export(&self, id: &str, tenant_id: &str) -> anyhow::Result<bool> {
+        let Some(existing) = self.get_scheduled_export(id, tenant_id).await? else {
+            return Ok(false);
+        };
+        let e = crate::models::scheduled_export::ScheduledExport { updated_at: Self::now_str(), ..existing };
+        self.write_scheduled_export(&e, 1).await?;
+        Ok(true)
+    }
+
+    /// Record a finished run: append it to the history and update the
+    /// export's last-run fields. A successful run also advances
+    /// `exported_until` to the end of its window.
+    pub async fn record_export_run(&self, run: &crate::models::scheduled_export::ExportRun) -> anyhow::Result<()> {
+        self.client
+            .query("INSERT INTO config_scheduled_export_runs (id, export_id, tenant_id, scheduled_at, started_at, finished_at, window_from, window_to, status, rows, bytes, object_path, error) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&run.id).bind(&run.export_id).bind(&run.tenant_id)
+            .bind(&run.scheduled_at).bind(&run.started_at).bind(&run.finished_at)
+            .bind(&run.window_from).bind(&run.window_to).bind(&run.status)
+            .bind(run.rows).bind(run.bytes).bind(&run.object_path).bind(&run.error)
+            .execute()
+            .await?;
+
+        // Re-read so a concurrent edit of the definition is not reverted.
+        if let Some(existing) = self.get_scheduled_export(&run.export_id, &run.tenant_id).await? {
+            let success = run.status == "success";
+            let e = crate::models::scheduled_export::ScheduledExport {
+                last_run_at: run.scheduled_at.clone(),
+                last_status: run.status.clone(),
+                last_error: run.error.clone(),
+                exported_until: if success { run.window_to.clone() } else { existing.exported_until.clone() },
+                ..existing
+            };
+            self.write_scheduled_export(&e, 0).await?;
+        }
+        Ok(())
+    }
+
+    /// Most recent runs of an export, newest first.
+    pub async fn list_export_runs(&self, export_id: &str, tenant_id: &str, limit: u64) -> anyhow::Result<Vec<crate::models::scheduled_export::ExportRun>> {
+        let rows = self.client
+            .query("SELECT id, export_id, tenant_id, scheduled_at, started_at, finished_at, window_from, window_to, status, rows, bytes, object_path, error FROM config_scheduled_export_runs WHERE tenant_id = ? AND export_id = ? ORDER BY started_at DESC LIMIT ?")
+            .bind(tenant_id).bind(export_id).bind(limit)
+            .fetch_all::<ExportRunRow>()
+            .await?;
+        Ok(rows.into_iter().map(|r| crate::models::scheduled_export::ExportRun {
+            id: r.id, export_id: r.export_id, tenant_id: r.tenant_id,
+            scheduled_at: r.scheduled_at, started_at: r.started_at, finished_at: r.finished_at,
+            window_from: r.window_from, window_to: r.window_to, status: r.status,
+            rows: r.rows, bytes: r.bytes, object_path: r.object_path, error: r.error,
+        }).collect())
+    }
 }
@@ -189,3 +189,9 @@ impl RushConfig {
         self.retention.defaults.logs_days
     }
 }
+
+/// A setting from the environment, or `default` when unset or unparsable
+/// (surrounding whitespace is ignored).
+pub fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key).ok().and_then(|s| s.trim().parse().ok()).unwrap_or(default)
+}
@@ -0,0 +1,603 @@
+//! Scheduled exports: run a saved query on a cron schedule and write its rows
+//! to object storage (S3 / MinIO) as CSV, NDJSON, Arrow IPC or Parquet.
+//!
+//! Each run covers the half-open window `[exported_until, tick - settle)`
+//! since the last successful one, so a failed or missed tick is picked up by
+//! the next run instead of leaving a gap, and a row on a window boundary is
+//! exported exactly once; the first run covers the saved query's
+//! `default_range`. The window ends `settle` seconds before the tick so rows
+//! that arrive a little late (ingest batching, spool replay) still land in a
+//! window that hasn't been exported yet.
+//!
+//! Rows stream from the ClickHouse cursor through the export encoders into a
+//! multipart upload, so an export is never held in memory. Objects are
+//! written to `{prefix}{tenant}/{destination}/{YYYY}/{MM}/{DD}/{name}-{tick}.{ext}`.
+//! Every run is appended to `config_scheduled_export_runs`, and failed runs
+//! notify the export's channels.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures_util::StreamExt;
+use clickhouse::Client;
+use object_store::{ObjectStore, WriteMultipart, path::Path};
+
+use crate::alert_engine;
+use crate::clickhouse_config::ConfigDb;
+use crate::config::env_or;
+use crate::handlers::export::{ExportFormat, ExportRow, ROW_BATCH, RowEncoder};
+use crate::models::query::{Filter, TimeRange};
+use crate::models::scheduled_export::{ExportRun, ScheduledExport};
+
+const DEFAULT_MAX_ROWS: u64 = 10_000_000;
+const DEFAULT_MAX_EXECUTION_SECS: u64 = 3_600;
+const DEFAULT_SETTLE_SECS: u64 = 300;
+const DEFAULT_CONCURRENCY: usize = 4;
+/// Multipart parts uploaded in parallel.
+const UPLOAD_CONCURRENCY: usize = 4;
+/// Bound on the occurrences walked to find the latest due tick (a
+/// minutely schedule after a week of downtime is ~10k).
+const MAX_TICKS_SCANNED: usize = 20_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportSchedulerConfig {
+    /// Rows one run may export; larger results fail the run.
+    pub max_rows: u64,
+    /// `max_execution_time` for the export query.
+    pub max_execution_secs: u64,
+    /// How far behind its tick a run's window ends.
+    pub settle_secs: u64,
+    /// Due exports run at the same time, up to this many.
+    pub concurrency: usize,
+}
+
+impl Default for ExportSchedulerConfig {
+    fn default() -> Self {
+        ExportSchedulerConfig {
+            max_rows: DEFAULT_MAX_ROWS,
+            max_execution_secs: DEFAULT_MAX_EXECUTION_SECS,
+            settle_secs: DEFAULT_SETTLE_SECS,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl ExportSchedulerConfig {
+    /// `RUSH_EXPORT_MAX_ROWS` (default 10000000),
+    /// `RUSH_EXPORT_MAX_EXECUTION_SECS` (3600), `RUSH_EXPORT_SETTLE_SECS` (300)
+    /// and `RUSH_EXPORT_CONCURRENCY` (4).
+    pub fn from_env() -> Self {
+        let d = ExportSchedulerConfig::default();
+        ExportSchedulerConfig {
+            max_rows: env_or("RUSH_EXPORT_MAX_ROWS", d.max_rows).max(1),
+            max_execution_secs: env_or("RUSH_EXPORT_MAX_EXECUTION_SECS", d.max_execution_secs).max(1),
+            settle_secs: env_or("RUSH_EXPORT_SETTLE_SECS", d.settle_secs),
+            concurrency: env_or("RUSH_EXPORT_CONCURRENCY", d.concurrency as u64).max(1) as usize,
+        }
+    }
+}
+
+/// Bucket and key prefix exports are written to.
+#[derive(Clone)]
+pub struct ExportTarget {
+    pub store: Arc<dyn ObjectStore>,
+    pub prefix: String,
+}
+
+// ── Schedules ──
+
+/// Parse a five-field cron expression (or an alias such as `@daily`).
+pub fn parse_schedule(schedule: &str) -> Result<croner::Cron, String> {
+    croner::Cron::new(schedule.trim()).parse().map_err(|e| format!("invalid schedule: {e}"))
+}
+
+/// The latest tick of `cron` in `(after, now]`, if any. Ticks missed while
+/// the scheduler was down collapse into one run (its window covers them).
+pub fn due_tick(cron: &croner::Cron, after: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut due = None;
+    let mut t = after;
+    for _ in 0..MAX_TICKS_SCANNED {
+        match cron.find_next_occurrence(&t, false) {
+            Ok(next) if next <= now => {
+                due = Some(next);
+                t = next;
+            }
+            _ => return due,
+        }
+    }
+    // Pathologically dense schedule: run now rather than walk further.
+    due.map(|_| now)
+}
+
+/// The half-open window `[from, to)` a run for `tick` exports: it ends
+/// `settle_secs` before the tick and starts where the last successful run
+/// ended.
+pub fn run_window(
+    exported_until: Option<DateTime<Utc>>,
+    tick: DateTime<Utc>,
+    default_range_secs: f64,
+    settle_secs: u64,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let to = tick - chrono::Duration::seconds(settle_secs as i64);
+    let from = exported_until
+        .filter(|t| *t < to)
+        .unwrap_or_else(|| to - chrono::Duration::milliseconds((default_range_secs * 1000.0) as i64));
+    (from, to)
+}
+
+/// Normalize a destination prefix: `/`-separated segments of
+/// `[A-Za-z0-9._-]`, no `.` / `..` segments, no leading or trailing `/`.
+pub fn normalize_destination(destination: &str) -> Result<String, String> {
+    let segments: Vec<&str> = destination.split('/').filter(|s| !s.is_empty()).collect();
+    for s in &segments {
+        if *s == "." || *s == ".." || !s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+            return Err(format!("invalid destination segment: {s:?}"));
+        }
+    }
+    Ok(segments.join("/"))
+}
+
+/// Object key for one run.
+pub fn object_key(prefix: &str, tenant_id: &str, destination: &str, name: &str, tick: DateTime<Utc>, format: ExportFormat) -> String {
+    let slug: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let dir = if destination.is_empty() { tenant_id.to_string() } else { format!("{tenant_id}/{destination}") };
+    format!(
+        "{prefix}{dir}/{}/{slug}-{}.{}",
+        tick.format("%Y/%m/%d"),
+        tick.format("%Y%m%dT%H%M%SZ"),
+        format.file_type().0,
+    )
+}
+
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+        .or_else(|| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok().map(|t| t.and_utc()))
+}
+
+fn format_time(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+// ── Engine ──
+
+/// Spawn the export scheduler. Checks for due exports every 60 seconds and
+/// runs them, up to `config.concurrency` at a time; the next check waits for
+/// the whole round. Without a `target` every run fails (and says so in its
+/// history) rather than silently not running.
+pub fn spawn(
+    ch: Client,
+    config_db: Arc<ConfigDb>,
+    smtp_config: alert_engine::SmtpConfig,
+    target: Option<ExportTarget>,
+    config: ExportSchedulerConfig,
+) {
+    tokio::spawn(async move {
+        let http_client = reqwest::Client::new();
+        let smtp_transport = alert_engine::build_smtp_transport(&smtp_config);
+        loop {
+            let exports = match config_db.list_enabled_scheduled_exports().await {
+                Ok(e) => e,
+                Err(e) => {
+                    tracing::error!(engine = "exports", error = %e, "failed to list scheduled exports");
+                    Vec::new()
+                }
+            };
+            let now = Utc::now();
+            let due: Vec<(ScheduledExport, DateTime<Utc>)> = exports
+                .into_iter()
+                .filter_map(|export| {
+                    let cron = parse_schedule(&export.schedule).ok()?;
+                    let after = parse_time(&export.last_run_at).or_else(|| parse_time(&export.created_at)).unwrap_or(now);
+                    let tick = due_tick(&cron, after, now)?;
+                    Some((export, tick))
+                })
+                .collect();
+            futures_util::stream::iter(due)
+                .for_each_concurrent(config.concurrency, |(export, tick)| {
+                    let (ch, config_db, target) = (&ch, &config_db, target.as_ref());
+                    let (http_client, smtp_config, smtp_transport) = (&http_client, &smtp_config, &smtp_transport);
+                    async move {
+                        let run = run_export(ch, config_db, target, &config, &export, tick).await;
+                        if let Err(e) = config_db.record_export_run(&run).await {
+                            tracing::error!(engine = "exports", export_id = %export.id, error = %e, "failed to record export run");
+                        }
+                        if run.status == "failed" {
+                            notify_failure(config_db, &export, &run, http_client, smtp_config, smtp_transport).await;
+                        }
+                    }
+                })
+                .await;
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        }
+    });
+}
+
+/// Run one export for `tick` and describe the outcome.
+async fn run_export(
+    ch: &Client,
+    config_db: &ConfigDb,
+    target: Option<&ExportTarget>,
+    config: &ExportSchedulerConfig,
+    export: &ScheduledExport,
+    tick: DateTime<Utc>,
+) -> ExportRun {
+    let started = Instant::now();
+    let mut run = ExportRun {
+        id: uuid::Uuid::new_v4().to_string(),
+        export_id: export.id.clone(),
+        tenant_id: export.tenant_id.clone(),
+        scheduled_at: format_time(tick),
+        started_at: format_time(Utc::now()),
+        finished_at: String::new(),
+        window_from: String::new(),
+        window_to: String::new(),
+        status: "failed".to_string(),
+        rows: 0,
+        bytes: 0,
+        object_path: String::new(),
+        error: String::new(),
+    };
+    match execute(ch, config_db, target, config, export, tick, &mut run).await {
+        Ok((rows, bytes)) => {
+            run.status = "success".to_string();
+            run.rows = rows;
+            run.bytes = bytes;
+            tracing::info!(
+                engine = "exports",
+                tenant_id = %export.tenant_id,
+                export_id = %export.id,
+                rows,
+                bytes,
+                path = %run.object_path,
+                duration_ms = started.elapsed().as_millis() as u64,
+                "scheduled export completed"
+            );
+        }
+        Err(e) => {
+            run.error = e.to_string();
+            tracing::warn!(engine = "exports", tenant_id = %export.tenant_id, export_id = %export.id, error = %e, "scheduled export failed");
+        }
+    }
+    run.finished_at = format_time(Utc::now());
+    run
+}
+
+/// Resolve the saved query and window, then stream the rows to the target.
+/// Fills in `run`'s window and object path as they become known.
+async fn execute(
+    ch: &Client,
+    config_db: &ConfigDb,
+    target: Option<&ExportTarget>,
+    config: &ExportSchedulerConfig,
+    export: &ScheduledExport,
+    tick: DateTime<Utc>,
+    run: &mut ExportRun,
+) -> anyhow::Result<(u64, u64)> {
+    let saved = config_db
+        .get_saved_query(&export.saved_query_id, &export.tenant_id, &export.owner_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("saved query {} not found", export.saved_query_id))?;
+    let format = ExportFormat::parse(&export.format)
+        .filter(|f| *f != ExportFormat::Json)
+        .ok_or_else(|| anyhow::anyhow!("unsupported export format {:?}", export.format))?;
+    let range_secs = crate::promql::types::parse_duration(&saved.default_range).unwrap_or(3600.0);
+    let (from, to) = run_window(parse_time(&export.exported_until), tick, range_secs, config.settle_secs);
+    run.window_from = format_time(from);
+    run.window_to = format_time(to);
+
+    let target = target.ok_or_else(|| anyhow::anyhow!("no export bucket configured (RUSH_EXPORT_S3_BUCKET)"))?;
+    run.object_path = object_key(&target.prefix, &export.tenant_id, &export.destination, &export.name, tick, format);
+
+    let filters: Vec<Filter> = serde_json::from_value(saved.filters)?;
+    let search = (!saved.search.is_empty()).then_some(saved.search.as_str());
+    let time_range = TimeRange { from: run.window_from.clone(), to: run.window_to.clone() };
+    // One extra row tells "exactly max_rows" apart from "truncated".
+    let limit = config.max_rows + 1;
+    let path = Path::from(run.object_path.as_str());
+    if saved.signal == "logs" {
+        let sql = crate::handlers::logs::export_sql(&export.tenant_id, &filters, &time_range, true, search, limit);
+        upload::<crate::models::log::LogRecord>(ch, &sql, &export.tenant_id, format, target, &path, config).await
+    } else {
+        let sql = crate::handlers::query::export_sql(&export.tenant_id, &filters, &time_range, true, search, limit);
+        upload::<crate::models::trace::WideEvent>(ch, &sql, &export.tenant_id, format, target, &path, config).await
+    }
+}
+
+/// Stream the query's rows into a multipart upload at `path`. Returns
+/// (rows, bytes). The upload is aborted on any error, so a failed run leaves
+/// no partial object behind.
+async fn upload<T>(
+    ch: &Client,
+    sql: &str,
+    tenant_id: &str,
+    format: ExportFormat,
+    target: &ExportTarget,
+    path: &Path,
+    config: &ExportSchedulerConfig,
+) -> anyhow::Result<(u64, u64)>
+where
+    T: clickhouse::Row + ExportRow + for<'b> serde::Deserialize<'b>,
+{
+    // Background export: the interactive time limit and the silent row cap
+    // (`result_overflow_mode = break`) would cut the file short; the LIMIT
+    // in the SQL bounds it instead.
+    let mut cursor = crate::tenant_query(ch, sql, tenant_id)
+        .with_option("max_execution_time", config.max_execution_secs.to_string())
+        .with_option("max_result_rows", "0")
+        .fetch::<T>()?;
+    write_object(&mut cursor, format, target, path, config.max_rows).await
+}
+
+/// Where `copy_rows` pulls rows from: the ClickHouse cursor in production.
+trait RowSource<T> {
+    async fn next_row(&mut self) -> anyhow::Result<Option<T>>;
+}
+
+impl<T> RowSource<T> for clickhouse::query::RowCursor<T>
+where
+    T: clickhouse::Row + for<'b> serde::Deserialize<'b>,
+{
+    async fn next_row(&mut self) -> anyhow::Result<Option<T>> {
+        Ok(self.next().await?)
+    }
+}
+
+/// Encode `source` into a multipart upload at `path`. Returns (rows, bytes).
+async fn write_object<T, S>(
+    source: &mut S,
+    format: ExportFormat,
+    target: &ExportTarget,
+    path: &Path,
+    max_rows: u64,
+) -> anyhow::Result<(u64, u64)>
+where
+    T: ExportRow,
+    S: RowSource<T>,
+{
+    let mut encoder = RowEncoder::new::<T>(format)?;
+    let mut writer = WriteMultipart::new(target.store.put_multipart(path).await?);
+
+    let result = match copy_rows(source, &mut encoder, &mut writer, max_rows).await {
+        Ok((rows, bytes)) => encoder.finish().map(|rest| {
+            writer.write(&rest);
+            (rows, bytes + rest.len() as u64)
+        }),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(v) => {
+            writer.finish().await?;
+            Ok(v)
+        }
+        Err(e) => {
+            let _ = writer.abort().await;
+            Err(e)
+        }
+    }
+}
+
+/// Encode every row of `source` into `writer`. Returns (rows, bytes).
+async fn copy_rows<T, S>(
+    source: &mut S,
+    encoder: &mut RowEncoder,
+    writer: &mut WriteMultipart,
+    max_rows: u64,
+) -> anyhow::Result<(u64, u64)>
+where
+    T: ExportRow,
+    S: RowSource<T>,
+{
+    let (mut rows, mut bytes) = (0u64, 0u64);
+    let mut batch = Vec::with_capacity(ROW_BATCH);
+    loop {
+        batch.clear();
+        let mut eof = false;
+        while batch.len() < ROW_BATCH {
+            match source.next_row().await? {
+                Some(row) => batch.push(row),
+                None => {
+                    eof = true;
+                    break;
+                }
+            }
+        }
+        rows += batch.len() as u64;
+        if rows > max_rows {
+            anyhow::bail!("export exceeds {max_rows} rows (RUSH_EXPORT_MAX_ROWS); narrow the saved query or schedule it more often");
+        }
+        let out = encoder.encode(&batch)?;
+        bytes += out.len() as u64;
+        writer.wait_for_capacity(UPLOAD_CONCURRENCY).await?;
+        writer.write(&out);
+        if eof {
+            return Ok((rows, bytes));
+        }
+    }
+}
+
+/// Tell the export's channels that a run failed.
+async fn notify_failure(
+    config_db: &ConfigDb,
+    export: &ScheduledExport,
+    run: &ExportRun,
+    http_client: &reqwest::Client,
+    smtp_config: &alert_engine::SmtpConfig,
+    smtp_transport: &Option<lettre::AsyncSmtpTransport<lettre::Tokio1Executor>>,
+) {
+    let alert_name = format!("Scheduled export: {}", export.name);
+    let message = format!(
+        "Scheduled export `{}` (tenant {}) failed for {} .. {}: {}. The next run will include this window.",
+        export.name, export.tenant_id, run.window_from, run.window_to, run.error
+    );
+    for channel_id in &export.notification_channels {
+        let Ok(Some(channel)) = config_db.get_channel_by_id(channel_id).await else { continue };
+        if !channel.enabled || channel.tenant_id != export.tenant_id {
+            continue;
+        }
+        let result = alert_engine::send_channel_notification(
+            &channel,
+            &message,
+            &alert_name,
+            "failed",
+            0.0,
+            0.0,
+            "exports",
+            "",
+            &message,
+            &export.id,
+            "",
+            http_client,
+            smtp_config,
+            smtp_transport,
+        )
+        .await;
+        let (status, error_msg) = match &result {
+            Ok(()) => ("sent", String::new()),
+            Err(e) => {
+                tracing::warn!(engine = "exports", export_id = %export.id, channel_id = %channel_id, error = %e, "export failure notification failed");
+                ("failed", e.clone())
+            }
+        };
+        let _ = config_db
+            .create_notification_log(channel_id, &export.tenant_id, "scheduled_export", &export.name, "error", status, &error_msg)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::log::LogRecord;
+
+    fn t(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn due_tick_collapses_missed_ticks() {
+        let daily = parse_schedule("0 3 * * *").unwrap();
+        assert_eq!(due_tick(&daily, t("2026-03-01T03:00:00Z"), t("2026-03-02T02:59:00Z")), None);
+        assert_eq!(
+            due_tick(&daily, t("2026-03-01T03:00:00Z"), t("2026-03-02T03:00:30Z")),
+            Some(t("2026-03-02T03:00:00Z"))
+        );
+        // Down for three days: one run, for the latest tick.
+        assert_eq!(
+            due_tick(&daily, t("2026-03-01T03:00:00Z"), t("2026-03-04T10:00:00Z")),
+            Some(t("2026-03-04T03:00:00Z"))
+        );
+        assert!(parse_schedule("0 0 3 * * *").is_err());
+    }
+
+    #[test]
+    fn window_resumes_from_last_success() {
+        let tick = t("2026-03-04T03:00:00Z");
+        assert_eq!(run_window(None, tick, 86400.0, 0), (t("2026-03-03T03:00:00Z"), tick));
+        assert_eq!(run_window(Some(t("2026-03-01T03:00:00Z")), tick, 86400.0, 0), (t("2026-03-01T03:00:00Z"), tick));
+    }
+
+    #[test]
+    fn window_trails_tick_by_settle_and_chains_without_overlap() {
+        let first = run_window(None, t("2026-03-04T03:00:00Z"), 3600.0, 300);
+        assert_eq!(first, (t("2026-03-04T01:55:00Z"), t("2026-03-04T02:55:00Z")));
+        // The next run starts exactly where this one ended; `[from, to)` keeps
+        // the shared second in one window only.
+        let next = run_window(Some(first.1), t("2026-03-04T04:00:00Z"), 3600.0, 300);
+        assert_eq!(next, (t("2026-03-04T02:55:00Z"), t("2026-03-04T03:55:00Z")));
+    }
+
+    #[test]
+    fn scheduled_export_sql_excludes_window_end() {
+        let range = TimeRange { from: "2026-03-04 02:00:00".into(), to: "2026-03-04 03:00:00".into() };
+        let logs = crate::handlers::logs::export_sql("acme", &[], &range, true, None, 10);
+        assert!(logs.contains("Timestamp < parseDateTimeBestEffort('2026-03-04 03:00:00')"), "{logs}");
+        let spans = crate::handlers::query::export_sql("acme", &[], &range, true, None, 10);
+        assert!(spans.contains("timestamp < parseDateTimeBestEffort('2026-03-04 03:00:00')"), "{spans}");
+        // The download endpoint keeps its inclusive end.
+        let download = crate::handlers::logs::export_sql("acme", &[], &range, false, None, 10);
+        assert!(!download.contains("Timestamp < "), "{download}");
+    }
+
+    /// Rows from a list, for exercising the upload path without ClickHouse.
+    struct Rows(std::vec::IntoIter<LogRecord>);
+
+    impl RowSource<LogRecord> for Rows {
+        async fn next_row(&mut self) -> anyhow::Result<Option<LogRecord>> {
+            Ok(self.0.next())
+        }
+    }
+
+    fn log(i: usize) -> LogRecord {
+        LogRecord {
+            timestamp: 1_770_000_000_000_000_000 + i as i64,
+            trace_id: String::new(),
+            span_id: String::new(),
+            severity_text: "INFO".to_string(),
+            severity_number: 9,
+            service_name: "api".to_string(),
+            body: format!("line {i}"),
+            resource_attributes: vec![],
+            scope_name: String::new(),
+            log_attributes: vec![],
+        }
+    }
+
+    fn memory_target() -> ExportTarget {
+        ExportTarget { store: Arc::new(object_store::memory::InMemory::new()), prefix: String::new() }
+    }
+
+    #[tokio::test]
+    async fn write_object_streams_every_row_to_the_store() {
+        let target = memory_target();
+        let path = Path::from("acme/run.ndjson");
+        // More than one encoder batch.
+        let n = ROW_BATCH + 10;
+        let (rows, bytes) =
+            write_object(&mut Rows((0..n).map(log).collect::<Vec<_>>().into_iter()), ExportFormat::Ndjson, &target, &path, 100_000)
+                .await
+                .unwrap();
+        assert_eq!(rows, n as u64);
+        let body = target.store.get(&path).await.unwrap().bytes().await.unwrap();
+        assert_eq!(body.len() as u64, bytes);
+        let lines: Vec<&str> = std::str::from_utf8(&body).unwrap().lines().collect();
+        assert_eq!(lines.len(), n);
+        assert!(lines[n - 1].contains(&format!("line {}", n - 1)));
+    }
+
+    #[tokio::test]
+    async fn write_object_over_row_cap_leaves_no_object() {
+        let target = memory_target();
+        let path = Path::from("acme/run.csv");
+        let err = write_object(&mut Rows((0..5).map(log).collect::<Vec<_>>().into_iter()), ExportFormat::Csv, &target, &path, 4)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exceeds 4 rows"), "{err}");
+        assert!(target.store.head(&path).await.is_err());
+
+        // Exactly at the cap is fine, and an empty result still writes a file.
+        let (rows, _) = write_object(&mut Rows((0..4).map(log).collect::<Vec<_>>().into_iter()), ExportFormat::Csv, &target, &path, 4)
+            .await
+            .unwrap();
+        assert_eq!(rows, 4);
+        let empty = Path::from("acme/empty.csv");
+        write_object(&mut Rows(Vec::new().into_iter()), ExportFormat::Csv, &target, &empty, 4).await.unwrap();
+        assert!(target.store.head(&empty).await.is_ok());
+    }
+
+    #[test]
+    fn destinations_and_keys() {
+        assert_eq!(normalize_destination("/compliance//audit/").unwrap(), "compliance/audit");
+        assert!(normalize_destination("a/../b").is_err());
+        assert!(normalize_destination("a b").is_err());
+        assert_eq!(
+            object_key("exports/", "acme", "compliance/audit", "Audit log", t("2026-03-04T03:00:00Z"), ExportFormat::Parquet),
+            "exports/acme/compliance/audit/2026/03/04/audit-log-20260304T030000Z.parquet"
+        );
+    }
+}
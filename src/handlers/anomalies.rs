@@ -456,7 +456,7 @@ pub async fn get_event_correlations(
     let bucket_rows: Vec<CorrelatedBucket> = crate::tenant_query(&state.ch, &bucket_query, tenant_id)
         .fetch_all()
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(|e| crate::query_limits::query_error(tenant_id, &e, "query failed"))?;
 
     // 5. Group by service, compute totals, take top 10
     let mut svc_map: HashMap<String, Vec<ServiceBucket>> = HashMap::new();
@@ -507,7 +507,7 @@ pub async fn get_event_correlations(
         crate::tenant_query(&state.ch, &log_query, tenant_id)
             .fetch_all::<CorrelationLog>()
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| crate::query_limits::query_error(tenant_id, &e, "query failed"))?
     } else {
         vec![]
     };
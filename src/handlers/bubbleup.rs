@@ -308,7 +308,7 @@ pub async fn bubbleup(
 
     let totals = totals_result.map_err(|e| {
         tracing::error!(error = %e, signal = %req.signal, handler = "bubbleup", "totals query failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("totals query failed: {e}"))
+        crate::query_limits::query_error(tenant_id, &e, "totals query failed")
     })?;
 
     let selection_count = totals.selection_count;
@@ -316,7 +316,7 @@ pub async fn bubbleup(
 
     let dimension_rows = dimension_result.map_err(|e| {
         tracing::error!(error = %e, signal = %req.signal, handler = "bubbleup", "dimensions query failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("dimensions query failed: {e}"))
+        crate::query_limits::query_error(tenant_id, &e, "dimensions query failed")
     })?;
 
     // Bucket rows back into per-dimension lists (rows arrive ordered by dim_idx).
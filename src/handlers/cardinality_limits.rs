@@ -0,0 +1,120 @@
+//! Per-tenant cardinality limits CRUD. Admin-only. Mutations reload the
+//! compiled limits in the live writer immediately (a background task also
+//! refreshes periodically). Metric `*` addresses the tenant default.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+
+use crate::AppState;
+use crate::cardinality_limiter::{DEFAULT_WINDOW_SECS, TENANT_DEFAULT_METRIC};
+use crate::clickhouse_config::CardinalityLimitRow;
+use crate::handlers::users::require_admin;
+
+#[derive(serde::Deserialize)]
+pub struct CardinalityLimitInput {
+    pub max_series: u64,
+    /// "drop" | "strip_labels"
+    #[serde(default = "default_action")]
+    pub action: String,
+    /// Labels to strip when over the limit (empty = the label with the most
+    /// distinct values).
+    #[serde(default)]
+    pub strip_labels: Vec<String>,
+    #[serde(default = "default_window")]
+    pub window_secs: u64,
+    #[serde(default)]
+    pub notification_channel_ids: Vec<String>,
+}
+
+fn default_action() -> String { "drop".into() }
+fn default_window() -> u64 { DEFAULT_WINDOW_SECS }
+
+async fn require_tenant(state: &AppState, id: &str) -> Result<(), (StatusCode, String)> {
+    state
+        .config_db
+        .get_tenant(id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "tenant not found".to_string()))?;
+    Ok(())
+}
+
+/// Recompile limits and hot-swap them into the live writer.
+async fn reload(state: &AppState) {
+    match state.config_db.compiled_cardinality_limits().await {
+        Ok(cfg) => state.writer.cardinality.set_config(cfg),
+        Err(e) => tracing::warn!(error = %e, "failed to reload cardinality limits"),
+    }
+}
+
+/// GET /api/v1/tenants/{id}/cardinality-limits
+pub async fn list(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    require_tenant(&state, &id).await?;
+    let limits = state.config_db.list_cardinality_limits(Some(&id)).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    // Live view from this replica's limiter (only limited metrics are tracked).
+    let active_series = state.writer.cardinality.active_series(&id);
+    Ok(Json(serde_json::json!({ "limits": limits, "active_series": active_series })))
+}
+
+/// PUT /api/v1/tenants/{id}/cardinality-limits/{metric}
+pub async fn upsert(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((id, metric)): Path<(String, String)>,
+    Json(input): Json<CardinalityLimitInput>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    require_tenant(&state, &id).await?;
+    if metric.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, format!("metric is required (use '{TENANT_DEFAULT_METRIC}' for the tenant default)")));
+    }
+    if input.max_series == 0 {
+        return Err((StatusCode::BAD_REQUEST, "max_series must be >= 1 (delete the limit to disable it)".to_string()));
+    }
+    if input.action != "drop" && input.action != "strip_labels" {
+        return Err((StatusCode::BAD_REQUEST, "action must be 'drop' or 'strip_labels'".to_string()));
+    }
+    if input.window_secs < 60 {
+        return Err((StatusCode::BAD_REQUEST, "window_secs must be >= 60".to_string()));
+    }
+    let row = CardinalityLimitRow {
+        tenant_id: id,
+        metric_name: metric.trim().to_string(),
+        max_series: input.max_series,
+        action: input.action,
+        strip_labels: input.strip_labels.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(","),
+        window_secs: input.window_secs,
+        notification_channel_ids: serde_json::to_string(&input.notification_channel_ids).unwrap_or_else(|_| "[]".into()),
+        updated_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
+    state.config_db.upsert_cardinality_limit(&row).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    reload(&state).await;
+    Ok(Json(row))
+}
+
+/// DELETE /api/v1/tenants/{id}/cardinality-limits/{metric}
+pub async fn delete(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path((id, metric)): Path<(String, String)>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    require_tenant(&state, &id).await?;
+    let deleted = state.config_db.delete_cardinality_limit(&id, &metric).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "no cardinality limit for this metric".to_string()));
+    }
+    reload(&state).await;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -14,7 +14,7 @@ use crate::models::dashboard::*;
 /// Extract the calling user from the session cookie.
 /// Returns (user_id, username, display_name, tenant_id, role).
 /// Falls back to anonymous/default context when no session exists (backward compat).
-async fn resolve_caller(
+pub(crate) async fn resolve_caller(
     state: &AppState,
     headers: &HeaderMap,
     tenant: &TenantContext,
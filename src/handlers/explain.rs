@@ -0,0 +1,91 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{Extension, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+use crate::TenantContext;
+use crate::handlers::jobs::{KINDS, SubmitJobRequest, dispatch};
+use crate::query_explain;
+
+/// Largest body the `dry_run` middleware buffers to look for the flag.
+const MAX_SNIFF_BYTES: usize = 4 * 1024 * 1024;
+
+/// POST /api/v1/query/explain
+///
+/// Takes the same `{kind, request}` body as `/api/v1/jobs`, builds the SQL the
+/// endpoint would run without reading any data, and returns each statement
+/// with its `EXPLAIN ESTIMATE` and index usage.
+pub async fn explain_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Json(req): Json<SubmitJobRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    if !KINDS.contains(&req.kind.as_str()) {
+        return Err((StatusCode::BAD_REQUEST, format!("unknown kind '{}' (expected one of: {})", req.kind, KINDS.join(", "))));
+    }
+    let ch = state.ch.clone();
+    let tenant_id = tenant.tenant_id.clone();
+    let (response, statements) = query_explain::dry_run(dispatch(state, tenant, &req.kind, req.request)).await;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+    Ok(Json(query_explain::explain(&ch, statements, &tenant_id).await).into_response())
+}
+
+fn flag(v: &str) -> bool {
+    matches!(v, "true" | "1")
+}
+
+/// Route middleware for `dry_run`: when the query string or the JSON / form
+/// body has `dry_run=true`, run the handler as a dry run and respond with the
+/// explain plan instead of its result.
+pub async fn dry_run(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let in_query = req
+        .uri()
+        .query()
+        .map(|q| url::form_urlencoded::parse(q.as_bytes()).any(|(k, v)| k == "dry_run" && flag(&v)))
+        .unwrap_or(false);
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    let (req, in_body) = if in_query {
+        (req, false)
+    } else if content_type.starts_with("application/json") || content_type.starts_with("application/x-www-form-urlencoded") {
+        let (parts, body) = req.into_parts();
+        let bytes = match axum::body::to_bytes(body, MAX_SNIFF_BYTES).await {
+            Ok(b) => b,
+            Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "request body too large").into_response(),
+        };
+        let in_body = if content_type.starts_with("application/json") {
+            serde_json::from_slice::<serde_json::Value>(&bytes)
+                .ok()
+                .and_then(|v| v.get("dry_run").and_then(|d| d.as_bool()))
+                .unwrap_or(false)
+        } else {
+            url::form_urlencoded::parse(&bytes).any(|(k, v)| k == "dry_run" && flag(&v))
+        };
+        (Request::from_parts(parts, Body::from(bytes)), in_body)
+    } else {
+        (req, false)
+    };
+    if !in_query && !in_body {
+        return next.run(req).await;
+    }
+
+    let Some(tenant_id) = req.extensions().get::<TenantContext>().map(|t| t.tenant_id.clone()) else {
+        return next.run(req).await;
+    };
+    let (response, statements) = query_explain::dry_run(next.run(req)).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    Json(query_explain::explain(&state.ch, statements, &tenant_id).await).into_response()
+}
@@ -1,4 +1,5 @@
-//! Shared helpers for exporting query results (logs/spans) as CSV or JSON.
+//! Shared helpers for exporting query results (logs/spans) as CSV, JSON,
+//! NDJSON, Arrow IPC or Parquet.
 //!
 //! The interactive query endpoints stay capped at 1000 rows; exports use the
 //! admin-configurable `export_max_rows` setting (default 1000) instead.
@@ -6,20 +7,65 @@
 use axum::body::Body;
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
+use arrow_json::reader::Decoder;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
 use bytes::Bytes;
 use clickhouse::query::RowCursor;
+use std::sync::Arc;
 
 use crate::AppState;
 
 pub const DEFAULT_EXPORT_MAX_ROWS: u64 = 1000;
 pub const EXPORT_MAX_ROWS_CEILING: u64 = 1_000_000;
 
-/// Export output format, parsed from the request body `{ "format": "csv" | "json" }`.
+/// Export output format, parsed from the request body
+/// `{ "format": "csv" | "json" | "ndjson" | "arrow" | "parquet" }`.
+/// Every format streams from the ClickHouse cursor.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ExportFormat {
     Csv,
+    /// A JSON array of rows.
     Json,
+    /// One JSON row per line.
+    Ndjson,
+    /// Arrow IPC stream format.
+    Arrow,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Arrow => "arrow",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            "ndjson" => Some(ExportFormat::Ndjson),
+            "arrow" => Some(ExportFormat::Arrow),
+            "parquet" => Some(ExportFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    /// File extension and content type of the download.
+    pub fn file_type(self) -> (&'static str, &'static str) {
+        match self {
+            ExportFormat::Csv => ("csv", "text/csv; charset=utf-8"),
+            ExportFormat::Json => ("json", "application/json; charset=utf-8"),
+            ExportFormat::Ndjson => ("ndjson", "application/x-ndjson"),
+            ExportFormat::Arrow => ("arrows", "application/vnd.apache.arrow.stream"),
+            ExportFormat::Parquet => ("parquet", "application/vnd.apache.parquet"),
+        }
+    }
 }
 
 impl Default for ExportFormat {
@@ -173,3 +219,424 @@ where
     }
     (StatusCode::OK, headers, Body::from_stream(stream)).into_response()
 }
+
+/// A row type that can be exported. NDJSON is the row's serde form. Arrow and
+/// Parquet schema field names are the serde names too; values go through
+/// arrow-json's serde decoder, so the columns hold the same data as the JSON
+/// export. CSV is a fixed, human-oriented subset of the columns.
+pub trait ExportRow: serde::Serialize {
+    /// CSV header line, including the trailing `\n`.
+    const CSV_HEADER: &'static str;
+
+    fn arrow_schema() -> SchemaRef;
+
+    /// One CSV line (including the trailing `\n`), escaped with `csv_field`.
+    fn csv_row(&self) -> String;
+}
+
+/// DateTime64(9) columns. The zone is spelled as an offset: arrow-json only
+/// resolves named zones with chrono-tz.
+fn timestamp_ns() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, Some("+00:00".into()))
+}
+
+fn list_of(item: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", item, true)))
+}
+
+fn string_map() -> DataType {
+    let entries = Field::new(
+        "entries",
+        DataType::Struct(
+            vec![Field::new("keys", DataType::Utf8, false), Field::new("values", DataType::Utf8, true)].into(),
+        ),
+        false,
+    );
+    DataType::Map(Arc::new(entries), false)
+}
+
+impl ExportRow for crate::models::trace::WideEvent {
+    const CSV_HEADER: &'static str = "Timestamp,Service,Method,Resource,Status,DurationMs,TraceId\n";
+
+    fn arrow_schema() -> SchemaRef {
+        let utf8 = |name| Field::new(name, DataType::Utf8, false);
+        Arc::new(Schema::new(vec![
+            utf8("tenant_id"),
+            Field::new("timestamp", timestamp_ns(), false),
+            utf8("trace_id"),
+            utf8("span_id"),
+            utf8("parent_span_id"),
+            utf8("service_name"),
+            utf8("span_name"),
+            utf8("kind"),
+            utf8("status"),
+            Field::new("duration_ns", DataType::UInt64, false),
+            utf8("http_method"),
+            utf8("http_path"),
+            Field::new("http_status_code", DataType::UInt16, false),
+            utf8("attributes"),
+            Field::new("event_names", list_of(DataType::Utf8), false),
+            Field::new("event_timestamps", list_of(timestamp_ns()), false),
+            Field::new("event_attributes", list_of(DataType::Utf8), false),
+            Field::new("link_trace_ids", list_of(DataType::Utf8), false),
+            Field::new("link_span_ids", list_of(DataType::Utf8), false),
+        ]))
+    }
+
+    fn csv_row(&self) -> String {
+        let duration_ms = format!("{:.3}", self.duration_ns as f64 / 1_000_000.0);
+        let status = if self.http_status_code > 0 {
+            self.http_status_code.to_string()
+        } else {
+            self.status.clone()
+        };
+        format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(&ts_rfc3339(self.timestamp)),
+            csv_field(&self.service_name),
+            csv_field(&self.http_method),
+            csv_field(&self.http_path),
+            csv_field(&status),
+            csv_field(&duration_ms),
+            csv_field(&self.trace_id),
+        )
+    }
+}
+
+impl ExportRow for crate::models::log::LogRecord {
+    const CSV_HEADER: &'static str = "Timestamp,Severity,ServiceName,Body,TraceId\n";
+
+    fn arrow_schema() -> SchemaRef {
+        let utf8 = |name| Field::new(name, DataType::Utf8, false);
+        Arc::new(Schema::new(vec![
+            Field::new("Timestamp", timestamp_ns(), false),
+            utf8("TraceId"),
+            utf8("SpanId"),
+            utf8("SeverityText"),
+            Field::new("SeverityNumber", DataType::UInt8, false),
+            utf8("ServiceName"),
+            utf8("Body"),
+            Field::new("ResourceAttributes", string_map(), false),
+            utf8("ScopeName"),
+            Field::new("LogAttributes", string_map(), false),
+        ]))
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{}\n",
+            csv_field(&ts_rfc3339(self.timestamp)),
+            csv_field(&self.severity_text),
+            csv_field(&self.service_name),
+            csv_field(&self.body),
+            csv_field(&self.trace_id),
+        )
+    }
+}
+
+/// Rows pulled from the cursor per encoded chunk.
+pub(crate) const ROW_BATCH: usize = 8192;
+/// Parquet row group size. The writer holds one row group in memory, so this
+/// (not the export row cap) bounds its footprint.
+const PARQUET_ROW_GROUP: usize = 65_536;
+
+/// Incremental encoder for the streaming formats. `encode` returns whatever
+/// bytes are ready; `finish` returns the rest (the closing `]` of a JSON
+/// array, the Arrow end-of-stream marker, the last Parquet row group and
+/// footer). CSV here is the bare header and rows, without the `#` preamble the
+/// download endpoints add.
+pub(crate) enum RowEncoder {
+    Csv { header: bool },
+    /// Rows written so far; the first one opens the array.
+    Json { written: u64 },
+    Ndjson,
+    Arrow { decoder: Decoder, writer: arrow_ipc::writer::StreamWriter<Vec<u8>> },
+    Parquet { decoder: Decoder, writer: parquet::arrow::ArrowWriter<Vec<u8>> },
+}
+
+impl RowEncoder {
+    pub(crate) fn new<T: ExportRow>(format: ExportFormat) -> anyhow::Result<Self> {
+        let schema = T::arrow_schema();
+        let decoder = || arrow_json::ReaderBuilder::new(schema.clone()).build_decoder();
+        Ok(match format {
+            ExportFormat::Arrow => RowEncoder::Arrow {
+                decoder: decoder()?,
+                writer: arrow_ipc::writer::StreamWriter::try_new(Vec::new(), &schema)?,
+            },
+            ExportFormat::Parquet => {
+                let props = parquet::file::properties::WriterProperties::builder()
+                    .set_compression(parquet::basic::Compression::ZSTD(Default::default()))
+                    .set_max_row_group_size(PARQUET_ROW_GROUP)
+                    .build();
+                RowEncoder::Parquet {
+                    decoder: decoder()?,
+                    writer: parquet::arrow::ArrowWriter::try_new(Vec::new(), schema.clone(), Some(props))?,
+                }
+            }
+            ExportFormat::Csv => RowEncoder::Csv { header: false },
+            ExportFormat::Json => RowEncoder::Json { written: 0 },
+            ExportFormat::Ndjson => RowEncoder::Ndjson,
+        })
+    }
+
+    pub(crate) fn encode<T: ExportRow>(&mut self, rows: &[T]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            RowEncoder::Csv { header } => {
+                let mut out = String::new();
+                if !std::mem::replace(header, true) {
+                    out.push_str(T::CSV_HEADER);
+                }
+                for row in rows {
+                    out.push_str(&row.csv_row());
+                }
+                Ok(out.into_bytes())
+            }
+            RowEncoder::Json { written } => {
+                let mut out = Vec::new();
+                for row in rows {
+                    out.extend_from_slice(if *written == 0 { b"[\n" } else { b",\n" });
+                    serde_json::to_writer(&mut out, row)?;
+                    *written += 1;
+                }
+                Ok(out)
+            }
+            RowEncoder::Ndjson => {
+                let mut out = Vec::new();
+                for row in rows {
+                    serde_json::to_writer(&mut out, row)?;
+                    out.push(b'\n');
+                }
+                Ok(out)
+            }
+            RowEncoder::Arrow { decoder, writer } => {
+                decoder.serialize(rows)?;
+                if let Some(batch) = decoder.flush()? {
+                    writer.write(&batch)?;
+                }
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            RowEncoder::Parquet { decoder, writer } => {
+                decoder.serialize(rows)?;
+                if let Some(batch) = decoder.flush()? {
+                    writer.write(&batch)?;
+                }
+                // Completed row groups are final: hand them out now. The
+                // writer tracks offsets itself, so draining its buffer is safe.
+                Ok(std::mem::take(writer.inner_mut()))
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            RowEncoder::Csv { .. } | RowEncoder::Ndjson => Ok(Vec::new()),
+            RowEncoder::Json { written: 0 } => Ok(b"[\n]\n".to_vec()),
+            RowEncoder::Json { .. } => Ok(b"\n]\n".to_vec()),
+            RowEncoder::Arrow { mut writer, .. } => {
+                writer.finish()?;
+                Ok(std::mem::take(writer.get_mut()))
+            }
+            RowEncoder::Parquet { writer, .. } => Ok(writer.into_inner()?),
+        }
+    }
+}
+
+/// Build a streaming JSON / NDJSON / Arrow IPC / Parquet file-download response.
+///
+/// Rows are pulled from the cursor `ROW_BATCH` at a time and encoded as they
+/// arrive, so memory stays bounded by one batch (one row group for Parquet)
+/// whatever the export size. As with `stream_csv_response`, a mid-stream error
+/// aborts the body; for Arrow and Parquet that leaves a truncated file that
+/// readers reject rather than silently short data.
+pub fn stream_rows_response<T>(cursor: RowCursor<T>, format: ExportFormat, filename_stem: &str) -> Response
+where
+    T: clickhouse::Row + ExportRow + for<'b> serde::Deserialize<'b> + Send + 'static,
+{
+    let (ext, content_type) = format.file_type();
+    let encoder = match RowEncoder::new::<T>(format) {
+        Ok(e) => e,
+        Err(e) => {
+            tracing::error!(error = %e, ?format, "export encoder init failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "export failed").into_response();
+        }
+    };
+
+    let stream = futures_util::stream::unfold((cursor, Some(encoder)), |(mut cursor, mut encoder)| async move {
+        let io_err = |e: &dyn std::fmt::Display| std::io::Error::other(e.to_string());
+        loop {
+            let enc = encoder.as_mut()?;
+            let mut rows = Vec::with_capacity(ROW_BATCH);
+            let mut eof = false;
+            while rows.len() < ROW_BATCH {
+                match cursor.next().await {
+                    Ok(Some(row)) => rows.push(row),
+                    Ok(None) => {
+                        eof = true;
+                        break;
+                    }
+                    Err(e) => return Some((Err(io_err(&e)), (cursor, None))),
+                }
+            }
+            let mut out = match enc.encode(&rows) {
+                Ok(out) => out,
+                Err(e) => return Some((Err(io_err(&e)), (cursor, None))),
+            };
+            if eof {
+                match encoder.take().map(RowEncoder::finish) {
+                    Some(Ok(rest)) => out.extend(rest),
+                    Some(Err(e)) => return Some((Err(io_err(&e)), (cursor, None))),
+                    None => {}
+                }
+            }
+            // Parquet only emits bytes per row group; keep pulling until it does.
+            if !out.is_empty() || eof {
+                return Some((Ok::<Bytes, std::io::Error>(Bytes::from(out)), (cursor, encoder)));
+            }
+        }
+    });
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Ok(cd) = HeaderValue::from_str(&format!("attachment; filename=\"{filename_stem}.{ext}\"")) {
+        headers.insert(header::CONTENT_DISPOSITION, cd);
+    }
+    (StatusCode::OK, headers, Body::from_stream(stream)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::log::LogRecord;
+
+    fn log(i: i64) -> LogRecord {
+        LogRecord {
+            timestamp: 1_700_000_000_000_000_000 + i,
+            trace_id: format!("t{i}"),
+            span_id: String::new(),
+            severity_text: "INFO".into(),
+            severity_number: 9,
+            service_name: "api".into(),
+            body: format!("line {i}"),
+            resource_attributes: vec![("host".into(), "a".into())],
+            scope_name: String::new(),
+            log_attributes: vec![],
+        }
+    }
+
+    fn encode_all<T: ExportRow>(format: ExportFormat, batches: &[Vec<T>]) -> Vec<u8> {
+        let mut enc = RowEncoder::new::<T>(format).unwrap();
+        let mut out = Vec::new();
+        for rows in batches {
+            out.extend(enc.encode(rows).unwrap());
+        }
+        out.extend(enc.finish().unwrap());
+        out
+    }
+
+    #[test]
+    fn columnar_exports_round_trip() {
+        let batches = vec![(0..3).map(log).collect::<Vec<_>>(), (3..5).map(log).collect()];
+
+        let ipc = encode_all(ExportFormat::Arrow, &batches);
+        let reader = arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(ipc), None).unwrap();
+        assert_eq!(reader.schema(), LogRecord::arrow_schema());
+        let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 5);
+
+        let pq = encode_all(ExportFormat::Parquet, &batches);
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(Bytes::from(pq))
+            .unwrap()
+            .build()
+            .unwrap();
+        let rows: usize = reader.map(|b| b.unwrap().num_rows()).sum();
+        assert_eq!(rows, 5);
+
+        let nd = String::from_utf8(encode_all(ExportFormat::Ndjson, &batches)).unwrap();
+        assert_eq!(nd.lines().count(), 5);
+        let first: serde_json::Value = serde_json::from_str(nd.lines().next().unwrap()).unwrap();
+        assert_eq!(first["ResourceAttributes"]["host"], "a");
+
+        let json: serde_json::Value = serde_json::from_slice(&encode_all(ExportFormat::Json, &batches)).unwrap();
+        let rows = json.as_array().unwrap();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[4]["Body"], "line 4");
+        let empty: serde_json::Value =
+            serde_json::from_slice(&encode_all::<LogRecord>(ExportFormat::Json, &[vec![]])).unwrap();
+        assert_eq!(empty, serde_json::json!([]));
+    }
+
+    #[test]
+    fn span_exports_keep_nested_and_unsigned_columns() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::{TimestampNanosecondType, UInt16Type, UInt64Type};
+        use crate::models::trace::WideEvent;
+
+        let span = WideEvent {
+            tenant_id: "t".into(),
+            timestamp: 1_700_000_000_000_000_000,
+            trace_id: "trace".into(),
+            span_id: "span".into(),
+            parent_span_id: String::new(),
+            service_name: "api".into(),
+            span_name: "GET /users".into(),
+            kind: "SERVER".into(),
+            status: "ERROR".into(),
+            duration_ns: u64::MAX,
+            http_method: "GET".into(),
+            http_path: "/users".into(),
+            http_status_code: 503,
+            attributes: r#"{"k":"v"}"#.into(),
+            event_names: vec!["exception".into(), "retry".into()],
+            event_timestamps: vec![1_700_000_000_000_000_001, 1_700_000_000_000_000_002],
+            event_attributes: vec![r#"{"a":1}"#.into(), "{}".into()],
+            link_trace_ids: vec!["other-trace".into()],
+            link_span_ids: vec!["other-span".into()],
+        };
+        let unlinked = WideEvent { link_trace_ids: vec![], link_span_ids: vec![], event_names: vec![], ..span.clone() };
+        let ipc = encode_all(ExportFormat::Arrow, &[vec![span, unlinked]]);
+        let mut reader = arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(ipc), None).unwrap();
+        assert_eq!(reader.schema(), WideEvent::arrow_schema());
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+
+        let column = |name| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        assert_eq!(column("duration_ns").as_primitive::<UInt64Type>().value(0), u64::MAX);
+        assert_eq!(column("http_status_code").as_primitive::<UInt16Type>().value(0), 503);
+
+        let names = column("event_names");
+        let names = names.as_list::<i32>();
+        let first = names.value(0);
+        let first = first.as_string::<i32>();
+        assert_eq!((first.value(0), first.value(1)), ("exception", "retry"));
+        assert!(names.value(1).is_empty());
+        let stamps = column("event_timestamps");
+        let stamps = stamps.as_list::<i32>().value(0);
+        assert_eq!(stamps.as_primitive::<TimestampNanosecondType>().value(1), 1_700_000_000_000_000_002);
+        let links = column("link_span_ids");
+        let links = links.as_list::<i32>();
+        assert_eq!(links.value(0).as_string::<i32>().value(0), "other-span");
+        assert!(links.value(1).is_empty());
+    }
+
+    #[test]
+    fn parquet_exports_span_row_groups() {
+        let total = PARQUET_ROW_GROUP + 100;
+        let rows: Vec<LogRecord> = (0..total as i64).map(log).collect();
+        let batches: Vec<Vec<LogRecord>> = rows.chunks(ROW_BATCH).map(<[LogRecord]>::to_vec).collect();
+
+        let pq = encode_all(ExportFormat::Parquet, &batches);
+        let builder = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(Bytes::from(pq)).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.metadata().row_group(0).num_rows() as usize, PARQUET_ROW_GROUP);
+        let mut read = 0;
+        let mut last_body = String::new();
+        for batch in builder.build().unwrap() {
+            let batch = batch.unwrap();
+            read += batch.num_rows();
+            let body = batch.column(batch.schema().index_of("Body").unwrap()).clone();
+            last_body = arrow_array::cast::AsArray::as_string::<i32>(&body).value(batch.num_rows() - 1).to_string();
+        }
+        assert_eq!(read, total);
+        assert_eq!(last_body, format!("line {}", total - 1));
+    }
+}
@@ -1,6 +1,8 @@
-//! Ingest-buffer status (durable spool depth) for the Stats/Settings surface.
+//! Ingest-buffer status (durable spool depth) for the Stats/Settings surface,
+//! plus admin management of the dead-letter (quarantine) area: records the
+//! replayer gave up on can be listed, inspected, re-driven or deleted.
 
-use axum::{Json, extract::State, http::{HeaderMap, StatusCode}, response::IntoResponse};
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}, response::IntoResponse};
 
 use crate::AppState;
 use crate::handlers::users::require_admin;
@@ -24,5 +26,96 @@ pub async fn buffer_status(
         "used_pct": pct,
         "oldest_age_secs": oldest_age_secs,
         "committed_total": b.committed_total(),
+        "corrupt_records_total": b.corrupt_records_total(),
+        "quarantine_count": b.dead_letter_count(),
+        "quarantine_bytes": b.dead_letter_bytes(),
+        "tenants": b.tenant_usage(),
+        // Kubernetes enrichment cache (null when disabled).
+        "k8s_enrichment": state.k8s.as_ref().map(|k| k.stats()),
     })))
 }
+
+/// GET /api/v1/ingest/deadletter — quarantined records, oldest first.
+pub async fn list_dead_letters(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let entries = state.writer.buffer.dead_letters().await.map_err(|e| {
+        tracing::error!(error = %e, "failed to list dead-letter entries");
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+    })?;
+    Ok(Json(serde_json::json!({ "entries": entries })))
+}
+
+/// GET /api/v1/ingest/deadletter/{id} — metadata plus the decoded rows.
+pub async fn get_dead_letter(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let (meta, table, payload) = read_entry(&state, &id).await?;
+    // The payload is the spooled serde_json `SpoolBatch`; fall back to the raw
+    // text if it doesn't even parse as JSON (that's often why it's here).
+    let batch = match serde_json::from_slice::<serde_json::Value>(&payload) {
+        Ok(v) => v,
+        Err(_) => serde_json::Value::String(String::from_utf8_lossy(&payload).into_owned()),
+    };
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "table": table,
+        "bytes": payload.len(),
+        "meta": meta,
+        "batch": batch,
+    })))
+}
+
+/// POST /api/v1/ingest/deadletter/{id}/redrive — append the record back to the
+/// live buffer (the replayer picks it up on its next pass) and drop the entry.
+pub async fn redrive_dead_letter(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let (meta, table, payload) = read_entry(&state, &id).await?;
+    state.writer.buffer.append(&meta.tenant, &table, payload).await
+        .map_err(|_| (StatusCode::TOO_MANY_REQUESTS, "ingest buffer is full".to_string()))?;
+    // At-least-once: if this delete fails the entry stays and a second re-drive
+    // would replay it twice, which the operator can see in the listing.
+    state.writer.buffer.delete_dead_letter(&id).await.map_err(|e| {
+        tracing::error!(error = %e, id = %id, "failed to delete re-driven dead-letter entry");
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+    })?;
+    Ok(Json(serde_json::json!({ "id": id, "table": table, "redriven": true })))
+}
+
+/// DELETE /api/v1/ingest/deadletter/{id} — discard a quarantined record.
+pub async fn delete_dead_letter(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let deleted = state.writer.buffer.delete_dead_letter(&id).await.map_err(|e| {
+        tracing::error!(error = %e, id = %id, "failed to delete dead-letter entry");
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+    })?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "dead-letter entry not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn read_entry(
+    state: &AppState,
+    id: &str,
+) -> Result<(crate::spool::DeadLetterMeta, String, Vec<u8>), (StatusCode, String)> {
+    state.writer.buffer.read_dead_letter(id).await
+        .map_err(|e| {
+            tracing::error!(error = %e, id = %id, "failed to read dead-letter entry");
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string())
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "dead-letter entry not found".to_string()))
+}
@@ -0,0 +1,199 @@
+use axum::{
+    Json,
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::AppState;
+use crate::TenantContext;
+use crate::handlers::{logs, pipe_query, query};
+use crate::query_jobs::{self, JobInfo, JobStatus};
+use crate::query_limits::HeldSlot;
+
+const DEFAULT_PAGE: u64 = 1_000;
+const MAX_PAGE: u64 = 10_000;
+
+/// `kind` names the endpoint to run and `request` is that endpoint's usual
+/// request body.
+#[derive(Debug, Deserialize)]
+pub struct SubmitJobRequest {
+    pub kind: String,
+    pub request: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResultsParams {
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+/// Job (and explain) kinds: `query`, `count`, `group`, `timeseries` and
+/// `export` run the `/api/v1/query…` endpoints, `logs…` the `/api/v1/logs…`
+/// ones, and `pipe` runs `/api/v1/query/pipe`.
+pub(crate) const KINDS: &[&str] = &[
+    "query", "count", "group", "timeseries", "export",
+    "logs", "logs_count", "logs_group", "logs_export", "pipe",
+];
+
+fn parse<T: serde::de::DeserializeOwned>(request: serde_json::Value) -> Result<Json<T>, (StatusCode, String)> {
+    serde_json::from_value(request)
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid request: {e}")))
+}
+
+/// Run the endpoint for `kind` with `request` as its body.
+pub(crate) async fn dispatch(state: AppState, tenant: TenantContext, kind: &str, request: serde_json::Value) -> Response {
+    let (state, tenant) = (State(state), Extension(tenant));
+    let result: Result<Response, (StatusCode, String)> = async {
+        match kind {
+            "query" => Ok(query::execute_query(state, tenant, parse(request)?).await?.into_response()),
+            "count" => Ok(query::count_query(state, tenant, parse(request)?).await?.into_response()),
+            "group" => Ok(query::group_query(state, tenant, parse(request)?).await?.into_response()),
+            "timeseries" => Ok(query::timeseries_query(state, tenant, parse(request)?).await?.into_response()),
+            "export" => query::export_query(state, tenant, parse(request)?).await,
+            "logs" => Ok(logs::query_logs(state, tenant, parse(request)?).await?.into_response()),
+            "logs_count" => Ok(logs::count_logs(state, tenant, parse(request)?).await?.into_response()),
+            "logs_group" => Ok(logs::group_logs(state, tenant, parse(request)?).await?.into_response()),
+            "logs_export" => logs::export_logs(state, tenant, parse(request)?).await,
+            "pipe" => pipe_query::pipe_query(state, tenant, parse(request)?).await,
+            _ => Err((StatusCode::BAD_REQUEST, format!("unknown kind '{kind}'"))),
+        }
+    }
+    .await;
+    result.unwrap_or_else(|e| e.into_response())
+}
+
+/// POST /api/v1/jobs
+///
+/// Runs a query endpoint in the background and returns 202 with the job. Poll
+/// `GET /api/v1/jobs/{id}` for status and progress, then page through
+/// `GET /api/v1/jobs/{id}/results`.
+pub async fn submit_job(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    slot: Option<Extension<HeldSlot>>,
+    Json(req): Json<SubmitJobRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if !KINDS.contains(&req.kind.as_str()) {
+        return Err((StatusCode::BAD_REQUEST, format!("unknown job kind '{}' (expected one of: {})", req.kind, KINDS.join(", "))));
+    }
+    let jobs = state.jobs.clone();
+    let ch = state.ch.clone();
+    let tenant_id = tenant.tenant_id.clone();
+    let kind = req.kind.clone();
+    // The job counts against the tenant's query budget until it finishes.
+    let run = async move {
+        let _slot = slot;
+        dispatch(state, tenant, &kind, req.request).await
+    };
+    let job = jobs
+        .submit(ch, &tenant_id, &req.kind, run)
+        .await
+        .map_err(|e| {
+            tracing::error!(tenant_id = %tenant_id, error = %e, "submitting query job failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "submitting job failed".to_string())
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("too many running jobs (max {} per tenant)", jobs.config().max_per_tenant),
+            )
+        })?;
+    tracing::info!(job_id = %job.id, kind = %job.kind, tenant_id = %tenant_id, "query job submitted");
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Look a job up for the tenant: 404 if it doesn't exist (or expired).
+async fn find_job(state: &AppState, tenant_id: &str, id: &str) -> Result<JobInfo, (StatusCode, String)> {
+    state
+        .jobs
+        .get(&state.ch, tenant_id, id)
+        .await
+        .map_err(|e| {
+            tracing::error!(job_id = %id, error = %e, "reading query job failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "reading job failed".to_string())
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "job not found".to_string()))
+}
+
+pub async fn list_jobs(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let jobs = state.jobs.list(&state.ch, &tenant.tenant_id).await.map_err(|e| {
+        tracing::error!(error = %e, "listing query jobs failed");
+        (StatusCode::INTERNAL_SERVER_ERROR, "listing jobs failed".to_string())
+    })?;
+    Ok(Json(serde_json::json!({ "jobs": jobs })))
+}
+
+/// GET /api/v1/jobs/{id} — status, plus live progress while running.
+pub async fn get_job(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut job = find_job(&state, &tenant.tenant_id, &id).await?;
+    if job.status == JobStatus::Running {
+        match query_jobs::progress(&state.ch, &job.id).await {
+            Ok(p) => job.progress = Some(p),
+            Err(e) => {
+                tracing::warn!(job_id = %job.id, error = %e, "reading job progress failed");
+            }
+        }
+    }
+    Ok(Json(job))
+}
+
+/// GET /api/v1/jobs/{id}/results?offset=&limit= — a page of a finished job's rows.
+pub async fn job_results(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Path(id): Path<String>,
+    Query(params): Query<ResultsParams>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let info = find_job(&state, &tenant.tenant_id, &id).await?;
+    match info.status {
+        JobStatus::Done => {}
+        JobStatus::Running => return Err((StatusCode::CONFLICT, "job is still running".to_string())),
+        JobStatus::Failed => return Err((StatusCode::CONFLICT, "job failed".to_string())),
+        JobStatus::Cancelled => return Err((StatusCode::CONFLICT, "job was cancelled".to_string())),
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+    let rows = query_jobs::fetch_rows(&state.ch, &tenant.tenant_id, &info.id, params.offset, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(job_id = %info.id, error = %e, "reading job results failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "reading job results failed".to_string())
+        })?;
+    let next_offset = params.offset + rows.len() as u64;
+    Ok(Json(serde_json::json!({
+        "job": info,
+        "rows": rows,
+        "offset": params.offset,
+        "next_offset": (next_offset < info.rows).then_some(next_offset),
+    })))
+}
+
+/// DELETE /api/v1/jobs/{id} — cancel a running job (`KILL QUERY` on its queries).
+pub async fn cancel_job(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let job = find_job(&state, &tenant.tenant_id, &id).await?;
+    if job.status != JobStatus::Running {
+        return Err((StatusCode::CONFLICT, "job already finished".to_string()));
+    }
+    let cancelled = state.jobs.cancel(&state.ch, &tenant.tenant_id, &job.id).await.map_err(|e| {
+        tracing::error!(job_id = %job.id, error = %e, "cancelling query job failed");
+        (StatusCode::INTERNAL_SERVER_ERROR, "cancelling job failed".to_string())
+    })?;
+    cancelled
+        .map(Json)
+        .ok_or_else(|| (StatusCode::CONFLICT, "job already finished".to_string()))
+}
@@ -1,20 +1,21 @@
 use axum::{
     Json,
-    extract::State,
+    extract::{Query, State, ws::{WebSocketUpgrade, rejection::WebSocketUpgradeRejection}},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension,
 };
 
 use crate::AppState;
 use crate::TenantContext;
+use crate::live_tail::{TailParams, TailSignal};
 use crate::models::log::LogRecord;
 use crate::models::query::{CountBucket, CountQueryRequest, Filter, FilterOp, TimeRange};
 use crate::query_builder::{format_value, build_log_search_sql, sanitize_datetime, QueryClauses};
 
 /// Resolve a log field name to a ClickHouse column expression.
 /// Uses materialized columns for common resource attributes (avoids Map lookups).
-fn resolve_log_field(field: &str) -> String {
+pub(crate) fn resolve_log_field(field: &str) -> String {
     match field {
         "service_name" | "ServiceName" => "ServiceName".to_string(),
         "severity" | "severity_text" | "SeverityText" => "SeverityText".to_string(),
@@ -50,7 +51,7 @@ fn resolve_log_field(field: &str) -> String {
 /// Build PREWHERE-optimized query clauses for logs.
 /// tenant_id + time range go into PREWHERE (evaluated at granule level before decompression);
 /// column filters and full-text search go into WHERE.
-fn build_log_where(filters: &[Filter], from: &str, to: &str, search: Option<&str>, tenant_id: &str) -> QueryClauses {
+pub(crate) fn build_log_where(filters: &[Filter], from: &str, to: &str, search: Option<&str>, tenant_id: &str) -> QueryClauses {
     let escaped_tenant = crate::query_builder::escape_string_literal(&tenant_id);
     let from = sanitize_datetime(from);
     let to = sanitize_datetime(to);
@@ -104,6 +105,23 @@ pub struct LogQueryRequest {
 
 fn default_limit() -> u64 { 100 }
 
+/// Live tail of logs as they are ingested: SSE, or WebSocket on upgrade.
+/// Takes the `LogQueryRequest` filters (JSON) and search as query params.
+pub async fn tail_logs(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Query(params): Query<TailParams>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, (StatusCode, String)> {
+    let filter = params.parse()?;
+    let sub = state
+        .writer
+        .tail
+        .subscribe(&tenant.tenant_id, TailSignal::Logs, filter)
+        .ok_or((StatusCode::TOO_MANY_REQUESTS, "too many live tails".to_string()))?;
+    Ok(crate::live_tail::respond(sub, ws))
+}
+
 /// Query logs from logs.
 pub async fn query_logs(
     State(state): State<AppState>,
@@ -155,7 +173,7 @@ pub async fn query_logs(
             .await
             .map_err(|e| {
                 tracing::error!(error = %e, signal = "logs", handler = "query_logs", "narrow query failed");
-                (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+                crate::query_limits::query_error(tenant_id, &e, "query failed")
             })?;
         if (narrow_rows.len() as u64) >= limit {
             let total = narrow_rows.len() as u64;
@@ -172,7 +190,7 @@ pub async fn query_logs(
                 .await
                 .map_err(|e| {
                     tracing::error!(error = %e, signal = "logs", handler = "query_logs", "full-range query failed");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+                    crate::query_limits::query_error(tenant_id, &e, "query failed")
                 })?;
             let total = rows.len() as u64;
             (rows, total)
@@ -192,7 +210,7 @@ pub async fn query_logs(
             .fetch_all::<LogRecord>().await
             .map_err(|e| {
                 tracing::error!(error = %e, signal = "logs", handler = "query_logs", "search query failed");
-                (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+                crate::query_limits::query_error(tenant_id, &e, "query failed")
             })?;
         let total = rows.len() as u64;
         (rows, total)
@@ -239,7 +257,32 @@ pub struct LogExportRequest {
     pub query_text: Option<String>,
 }
 
-/// Export logs matching the current query as a CSV or JSON file.
+/// Log rows for an export (the download endpoint and scheduled exports), newest first.
+/// `end_exclusive` turns the range into `[from, to)` so consecutive scheduled
+/// windows don't both export the boundary second.
+pub(crate) fn export_sql(
+    tenant_id: &str,
+    filters: &[Filter],
+    time_range: &TimeRange,
+    end_exclusive: bool,
+    search: Option<&str>,
+    limit: u64,
+) -> String {
+    let select_cols = "Timestamp, TraceId, SpanId, SeverityText, SeverityNumber, \
+         ServiceName, Body, ResourceAttributes, ScopeName, LogAttributes";
+    let mut clauses = build_log_where(filters, &time_range.from, &time_range.to, search, tenant_id);
+    if end_exclusive {
+        let to = sanitize_datetime(&time_range.to);
+        clauses.prewhere.push_str(&format!(" AND Timestamp < parseDateTimeBestEffort('{to}')"));
+    }
+    format!(
+        "SELECT {select_cols} FROM logs {} \
+         ORDER BY TimestampTime DESC, Timestamp DESC LIMIT {limit}",
+        clauses.to_sql(),
+    )
+}
+
+/// Export logs matching the current query as a CSV, JSON, NDJSON, Arrow or Parquet file.
 /// Limit is clamped to the admin-configured `export_max_rows` (not the 1000 cap).
 pub async fn export_logs(
     State(state): State<AppState>,
@@ -258,14 +301,7 @@ pub async fn export_logs(
     let cap = export::read_export_max_rows(&state).await;
     let limit = export::effective_limit(req.limit, cap);
 
-    let select_cols = "Timestamp, TraceId, SpanId, SeverityText, SeverityNumber, \
-         ServiceName, Body, ResourceAttributes, ScopeName, LogAttributes";
-    let clauses = build_log_where(&req.filters, &req.time_range.from, &req.time_range.to, req.search.as_deref(), tenant_id);
-    let sql = format!(
-        "SELECT {select_cols} FROM logs {} \
-         ORDER BY TimestampTime DESC, Timestamp DESC LIMIT {limit}",
-        clauses.to_sql(),
-    );
+    let sql = export_sql(tenant_id, &req.filters, &req.time_range, false, req.search.as_deref(), limit);
 
     let unix = chrono::Utc::now().timestamp();
     match req.format {
@@ -280,51 +316,29 @@ pub async fn export_logs(
                 "logs", &req.time_range.from, &req.time_range.to,
                 req.search.as_deref(), req.query_text.as_deref(),
             );
-            prelude.push_str("Timestamp,Severity,ServiceName,Body,TraceId\n");
+            prelude.push_str(<LogRecord as export::ExportRow>::CSV_HEADER);
 
             let cursor = crate::tenant_query(&state.ch, &sql, tenant_id)
                 .fetch::<LogRecord>()
                 .map_err(|e| {
                     tracing::error!(error = %e, signal = "logs", handler = "export_logs", "export stream init failed");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "export query failed".into())
+                    crate::query_limits::query_error(tenant_id, &e, "export query failed")
                 })?;
 
-            let fmt_row = |r: &LogRecord| -> String {
-                format!(
-                    "{},{},{},{},{}\n",
-                    export::csv_field(&export::ts_rfc3339(r.timestamp)),
-                    export::csv_field(&r.severity_text),
-                    export::csv_field(&r.service_name),
-                    export::csv_field(&r.body),
-                    export::csv_field(&r.trace_id),
-                )
-            };
+            let fmt_row = |r: &LogRecord| export::ExportRow::csv_row(r);
             Ok(export::stream_csv_response(cursor, prelude, fmt_row, &format!("rush-logs-{unix}.csv")))
         }
-        export::ExportFormat::Json => {
-            // JSON export stays on the buffered fetch_all path: its output is
-            // serde_json::to_string_pretty over the full envelope, which can't be
-            // reproduced byte-for-byte while streaming row-by-row. Row count is still
-            // capped by LIMIT, so memory is bounded by the cap (default 1000). See report.
-            let rows = crate::tenant_query(&state.ch, &sql, tenant_id)
-                .fetch_all::<LogRecord>().await
+        export::ExportFormat::Json
+        | export::ExportFormat::Ndjson
+        | export::ExportFormat::Arrow
+        | export::ExportFormat::Parquet => {
+            let cursor = crate::tenant_query(&state.ch, &sql, tenant_id)
+                .fetch::<LogRecord>()
                 .map_err(|e| {
-                    tracing::error!(error = %e, signal = "logs", handler = "export_logs", "export query failed");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "export query failed".into())
+                    tracing::error!(error = %e, signal = "logs", handler = "export_logs", "export stream init failed");
+                    crate::query_limits::query_error(tenant_id, &e, "export query failed")
                 })?;
-            let body = serde_json::json!({
-                "query": {
-                    "signal": "logs",
-                    "time_range": { "from": req.time_range.from, "to": req.time_range.to },
-                    "search": req.search,
-                    "query_text": req.query_text,
-                },
-                "exported_at": chrono::Utc::now().to_rfc3339(),
-                "count": rows.len(),
-                "rows": rows,
-            });
-            let s = serde_json::to_string_pretty(&body).unwrap_or_else(|_| "{}".into());
-            Ok(export::file_response(s, "application/json; charset=utf-8", &format!("rush-logs-{unix}.json")))
+            Ok(export::stream_rows_response(cursor, req.format, &format!("rush-logs-{unix}")))
         }
     }
 }
@@ -369,7 +383,7 @@ pub async fn count_logs(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "logs", handler = "count_logs", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(buckets))
@@ -409,7 +423,7 @@ pub async fn group_logs(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "logs", handler = "group_logs", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     let json_rows: Vec<serde_json::Value> = rows
@@ -419,3 +433,368 @@ pub async fn group_logs(
 
     Ok(Json(serde_json::json!({ "groups": json_rows })))
 }
+
+/// Distinct (service, digit-masked body) shapes mined per request.
+const MAX_PATTERN_SHAPES: u64 = 20_000;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LogPatternsRequest {
+    /// The window to mine (e.g. the incident).
+    pub selection: TimeRange,
+    /// Optional comparison window; without it every delta is against zero.
+    #[serde(default)]
+    pub baseline: Option<TimeRange>,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub search: Option<String>,
+    /// Max templates returned (default 50).
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct LogPattern {
+    /// Stable per (tenant, service) while the miner is cached.
+    pub id: u32,
+    pub service_name: String,
+    pub template: String,
+    pub selection_count: u64,
+    pub baseline_count: u64,
+    pub selection_pct: f64,
+    pub baseline_pct: f64,
+    pub lift: f64,
+    /// Present in the selection but not in the baseline.
+    pub is_new: bool,
+    /// Earliest matching line in the scanned windows, ns since epoch.
+    pub first_seen: i64,
+    pub samples: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct LogPatternsResponse {
+    pub patterns: Vec<LogPattern>,
+    pub selection_count: u64,
+    pub baseline_count: u64,
+    /// More than `MAX_PATTERN_SHAPES` shapes matched; rare ones were skipped.
+    pub truncated: bool,
+}
+
+#[derive(Debug, serde::Deserialize, clickhouse::Row)]
+struct ShapeRow {
+    service_name: String,
+    shape: String,
+    sel_count: u64,
+    base_count: u64,
+    first_seen: i64,
+    samples: Vec<String>,
+}
+
+/// Mine log templates (Drain) over the bodies matching the filters, with
+/// counts in the selection vs the baseline window like `bubbleup`.
+///
+/// ClickHouse first collapses bodies to (service, body with digit runs
+/// masked) shapes, so the miner sees thousands of shapes rather than every
+/// line; the shapes are then clustered by the tenant's per-service miner
+/// (see `crate::log_patterns`).
+pub async fn log_patterns(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Json(req): Json<LogPatternsRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let start = std::time::Instant::now();
+    let tenant_id = &tenant.tenant_id;
+    if let Some(ref s) = req.search
+        && s.len() > 512
+    {
+        return Err((StatusCode::BAD_REQUEST, "search query too long (max 512 chars)".into()));
+    }
+    let limit = req.limit.unwrap_or(50).clamp(1, 500) as usize;
+
+    let window = |w: &TimeRange| {
+        let from = sanitize_datetime(&w.from);
+        let to = sanitize_datetime(&w.to);
+        format!("Timestamp >= parseDateTimeBestEffort('{from}') AND Timestamp <= parseDateTimeBestEffort('{to}')")
+    };
+    let sel_cond = window(&req.selection);
+    let base_cond = req.baseline.as_ref().map(window).unwrap_or_else(|| "0".to_string());
+    // The scan covers both windows; compare the bounds as times, since the two
+    // windows may be written in different formats or offsets.
+    let (earliest, latest) = match &req.baseline {
+        Some(b) => {
+            let at = |s: &str| {
+                crate::query_builder::parse_datetime(s).ok_or_else(|| {
+                    (StatusCode::BAD_REQUEST, format!("invalid time {s:?} (expected RFC 3339 or YYYY-MM-DD HH:MM:SS)"))
+                })
+            };
+            let earliest = if at(&b.from)? < at(&req.selection.from)? { &b.from } else { &req.selection.from };
+            let latest = if at(&b.to)? > at(&req.selection.to)? { &b.to } else { &req.selection.to };
+            (earliest.as_str(), latest.as_str())
+        }
+        None => (req.selection.from.as_str(), req.selection.to.as_str()),
+    };
+    // earliest..latest prunes granules; rows in the gap between the windows count
+    // toward neither side, so they must not reach first_seen or the shapes LIMIT.
+    let clauses = build_log_where(&req.filters, earliest, latest, req.search.as_deref(), tenant_id)
+        .with_where_extra(&format!("({sel_cond} OR {base_cond})"));
+
+    let shapes_sql = format!(
+        "SELECT ServiceName AS service_name, \
+            replaceRegexpAll(substringUTF8(Body, 1, 1024), '[0-9]+', '0') AS shape, \
+            countIf({sel_cond}) AS sel_count, \
+            countIf({base_cond}) AS base_count, \
+            toUnixTimestamp64Nano(minIf(Timestamp, {sel_cond} OR {base_cond})) AS first_seen, \
+            groupUniqArray(3)(substringUTF8(Body, 1, 1024)) AS samples \
+         FROM logs {} \
+         GROUP BY service_name, shape \
+         ORDER BY sel_count DESC, base_count DESC \
+         LIMIT {MAX_PATTERN_SHAPES}",
+        clauses.to_sql(),
+    );
+    let totals_sql = format!(
+        "SELECT countIf({sel_cond}) AS selection_count, countIf({base_cond}) AS baseline_count FROM logs {}",
+        clauses.to_sql(),
+    );
+
+    #[derive(Debug, serde::Deserialize, clickhouse::Row)]
+    struct TotalRow {
+        selection_count: u64,
+        baseline_count: u64,
+    }
+
+    let (shapes, totals) = tokio::join!(
+        crate::tenant_query(&state.ch, &shapes_sql, tenant_id).fetch_all::<ShapeRow>(),
+        crate::tenant_query(&state.ch, &totals_sql, tenant_id).fetch_one::<TotalRow>(),
+    );
+    let shapes = shapes.map_err(|e| {
+        tracing::error!(error = %e, signal = "logs", handler = "log_patterns", "shapes query failed");
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
+    })?;
+    let totals = totals.map_err(|e| {
+        tracing::error!(error = %e, signal = "logs", handler = "log_patterns", "totals query failed");
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
+    })?;
+    let truncated = shapes.len() as u64 >= MAX_PATTERN_SHAPES;
+
+    let mut by_service: std::collections::HashMap<String, Vec<ShapeRow>> = std::collections::HashMap::new();
+    for row in shapes {
+        by_service.entry(row.service_name.clone()).or_default().push(row);
+    }
+
+    let mut patterns = Vec::new();
+    for (service, rows) in by_service {
+        let miner = crate::log_patterns::miner(tenant_id, &service);
+        let mut drain = miner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut merged: std::collections::HashMap<u32, LogPattern> = std::collections::HashMap::new();
+        for row in rows {
+            let id = drain.add(&row.shape);
+            let p = merged.entry(id).or_insert_with(|| LogPattern {
+                id,
+                service_name: service.clone(),
+                template: String::new(),
+                selection_count: 0,
+                baseline_count: 0,
+                selection_pct: 0.0,
+                baseline_pct: 0.0,
+                lift: 0.0,
+                is_new: false,
+                first_seen: i64::MAX,
+                samples: Vec::new(),
+            });
+            p.selection_count += row.sel_count;
+            p.baseline_count += row.base_count;
+            p.first_seen = p.first_seen.min(row.first_seen);
+            for s in row.samples {
+                if p.samples.len() < 3 {
+                    p.samples.push(s);
+                }
+            }
+        }
+        for (id, mut p) in merged {
+            // Templates only widen, so read them after the whole batch is mined.
+            p.template = drain.template(id).map(|t| t.text()).unwrap_or_default();
+            patterns.push(p);
+        }
+    }
+
+    let (selection_count, baseline_count) = (totals.selection_count, totals.baseline_count);
+    for p in &mut patterns {
+        let sel_pct = if selection_count > 0 { (p.selection_count as f64 / selection_count as f64) * 100.0 } else { 0.0 };
+        let base_pct = if baseline_count > 0 { (p.baseline_count as f64 / baseline_count as f64) * 100.0 } else { 0.0 };
+        let lift = sel_pct / base_pct.max(0.01);
+        p.selection_pct = (sel_pct * 100.0).round() / 100.0;
+        p.baseline_pct = (base_pct * 100.0).round() / 100.0;
+        p.lift = (lift * 100.0).round() / 100.0;
+        p.is_new = req.baseline.is_some() && p.selection_count > 0 && p.baseline_count == 0;
+    }
+    // With a baseline, most over-represented first; otherwise most frequent.
+    if req.baseline.is_some() {
+        patterns.sort_by(|a, b| b.lift.total_cmp(&a.lift).then(b.selection_count.cmp(&a.selection_count)));
+    } else {
+        patterns.sort_by_key(|p| std::cmp::Reverse(p.selection_count));
+    }
+    patterns.truncate(limit);
+
+    tracing::info!(
+        signal = "logs",
+        tenant_id = %tenant_id,
+        query = "log_patterns",
+        patterns = patterns.len(),
+        truncated,
+        duration_ms = start.elapsed().as_millis() as u64,
+        "log patterns mined"
+    );
+
+    Ok(Json(LogPatternsResponse { patterns, selection_count, baseline_count, truncated }))
+}
+
+/// Resource attributes that identify a log stream, most specific first. The
+/// ones present on the anchor line (non-empty) must match on its neighbors;
+/// anything else (versions, SDK attributes, …) is ignored.
+const STREAM_KEYS: &[&str] = &[
+    "k8s.namespace.name",
+    "k8s.pod.name",
+    "k8s.container.name",
+    "container.id",
+    "host.name",
+    "service.instance.id",
+];
+
+const DEFAULT_CONTEXT_LINES: u32 = 20;
+const MAX_CONTEXT_LINES: u32 = 200;
+const DEFAULT_CONTEXT_WINDOW_SECS: i64 = 300;
+const MAX_CONTEXT_WINDOW_SECS: i64 = 3600;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LogContextRequest {
+    /// Anchor line timestamp, ns since epoch (the `Timestamp` of a `LogRecord`).
+    pub timestamp: i64,
+    pub service_name: String,
+    /// The anchor line's `ResourceAttributes`; see `STREAM_KEYS`.
+    #[serde(default)]
+    pub resource: std::collections::HashMap<String, String>,
+    /// Lines before / after the anchor (default 20, max 200).
+    pub before: Option<u32>,
+    pub after: Option<u32>,
+    /// How far either side of the anchor to look (default 300s, max 3600s).
+    pub window_secs: Option<i64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct LogContextResponse {
+    /// The attributes the stream was matched on.
+    pub stream: std::collections::BTreeMap<String, String>,
+    /// Oldest first, ending just before the anchor.
+    pub before: Vec<LogRecord>,
+    /// Lines at exactly the anchor timestamp (the anchor and any ties).
+    pub anchor: Vec<LogRecord>,
+    /// Oldest first, starting just after the anchor.
+    pub after: Vec<LogRecord>,
+}
+
+/// The stream identity of an anchor line, as (attribute, value) pairs and
+/// the matching SQL conditions.
+fn log_stream_conditions(
+    service_name: &str,
+    resource: &std::collections::HashMap<String, String>,
+) -> (std::collections::BTreeMap<String, String>, Vec<String>) {
+    let mut stream = std::collections::BTreeMap::new();
+    let mut conditions = vec![format!("ServiceName = {}", format_value(&serde_json::json!(service_name)))];
+    for key in STREAM_KEYS {
+        if let Some(value) = resource.get(*key).filter(|v| !v.is_empty()) {
+            let col = resolve_log_field(&format!("resource.{key}"));
+            conditions.push(format!("{col} = {}", format_value(&serde_json::json!(value))));
+            stream.insert(key.to_string(), value.clone());
+        }
+    }
+    (stream, conditions)
+}
+
+/// Neighbors of one log line from the same stream (service + the pod /
+/// container / host attributes in `STREAM_KEYS`).
+///
+/// Each side is its own query ordered on the primary key's time columns with
+/// a LIMIT, so ClickHouse reads in key order from the anchor outwards and
+/// stops early; the `window_secs` bound caps the scan when the stream is
+/// sparse.
+pub async fn log_context(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Json(req): Json<LogContextRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let tenant_id = &tenant.tenant_id;
+    if req.service_name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "service_name is required".into()));
+    }
+    let before = req.before.unwrap_or(DEFAULT_CONTEXT_LINES).min(MAX_CONTEXT_LINES);
+    let after = req.after.unwrap_or(DEFAULT_CONTEXT_LINES).min(MAX_CONTEXT_LINES);
+    let window = req.window_secs.unwrap_or(DEFAULT_CONTEXT_WINDOW_SECS).clamp(1, MAX_CONTEXT_WINDOW_SECS);
+
+    let ts = req.timestamp;
+    let secs = ts.div_euclid(1_000_000_000);
+    let (lo, hi) = (secs - window, secs + window + 1);
+    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
+    let prewhere = |from: i64, to: i64| {
+        format!(
+            "tenant_id = '{escaped_tenant}' \
+             AND TimestampDate >= toDate(toDateTime({from})) AND TimestampDate <= toDate(toDateTime({to})) \
+             AND TimestampTime >= toDateTime({from}) AND TimestampTime <= toDateTime({to})"
+        )
+    };
+    let (stream, conditions) = log_stream_conditions(&req.service_name, &req.resource);
+    let stream_where = conditions.join(" AND ");
+    let anchor_ts = format!("fromUnixTimestamp64Nano(toInt64({ts}))");
+    let select_cols = "Timestamp, TraceId, SpanId, SeverityText, SeverityNumber, \
+         ServiceName, Body, ResourceAttributes, ScopeName, LogAttributes";
+
+    let before_sql = format!(
+        "SELECT {select_cols} FROM logs PREWHERE {} WHERE {stream_where} AND Timestamp < {anchor_ts} \
+         ORDER BY TimestampDate DESC, TimestampTime DESC, Timestamp DESC LIMIT {before}",
+        prewhere(lo, secs),
+    );
+    let anchor_sql = format!(
+        "SELECT {select_cols} FROM logs PREWHERE {} WHERE {stream_where} AND Timestamp = {anchor_ts} \
+         LIMIT {MAX_CONTEXT_LINES}",
+        prewhere(secs, secs),
+    );
+    let after_sql = format!(
+        "SELECT {select_cols} FROM logs PREWHERE {} WHERE {stream_where} AND Timestamp > {anchor_ts} \
+         ORDER BY TimestampDate ASC, TimestampTime ASC, Timestamp ASC LIMIT {after}",
+        prewhere(secs, hi),
+    );
+
+    let (before_rows, anchor_rows, after_rows) = tokio::join!(
+        crate::tenant_query(&state.ch, &before_sql, tenant_id).fetch_all::<LogRecord>(),
+        crate::tenant_query(&state.ch, &anchor_sql, tenant_id).fetch_all::<LogRecord>(),
+        crate::tenant_query(&state.ch, &after_sql, tenant_id).fetch_all::<LogRecord>(),
+    );
+    let failed = |e: clickhouse::error::Error| {
+        tracing::error!(error = %e, signal = "logs", handler = "log_context", "query failed");
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
+    };
+    let mut before_rows = before_rows.map_err(failed)?;
+    before_rows.reverse();
+
+    Ok(Json(LogContextResponse {
+        stream,
+        before: before_rows,
+        anchor: anchor_rows.map_err(failed)?,
+        after: after_rows.map_err(failed)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_identity_uses_only_stream_keys() {
+        let resource = std::collections::HashMap::from([
+            ("k8s.pod.name".to_string(), "api-7d9f-x2".to_string()),
+            ("k8s.container.name".to_string(), String::new()),
+            ("service.version".to_string(), "1.4.2".to_string()),
+        ]);
+        let (stream, conditions) = log_stream_conditions("api", &resource);
+        assert_eq!(stream.len(), 1);
+        assert_eq!(conditions, vec!["ServiceName = 'api'".to_string(), "mat_k8s_pod = 'api-7d9f-x2'".to_string()]);
+    }
+}
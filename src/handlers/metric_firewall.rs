@@ -18,7 +18,7 @@ pub struct FirewallRuleInput {
     pub name: String,
     #[serde(default = "default_true")]
     pub enabled: bool,
-    /// "allow" | "block" | "drop_label"
+    /// "allow" | "block" | "drop_label" | "replace" | "labelmap" | "labelkeep" | "hashmod"
     pub action: String,
     #[serde(default)]
     pub metric_pattern: String,
@@ -34,9 +34,28 @@ pub struct FirewallRuleInput {
     pub drop_label_pattern: String,
     #[serde(default)]
     pub drop_label_regex: bool,
+    /// Relabel source labels, comma-separated (`__name__` = metric name).
+    #[serde(default)]
+    pub source_labels: String,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    /// Relabel regex; fully anchored like Prometheus.
+    #[serde(default = "default_regex")]
+    pub regex: String,
+    #[serde(default)]
+    pub target_label: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+    #[serde(default)]
+    pub modulus: u64,
 }
 
 fn default_true() -> bool { true }
+fn default_separator() -> String { ";".into() }
+fn default_regex() -> String { "(.*)".into() }
+fn default_replacement() -> String { "$1".into() }
+
+const ACTIONS: &[&str] = &["allow", "block", "drop_label", "replace", "labelmap", "labelkeep", "hashmod"];
 
 fn b(v: bool) -> u8 { if v { 1 } else { 0 } }
 
@@ -45,8 +64,8 @@ fn validate(input: &FirewallRuleInput, id: String, created_at: String) -> Result
     if input.name.trim().is_empty() {
         return Err((StatusCode::BAD_REQUEST, "name is required".into()));
     }
-    if input.action != "allow" && input.action != "block" && input.action != "drop_label" {
-        return Err((StatusCode::BAD_REQUEST, "action must be 'allow', 'block' or 'drop_label'".into()));
+    if !ACTIONS.contains(&input.action.as_str()) {
+        return Err((StatusCode::BAD_REQUEST, format!("action must be one of: {}", ACTIONS.join(", "))));
     }
     // Validate any regexes so the user gets immediate feedback.
     let check = |pat: &str, is_re: bool, label: &str| -> Result<(), (StatusCode, String)> {
@@ -59,6 +78,17 @@ fn validate(input: &FirewallRuleInput, id: String, created_at: String) -> Result
     check(&input.match_label_value, input.match_label_value_regex, "label value")?;
     check(&input.drop_label_pattern, input.drop_label_regex, "drop label")?;
 
+    if matches!(input.action.as_str(), "replace" | "labelmap" | "labelkeep") {
+        regex::Regex::new(&format!("^(?:{})$", input.regex))
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid relabel regex: {e}")))?;
+    }
+    if matches!(input.action.as_str(), "replace" | "hashmod") && input.target_label.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, format!("{} rules require a target label", input.action)));
+    }
+    if input.action == "hashmod" && input.modulus == 0 {
+        return Err((StatusCode::BAD_REQUEST, "hashmod rules require a non-zero modulus".into()));
+    }
+
     if input.action == "drop_label" && input.drop_label_pattern.trim().is_empty() {
         return Err((StatusCode::BAD_REQUEST, "drop_label rules require a drop label pattern".into()));
     }
@@ -80,6 +110,12 @@ fn validate(input: &FirewallRuleInput, id: String, created_at: String) -> Result
         match_label_value_regex: b(input.match_label_value_regex),
         drop_label_pattern: input.drop_label_pattern.clone(),
         drop_label_regex: b(input.drop_label_regex),
+        source_labels: input.source_labels.split(',').map(str::trim).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(","),
+        separator: input.separator.clone(),
+        regex: input.regex.clone(),
+        target_label: input.target_label.trim().to_string(),
+        replacement: input.replacement.clone(),
+        modulus: input.modulus,
         created_at,
     })
 }
@@ -93,8 +129,8 @@ fn validate(input: &FirewallRuleInput, id: String, created_at: String) -> Result
 fn forbid_block_everything(rules_after: &[MetricFirewallRule]) -> Result<(), (StatusCode, String)> {
     let catch_all_block = rules_after.iter().any(|r| {
         r.enabled == 1
-            && r.action != "drop_label"
             && r.action != "allow"
+            && !crate::metric_firewall::is_rewrite_action(&r.action)
             && r.metric_pattern.is_empty()
             && r.match_label_key.is_empty()
     });
@@ -15,6 +15,7 @@ use crate::AppState;
 use crate::TenantContext;
 use crate::models::metrics::*;
 use crate::promql;
+use crate::promql::sql::NameTable;
 
 /// Short-TTL cache for Prometheus metadata endpoints (/labels, /label/{name}/values).
 /// Grafana variable refresh hammers these with the same expressions; each miss costs
@@ -87,7 +88,7 @@ async fn prom_query_inner(
 
     let series = promql::evaluate_instant_query(&state.ch, &params.query, eval_time, 300.0, tenant_id)
         .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("PromQL error: {e}")))?;
+        .map_err(|e| (crate::query_limits::promql_error_status(&e), format!("PromQL error: {e}")))?;
 
     // Return the latest value from each series
     let result: Vec<VectorResult> = series
@@ -139,6 +140,9 @@ pub struct RangeQueryParams {
     pub start: String,
     pub end: String,
     pub step: Option<String>,
+    /// Serve range functions from the metric rollups where the step grid allows;
+    /// see `promql::rollup` for the error bounds.
+    pub approximate: Option<bool>,
 }
 
 pub async fn prom_query_range(
@@ -171,9 +175,50 @@ async fn prom_query_range_inner(
         .and_then(|s| parse_step(s).ok())
         .unwrap_or(15.0);
 
-    let series = promql::evaluate_range_query(&state.ch, &params.query, start, end, step, tenant_id)
-        .await
-        .map_err(|e| (StatusCode::BAD_REQUEST, format!("PromQL error: {e}")))?;
+    // Whole-second ranges aligned to the step go through the result cache:
+    // every chunk then starts on a step, so chunked evaluation lines up with
+    // evaluating the whole range at once. Approximate queries bypass it: whether
+    // a rollup served them is only known per evaluation, and rollup reads are
+    // cheap to repeat anyway. So do queries using `@` (a chunk would resolve
+    // `@ start()` / `@ end()` against its own bounds) or a negative offset (a
+    // chunk would read past its end, so an old chunk could depend on new data).
+    let aligned = [start, end, step].iter().all(|v| v.fract() == 0.0)
+        && step >= 1.0
+        && start % step == 0.0
+        && !promql::eval::uses_at_or_negative_offset(&params.query);
+    let (series, rollup_used) = match aligned.then(|| crate::query_cache::chunk_secs(step as i64)).flatten() {
+        _ if params.approximate.unwrap_or(false) => {
+            promql::rollup::approximate(promql::evaluate_range_query(
+                &state.ch,
+                &params.query,
+                start,
+                end,
+                step,
+                tenant_id,
+            ))
+            .await
+        }
+        Some(chunk) => {
+            let naming = crate::metric_naming::for_tenant(tenant_id);
+            let key = format!("promql:{step}:{}:{}", naming.as_str(), params.query);
+            let series = state
+                .query_cache
+                .run(tenant_id, &key, start as i64, end as i64, chunk, |a, z| {
+                    let (ch, query) = (&state.ch, params.query.as_str());
+                    async move {
+                        promql::evaluate_range_query(ch, query, a as f64, z as f64, step, tenant_id)
+                            .await
+                            .map(crate::query_cache::Matrix)
+                    }
+                })
+                .await
+                .map(|m| m.0);
+            (series, false)
+        }
+        None => (promql::evaluate_range_query(&state.ch, &params.query, start, end, step, tenant_id).await, false),
+    };
+    let series =
+        series.map_err(|e| (crate::query_limits::promql_error_status(&e), format!("PromQL error: {e}")))?;
 
     let result: Vec<MatrixResult> = series
         .into_iter()
@@ -210,6 +255,7 @@ async fn prom_query_range_inner(
         data: MatrixData {
             result_type: "matrix",
             result,
+            rollup_used,
         },
     }))
 }
@@ -280,24 +326,23 @@ async fn prom_series_inner(
             format!("TimeUnix >= toDateTime64({}, 9)", start_secs as i64),
             format!("TimeUnix <= toDateTime64({}, 9)", end_secs as i64),
         ];
-        if let Some(name) = &vs.name {
-            if !name.is_empty() {
-                where_parts.push(format!("MetricName = '{}'", crate::query_builder::escape_string_literal(&name)));
-            }
-        }
+        let naming = crate::metric_naming::for_tenant(tenant_id);
         // Add matchers (skip __name__ since we handle it via vs.name)
         let non_name_matchers: Vec<_> = vs.matchers.matchers.iter()
             .filter(|m| m.name != "__name__")
             .cloned()
             .collect();
-        where_parts.extend(promql::matchers_to_sql(&non_name_matchers));
-
-        let where_clause = where_parts.join(" AND ");
+        where_parts.extend(promql::matchers_to_sql(&non_name_matchers, naming, NameTable::Gauge));
 
         // Query gauge and sum tables concurrently — one of them is almost always
         // empty for a given metric, so serializing the two round-trips just added
-        // latency.
-        let make_sql = |table: &str| {
+        // latency. The name condition depends on the table in prometheus mode.
+        let make_sql = |table: &str, names: NameTable| {
+            let mut parts = where_parts.clone();
+            if let Some(name) = vs.name.as_deref().filter(|n| !n.is_empty()) {
+                parts.push(promql::sql::metric_name_sql(name, naming, names));
+            }
+            let where_clause = parts.join(" AND ");
             format!(
                 "SELECT DISTINCT MetricName, ServiceName, Attributes \
                  FROM {table} \
@@ -305,7 +350,7 @@ async fn prom_series_inner(
                  LIMIT 1000"
             )
         };
-        let (gauge_sql, sum_sql) = (make_sql("metrics_gauge"), make_sql("metrics_sum"));
+        let (gauge_sql, sum_sql) = (make_sql("metrics_gauge", NameTable::Gauge), make_sql("metrics_sum", NameTable::Sum));
         let (gauge_rows, sum_rows) = tokio::join!(
             crate::tenant_query(&state.ch, &gauge_sql, tenant_id).fetch_all::<SeriesRow>(),
             crate::tenant_query(&state.ch, &sum_sql, tenant_id).fetch_all::<SeriesRow>(),
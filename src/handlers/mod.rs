@@ -1,6 +1,7 @@
 pub mod alerts;
 pub mod anomalies;
 pub mod bubbleup;
+pub mod cardinality_limits;
 pub mod argocd;
 pub mod auth;
 pub mod custom_skills;
@@ -14,9 +15,11 @@ pub mod metric_firewall;
 pub mod ingest_buffer;
 pub mod deploys;
 pub mod detection;
+pub mod explain;
 pub mod export;
 pub mod groups;
 pub mod health;
+pub mod jobs;
 pub mod funnels;
 pub mod logs;
 pub mod maintenance;
@@ -24,10 +27,13 @@ pub mod metrics;
 pub mod monitors;
 pub mod parse_query;
 pub mod parse_promql;
+pub mod pipe_query;
 pub mod query;
 pub mod remote_write;
 pub mod retention;
 pub mod rum;
+pub mod saved_queries;
+pub mod scheduled_exports;
 pub mod service_links;
 pub mod services;
 pub mod settings;
@@ -15,17 +15,19 @@
 ///   Missing / other         → attempt protobuf decode; 400 on failure
 use axum::{
     body::Bytes,
-    extract::State,
+    extract::{ConnectInfo, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Extension,
 };
 use prost::Message;
+use std::net::SocketAddr;
 use serde::Deserialize;
 
 use crate::AppState;
 use crate::TenantContext;
 use crate::ch_writer::{SpoolBatch, WriteError};
+use crate::metric_naming::{MetricKind, MetricNaming, prometheus_metric_name};
 use crate::models::ingest::{
     ExpHistogramRow, GaugeRow, HistogramRow, LogInsertRow, SummaryRow, SumRow, TraceInsertRow,
 };
@@ -161,6 +163,30 @@ fn kv_to_attrs(kvs: &[KeyValue]) -> Vec<(String, String)> {
         .collect()
 }
 
+/// Resource attributes, with missing `k8s.*` metadata filled in when
+/// Kubernetes enrichment is enabled (see `crate::k8s_enrich`).
+fn resource_attrs(
+    state: &AppState,
+    tenant_id: &str,
+    resource: Option<&opentelemetry_proto::tonic::resource::v1::Resource>,
+    peer: Option<std::net::IpAddr>,
+) -> Vec<(String, String)> {
+    let mut attrs = resource.map(|r| kv_to_attrs(&r.attributes)).unwrap_or_default();
+    if let Some(k8s) = &state.k8s {
+        k8s.enrich(tenant_id, &mut attrs, peer);
+    }
+    attrs
+}
+
+/// Naming class of an OTLP metric (for Prometheus-compatible names).
+fn metric_kind(data: &Option<MetricData>) -> MetricKind {
+    match data {
+        Some(MetricData::Gauge(_)) => MetricKind::Gauge,
+        Some(MetricData::Sum(s)) if s.is_monotonic => MetricKind::Counter,
+        _ => MetricKind::Other,
+    }
+}
+
 /// Extract the service.name from a Resource's attributes, defaulting to "".
 fn resource_service_name(resource: &opentelemetry_proto::tonic::resource::v1::Resource) -> String {
     resource
@@ -246,10 +272,12 @@ fn status_code_name(code: i32) -> &'static str {
 pub async fn ingest_otlp_traces(
     State(state): State<AppState>,
     Extension(tenant): Extension<TenantContext>,
+    connect: Option<Extension<ConnectInfo<SocketAddr>>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let tenant_id = &tenant.tenant_id;
+    let peer = connect.map(|Extension(ConnectInfo(addr))| addr.ip());
 
     let req: ExportTraceServiceRequest = decode_proto(&headers, body.clone()).await?;
 
@@ -264,7 +292,7 @@ pub async fn ingest_otlp_traces(
         // service.name/schema_url strings ONCE, behind Arc, then hand cheap Arc
         // clones to every span row instead of re-allocating per span.
         let resource_attributes: std::sync::Arc<Vec<(String, String)>> = std::sync::Arc::new(
-            resource.map(|r| kv_to_attrs(&r.attributes)).unwrap_or_default(),
+            resource_attrs(&state, &tenant_id, resource, peer),
         );
         let service_name: std::sync::Arc<str> = resource
             .map(|r| resource_service_name(r))
@@ -404,10 +432,12 @@ pub async fn ingest_otlp_traces(
 pub async fn ingest_otlp_logs(
     State(state): State<AppState>,
     Extension(tenant): Extension<TenantContext>,
+    connect: Option<Extension<ConnectInfo<SocketAddr>>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let tenant_id = &tenant.tenant_id;
+    let peer = connect.map(|Extension(ConnectInfo(addr))| addr.ip());
 
     let req: ExportLogsServiceRequest = decode_proto(&headers, body.clone()).await?;
 
@@ -425,7 +455,7 @@ pub async fn ingest_otlp_logs(
         // Per-resource shared data behind Arc (allocated once, cheaply cloned
         // into each log row).
         let resource_attributes: std::sync::Arc<Vec<(String, String)>> = std::sync::Arc::new(
-            resource.map(|r| kv_to_attrs(&r.attributes)).unwrap_or_default(),
+            resource_attrs(&state, &tenant_id, resource, peer),
         );
         let service_name = resource
             .map(|r| resource_service_name(r))
@@ -515,10 +545,12 @@ pub async fn ingest_otlp_logs(
 pub async fn ingest_otlp_metrics(
     State(state): State<AppState>,
     Extension(tenant): Extension<TenantContext>,
+    connect: Option<Extension<ConnectInfo<SocketAddr>>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let tenant_id = &tenant.tenant_id;
+    let peer = connect.map(|Extension(ConnectInfo(addr))| addr.ip());
 
     let req: ExportMetricsServiceRequest = decode_proto(&headers, body.clone()).await?;
 
@@ -534,10 +566,14 @@ pub async fn ingest_otlp_metrics(
     // place of the previous per-datapoint String/Vec clones.
     let tenant_id: std::sync::Arc<str> = tenant_id.as_str().into();
 
+    // Tenants in `prometheus` naming mode get Prometheus-compatible metric
+    // names and label keys (see `crate::metric_naming`).
+    let naming = crate::metric_naming::for_tenant(&tenant_id);
+
     for rm in &req.resource_metrics {
         let resource = rm.resource.as_ref();
         let resource_attributes: std::sync::Arc<Vec<(String, String)>> = std::sync::Arc::new(
-            resource.map(|r| kv_to_attrs(&r.attributes)).unwrap_or_default(),
+            naming.attrs(resource_attrs(&state, &tenant_id, resource, peer)),
         );
         let service_name: std::sync::Arc<str> = resource
             .map(|r| resource_service_name(r))
@@ -558,14 +594,19 @@ pub async fn ingest_otlp_metrics(
             let scope_schema_url: std::sync::Arc<str> = sm.schema_url.as_str().into();
 
             for metric in &sm.metrics {
-                let metric_name: std::sync::Arc<str> = metric.name.as_str().into();
+                let metric_name: std::sync::Arc<str> = match naming {
+                    MetricNaming::Otel => metric.name.as_str().into(),
+                    MetricNaming::Prometheus => {
+                        prometheus_metric_name(&metric.name, &metric.unit, metric_kind(&metric.data)).into()
+                    }
+                };
                 let metric_description: std::sync::Arc<str> = metric.description.as_str().into();
                 let metric_unit: std::sync::Arc<str> = metric.unit.as_str().into();
 
                 match &metric.data {
                     Some(MetricData::Gauge(g)) => {
                         for dp in &g.data_points {
-                            let attrs = kv_to_attrs(&dp.attributes);
+                            let attrs = naming.attrs(kv_to_attrs(&dp.attributes));
                             let ex = extract_exemplars(&dp.exemplars);
                             use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
                             let value = match &dp.value {
@@ -603,7 +644,7 @@ pub async fn ingest_otlp_metrics(
                         let agg_temp = s.aggregation_temporality;
                         let is_monotonic = s.is_monotonic;
                         for dp in &s.data_points {
-                            let attrs = kv_to_attrs(&dp.attributes);
+                            let attrs = naming.attrs(kv_to_attrs(&dp.attributes));
                             let ex = extract_exemplars(&dp.exemplars);
                             use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
                             let value = match &dp.value {
@@ -642,7 +683,7 @@ pub async fn ingest_otlp_metrics(
                     Some(MetricData::Histogram(h)) => {
                         let agg_temp = h.aggregation_temporality;
                         for dp in &h.data_points {
-                            let attrs = kv_to_attrs(&dp.attributes);
+                            let attrs = naming.attrs(kv_to_attrs(&dp.attributes));
                             let ex = extract_exemplars(&dp.exemplars);
                             histogram_rows.push(HistogramRow {
                                 tenant_id: tenant_id.clone(),
@@ -679,7 +720,7 @@ pub async fn ingest_otlp_metrics(
                     Some(MetricData::ExponentialHistogram(eh)) => {
                         let agg_temp = eh.aggregation_temporality;
                         for dp in &eh.data_points {
-                            let attrs = kv_to_attrs(&dp.attributes);
+                            let attrs = naming.attrs(kv_to_attrs(&dp.attributes));
                             let ex = extract_exemplars(&dp.exemplars);
                             let (pos_offset, pos_counts) = dp
                                 .positive
@@ -729,7 +770,7 @@ pub async fn ingest_otlp_metrics(
                     }
                     Some(MetricData::Summary(s)) => {
                         for dp in &s.data_points {
-                            let attrs = kv_to_attrs(&dp.attributes);
+                            let attrs = naming.attrs(kv_to_attrs(&dp.attributes));
                             let mut quantiles_vec = Vec::with_capacity(dp.quantile_values.len());
                             let mut values_vec = Vec::with_capacity(dp.quantile_values.len());
                             for qv in &dp.quantile_values {
@@ -941,7 +982,13 @@ pub async fn ingest_vector_logs(
         .into_iter()
         .map(|e| {
             let ts = if e.timestamp != 0 { e.timestamp } else { now_ns };
-            let resource_attributes = std::sync::Arc::new(json_obj_to_attrs(&e.resource_attributes));
+            let mut resource_attributes = json_obj_to_attrs(&e.resource_attributes);
+            // Vector is the sender, so only the pod uid / ip attributes can key
+            // the Kubernetes lookup — never the peer address.
+            if let Some(k8s) = &state.k8s {
+                k8s.enrich(tenant_id, &mut resource_attributes, None);
+            }
+            let resource_attributes = std::sync::Arc::new(resource_attributes);
             let log_attributes = json_obj_to_attrs(&e.log_attributes);
             LogInsertRow {
                 tenant_id: tenant_arc.clone(),
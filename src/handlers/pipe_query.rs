@@ -0,0 +1,80 @@
+use axum::{
+    Extension, Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+use crate::TenantContext;
+use crate::models::query::TimeRange;
+use crate::pipeql::{self, Signal};
+
+/// Same cap as the search box; the parser's nesting limit covers the rest.
+const MAX_QUERY_LEN: usize = 512;
+
+#[derive(Debug, Deserialize)]
+pub struct PipeQueryRequest {
+    pub query: String,
+    /// `spans` (default) or `logs`.
+    #[serde(default)]
+    pub signal: Option<String>,
+    pub time_range: TimeRange,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PipeQueryResponse {
+    pub columns: Vec<String>,
+    /// One object per row, keyed by column name.
+    pub rows: Vec<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// POST /api/v1/query/pipe
+///
+/// Runs a pipe query (`status:error | stats count() by service`) over spans
+/// or logs. Syntax and compile errors are 400 with
+/// `{"error", "start", "end"}` so the editor can underline the source.
+pub async fn pipe_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Json(req): Json<PipeQueryRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let tenant_id = &tenant.tenant_id;
+    if req.query.len() > MAX_QUERY_LEN {
+        return Err((StatusCode::BAD_REQUEST, format!("pipe query too long (max {MAX_QUERY_LEN} chars)")));
+    }
+    let signal = match req.signal.as_deref() {
+        None => Signal::Spans,
+        Some(s) => Signal::parse(s)
+            .ok_or((StatusCode::BAD_REQUEST, format!("unknown signal '{s}' (expected spans or logs)")))?,
+    };
+    let compiled = match pipeql::compile(&req.query, signal, &req.time_range.from, &req.time_range.to, tenant_id) {
+        Ok(c) => c,
+        Err(e) => {
+            let body = serde_json::json!({ "error": e.message, "start": e.start, "end": e.end });
+            return Ok((StatusCode::BAD_REQUEST, Json(body)).into_response());
+        }
+    };
+
+    // One JSON object per row keeps the column set dynamic.
+    let sql = format!("SELECT formatRowNoNewline('JSONEachRow', *) FROM ({})", compiled.sql);
+    let lines = crate::tenant_query(&state.ch, &sql, tenant_id)
+        .fetch_all::<String>()
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, handler = "pipe_query", "pipe query failed");
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
+        })?;
+
+    let mut rows = Vec::with_capacity(lines.len());
+    for line in &lines {
+        match serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line) {
+            Ok(row) => rows.push(row),
+            Err(e) => {
+                tracing::warn!(error = %e, handler = "pipe_query", "unparseable result row");
+            }
+        }
+    }
+    Ok(Json(PipeQueryResponse { columns: compiled.columns, rows }).into_response())
+}
@@ -1,20 +1,38 @@
 use axum::{
     Json,
-    extract::State,
+    extract::{Query, State, ws::{WebSocketUpgrade, rejection::WebSocketUpgradeRejection}},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Extension,
 };
 
 use crate::AppState;
 use crate::TenantContext;
+use crate::live_tail::{TailParams, TailSignal};
 use crate::models::query::{
     CountBucket, CountQueryRequest, CountRow, GroupedTimeseriesBucket, QueryRequest,
-    TimeseriesBucket, TimeseriesRequest,
+    TimeRange, TimeseriesBucket, TimeseriesRequest,
 };
 use crate::models::trace::WideEvent;
 use crate::query_builder::{resolve_field, build_where_clause_with_search};
 
+/// Live tail of spans as they are ingested: SSE, or WebSocket on upgrade.
+/// Takes the `QueryRequest` filters (JSON) and search as query params.
+pub async fn tail_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    Query(params): Query<TailParams>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, (StatusCode, String)> {
+    let filter = params.parse()?;
+    let sub = state
+        .writer
+        .tail
+        .subscribe(&tenant.tenant_id, TailSignal::Spans, filter)
+        .ok_or((StatusCode::TOO_MANY_REQUESTS, "too many live tails".to_string()))?;
+    Ok(crate::live_tail::respond(sub, ws))
+}
+
 /// Execute a structured query against spans.
 pub async fn execute_query(
     State(state): State<AppState>,
@@ -91,7 +109,7 @@ pub async fn execute_query(
         );
         let rows = rows_result.map_err(|e| {
             tracing::error!(error = %e, signal = "traces", handler = "execute_query", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
         let total = count_result.map(|r| r.count).unwrap_or(0);
         let next = rows.last().map(|r| crate::query_builder::KeysetCursor {
@@ -107,7 +125,7 @@ pub async fn execute_query(
         );
         let rows = rows_result.map_err(|e| {
             tracing::error!(error = %e, signal = "traces", handler = "execute_query", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
         let total = count_result.map(|r| r.count).unwrap_or(0);
         let next = rows.last().map(|r| crate::query_builder::KeysetCursor {
@@ -170,7 +188,30 @@ pub struct SpanExportRequest {
     pub query_text: Option<String>,
 }
 
-/// Export spans matching the current query as a CSV or JSON file.
+/// Span rows for an export (the download endpoint and scheduled exports), newest first.
+/// `end_exclusive` turns the range into `[from, to)` (see the logs `export_sql`).
+pub(crate) fn export_sql(
+    tenant_id: &str,
+    filters: &[crate::models::query::Filter],
+    time_range: &TimeRange,
+    end_exclusive: bool,
+    search: Option<&str>,
+    limit: u64,
+) -> String {
+    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
+    let mut clauses = build_where_clause_with_search(filters, &time_range.from, &time_range.to, search)
+        .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"));
+    if end_exclusive {
+        let to = crate::query_builder::sanitize_datetime(&time_range.to);
+        clauses.prewhere.push_str(&format!(" AND timestamp < parseDateTimeBestEffort('{to}')"));
+    }
+    format!(
+        "SELECT * FROM spans {} ORDER BY timestamp DESC LIMIT {limit}",
+        clauses.to_sql(),
+    )
+}
+
+/// Export spans matching the current query as a CSV, JSON, NDJSON, Arrow or Parquet file.
 /// Limit is clamped to the admin-configured `export_max_rows` (not the 1000 cap).
 pub async fn export_query(
     State(state): State<AppState>,
@@ -189,13 +230,7 @@ pub async fn export_query(
     let cap = export::read_export_max_rows(&state).await;
     let limit = export::effective_limit(req.limit, cap);
 
-    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
-    let clauses = build_where_clause_with_search(&req.filters, &req.time_range.from, &req.time_range.to, req.search.as_deref())
-        .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"));
-    let sql = format!(
-        "SELECT * FROM spans {} ORDER BY timestamp DESC LIMIT {limit}",
-        clauses.to_sql(),
-    );
+    let sql = export_sql(tenant_id, &req.filters, &req.time_range, false, req.search.as_deref(), limit);
 
     let unix = chrono::Utc::now().timestamp();
     match req.format {
@@ -207,61 +242,70 @@ pub async fn export_query(
                 "spans", &req.time_range.from, &req.time_range.to,
                 req.search.as_deref(), req.query_text.as_deref(),
             );
-            prelude.push_str("Timestamp,Service,Method,Resource,Status,DurationMs,TraceId\n");
+            prelude.push_str(<WideEvent as export::ExportRow>::CSV_HEADER);
 
             let cursor = crate::tenant_query(&state.ch, &sql, tenant_id)
                 .fetch::<WideEvent>()
                 .map_err(|e| {
                     tracing::error!(error = %e, signal = "traces", handler = "export_query", "export stream init failed");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "export query failed".into())
+                    crate::query_limits::query_error(tenant_id, &e, "export query failed")
                 })?;
 
-            let fmt_row = |r: &WideEvent| -> String {
-                let duration_ms = format!("{:.3}", r.duration_ns as f64 / 1_000_000.0);
-                let status = if r.http_status_code > 0 {
-                    r.http_status_code.to_string()
-                } else {
-                    r.status.clone()
-                };
-                format!(
-                    "{},{},{},{},{},{},{}\n",
-                    export::csv_field(&export::ts_rfc3339(r.timestamp)),
-                    export::csv_field(&r.service_name),
-                    export::csv_field(&r.http_method),
-                    export::csv_field(&r.http_path),
-                    export::csv_field(&status),
-                    export::csv_field(&duration_ms),
-                    export::csv_field(&r.trace_id),
-                )
-            };
+            let fmt_row = |r: &WideEvent| export::ExportRow::csv_row(r);
             Ok(export::stream_csv_response(cursor, prelude, fmt_row, &format!("rush-spans-{unix}.csv")))
         }
-        export::ExportFormat::Json => {
-            // JSON export stays buffered (to_string_pretty envelope can't stream
-            // byte-identically). Row count is capped by LIMIT. See report.
-            let rows = crate::tenant_query(&state.ch, &sql, tenant_id)
-                .fetch_all::<WideEvent>().await
+        export::ExportFormat::Json
+        | export::ExportFormat::Ndjson
+        | export::ExportFormat::Arrow
+        | export::ExportFormat::Parquet => {
+            let cursor = crate::tenant_query(&state.ch, &sql, tenant_id)
+                .fetch::<WideEvent>()
                 .map_err(|e| {
-                    tracing::error!(error = %e, signal = "traces", handler = "export_query", "export query failed");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "export query failed".into())
+                    tracing::error!(error = %e, signal = "traces", handler = "export_query", "export stream init failed");
+                    crate::query_limits::query_error(tenant_id, &e, "export query failed")
                 })?;
-            let body = serde_json::json!({
-                "query": {
-                    "signal": "spans",
-                    "time_range": { "from": req.time_range.from, "to": req.time_range.to },
-                    "search": req.search,
-                    "query_text": req.query_text,
-                },
-                "exported_at": chrono::Utc::now().to_rfc3339(),
-                "count": rows.len(),
-                "rows": rows,
-            });
-            let s = serde_json::to_string_pretty(&body).unwrap_or_else(|_| "{}".into());
-            Ok(export::file_response(s, "application/json; charset=utf-8", &format!("rush-spans-{unix}.json")))
+            Ok(export::stream_rows_response(cursor, req.format, &format!("rush-spans-{unix}")))
         }
     }
 }
 
+/// Run a time-bucketed query through the result cache (see `query_cache`).
+/// `fetch(from, to)` reads one time range; `key` is the query's SQL without
+/// a time range. Ranges that don't parse are read in one go.
+async fn cached_buckets<R, F, Fut>(
+    state: &AppState,
+    tenant_id: &str,
+    time_range: &TimeRange,
+    interval: &str,
+    key: &str,
+    fetch: F,
+) -> Result<Vec<R>, (StatusCode, String)>
+where
+    R: crate::query_cache::Bucketed + Clone + serde::Serialize + serde::de::DeserializeOwned,
+    F: Fn(String, String) -> Fut,
+    Fut: Future<Output = Result<Vec<R>, (StatusCode, String)>>,
+{
+    use crate::query_cache::{chunk_secs, format_time, parse_range};
+    let chunk = crate::query_builder::bucket_interval_secs(interval).and_then(|s| chunk_secs(s as i64));
+    let (Some((from, to)), Some(chunk)) = (parse_range(&time_range.from, &time_range.to), chunk) else {
+        return fetch(time_range.from.clone(), time_range.to.clone()).await;
+    };
+    state
+        .query_cache
+        .run(tenant_id, key, from, to, chunk, |a, b| {
+            // The ends of the range keep the caller's own timestamps.
+            let a = if a == from { time_range.from.clone() } else { format_time(a) };
+            let b = if b == to { time_range.to.clone() } else { format_time(b) };
+            fetch(a, b)
+        })
+        .await
+}
+
+/// GET /api/v1/query/cache — range-query result cache size and hit ratio.
+pub async fn cache_stats(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.query_cache.stats())
+}
+
 /// Count events bucketed by time interval.
 pub async fn count_query(
     State(state): State<AppState>,
@@ -270,8 +314,6 @@ pub async fn count_query(
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let tenant_id = &tenant.tenant_id;
     let escaped_tenant = crate::query_builder::escape_string_literal(&tenant_id);
-    let clauses = build_where_clause_with_search(&req.filters, &req.time_range.from, &req.time_range.to, req.search.as_deref())
-        .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"));
 
     // The interval is client-supplied: clamp so (range / interval) <= 2000 buckets
     // (a 1s interval over 30d would otherwise be ~2.6M GROUP BY buckets).
@@ -289,25 +331,33 @@ pub async fn count_query(
         _ => "toStartOfMinute(timestamp)",
     };
 
-    let sql = format!(
-        "SELECT toString({interval_fn}) as bucket, count() as count, \
-         countIf(http_status_code >= 500 OR status = 'ERROR') as error_count \
-         FROM spans {} \
-         GROUP BY bucket \
-         ORDER BY bucket ASC",
-        clauses.to_sql(),
-    );
+    let make_sql = |from: &str, to: &str| {
+        let clauses = build_where_clause_with_search(&req.filters, from, to, req.search.as_deref())
+            .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"));
+        format!(
+            "SELECT toString({interval_fn}) as bucket, count() as count, \
+             countIf(http_status_code >= 500 OR status = 'ERROR') as error_count \
+             FROM spans {} \
+             GROUP BY bucket \
+             ORDER BY bucket ASC",
+            clauses.to_sql(),
+        )
+    };
 
-    let buckets = crate::tenant_query(&state.ch, &sql, tenant_id)
-        .fetch_all::<CountBucket>()
-        .await
-        .map_err(|e| {
-            tracing::error!(error = %e, signal = "traces", handler = "count_query", "query failed");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "query failed".into(),
-            )
-        })?;
+    let ch = &state.ch;
+    let buckets = cached_buckets(&state, tenant_id, &req.time_range, interval, &make_sql("", ""), |from, to| {
+        let sql = make_sql(&from, &to);
+        async move {
+            crate::tenant_query(ch, &sql, tenant_id)
+                .fetch_all::<CountBucket>()
+                .await
+                .map_err(|e| {
+                    tracing::error!(error = %e, signal = "traces", handler = "count_query", "query failed");
+                    crate::query_limits::query_error(tenant_id, &e, "query failed")
+                })
+        }
+    })
+    .await?;
 
     Ok(Json(buckets))
 }
@@ -371,10 +421,7 @@ pub async fn group_query(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "traces", handler = "group_query", "query failed");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "query failed".into(),
-            )
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     let json_rows: Vec<serde_json::Value> = rows
@@ -398,8 +445,12 @@ pub async fn timeseries_query(
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     let tenant_id = &tenant.tenant_id;
     let escaped_tenant = crate::query_builder::escape_string_literal(&tenant_id);
-    let clauses = build_where_clause_with_search(&req.filters, &req.time_range.from, &req.time_range.to, req.search.as_deref())
-        .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"));
+    let where_sql = |from: &str, to: &str| {
+        build_where_clause_with_search(&req.filters, from, to, req.search.as_deref())
+            .with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'"))
+            .to_sql()
+    };
+    let ch = &state.ch;
 
     // The interval is client-supplied: clamp so (range / interval) <= 2000 buckets
     // (a 1s interval over 30d would otherwise be ~2.6M GROUP BY buckets). Mirrors count_query.
@@ -419,29 +470,37 @@ pub async fn timeseries_query(
 
     if let Some(ref group_field) = req.group_by {
         let col = resolve_field(group_field);
-        let sql = format!(
-            "SELECT \
-                toString({interval_fn}) as bucket, \
-                toString({col}) as group_key, \
-                count() as count, \
-                countIf(http_status_code >= 500) as error_count, \
-                avg(duration_ns) / 1000000.0 as avg_duration_ms, \
-                quantile(0.5)(duration_ns) / 1000000.0 as p50_ms, \
-                quantile(0.95)(duration_ns) / 1000000.0 as p95_ms, \
-                quantile(0.99)(duration_ns) / 1000000.0 as p99_ms \
-             FROM spans {} \
-             GROUP BY bucket, group_key \
-             ORDER BY bucket ASC, count DESC",
-            clauses.to_sql(),
-        );
-
-        let buckets = crate::tenant_query(&state.ch, &sql, tenant_id)
-            .fetch_all::<GroupedTimeseriesBucket>()
-            .await
-            .map_err(|e| {
-                tracing::error!(error = %e, signal = "traces", handler = "timeseries_query", "query failed");
-                (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
-            })?;
+        let make_sql = |from: &str, to: &str| {
+            format!(
+                "SELECT \
+                    toString({interval_fn}) as bucket, \
+                    toString({col}) as group_key, \
+                    count() as count, \
+                    countIf(http_status_code >= 500) as error_count, \
+                    avg(duration_ns) / 1000000.0 as avg_duration_ms, \
+                    quantile(0.5)(duration_ns) / 1000000.0 as p50_ms, \
+                    quantile(0.95)(duration_ns) / 1000000.0 as p95_ms, \
+                    quantile(0.99)(duration_ns) / 1000000.0 as p99_ms \
+                 FROM spans {} \
+                 GROUP BY bucket, group_key \
+                 ORDER BY bucket ASC, count DESC",
+                where_sql(from, to),
+            )
+        };
+
+        let buckets = cached_buckets(&state, tenant_id, &req.time_range, interval, &make_sql("", ""), |from, to| {
+            let sql = make_sql(&from, &to);
+            async move {
+                crate::tenant_query(ch, &sql, tenant_id)
+                    .fetch_all::<GroupedTimeseriesBucket>()
+                    .await
+                    .map_err(|e| {
+                        tracing::error!(error = %e, signal = "traces", handler = "timeseries_query", "query failed");
+                        crate::query_limits::query_error(tenant_id, &e, "query failed")
+                    })
+            }
+        })
+        .await?;
 
         // Only track usage if results returned
         if !buckets.is_empty() {
@@ -454,28 +513,36 @@ pub async fn timeseries_query(
 
         Ok(Json(serde_json::json!({ "buckets": buckets, "grouped": true })))
     } else {
-        let sql = format!(
-            "SELECT \
-                toString({interval_fn}) as bucket, \
-                count() as count, \
-                countIf(http_status_code >= 500) as error_count, \
-                avg(duration_ns) / 1000000.0 as avg_duration_ms, \
-                quantile(0.5)(duration_ns) / 1000000.0 as p50_ms, \
-                quantile(0.95)(duration_ns) / 1000000.0 as p95_ms, \
-                quantile(0.99)(duration_ns) / 1000000.0 as p99_ms \
-             FROM spans {} \
-             GROUP BY bucket \
-             ORDER BY bucket ASC",
-            clauses.to_sql(),
-        );
-
-        let buckets = crate::tenant_query(&state.ch, &sql, tenant_id)
-            .fetch_all::<TimeseriesBucket>()
-            .await
-            .map_err(|e| {
-                tracing::error!(error = %e, signal = "traces", handler = "timeseries_query", "query failed");
-                (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
-            })?;
+        let make_sql = |from: &str, to: &str| {
+            format!(
+                "SELECT \
+                    toString({interval_fn}) as bucket, \
+                    count() as count, \
+                    countIf(http_status_code >= 500) as error_count, \
+                    avg(duration_ns) / 1000000.0 as avg_duration_ms, \
+                    quantile(0.5)(duration_ns) / 1000000.0 as p50_ms, \
+                    quantile(0.95)(duration_ns) / 1000000.0 as p95_ms, \
+                    quantile(0.99)(duration_ns) / 1000000.0 as p99_ms \
+                 FROM spans {} \
+                 GROUP BY bucket \
+                 ORDER BY bucket ASC",
+                where_sql(from, to),
+            )
+        };
+
+        let buckets = cached_buckets(&state, tenant_id, &req.time_range, interval, &make_sql("", ""), |from, to| {
+            let sql = make_sql(&from, &to);
+            async move {
+                crate::tenant_query(ch, &sql, tenant_id)
+                    .fetch_all::<TimeseriesBucket>()
+                    .await
+                    .map_err(|e| {
+                        tracing::error!(error = %e, signal = "traces", handler = "timeseries_query", "query failed");
+                        crate::query_limits::query_error(tenant_id, &e, "query failed")
+                    })
+            }
+        })
+        .await?;
 
         // Only track usage if results returned
         if !buckets.is_empty() {
@@ -383,7 +383,7 @@ pub async fn list_apps(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "list_apps", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(serde_json::json!({ "apps": rows })))
@@ -417,7 +417,7 @@ pub async fn query_events(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "query_events", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Serialize the typed rows directly — RumRecord's serde rename attributes already
@@ -466,7 +466,7 @@ pub async fn vitals(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "vitals", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(serde_json::json!({ "vitals": rows })))
@@ -502,7 +502,7 @@ pub async fn pages(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "pages", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(serde_json::json!({ "pages": rows })))
@@ -545,7 +545,7 @@ pub async fn errors(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "errors", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(serde_json::json!({ "errors": rows })))
@@ -584,7 +584,7 @@ pub async fn sessions(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "sessions", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(serde_json::json!({ "sessions": rows })))
@@ -617,7 +617,7 @@ pub async fn session_detail(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "session_detail", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Serialize typed rows directly (see query_events) — same JSON shape, no
@@ -658,7 +658,7 @@ pub async fn list_replay_sessions(
     let rows = crate::tenant_query(&state.ch, &sql, tenant_id)
         .fetch_all::<ReplaySessionRow>()
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into()))?;
+        .map_err(|e| crate::query_limits::query_error(tenant_id, &e, "query failed"))?;
     let ids: Vec<String> = rows.into_iter().map(|r| r.session_id).collect();
     Ok(Json(serde_json::json!({ "session_ids": ids })))
 }
@@ -723,7 +723,7 @@ pub async fn get_replay(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "rum", handler = "get_replay", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Concatenate all events across chunks in order
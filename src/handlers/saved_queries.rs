@@ -0,0 +1,375 @@
+use axum::{
+    Json,
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::AppState;
+use crate::TenantContext;
+use crate::handlers::dashboards::resolve_caller;
+use crate::handlers::users::{require_auth, require_write};
+use crate::models::query::{Filter, QueryRequest, TimeRange};
+use crate::models::saved_query::*;
+
+/// Longest `default_range` a saved query may carry.
+const MAX_DEFAULT_RANGE_SECS: f64 = 30.0 * 86400.0;
+/// Most columns a saved query may keep.
+const MAX_COLUMNS: usize = 64;
+
+/// Row fields of `/api/v1/query` (`WideEvent`) — the columns a saved span
+/// list may keep.
+const SPAN_COLUMNS: &[&str] = &[
+    "tenant_id", "timestamp", "trace_id", "span_id", "parent_span_id", "service_name", "span_name", "kind",
+    "status", "duration_ns", "http_method", "http_path", "http_status_code", "attributes", "event_names",
+    "event_timestamps", "event_attributes", "link_trace_ids", "link_span_ids",
+];
+/// The `SlimEvent` subset; a column set inside it runs the slim projection.
+const SLIM_SPAN_COLUMNS: &[&str] = &[
+    "timestamp", "service_name", "span_name", "http_method", "http_path", "http_status_code", "duration_ns",
+    "status", "trace_id", "span_id",
+];
+/// Row fields of `/api/v1/logs` (`LogRecord`).
+const LOG_COLUMNS: &[&str] = &[
+    "Timestamp", "TraceId", "SpanId", "SeverityText", "SeverityNumber", "ServiceName", "Body",
+    "ResourceAttributes", "ScopeName", "LogAttributes",
+];
+
+/// Check a create/update body and turn it into the stored shape (ids and
+/// timestamps are filled in by the caller / ConfigDb).
+fn validate(req: SavedQueryRequest) -> Result<SavedQuery, (StatusCode, String)> {
+    if req.name.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "name must not be empty".to_string()));
+    }
+    if req.name.len() > 255 {
+        return Err((StatusCode::BAD_REQUEST, "name must not exceed 255 characters".to_string()));
+    }
+    if req.description.len() > 1024 {
+        return Err((StatusCode::BAD_REQUEST, "description must not exceed 1024 characters".to_string()));
+    }
+    if req.search.len() > 512 {
+        return Err((StatusCode::BAD_REQUEST, "search query too long (max 512 chars)".to_string()));
+    }
+    if !matches!(req.visibility.as_str(), "private" | "tenant") {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid visibility: {}", req.visibility)));
+    }
+    if !matches!(req.signal.as_str(), "spans" | "logs") {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid signal: {} (expected spans or logs)", req.signal)));
+    }
+    serde_json::from_value::<Vec<Filter>>(req.filters.clone())
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid filters: {e}")))?;
+    validate_columns(&req.signal, &req.group_by, &req.columns)?;
+    range_secs(&req.default_range)?;
+    Ok(SavedQuery {
+        id: String::new(),
+        name: req.name,
+        description: req.description,
+        tenant_id: String::new(),
+        owner_id: String::new(),
+        visibility: req.visibility,
+        signal: req.signal,
+        filters: req.filters,
+        search: req.search,
+        group_by: req.group_by,
+        aggregation: req.aggregation,
+        columns: req.columns,
+        default_range: req.default_range,
+        created_at: String::new(),
+        updated_at: String::new(),
+    })
+}
+
+/// Columns only shape list results, so they must name row fields of the
+/// signal's list handler and can't be combined with `group_by`.
+fn validate_columns(signal: &str, group_by: &[String], columns: &[String]) -> Result<(), (StatusCode, String)> {
+    if columns.is_empty() {
+        return Ok(());
+    }
+    if !group_by.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "columns apply to list queries only, not with group_by".to_string()));
+    }
+    if columns.len() > MAX_COLUMNS {
+        return Err((StatusCode::BAD_REQUEST, format!("too many columns (max {MAX_COLUMNS})")));
+    }
+    let known = if signal == "logs" { LOG_COLUMNS } else { SPAN_COLUMNS };
+    for (i, c) in columns.iter().enumerate() {
+        if !known.contains(&c.as_str()) {
+            return Err((StatusCode::BAD_REQUEST, format!("unknown {signal} column: {c}")));
+        }
+        if columns[..i].contains(c) {
+            return Err((StatusCode::BAD_REQUEST, format!("duplicate column: {c}")));
+        }
+    }
+    Ok(())
+}
+
+fn range_secs(range: &str) -> Result<f64, (StatusCode, String)> {
+    match crate::promql::types::parse_duration(range) {
+        Ok(secs) if secs <= MAX_DEFAULT_RANGE_SECS => Ok(secs),
+        Ok(_) => Err((StatusCode::BAD_REQUEST, "default_range must be at most 30d".to_string())),
+        Err(e) => Err((StatusCode::BAD_REQUEST, format!("invalid default_range: {e}"))),
+    }
+}
+
+pub async fn list_saved_queries(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    let queries = state
+        .config_db
+        .list_saved_queries(&tenant.tenant_id, &user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(serde_json::json!({ "saved_queries": queries })))
+}
+
+pub async fn create_saved_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Json(req): Json<SavedQueryRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    let query = SavedQuery {
+        id: uuid::Uuid::new_v4().to_string(),
+        tenant_id: tenant.tenant_id.clone(),
+        owner_id: user_id.clone(),
+        ..validate(req)?
+    };
+    state
+        .config_db
+        .create_saved_query(&query).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let created = state
+        .config_db
+        .get_saved_query(&query.id, &tenant.tenant_id, &user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "failed to read created saved query".to_string()))?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn get_saved_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    let query = state
+        .config_db
+        .get_saved_query(&id, &tenant.tenant_id, &user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "saved query not found".to_string()))?;
+    Ok(Json(query))
+}
+
+pub async fn update_saved_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<SavedQueryRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let (user_id, _, _, _, role) = resolve_caller(&state, &headers, &tenant).await;
+    let update = SavedQuery { id: id.clone(), tenant_id: tenant.tenant_id.clone(), ..validate(req)? };
+    let updated = state
+        .config_db
+        .update_saved_query(&update, &user_id, &role).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, "saved query not found".to_string()));
+    }
+    let query = state
+        .config_db
+        .get_saved_query(&id, &tenant.tenant_id, &user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "failed to read saved query".to_string()))?;
+    Ok(Json(query))
+}
+
+pub async fn delete_saved_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let (user_id, _, _, _, role) = resolve_caller(&state, &headers, &tenant).await;
+    let deleted = state
+        .config_db
+        .delete_saved_query(&id, &tenant.tenant_id, &user_id, &role).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "saved query not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// POST /api/v1/saved-queries/{id}/execute
+///
+/// Runs a saved query through the same handler Explore uses for its signal
+/// and shape (`/api/v1/query`, `/api/v1/query/group`, `/api/v1/logs` or
+/// `/api/v1/logs/group`) and returns that handler's response, with list rows
+/// cut down to the saved `columns` (if any). The time range defaults to the
+/// last `default_range`.
+pub async fn execute_saved_query(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Option<Json<ExecuteSavedQueryRequest>>,
+) -> Result<Response, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    let saved = state
+        .config_db
+        .get_saved_query(&id, &tenant.tenant_id, &user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "saved query not found".to_string()))?;
+    let Json(req) = body.unwrap_or_default();
+    // Columns only shape list rows (rows saved before they were validated may
+    // still pair them with group_by).
+    let columns = if saved.group_by.is_empty() { saved.columns.clone() } else { Vec::new() };
+
+    let state = State(state);
+    let tenant = Extension(tenant);
+    let resp = match plan(saved, req, chrono::Utc::now())? {
+        Plan::Logs(req) => crate::handlers::logs::query_logs(state, tenant, Json(req)).await.map(IntoResponse::into_response),
+        Plan::LogGroups(req) => crate::handlers::logs::group_logs(state, tenant, Json(req)).await.map(IntoResponse::into_response),
+        Plan::Spans(req) => crate::handlers::query::execute_query(state, tenant, Json(req)).await.map(IntoResponse::into_response),
+        Plan::SpanGroups(req) => crate::handlers::query::group_query(state, tenant, Json(req)).await.map(IntoResponse::into_response),
+    }?;
+    if columns.is_empty() || !resp.status().is_success() {
+        return Ok(resp);
+    }
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut json: serde_json::Value = serde_json::from_slice(&bytes)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    project_rows(&mut json, &columns);
+    Ok(Json(json).into_response())
+}
+
+/// The handler request a saved query runs as.
+enum Plan {
+    Logs(crate::handlers::logs::LogQueryRequest),
+    LogGroups(QueryRequest),
+    Spans(QueryRequest),
+    SpanGroups(QueryRequest),
+}
+
+/// Build the handler request for `saved`, with the execute body's overrides.
+/// Span lists whose columns all fit the slim projection ask for it, so the
+/// query doesn't read columns the result won't keep.
+fn plan(
+    saved: SavedQuery,
+    req: ExecuteSavedQueryRequest,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Plan, (StatusCode, String)> {
+    let time_range = match req.time_range {
+        Some(tr) => tr,
+        None => {
+            let secs = range_secs(&saved.default_range)?;
+            let from = now - chrono::Duration::milliseconds((secs * 1000.0) as i64);
+            TimeRange {
+                from: from.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                to: now.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            }
+        }
+    };
+    let filters: Vec<Filter> = serde_json::from_value(saved.filters)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("stored filters are invalid: {e}")))?;
+    let search = (!saved.search.is_empty()).then_some(saved.search);
+    let limit = req.limit.unwrap_or(100);
+    let offset = req.offset.unwrap_or(0);
+
+    let list = saved.group_by.is_empty();
+    if saved.signal == "logs" && list {
+        return Ok(Plan::Logs(crate::handlers::logs::LogQueryRequest { time_range, filters, limit, offset, search }));
+    }
+    let slim = !saved.columns.is_empty() && saved.columns.iter().all(|c| SLIM_SPAN_COLUMNS.contains(&c.as_str()));
+    let req = QueryRequest {
+        time_range,
+        filters,
+        group_by: saved.group_by,
+        aggregation: saved.aggregation,
+        limit,
+        offset,
+        search,
+        cursor: None,
+        columns: slim.then(|| "list".to_string()),
+    };
+    Ok(match (saved.signal.as_str(), list) {
+        ("logs", _) => Plan::LogGroups(req),
+        (_, true) => Plan::Spans(req),
+        (_, false) => Plan::SpanGroups(req),
+    })
+}
+
+/// Keep only `columns` in every object of the response's `rows` array.
+fn project_rows(body: &mut serde_json::Value, columns: &[String]) {
+    let Some(rows) = body.get_mut("rows").and_then(|r| r.as_array_mut()) else { return };
+    for row in rows {
+        let Some(obj) = row.as_object_mut() else { continue };
+        let projected: serde_json::Map<String, serde_json::Value> =
+            columns.iter().filter_map(|c| obj.remove(c).map(|v| (c.clone(), v))).collect();
+        *obj = projected;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(signal: &str, group_by: &[&str], columns: &[&str]) -> SavedQueryRequest {
+        serde_json::from_value(serde_json::json!({
+            "name": "q",
+            "signal": signal,
+            "group_by": group_by,
+            "columns": columns,
+        }))
+        .unwrap()
+    }
+
+    fn saved(signal: &str, columns: &[&str]) -> SavedQuery {
+        SavedQuery { id: "id".into(), tenant_id: "t".into(), ..validate(request(signal, &[], columns)).unwrap() }
+    }
+
+    #[test]
+    fn rejects_bad_columns() {
+        let err = |r| validate(r).unwrap_err().1;
+        assert!(err(request("spans", &[], &["nope"])).contains("unknown spans column"));
+        assert!(err(request("logs", &[], &["service_name"])).contains("unknown logs column"));
+        assert!(err(request("spans", &[], &["span_name", "span_name"])).contains("duplicate"));
+        assert!(err(request("spans", &["service_name"], &["span_name"])).contains("group_by"));
+        assert!(validate(request("logs", &[], &["Timestamp", "Body"])).is_ok());
+        assert!(validate(request("spans", &["service_name"], &[])).is_ok());
+    }
+
+    #[test]
+    fn execute_applies_stored_columns() {
+        let now = chrono::Utc::now();
+        let Plan::Spans(req) = plan(saved("spans", &["span_name", "duration_ns"]), Default::default(), now).unwrap() else {
+            panic!("expected a span list");
+        };
+        assert_eq!(req.columns.as_deref(), Some("list"), "slim-only columns use the slim projection");
+        let Plan::Spans(req) = plan(saved("spans", &["span_name", "attributes"]), Default::default(), now).unwrap() else {
+            panic!("expected a span list");
+        };
+        assert_eq!(req.columns, None);
+
+        let mut body = serde_json::json!({
+            "rows": [{ "timestamp": 1, "span_name": "GET /", "duration_ns": 5, "status": "OK" }],
+            "total": 1,
+        });
+        project_rows(&mut body, &["duration_ns".to_string(), "span_name".to_string()]);
+        assert_eq!(body["rows"][0], serde_json::json!({ "duration_ns": 5, "span_name": "GET /" }));
+        assert_eq!(body["total"], 1);
+    }
+}
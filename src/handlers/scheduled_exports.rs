@@ -0,0 +1,187 @@
+use axum::{
+    Json,
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+
+use crate::AppState;
+use crate::TenantContext;
+use crate::handlers::dashboards::resolve_caller;
+use crate::handlers::export::ExportFormat;
+use crate::handlers::users::{require_auth, require_write};
+use crate::models::scheduled_export::*;
+
+/// Check a create/update body against the caller's saved queries and
+/// channels and turn it into the stored shape (ids, run state and
+/// timestamps are filled in by the caller / ConfigDb).
+async fn validate(
+    state: &AppState,
+    tenant_id: &str,
+    user_id: &str,
+    req: ScheduledExportRequest,
+) -> Result<ScheduledExport, (StatusCode, String)> {
+    if req.name.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "name must not be empty".to_string()));
+    }
+    if req.name.len() > 255 {
+        return Err((StatusCode::BAD_REQUEST, "name must not exceed 255 characters".to_string()));
+    }
+    crate::export_scheduler::parse_schedule(&req.schedule).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if req.format == ExportFormat::Json {
+        return Err((StatusCode::BAD_REQUEST, "format must be csv, ndjson, arrow or parquet".to_string()));
+    }
+    let destination = crate::export_scheduler::normalize_destination(&req.destination)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    state
+        .config_db
+        .get_saved_query(&req.saved_query_id, tenant_id, user_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("saved query not found: {}", req.saved_query_id)))?;
+    for channel_id in &req.notification_channels {
+        state
+            .config_db
+            .get_channel(channel_id, tenant_id).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("notification channel not found: {channel_id}")))?;
+    }
+    Ok(ScheduledExport {
+        id: String::new(),
+        tenant_id: tenant_id.to_string(),
+        owner_id: user_id.to_string(),
+        name: req.name,
+        saved_query_id: req.saved_query_id,
+        schedule: req.schedule.trim().to_string(),
+        format: req.format.as_str().to_string(),
+        destination,
+        notification_channels: req.notification_channels,
+        enabled: req.enabled,
+        last_run_at: String::new(),
+        last_status: String::new(),
+        last_error: String::new(),
+        exported_until: String::new(),
+        created_at: String::new(),
+        updated_at: String::new(),
+    })
+}
+
+pub async fn list_scheduled_exports(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let exports = state
+        .config_db
+        .list_scheduled_exports(&tenant.tenant_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(serde_json::json!({ "scheduled_exports": exports })))
+}
+
+pub async fn create_scheduled_export(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Json(req): Json<ScheduledExportRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    let export = ScheduledExport {
+        id: uuid::Uuid::new_v4().to_string(),
+        ..validate(&state, &tenant.tenant_id, &user_id, req).await?
+    };
+    state
+        .config_db
+        .create_scheduled_export(&export).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let created = state
+        .config_db
+        .get_scheduled_export(&export.id, &tenant.tenant_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "failed to read created scheduled export".to_string()))?;
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn get_scheduled_export(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let export = state
+        .config_db
+        .get_scheduled_export(&id, &tenant.tenant_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "scheduled export not found".to_string()))?;
+    Ok(Json(export))
+}
+
+pub async fn update_scheduled_export(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<ScheduledExportRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let (user_id, _, _, _, _) = resolve_caller(&state, &headers, &tenant).await;
+    // The editor becomes the owner: the saved query was checked against them.
+    let update = ScheduledExport { id: id.clone(), ..validate(&state, &tenant.tenant_id, &user_id, req).await? };
+    let updated = state
+        .config_db
+        .update_scheduled_export(&update).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, "scheduled export not found".to_string()));
+    }
+    let export = state
+        .config_db
+        .get_scheduled_export(&id, &tenant.tenant_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .ok_or_else(|| (StatusCode::INTERNAL_SERVER_ERROR, "failed to read scheduled export".to_string()))?;
+    Ok(Json(export))
+}
+
+pub async fn delete_scheduled_export(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_write(&state, &headers).await?;
+    let deleted = state
+        .config_db
+        .delete_scheduled_export(&id, &tenant.tenant_id).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "scheduled export not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RunsParams {
+    #[serde(default = "default_runs_limit")]
+    pub limit: u64,
+}
+
+fn default_runs_limit() -> u64 {
+    50
+}
+
+/// GET /api/v1/scheduled-exports/{id}/runs — run history, newest first.
+pub async fn list_export_runs(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<TenantContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<RunsParams>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_auth(&state, &headers).await?;
+    let runs = state
+        .config_db
+        .list_export_runs(&id, &tenant.tenant_id, params.limit.clamp(1, 500)).await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(serde_json::json!({ "runs": runs })))
+}
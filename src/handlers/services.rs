@@ -44,10 +44,7 @@ pub async fn list_services(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, handler = "list_services", "query failed");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "query failed".into(),
-            )
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     tracing::info!(
@@ -164,12 +161,12 @@ pub async fn service_graph(
 
     let nodes = nodes_result.map_err(|e| {
         tracing::error!(error = %e, handler = "service_graph", "nodes query failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
     })?;
 
     let edges = edges_result.map_err(|e| {
         tracing::error!(error = %e, handler = "service_graph", "edges query failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
     })?;
 
     Ok(Json(ServiceGraph { nodes, edges }))
@@ -258,7 +255,7 @@ pub async fn service_latency_histogram(
 
     let buckets = buckets_res.map_err(|e| {
         tracing::error!(error = %e, handler = "service_latency_histogram", "buckets query failed");
-        (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+        crate::query_limits::query_error(tenant_id, &e, "query failed")
     })?;
 
     // No rows ⇒ no traffic in window; return an empty distribution rather than 500.
@@ -366,7 +363,7 @@ pub async fn service_endpoints(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, handler = "service_endpoints", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(EndpointsResponse {
@@ -473,7 +470,7 @@ pub async fn service_errors(
         .await
         .map_err(|e| {
             tracing::error!(error = %e, handler = "service_errors", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     Ok(Json(ErrorsResponse {
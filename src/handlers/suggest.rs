@@ -118,7 +118,7 @@ pub async fn suggest_values(
         .await
         .map_err(|e| {
             tracing::error!("Suggest query failed: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     let values: Vec<String> = rows.into_iter().map(|r| r.val).filter(|v| !v.is_empty()).collect();
@@ -7,6 +7,8 @@ use axum::{
 use uuid::Uuid;
 
 use crate::AppState;
+use crate::metric_naming::MetricNaming;
+use crate::query_limits::{self, QueryLimits, QueryLimitsUpdate};
 use crate::handlers::users::{require_admin, require_auth};
 
 #[derive(serde::Deserialize)]
@@ -217,3 +219,131 @@ pub async fn set_auth_required(
         created_at: tenant.4,
     }))
 }
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MetricNamingBody {
+    /// `otel` (names as received) or `prometheus` (normalized at ingest, OTel
+    /// spellings with a matching unit resolved at query time).
+    pub mode: String,
+}
+
+/// GET /api/v1/tenants/{id}/metric-naming
+pub async fn get_metric_naming(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    state
+        .config_db
+        .get_tenant(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "tenant not found".to_string()))?;
+    let mode = state
+        .config_db
+        .get_tenant_metric_naming(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .and_then(|m| MetricNaming::parse(&m))
+        .unwrap_or_default();
+    Ok(Json(MetricNamingBody { mode: mode.as_str().to_string() }))
+}
+
+/// PUT /api/v1/tenants/{id}/metric-naming
+pub async fn set_metric_naming(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<MetricNamingBody>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let mode = MetricNaming::parse(&req.mode).ok_or_else(|| {
+        (StatusCode::BAD_REQUEST, format!("invalid mode: {}. Must be one of: otel, prometheus", req.mode))
+    })?;
+    state
+        .config_db
+        .get_tenant(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "tenant not found".to_string()))?;
+    state
+        .config_db
+        .set_tenant_metric_naming(&id, mode.as_str()).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    crate::metric_naming::set_tenant_mode(&id, mode);
+    Ok(Json(MetricNamingBody { mode: mode.as_str().to_string() }))
+}
+
+#[derive(serde::Serialize)]
+pub struct QueryLimitsBody {
+    #[serde(flatten)]
+    pub limits: QueryLimits,
+    /// False when the tenant runs on the process-wide `RUSH_QUERY_*` defaults.
+    pub overridden: bool,
+}
+
+/// GET /api/v1/tenants/{id}/query-limits
+pub async fn get_query_limits(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    state
+        .config_db
+        .get_tenant(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "tenant not found".to_string()))?;
+    let stored = state
+        .config_db
+        .get_tenant_query_limits(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    Ok(Json(QueryLimitsBody {
+        overridden: stored.is_some(),
+        limits: stored.unwrap_or_else(query_limits::defaults),
+    }))
+}
+
+/// PUT /api/v1/tenants/{id}/query-limits — fields left out keep their
+/// current value; 0 means unlimited.
+pub async fn set_query_limits(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<QueryLimitsUpdate>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    state
+        .config_db
+        .get_tenant(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "tenant not found".to_string()))?;
+    let current = state
+        .config_db
+        .get_tenant_query_limits(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?
+        .unwrap_or_else(query_limits::defaults);
+    let limits = req.apply(current);
+    state
+        .config_db
+        .set_tenant_query_limits(&id, &limits).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    query_limits::set_tenant_override(&id, Some(limits));
+    Ok(Json(QueryLimitsBody { limits, overridden: true }))
+}
+
+/// DELETE /api/v1/tenants/{id}/query-limits — back to the defaults.
+pub async fn delete_query_limits(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    require_admin(&state, &headers).await?;
+    let deleted = state
+        .config_db
+        .delete_tenant_query_limits(&id).await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_string()))?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "tenant has no query limit override".to_string()));
+    }
+    query_limits::set_tenant_override(&id, None);
+    Ok(StatusCode::NO_CONTENT)
+}
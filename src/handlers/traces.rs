@@ -38,7 +38,7 @@ pub async fn get_trace(
         cnt: u64,
     }
 
-    let bounds = crate::tenant_query(
+    let bounds = crate::tenant_query_bound(
             &state.ch,
             &format!(
                 "SELECT min(toUnixTimestamp64Nano(timestamp)) AS min_ns, \
@@ -47,8 +47,8 @@ pub async fn get_trace(
                  PREWHERE tenant_id = '{escaped_tenant}' WHERE trace_id = ?"
             ),
             tenant_id,
+            |q| q.bind(&trace_id),
         )
-        .bind(&trace_id)
         .fetch_one::<TraceTimeBounds>()
         .await;
 
@@ -69,19 +69,11 @@ pub async fn get_trace(
         }
     };
 
-    let fetch_spans = |time_bound: String| {
-        let sql = format!(
-            "SELECT * FROM spans PREWHERE tenant_id = '{escaped_tenant}'{time_bound} WHERE trace_id = ? ORDER BY timestamp ASC"
-        );
-        let q = crate::tenant_query(&state.ch, &sql, tenant_id).bind(&trace_id);
-        async move { q.fetch_all::<WideEvent>().await }
-    };
-
-    let mut rows = fetch_spans(time_bound.clone())
+    let mut rows = fetch_spans(&state.ch, tenant_id, &trace_id, &time_bound)
         .await
         .map_err(|e| {
             tracing::error!(error = %e, signal = "traces", handler = "get_trace", "ClickHouse query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Defensive fallback: the MV is populated asynchronously, so if the bounded fetch
@@ -89,11 +81,11 @@ pub async fn get_trace(
     // trace exists only in spans, e.g. data ingested before the MV was created),
     // retry without the time bound before declaring 404.
     if rows.is_empty() && !time_bound.is_empty() {
-        rows = fetch_spans(String::new())
+        rows = fetch_spans(&state.ch, tenant_id, &trace_id, "")
             .await
             .map_err(|e| {
                 tracing::error!(error = %e, signal = "traces", handler = "get_trace", "ClickHouse fallback query failed");
-                (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+                crate::query_limits::query_error(tenant_id, &e, "query failed")
             })?;
     }
 
@@ -105,6 +97,21 @@ pub async fn get_trace(
     Ok(Json(trace))
 }
 
+/// The trace's spans, restricted by `time_bound` (extra `PREWHERE` conditions,
+/// empty for an unbounded scan).
+async fn fetch_spans(
+    ch: &clickhouse::Client,
+    tenant_id: &str,
+    trace_id: &str,
+    time_bound: &str,
+) -> Result<Vec<WideEvent>, clickhouse::error::Error> {
+    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
+    let sql = format!(
+        "SELECT * FROM spans PREWHERE tenant_id = '{escaped_tenant}'{time_bound} WHERE trace_id = ? ORDER BY timestamp ASC"
+    );
+    crate::tenant_query_bound(ch, &sql, tenant_id, |q| q.bind(trace_id)).fetch_all::<WideEvent>().await
+}
+
 /// Build the span tree from a flat list of wide events.
 fn assemble_trace(trace_id: &str, events: Vec<WideEvent>) -> TraceResponse {
     // Deduplicate by span_id, keeping the first occurrence
@@ -223,3 +230,54 @@ fn assemble_trace(trace_id: &str, events: Vec<WideEvent>) -> TraceResponse {
         services,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stand-in ClickHouse HTTP endpoint that records every statement and
+    /// answers with no rows.
+    async fn fake_clickhouse() -> (clickhouse::Client, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::sync::{Arc, Mutex};
+        let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = seen.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri, body: String| {
+            let log = log.clone();
+            async move {
+                let sql = if body.is_empty() {
+                    url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+                        .find(|(k, _)| k == "query")
+                        .map(|(_, v)| v.into_owned())
+                        .unwrap_or_default()
+                } else {
+                    body
+                };
+                log.lock().unwrap().push(sql);
+                Vec::<u8>::new()
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = clickhouse::Client::default().with_url(url).with_compression(clickhouse::Compression::None);
+        (client, seen)
+    }
+
+    #[tokio::test]
+    async fn dry_run_of_a_bound_lookup_can_be_explained() {
+        let (ch, seen) = fake_clickhouse().await;
+        let trace_id = "0af7651916cd43dd8448eb211c80319c";
+        let (rows, statements) =
+            crate::query_explain::dry_run(fetch_spans(&ch, "acme", trace_id, "")).await;
+        assert!(rows.unwrap().is_empty());
+        assert_eq!(statements.len(), 1);
+        assert!(statements[0].ends_with(&format!("WHERE trace_id = '{trace_id}' ORDER BY timestamp ASC")));
+
+        let plan = crate::query_explain::explain(&ch, statements, "acme").await;
+        assert!(plan.statements[0].error.is_none(), "{:?}", plan.statements[0].error);
+        let seen = seen.lock().unwrap();
+        let explained: Vec<_> = seen.iter().filter(|q| q.starts_with("EXPLAIN")).collect();
+        assert_eq!(explained.len(), 2);
+        assert!(explained.iter().all(|q| q.contains(&format!("trace_id = '{trace_id}'"))), "{explained:?}");
+    }
+}
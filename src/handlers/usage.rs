@@ -87,7 +87,7 @@ pub async fn get_usage(
         .await
         .map_err(|e| {
             tracing::error!("Usage query failed: {e}");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Count total tracked signals
@@ -146,14 +146,14 @@ pub async fn usage_summary(
          GROUP BY signal"
     );
 
-    let rows = state
-        .ch
-        .query(&sql)
+    // The global view spans every tenant, so no single tenant's limits apply.
+    let query = if is_global { state.ch.query(&sql) } else { crate::tenant_query(&state.ch, &sql, tenant_id) };
+    let rows = query
         .fetch_all::<SignalUsageRow>()
         .await
         .map_err(|e| {
             tracing::error!(error = %e, handler = "usage_summary", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     let mut signals = HashMap::new();
@@ -224,14 +224,12 @@ pub async fn usage_breakdown(
 
     sql.push_str(&format!(" GROUP BY ts, signal ORDER BY ts"));
 
-    let rows = state
-        .ch
-        .query(&sql)
+    let rows = crate::tenant_query(&state.ch, &sql, tenant_id)
         .fetch_all::<BreakdownRow>()
         .await
         .map_err(|e| {
             tracing::error!(error = %e, handler = "usage_breakdown", "query failed");
-            (StatusCode::INTERNAL_SERVER_ERROR, "query failed".into())
+            crate::query_limits::query_error(tenant_id, &e, "query failed")
         })?;
 
     // Group by timestamp
@@ -0,0 +1,390 @@
+//! Kubernetes metadata enrichment for incoming telemetry (`RUSH_K8S_ENRICH=true`).
+//!
+//! Only collectors running the `k8sattributes` processor populate `k8s.*`
+//! resource attributes, so the `mat_k8s_*` columns are empty for anything sent
+//! straight from an SDK or through a plain collector. This watches pods via the
+//! Kubernetes API and keeps an in-memory index by pod UID and pod IP; the OTLP
+//! and Vector ingest handlers call `K8sEnricher::enrich` once per resource
+//! (before `ChWriter::write`), which fills in missing namespace, pod, node,
+//! workload (deployment / statefulset / daemonset / job) and pod labels.
+//!
+//! A resource is looked up, in order, by its `k8s.pod.uid` attribute, its
+//! `k8s.pod.ip` attribute, or — only with `RUSH_K8S_ENRICH_PEER_IP=true` — the
+//! connection's peer address (in-cluster ClusterIP traffic keeps the source pod
+//! IP). The peer fallback is off by default: behind a collector or gateway the
+//! peer is the proxy pod, whose metadata would land on everything it forwards.
+//! Attributes already present are never overwritten. Host-network pods are not
+//! indexed by IP (their IP is the node's).
+//!
+//! The pod index covers the cluster, not a tenant, so enrichment is limited to
+//! the tenants in `RUSH_K8S_ENRICH_TENANTS` (default: `default`), each
+//! optionally to its own namespaces (`acme=shop|payments`). Any other tenant's
+//! resources pass through untouched, so a tenant can't read another's pod
+//! names and labels by sending their UIDs or IPs.
+//!
+//! Lookups are counted as hits (metadata found) or misses (a key was available
+//! but no pod matched) — see `stats`.
+
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use k8s_openapi::api::core::v1::Pod;
+
+#[derive(Debug, Clone)]
+pub struct K8sEnrichConfig {
+    /// Watch a single namespace instead of the whole cluster.
+    pub namespace: Option<String>,
+    /// Copy pod labels as `k8s.pod.label.<key>`.
+    pub pod_labels: bool,
+    /// Fall back to the connection's peer address when the resource carries
+    /// neither a pod UID nor a pod IP.
+    pub peer_ip: bool,
+    /// Tenants whose resources are enriched, each with the namespaces its pods
+    /// may come from (`None`: any namespace).
+    pub tenants: HashMap<String, Option<HashSet<String>>>,
+}
+
+impl K8sEnrichConfig {
+    /// `Some` when `RUSH_K8S_ENRICH=true`. `RUSH_K8S_ENRICH_NAMESPACE` limits the
+    /// watch to one namespace; `RUSH_K8S_ENRICH_POD_LABELS=false` skips labels;
+    /// `RUSH_K8S_ENRICH_PEER_IP=true` enables the peer-address fallback;
+    /// `RUSH_K8S_ENRICH_TENANTS` lists the enriched tenants (see `parse_tenants`).
+    pub fn from_env() -> Option<Self> {
+        if !matches!(std::env::var("RUSH_K8S_ENRICH").ok()?.trim(), "true" | "1") {
+            return None;
+        }
+        let namespace = std::env::var("RUSH_K8S_ENRICH_NAMESPACE").ok().filter(|s| !s.is_empty());
+        let pod_labels = std::env::var("RUSH_K8S_ENRICH_POD_LABELS").map(|v| v.trim() != "false").unwrap_or(true);
+        let peer_ip = std::env::var("RUSH_K8S_ENRICH_PEER_IP").is_ok_and(|v| matches!(v.trim(), "true" | "1"));
+        let tenants = parse_tenants(&std::env::var("RUSH_K8S_ENRICH_TENANTS").unwrap_or_else(|_| "default".into()));
+        Some(K8sEnrichConfig { namespace, pod_labels, peer_ip, tenants })
+    }
+}
+
+/// Parse `tenant,other=ns1|ns2`: a bare tenant may be enriched from any
+/// namespace, `tenant=…` only from the listed ones.
+pub fn parse_tenants(spec: &str) -> HashMap<String, Option<HashSet<String>>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((tenant, namespaces)) => (
+                tenant.trim().to_string(),
+                Some(namespaces.split('|').map(str::trim).filter(|n| !n.is_empty()).map(String::from).collect()),
+            ),
+            None => (entry.to_string(), None),
+        })
+        .collect()
+}
+
+/// What we know about one pod, as resource attributes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PodMeta {
+    pub uid: String,
+    pub ip: Option<String>,
+    pub attrs: Vec<(String, String)>,
+}
+
+impl PodMeta {
+    fn namespace(&self) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == "k8s.namespace.name").map(|(_, v)| v.as_str())
+    }
+
+    pub fn from_pod(pod: &Pod, pod_labels: bool) -> Option<Self> {
+        let md = &pod.metadata;
+        let uid = md.uid.clone()?;
+        let mut attrs = vec![("k8s.pod.uid".to_string(), uid.clone())];
+        if let Some(ns) = &md.namespace {
+            attrs.push(("k8s.namespace.name".into(), ns.clone()));
+        }
+        if let Some(name) = &md.name {
+            attrs.push(("k8s.pod.name".into(), name.clone()));
+        }
+        let spec = pod.spec.as_ref();
+        if let Some(node) = spec.and_then(|s| s.node_name.clone()) {
+            attrs.push(("k8s.node.name".into(), node));
+        }
+        let labels = md.labels.clone().unwrap_or_default();
+        for owner in md.owner_references.iter().flatten().filter(|o| o.controller == Some(true)) {
+            match owner.kind.as_str() {
+                "ReplicaSet" => {
+                    attrs.push(("k8s.replicaset.name".into(), owner.name.clone()));
+                    // Deployment-managed ReplicaSets are named <deployment>-<pod-template-hash>.
+                    if let Some(hash) = labels.get("pod-template-hash")
+                        && let Some(dep) = owner.name.strip_suffix(&format!("-{hash}"))
+                    {
+                        attrs.push(("k8s.deployment.name".into(), dep.to_string()));
+                    }
+                }
+                "StatefulSet" => attrs.push(("k8s.statefulset.name".into(), owner.name.clone())),
+                "DaemonSet" => attrs.push(("k8s.daemonset.name".into(), owner.name.clone())),
+                "Job" => attrs.push(("k8s.job.name".into(), owner.name.clone())),
+                _ => {}
+            }
+        }
+        if pod_labels {
+            for (k, v) in &labels {
+                attrs.push((format!("k8s.pod.label.{k}"), v.clone()));
+            }
+        }
+        let host_network = spec.and_then(|s| s.host_network).unwrap_or(false);
+        let ip = if host_network { None } else { pod.status.as_ref().and_then(|s| s.pod_ip.clone()) };
+        Some(PodMeta { uid, ip, attrs })
+    }
+}
+
+/// Enrichment counters (see `K8sEnricher::stats`).
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct EnrichStats {
+    pub pods: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub struct K8sEnricher {
+    cfg: K8sEnrichConfig,
+    by_uid: DashMap<String, Arc<PodMeta>>,
+    /// Pod IP → UID.
+    by_ip: DashMap<String, String>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl K8sEnricher {
+    pub fn new(cfg: K8sEnrichConfig) -> Self {
+        K8sEnricher {
+            cfg,
+            by_uid: DashMap::new(),
+            by_ip: DashMap::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> EnrichStats {
+        EnrichStats {
+            pods: self.by_uid.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn upsert(&self, meta: PodMeta) {
+        if let Some(old) = self.by_uid.get(&meta.uid).map(|m| m.ip.clone())
+            && let Some(old_ip) = old
+            && meta.ip.as_ref() != Some(&old_ip)
+        {
+            self.by_ip.remove_if(&old_ip, |_, uid| *uid == meta.uid);
+        }
+        if let Some(ip) = &meta.ip {
+            self.by_ip.insert(ip.clone(), meta.uid.clone());
+        }
+        self.by_uid.insert(meta.uid.clone(), Arc::new(meta));
+    }
+
+    pub fn remove(&self, uid: &str) {
+        if let Some((_, meta)) = self.by_uid.remove(uid)
+            && let Some(ip) = &meta.ip
+        {
+            // The IP may already belong to a newer pod.
+            self.by_ip.remove_if(ip, |_, u| u == uid);
+        }
+    }
+
+    /// Drop every pod not in `live` (after a watch re-list).
+    fn retain(&self, live: &HashSet<String>) {
+        let stale: Vec<String> = self.by_uid.iter().filter(|e| !live.contains(e.key())).map(|e| e.key().clone()).collect();
+        for uid in stale {
+            self.remove(&uid);
+        }
+    }
+
+    /// Fill missing `k8s.*` resource attributes of `tenant`'s resource in place.
+    /// `peer` is the connection's remote address, used only when the peer-IP
+    /// fallback is enabled.
+    pub fn enrich(&self, tenant: &str, attrs: &mut Vec<(String, String)>, peer: Option<IpAddr>) {
+        let Some(namespaces) = self.cfg.tenants.get(tenant) else {
+            return;
+        };
+        let get = |k: &str| attrs.iter().find(|(ak, _)| ak == k).map(|(_, v)| v.clone());
+        if get("k8s.namespace.name").is_some() && get("k8s.pod.name").is_some() {
+            return;
+        }
+        let uid = match (get("k8s.pod.uid"), get("k8s.pod.ip"), peer.filter(|_| self.cfg.peer_ip)) {
+            (Some(uid), _, _) => Some(uid),
+            (None, Some(ip), _) => self.by_ip.get(&ip).map(|u| u.clone()),
+            (None, None, Some(ip)) => self.by_ip.get(&ip.to_string()).map(|u| u.clone()),
+            (None, None, None) => return,
+        };
+        let meta = uid
+            .and_then(|u| self.by_uid.get(&u).map(|m| m.clone()))
+            .filter(|m| namespaces.as_ref().is_none_or(|ns| m.namespace().is_some_and(|n| ns.contains(n))));
+        let Some(meta) = meta else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        for (k, v) in &meta.attrs {
+            if !attrs.iter().any(|(ak, _)| ak == k) {
+                attrs.push((k.clone(), v.clone()));
+            }
+        }
+    }
+
+    /// Watch pods and keep the index current. Runs until the process exits;
+    /// the watcher re-lists (with backoff) after API errors.
+    pub fn spawn_watcher(self: &Arc<Self>) {
+        use futures_util::StreamExt;
+        use kube::runtime::{WatchStreamExt, watcher};
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            let client = match kube::Client::try_default().await {
+                Ok(c) => c,
+                Err(e) => {
+                    tracing::error!(error = %e, "k8s enrichment: Kubernetes API not available — enrichment disabled");
+                    return;
+                }
+            };
+            let api: kube::Api<Pod> = match &this.cfg.namespace {
+                Some(ns) => kube::Api::namespaced(client, ns),
+                None => kube::Api::all(client),
+            };
+            let mut stream = watcher(api, watcher::Config::default()).default_backoff().boxed();
+            let mut relist: HashSet<String> = HashSet::new();
+            while let Some(ev) = stream.next().await {
+                match ev {
+                    Ok(watcher::Event::Apply(pod)) | Ok(watcher::Event::InitApply(pod)) => {
+                        if let Some(meta) = PodMeta::from_pod(&pod, this.cfg.pod_labels) {
+                            relist.insert(meta.uid.clone());
+                            this.upsert(meta);
+                        }
+                    }
+                    Ok(watcher::Event::Delete(pod)) => {
+                        if let Some(uid) = &pod.metadata.uid {
+                            this.remove(uid);
+                        }
+                    }
+                    Ok(watcher::Event::Init) => relist.clear(),
+                    Ok(watcher::Event::InitDone) => {
+                        this.retain(&relist);
+                        relist.clear();
+                        tracing::info!(pods = this.by_uid.len(), "k8s enrichment: pod index synced");
+                    }
+                    Err(e) => tracing::warn!(error = %e, "k8s enrichment: pod watch error"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pod(uid: &str, ip: &str) -> Pod {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "uid": uid,
+                "name": format!("web-5d9c7b8f4-{uid}"),
+                "namespace": "prod",
+                "labels": { "app": "web", "pod-template-hash": "5d9c7b8f4" },
+                "ownerReferences": [{
+                    "apiVersion": "apps/v1", "kind": "ReplicaSet", "name": "web-5d9c7b8f4",
+                    "uid": "rs", "controller": true
+                }]
+            },
+            "spec": { "nodeName": "node-1", "containers": [] },
+            "status": { "podIP": ip }
+        }))
+        .unwrap()
+    }
+
+    fn enricher() -> K8sEnricher {
+        K8sEnricher::new(K8sEnrichConfig {
+            namespace: None,
+            pod_labels: true,
+            peer_ip: true,
+            tenants: parse_tenants("default"),
+        })
+    }
+
+    fn attr<'a>(attrs: &'a [(String, String)], k: &str) -> Option<&'a str> {
+        attrs.iter().find(|(ak, _)| ak == k).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn fills_missing_attributes_by_peer_ip_and_uid() {
+        let e = enricher();
+        e.upsert(PodMeta::from_pod(&pod("u1", "10.0.0.5"), true).unwrap());
+
+        let mut attrs = vec![("service.name".to_string(), "web".to_string())];
+        e.enrich("default", &mut attrs, Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(attr(&attrs, "k8s.namespace.name"), Some("prod"));
+        assert_eq!(attr(&attrs, "k8s.deployment.name"), Some("web"));
+        assert_eq!(attr(&attrs, "k8s.node.name"), Some("node-1"));
+        assert_eq!(attr(&attrs, "k8s.pod.label.app"), Some("web"));
+
+        // Existing attributes win; lookup by uid ignores the peer.
+        let mut attrs = vec![("k8s.pod.uid".to_string(), "u1".to_string()), ("k8s.node.name".to_string(), "x".to_string())];
+        e.enrich("default", &mut attrs, Some("10.9.9.9".parse().unwrap()));
+        assert_eq!(attr(&attrs, "k8s.node.name"), Some("x"));
+        assert_eq!(attr(&attrs, "k8s.pod.name"), Some("web-5d9c7b8f4-u1"));
+
+        let mut attrs = Vec::new();
+        e.enrich("default", &mut attrs, Some("10.9.9.9".parse().unwrap()));
+        assert!(attrs.is_empty());
+        let s = e.stats();
+        assert_eq!((s.pods, s.hits, s.misses), (1, 2, 1));
+    }
+
+    #[test]
+    fn enrichment_is_scoped_to_listed_tenants_and_namespaces() {
+        let e = K8sEnricher::new(K8sEnrichConfig {
+            namespace: None,
+            pod_labels: true,
+            peer_ip: false,
+            tenants: parse_tenants("default, acme=prod|staging, other=kube-system"),
+        });
+        e.upsert(PodMeta::from_pod(&pod("u1", "10.0.0.5"), true).unwrap());
+        let by_uid = || vec![("k8s.pod.uid".to_string(), "u1".to_string())];
+
+        // Unlisted tenant: untouched, not even counted.
+        let mut attrs = by_uid();
+        e.enrich("intruder", &mut attrs, None);
+        assert_eq!(attrs.len(), 1);
+        // Listed tenant, pod outside its namespaces: a miss.
+        let mut attrs = by_uid();
+        e.enrich("other", &mut attrs, None);
+        assert_eq!(attrs.len(), 1);
+        // Listed tenant and namespace.
+        let mut attrs = by_uid();
+        e.enrich("acme", &mut attrs, None);
+        assert_eq!(attr(&attrs, "k8s.namespace.name"), Some("prod"));
+
+        // Peer fallback is off: a bare resource from the pod's IP stays bare.
+        let mut attrs = Vec::new();
+        e.enrich("default", &mut attrs, Some("10.0.0.5".parse().unwrap()));
+        assert!(attrs.is_empty());
+        let s = e.stats();
+        assert_eq!((s.hits, s.misses), (1, 1));
+    }
+
+    #[test]
+    fn reused_ip_follows_the_newest_pod() {
+        let e = enricher();
+        e.upsert(PodMeta::from_pod(&pod("old", "10.0.0.5"), true).unwrap());
+        e.upsert(PodMeta::from_pod(&pod("new", "10.0.0.5"), true).unwrap());
+        e.remove("old");
+        let mut attrs = Vec::new();
+        e.enrich("default", &mut attrs, Some("10.0.0.5".parse().unwrap()));
+        assert_eq!(attr(&attrs, "k8s.pod.uid"), Some("new"));
+
+        e.retain(&HashSet::new());
+        assert_eq!(e.stats().pods, 0);
+        assert!(e.by_ip.is_empty());
+    }
+}
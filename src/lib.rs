@@ -1,17 +1,28 @@
 pub mod alert_engine;
 pub mod anomaly_engine;
+pub mod cardinality_limiter;
 pub mod ch_writer;
 pub mod clickhouse_config;
 pub mod config;
 pub mod eval_state;
+pub mod export_scheduler;
 pub mod handlers;
+pub mod k8s_enrich;
+pub mod live_tail;
+pub mod log_patterns;
 pub mod metric_firewall;
+pub mod metric_naming;
 pub mod migrations;
 pub mod models;
 pub mod monitor_engine;
 pub mod object_store_spool;
+pub mod pipeql;
 pub mod promql;
 pub mod query_builder;
+pub mod query_cache;
+pub mod query_explain;
+pub mod query_jobs;
+pub mod query_limits;
 pub mod retention_enforcer;
 pub mod rollup;
 pub mod saml;
@@ -19,8 +30,10 @@ pub mod siem_engine;
 pub mod slo_engine;
 pub mod spool;
 pub mod stats_engine;
+pub mod temporality;
 pub mod usage_accumulator;
 pub mod usage_tracker;
+pub mod wal;
 
 use clickhouse::Client;
 use clickhouse::query::Query;
@@ -81,15 +94,42 @@ pub fn row_policy_supported() -> bool {
 /// configured), the query runs without it — the API-layer WHERE clause is still the primary
 /// tenant isolation mechanism.
 pub fn tenant_query(ch: &Client, sql: &str, tenant_id: &str) -> Query {
-    // Read guardrails: cap result sets so a single pathological query (PromQL over a
+    // Read guardrails: the tenant's scan limits (see `query_limits`) cap execution
+    // time, bytes read and result rows, so a single pathological query (PromQL over a
     // huge range, export with a wide window, etc.) cannot stream unbounded rows into
-    // this process. `break` truncates silently at the cap instead of erroring, which
-    // is acceptable for the read path. Note: deliberately NOT setting readonly=2 here
-    // because this client is shared with paths that set their own settings.
-    let q = ch
-        .query(sql)
-        .with_option("max_result_rows", "500000")
-        .with_option("result_overflow_mode", "break");
+    // this process. The default row cap truncates silently (`break`), which is
+    // acceptable for the read path; a row limit set for the tenant fails the query
+    // instead, so it surfaces as a 422 (see `query_limits::result_overflow_mode`).
+    // Note: deliberately NOT setting readonly=2 here because this client is shared
+    // with paths that set their own settings.
+    //
+    // Inside a query job (see `query_jobs`) the query is tagged with the job's
+    // query_id for progress/KILL, gets the job's time and row limits, and fails
+    // rather than truncating at the row cap. So does a read whose chunks the
+    // result cache is going to keep (see `query_cache::filling`).
+    //
+    // In a dry run (see `query_explain`) the statement is recorded and read
+    // with LIMIT 0, so the handler completes without scanning anything.
+    let dry_run_sql;
+    let sql = if query_explain::capture(sql) {
+        dry_run_sql = format!("SELECT * FROM ({sql}) LIMIT 0");
+        dry_run_sql.as_str()
+    } else {
+        sql
+    };
+    let mut q = ch.query(sql);
+    for (name, value) in query_limits::settings(tenant_id) {
+        q = q.with_option(name, value);
+    }
+    let q = match query_jobs::current_query_settings() {
+        Some(job) => q
+            .with_option("query_id", job.query_id)
+            .with_option("max_execution_time", job.max_execution_time)
+            .with_option("max_result_rows", job.max_result_rows)
+            .with_option("result_overflow_mode", "throw"),
+        None if query_cache::filling() => q.with_option("result_overflow_mode", "throw"),
+        None => q.with_option("result_overflow_mode", query_limits::result_overflow_mode(tenant_id)),
+    };
     if ROW_POLICY_SUPPORTED.load(Ordering::Relaxed) == 1 {
         q.with_option("rush_tenant_id", tenant_id)
     } else {
@@ -97,6 +137,19 @@ pub fn tenant_query(ch: &Client, sql: &str, tenant_id: &str) -> Query {
     }
 }
 
+/// `tenant_query` for a statement with `?` placeholders, which `bind` fills
+/// in. A dry run records the statement with the values in place, so it can be
+/// explained (binding after `tenant_query` would record the bare `?`s).
+pub fn tenant_query_bound(ch: &Client, sql: &str, tenant_id: &str, bind: impl FnOnce(Query) -> Query) -> Query {
+    if !query_explain::active() {
+        return bind(tenant_query(ch, sql, tenant_id));
+    }
+    // Render the bound statement and read it back as a template, with any `?`
+    // inside a bound value escaped.
+    let bound = bind(ch.query(sql)).sql_display().to_string();
+    tenant_query(ch, &bound.replace('?', "??"), tenant_id)
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub ch: Client,
@@ -110,4 +163,10 @@ pub struct AppState {
     pub login_limiter: Arc<DashMap<String, (u32, Instant)>>,
     /// API key resolution cache: key_hash → (tenant_id, cached_at). TTL 60s.
     pub api_key_cache: Arc<DashMap<String, (String, Instant)>>,
+    /// Kubernetes metadata enrichment for ingest (`RUSH_K8S_ENRICH=true`).
+    pub k8s: Option<Arc<k8s_enrich::K8sEnricher>>,
+    /// Background query jobs (`/api/v1/jobs`).
+    pub jobs: Arc<query_jobs::QueryJobs>,
+    /// Chunked result cache for range queries (`query_cache`).
+    pub query_cache: Arc<query_cache::QueryCache>,
 }
@@ -0,0 +1,731 @@
+//! Live tail: push logs and spans to connected clients as they are ingested.
+//!
+//! `ChWriter::write` hands every accepted Logs / SpansRaw / Spans batch to
+//! `LiveTail::publish` after the firewall and limits: with batching on once
+//! the rows are buffered (and in the WAL, if on), with batching off once they
+//! are inserted or spooled. Rows that fail the WAL append or, unbatched, the
+//! spool are never shown; with batching on, rows a later flush can't spool
+//! (their tenant over its spool share during an outage) have already been
+//! shown. Each subscriber carries the same `filters` / `search` shapes as
+//! `LogQueryRequest` / `QueryRequest`, evaluated in memory with the SQL
+//! semantics of `resolve_log_field` / `resolve_field` and the free-text search
+//! builders.
+//!
+//! Subscribers only ever see their own tenant's rows. Delivery never blocks
+//! ingest: each subscriber has a bounded buffer and a rows-per-second token
+//! bucket, and rows that don't fit either are dropped and reported to the
+//! client as a `dropped` count. With no subscribers, `publish` is a single
+//! atomic load.
+//!
+//! Tails are served over SSE, or over WebSocket when the request is an
+//! upgrade (see `respond`).
+
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade, rejection::WebSocketUpgradeRejection};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::ch_writer::SpoolBatch;
+use crate::config::env_or;
+use crate::models::ingest::{LogInsertRow, TraceInsertRow};
+use crate::models::query::{Filter, FilterOp};
+use crate::models::trace::WideEvent;
+use crate::query_builder::{SearchMatcher, like_match, search_term_pattern};
+
+const DEFAULT_BUFFER: usize = 1_000;
+const DEFAULT_MAX_ROWS_PER_SEC: u32 = 200;
+const DEFAULT_MAX_SUBSCRIBERS: usize = 256;
+const DEFAULT_MAX_PER_TENANT: usize = 16;
+
+/// How often a quiet stream checks for a pending `dropped` notice.
+const DROPPED_NOTICE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+pub struct TailConfig {
+    /// Rows buffered per subscriber before new rows are dropped.
+    pub buffer: usize,
+    /// Rows delivered per subscriber per second (burst of the same size).
+    pub max_rows_per_sec: u32,
+    pub max_subscribers: usize,
+    pub max_per_tenant: usize,
+}
+
+impl Default for TailConfig {
+    fn default() -> Self {
+        TailConfig {
+            buffer: DEFAULT_BUFFER,
+            max_rows_per_sec: DEFAULT_MAX_ROWS_PER_SEC,
+            max_subscribers: DEFAULT_MAX_SUBSCRIBERS,
+            max_per_tenant: DEFAULT_MAX_PER_TENANT,
+        }
+    }
+}
+
+impl TailConfig {
+    /// `RUSH_TAIL_BUFFER` (default 1000), `RUSH_TAIL_MAX_ROWS_PER_SEC` (200),
+    /// `RUSH_TAIL_MAX_SUBSCRIBERS` (256) and `RUSH_TAIL_MAX_PER_TENANT` (16).
+    pub fn from_env() -> Self {
+        let d = TailConfig::default();
+        TailConfig {
+            buffer: env_or("RUSH_TAIL_BUFFER", d.buffer).max(1),
+            max_rows_per_sec: env_or("RUSH_TAIL_MAX_ROWS_PER_SEC", d.max_rows_per_sec).max(1),
+            max_subscribers: env_or("RUSH_TAIL_MAX_SUBSCRIBERS", d.max_subscribers),
+            max_per_tenant: env_or("RUSH_TAIL_MAX_PER_TENANT", d.max_per_tenant),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailSignal {
+    Logs,
+    Spans,
+}
+
+/// Query string of the tail endpoints: `filters` is a JSON array of `Filter`
+/// objects (the body shape of the query endpoints), `search` the free-text
+/// search string.
+#[derive(Debug, Default, Deserialize)]
+pub struct TailParams {
+    #[serde(default)]
+    pub filters: Option<String>,
+    #[serde(default)]
+    pub search: Option<String>,
+}
+
+impl TailParams {
+    pub fn parse(&self) -> Result<TailFilter, (StatusCode, String)> {
+        if let Some(ref s) = self.search
+            && s.len() > 512
+        {
+            return Err((StatusCode::BAD_REQUEST, "search query too long (max 512 chars)".into()));
+        }
+        let filters: Vec<Filter> = match self.filters.as_deref().map(str::trim) {
+            None | Some("") => Vec::new(),
+            Some(raw) => serde_json::from_str(raw)
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("invalid filters: {e}")))?,
+        };
+        Ok(TailFilter::new(filters, self.search.as_deref()))
+    }
+}
+
+/// A subscriber's compiled filters + search.
+#[derive(Debug)]
+pub struct TailFilter {
+    filters: Vec<Filter>,
+    search: Option<SearchMatcher>,
+}
+
+impl TailFilter {
+    pub fn new(filters: Vec<Filter>, search: Option<&str>) -> Self {
+        TailFilter { filters, search: search.and_then(SearchMatcher::parse) }
+    }
+
+    fn matches(&self, row: &dyn TailRow) -> bool {
+        self.filters.iter().all(|f| filter_matches(f, row.field(&f.field)))
+            && self
+                .search
+                .as_ref()
+                .is_none_or(|s| s.matches(&|term| row.term(term), &|k, v| row.kv(k, v)))
+    }
+}
+
+/// A row as the tail filters see it.
+trait TailRow {
+    /// The value of a filter field, or `None` where the SQL expression is NULL.
+    fn field(&self, name: &str) -> Option<Cow<'_, str>>;
+    /// Whether a free-text search term matches.
+    fn term(&self, term: &str) -> bool;
+    /// Whether a `key=value` search pair matches.
+    fn kv(&self, key: &str, value: &str) -> bool;
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], key: &str) -> &'a str {
+    attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str()).unwrap_or("")
+}
+
+/// Exact 32-hex / 16-hex search terms are trace / span id lookups.
+fn id_term(term: &str) -> Option<usize> {
+    let t = term.trim();
+    (!t.is_empty() && t.chars().all(|c| c.is_ascii_hexdigit()) && matches!(t.len(), 16 | 32)).then_some(t.len())
+}
+
+/// `key=value` search value: exact, or case-insensitive with `*` wildcards.
+fn kv_value_matches(actual: &str, value: &str) -> bool {
+    if value.contains('*') {
+        let pattern = value.to_lowercase().replace('%', "\\%").replace('_', "\\_").replace('*', "%");
+        like_match(&pattern, &actual.to_lowercase())
+    } else {
+        actual == value
+    }
+}
+
+impl TailRow for LogInsertRow {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        Some(match name {
+            "service_name" | "ServiceName" => Cow::Borrowed(self.service_name.as_str()),
+            "severity" | "severity_text" | "SeverityText" => Cow::Borrowed(self.severity_text.as_str()),
+            "severity_number" | "SeverityNumber" => Cow::Owned(self.severity_number.to_string()),
+            "body" | "Body" => Cow::Borrowed(self.body.as_str()),
+            "trace_id" | "TraceId" => Cow::Borrowed(self.trace_id.as_str()),
+            "span_id" | "SpanId" => Cow::Borrowed(self.span_id.as_str()),
+            "scope_name" | "ScopeName" => Cow::Borrowed(&*self.scope_name),
+            _ => {
+                if let Some(key) = name.strip_prefix("resource.") {
+                    Cow::Borrowed(attr(&self.resource_attributes, key))
+                } else if let Some(key) = name.strip_prefix("log.") {
+                    Cow::Borrowed(attr(&self.log_attributes, key))
+                } else {
+                    let v = attr(&self.log_attributes, name);
+                    Cow::Borrowed(if v.is_empty() { attr(&self.resource_attributes, name) } else { v })
+                }
+            }
+        })
+    }
+
+    fn term(&self, term: &str) -> bool {
+        match id_term(term) {
+            Some(32) => self.trace_id == term.trim(),
+            Some(_) => self.span_id == term.trim(),
+            None => like_match(&search_term_pattern(term), &self.body.to_lowercase()),
+        }
+    }
+
+    fn kv(&self, key: &str, value: &str) -> bool {
+        kv_value_matches(attr(&self.log_attributes, key), value)
+            || kv_value_matches(attr(&self.resource_attributes, key), value)
+    }
+}
+
+/// A span as stored in `spans`, with its attributes parsed for lookups.
+struct SpanView {
+    event: WideEvent,
+    attrs: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SpanView {
+    fn from_wide(event: &WideEvent) -> Self {
+        let attrs = match serde_json::from_str(&event.attributes) {
+            Ok(serde_json::Value::Object(m)) => m,
+            _ => serde_json::Map::new(),
+        };
+        SpanView { event: event.clone(), attrs }
+    }
+
+    /// The `spans_mv` projection of a `spans_raw` row.
+    fn from_raw(r: &TraceInsertRow) -> Self {
+        let a = |k: &str| attr(&r.span_attributes, k);
+        let first = |keys: &[&str]| keys.iter().map(|k| a(k)).find(|v| !v.is_empty()).unwrap_or("");
+        let http_path = match first(&["http.route", "http.target", "url.path"]) {
+            "" => r.span_name.clone(),
+            p => p.to_string(),
+        };
+        let attrs: serde_json::Map<String, serde_json::Value> = r
+            .span_attributes
+            .iter()
+            .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+            .collect();
+        let event = WideEvent {
+            tenant_id: r.tenant_id.to_string(),
+            timestamp: r.timestamp,
+            trace_id: r.trace_id.clone(),
+            span_id: r.span_id.clone(),
+            parent_span_id: r.parent_span_id.clone(),
+            service_name: r.service_name.to_string(),
+            span_name: r.span_name.clone(),
+            kind: r.span_kind.clone(),
+            status: r.status_code.clone(),
+            duration_ns: r.duration,
+            http_method: a("http.method").to_string(),
+            http_path,
+            http_status_code: first(&["http.status_code", "http.response.status_code"]).parse().unwrap_or(0),
+            attributes: serde_json::Value::Object(attrs.clone()).to_string(),
+            event_names: r.events_name.clone(),
+            event_timestamps: r.events_timestamp.clone(),
+            event_attributes: r
+                .events_attributes
+                .iter()
+                .map(|ea| {
+                    let m: serde_json::Map<String, serde_json::Value> =
+                        ea.iter().map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone()))).collect();
+                    serde_json::Value::Object(m).to_string()
+                })
+                .collect(),
+            link_trace_ids: r.links_trace_id.clone(),
+            link_span_ids: r.links_span_id.clone(),
+        };
+        SpanView { event, attrs }
+    }
+
+    /// `JSONExtractString(attributes, key)`, then the nested path.
+    fn attribute(&self, key: &str) -> Cow<'_, str> {
+        fn as_str(v: &serde_json::Value) -> Cow<'_, str> {
+            match v {
+                serde_json::Value::String(s) => Cow::Borrowed(s.as_str()),
+                _ => Cow::Borrowed(""),
+            }
+        }
+        if let Some(v) = self.attrs.get(key) {
+            let s = as_str(v);
+            if !s.is_empty() {
+                return s;
+            }
+        }
+        let mut parts = key.split('.');
+        let mut cur = parts.next().and_then(|p| self.attrs.get(p));
+        for p in parts {
+            cur = cur.and_then(|v| v.get(p));
+        }
+        cur.map(as_str).unwrap_or(Cow::Borrowed(""))
+    }
+}
+
+impl TailRow for SpanView {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let e = &self.event;
+        if let Some(key) = name.strip_prefix("attributes.") {
+            return Some(self.attribute(key));
+        }
+        Some(match name {
+            "level" => Cow::Owned(e.status.to_lowercase()),
+            "timestamp" => Cow::Owned(e.timestamp.to_string()),
+            "trace_id" => Cow::Borrowed(e.trace_id.as_str()),
+            "span_id" => Cow::Borrowed(e.span_id.as_str()),
+            "parent_span_id" => Cow::Borrowed(e.parent_span_id.as_str()),
+            "service_name" => Cow::Borrowed(e.service_name.as_str()),
+            "span_name" => Cow::Borrowed(e.span_name.as_str()),
+            "kind" => Cow::Borrowed(e.kind.as_str()),
+            "status" => Cow::Borrowed(e.status.as_str()),
+            "duration_ns" => Cow::Owned(e.duration_ns.to_string()),
+            "http_method" => Cow::Borrowed(e.http_method.as_str()),
+            "http_path" => Cow::Borrowed(e.http_path.as_str()),
+            "http_status_code" => Cow::Owned(e.http_status_code.to_string()),
+            "attributes" => Cow::Borrowed(e.attributes.as_str()),
+            _ => return None,
+        })
+    }
+
+    fn term(&self, term: &str) -> bool {
+        let e = &self.event;
+        match id_term(term) {
+            Some(32) => e.trace_id == term.trim(),
+            Some(_) => e.span_id == term.trim(),
+            // `SEARCH_BLOB_EXPR`: attributes + event attributes.
+            None => {
+                let blob = format!("{} {}", e.attributes, e.event_attributes.join(" ")).to_lowercase();
+                like_match(&search_term_pattern(term), &blob)
+            }
+        }
+    }
+
+    fn kv(&self, key: &str, value: &str) -> bool {
+        let actual = match self.attrs.get(key) {
+            Some(serde_json::Value::String(s)) => s.as_str(),
+            _ => "",
+        };
+        kv_value_matches(actual, value)
+    }
+}
+
+/// The literal a filter value formats to in SQL (`format_value`).
+fn value_str(v: &serde_json::Value) -> Cow<'_, str> {
+    match v {
+        serde_json::Value::String(s) => Cow::Borrowed(s.as_str()),
+        serde_json::Value::Number(n) => Cow::Owned(n.to_string()),
+        serde_json::Value::Bool(b) => Cow::Borrowed(if *b { "1" } else { "0" }),
+        _ => Cow::Borrowed(""),
+    }
+}
+
+fn compare(actual: &str, v: &serde_json::Value) -> std::cmp::Ordering {
+    let expected = value_str(v);
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal),
+        _ => actual.cmp(expected.as_ref()),
+    }
+}
+
+/// IN-list items, split the way `format_array_value` splits them.
+fn list_items(v: &serde_json::Value) -> Vec<serde_json::Value> {
+    match v {
+        serde_json::Value::Array(items) => items.clone(),
+        serde_json::Value::String(s) => {
+            s.split(',').map(|p| serde_json::Value::String(p.trim().to_string())).collect()
+        }
+        other => vec![other.clone()],
+    }
+}
+
+fn filter_matches(f: &Filter, actual: Option<Cow<'_, str>>) -> bool {
+    use std::cmp::Ordering::*;
+    let Some(actual) = actual else { return false };
+    match f.op {
+        FilterOp::Eq => compare(&actual, &f.value) == Equal,
+        FilterOp::Ne => compare(&actual, &f.value) != Equal,
+        FilterOp::Gt => compare(&actual, &f.value) == Greater,
+        FilterOp::Gte => compare(&actual, &f.value) != Less,
+        FilterOp::Lt => compare(&actual, &f.value) == Less,
+        FilterOp::Lte => compare(&actual, &f.value) != Greater,
+        FilterOp::Like => like_match(&value_str(&f.value), &actual),
+        FilterOp::NotLike => !like_match(&value_str(&f.value), &actual),
+        FilterOp::In => list_items(&f.value).iter().any(|v| compare(&actual, v) == Equal),
+        FilterOp::NotIn => !list_items(&f.value).iter().any(|v| compare(&actual, v) == Equal),
+    }
+}
+
+/// The `query_logs` row shape (`LogRecord`).
+fn log_json(r: &LogInsertRow) -> String {
+    fn pairs(p: &[(String, String)]) -> serde_json::Map<String, serde_json::Value> {
+        p.iter().map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone()))).collect()
+    }
+    serde_json::json!({
+        "Timestamp": r.timestamp,
+        "TraceId": r.trace_id,
+        "SpanId": r.span_id,
+        "SeverityText": r.severity_text,
+        "SeverityNumber": r.severity_number,
+        "ServiceName": r.service_name,
+        "Body": r.body,
+        "ResourceAttributes": pairs(&r.resource_attributes),
+        "ScopeName": &*r.scope_name,
+        "LogAttributes": pairs(&r.log_attributes),
+    })
+    .to_string()
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+struct Subscriber {
+    tenant: String,
+    signal: TailSignal,
+    filter: TailFilter,
+    tx: mpsc::Sender<Arc<str>>,
+    bucket: Mutex<TokenBucket>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+    /// Queue a row, or count it as dropped when over the rate or the buffer
+    /// is full.
+    fn offer(&self, row: &Arc<str>, rate: f64) {
+        let allowed = {
+            let mut b = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            b.tokens = (b.tokens + now.duration_since(b.last).as_secs_f64() * rate).min(rate);
+            b.last = now;
+            if b.tokens >= 1.0 {
+                b.tokens -= 1.0;
+                true
+            } else {
+                false
+            }
+        };
+        if !allowed || self.tx.try_send(row.clone()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Hub of live-tail subscribers, fed by `ChWriter::write`.
+pub struct LiveTail {
+    cfg: TailConfig,
+    next_id: AtomicU64,
+    subs: DashMap<u64, Arc<Subscriber>>,
+    active: AtomicUsize,
+}
+
+impl LiveTail {
+    pub fn new(cfg: TailConfig) -> Self {
+        LiveTail { cfg, next_id: AtomicU64::new(0), subs: DashMap::new(), active: AtomicUsize::new(0) }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Register a tail. `None` when the global or per-tenant subscriber cap
+    /// is reached.
+    pub fn subscribe(self: &Arc<Self>, tenant: &str, signal: TailSignal, filter: TailFilter) -> Option<Subscription> {
+        if self.subs.len() >= self.cfg.max_subscribers
+            || self.subs.iter().filter(|s| s.tenant == tenant).count() >= self.cfg.max_per_tenant
+        {
+            return None;
+        }
+        let (tx, rx) = mpsc::channel(self.cfg.buffer);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let rate = self.cfg.max_rows_per_sec as f64;
+        self.subs.insert(
+            id,
+            Arc::new(Subscriber {
+                tenant: tenant.to_string(),
+                signal,
+                filter,
+                tx,
+                bucket: Mutex::new(TokenBucket { tokens: rate, last: Instant::now() }),
+                dropped: dropped.clone(),
+            }),
+        );
+        self.active.store(self.subs.len(), Ordering::Relaxed);
+        Some(Subscription { hub: self.clone(), id, rx, dropped })
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.subs.remove(&id);
+        self.active.store(self.subs.len(), Ordering::Relaxed);
+    }
+
+    /// End every open tail (graceful shutdown would otherwise wait on them).
+    pub fn close_all(&self) {
+        self.subs.clear();
+        self.active.store(0, Ordering::Relaxed);
+    }
+
+    /// Deliver a batch's rows to matching subscribers. Never blocks.
+    pub fn publish(&self, batch: &SpoolBatch) {
+        if self.subscribers() == 0 {
+            return;
+        }
+        let signal = match batch {
+            SpoolBatch::Logs(_) => TailSignal::Logs,
+            SpoolBatch::SpansRaw(_) | SpoolBatch::Spans(_) => TailSignal::Spans,
+            _ => return,
+        };
+        let subs: Vec<Arc<Subscriber>> =
+            self.subs.iter().filter(|s| s.signal == signal).map(|s| s.value().clone()).collect();
+        if subs.is_empty() {
+            return;
+        }
+        let rate = self.cfg.max_rows_per_sec as f64;
+        match batch {
+            SpoolBatch::Logs(rows) => {
+                for row in rows {
+                    deliver(&subs, &row.tenant_id, row, || log_json(row), rate);
+                }
+            }
+            SpoolBatch::SpansRaw(rows) => {
+                for row in rows.iter().filter(|r| subs.iter().any(|s| *s.tenant == *r.tenant_id)) {
+                    let view = SpanView::from_raw(row);
+                    deliver(&subs, &row.tenant_id, &view, || span_json(&view.event), rate);
+                }
+            }
+            SpoolBatch::Spans(rows) => {
+                for row in rows.iter().filter(|r| subs.iter().any(|s| s.tenant == r.tenant_id)) {
+                    let view = SpanView::from_wide(row);
+                    deliver(&subs, &row.tenant_id, &view, || span_json(&view.event), rate);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn span_json(e: &WideEvent) -> String {
+    serde_json::to_string(e).unwrap_or_default()
+}
+
+/// Offer one row to every subscriber of its tenant whose filter matches,
+/// serializing it at most once.
+fn deliver(subs: &[Arc<Subscriber>], tenant: &str, row: &dyn TailRow, json: impl FnOnce() -> String, rate: f64) {
+    let mut json = Some(json);
+    let mut payload: Option<Arc<str>> = None;
+    for sub in subs.iter().filter(|s| s.tenant == tenant) {
+        if !sub.filter.matches(row) {
+            continue;
+        }
+        let payload = payload.get_or_insert_with(|| Arc::from(json.take().map(|f| f()).unwrap_or_default()));
+        sub.offer(payload, rate);
+    }
+}
+
+pub enum TailEvent {
+    /// One row, as JSON.
+    Row(Arc<str>),
+    /// Rows dropped (rate limit or full buffer) since the last notice.
+    Dropped(u64),
+}
+
+/// An open tail; unregisters itself when dropped (client disconnect).
+pub struct Subscription {
+    hub: Arc<LiveTail>,
+    id: u64,
+    rx: mpsc::Receiver<Arc<str>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// The next event; `None` once the hub closed the tail.
+    pub async fn next(&mut self) -> Option<TailEvent> {
+        loop {
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                return Some(TailEvent::Dropped(dropped));
+            }
+            tokio::select! {
+                row = self.rx.recv() => return row.map(TailEvent::Row),
+                _ = tokio::time::sleep(DROPPED_NOTICE_INTERVAL) => {}
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.hub.unsubscribe(self.id);
+    }
+}
+
+/// Serve a tail over WebSocket when the request is an upgrade, else as SSE.
+///
+/// SSE sends each row as a default `message` event with the row JSON as
+/// data, and drops as a `dropped` event with `{"dropped": n}`. WebSocket
+/// sends the same JSON documents as text messages.
+pub fn respond(sub: Subscription, ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>) -> Response {
+    match ws {
+        Ok(ws) => ws.on_upgrade(move |socket| serve_ws(socket, sub)).into_response(),
+        Err(_) => {
+            let stream = futures_util::stream::unfold(sub, |mut sub| async move {
+                let event = match sub.next().await? {
+                    TailEvent::Row(row) => Event::default().data(&*row),
+                    TailEvent::Dropped(n) => Event::default().event("dropped").data(dropped_json(n)),
+                };
+                Some((Ok::<_, Infallible>(event), sub))
+            });
+            Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+        }
+    }
+}
+
+fn dropped_json(n: u64) -> String {
+    serde_json::json!({ "dropped": n }).to_string()
+}
+
+async fn serve_ws(mut socket: WebSocket, mut sub: Subscription) {
+    loop {
+        tokio::select! {
+            event = sub.next() => {
+                let text = match event {
+                    Some(TailEvent::Row(row)) => row.to_string(),
+                    Some(TailEvent::Dropped(n)) => dropped_json(n),
+                    None => break,
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    return;
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                _ => {}
+            }
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log(tenant: &str, service: &str, body: &str) -> LogInsertRow {
+        LogInsertRow {
+            tenant_id: tenant.into(),
+            timestamp: 1,
+            trace_id: "0af7651916cd43dd8448eb211c80319c".into(),
+            span_id: String::new(),
+            trace_flags: 0,
+            severity_text: "ERROR".into(),
+            severity_number: 17,
+            body: body.into(),
+            service_name: service.into(),
+            resource_schema_url: "".into(),
+            resource_attributes: Arc::new(vec![("k8s.namespace.name".into(), "prod".into())]),
+            scope_schema_url: "".into(),
+            scope_name: "".into(),
+            scope_version: "".into(),
+            scope_attributes: Arc::new(vec![]),
+            log_attributes: vec![("user.id".into(), "42".into())],
+            event_name: String::new(),
+        }
+    }
+
+    fn filter(field: &str, op: FilterOp, value: serde_json::Value) -> Filter {
+        Filter { field: field.into(), op, value }
+    }
+
+    #[test]
+    fn log_filters_and_search_follow_sql_semantics() {
+        let row = log("t1", "checkout", "Payment FAILED for order 7");
+        let m = |filters: Vec<Filter>, search: Option<&str>| TailFilter::new(filters, search).matches(&row);
+
+        assert!(m(vec![filter("service_name", FilterOp::Eq, json!("checkout"))], None));
+        assert!(m(vec![filter("resource.k8s.namespace.name", FilterOp::Eq, json!("prod"))], None));
+        assert!(m(vec![filter("user.id", FilterOp::Gte, json!(40))], None));
+        assert!(m(vec![filter("severity", FilterOp::In, json!("WARN,ERROR"))], None));
+        assert!(!m(vec![filter("body", FilterOp::NotLike, json!("%FAILED%"))], None));
+        assert!(m(vec![], Some("payment fail*")));
+        assert!(m(vec![], Some("timeout OR user.id=42")));
+        assert!(m(vec![], Some("0af7651916cd43dd8448eb211c80319c")));
+        assert!(!m(vec![], Some("payment timeout")));
+    }
+
+    #[test]
+    fn raw_spans_are_projected_like_spans_mv() {
+        let mut raw: TraceInsertRow = serde_json::from_value(json!({
+            "tenant_id": "t1", "Timestamp": 1, "TraceId": "a", "SpanId": "b", "ParentSpanId": "",
+            "TraceState": "", "SpanName": "GET /x", "SpanKind": "Server", "ServiceName": "api",
+            "ResourceAttributes": [], "ScopeName": "", "ScopeVersion": "", "SpanAttributes": [],
+            "Duration": 5, "StatusCode": "Error", "StatusMessage": "",
+            "Events.Timestamp": [], "Events.Name": [], "Events.Attributes": [],
+            "Links.TraceId": [], "Links.SpanId": [], "Links.TraceState": [], "Links.Attributes": []
+        }))
+        .unwrap();
+        raw.span_attributes = vec![
+            ("http.route".into(), "/users/{id}".into()),
+            ("http.response.status_code".into(), "503".into()),
+        ];
+        let view = SpanView::from_raw(&raw);
+        assert_eq!(view.event.http_path, "/users/{id}");
+        assert_eq!(view.event.http_status_code, 503);
+        let m = |filters: Vec<Filter>, search: Option<&str>| TailFilter::new(filters, search).matches(&view);
+        assert!(m(vec![filter("level", FilterOp::Eq, json!("error"))], None));
+        assert!(m(vec![filter("http_status_code", FilterOp::Gt, json!(499))], None));
+        assert!(m(vec![filter("attributes.http.route", FilterOp::Like, json!("/users/%"))], None));
+        assert!(m(vec![], Some("http.route=/users/*")));
+        assert!(!m(vec![filter("no_such_column", FilterOp::Ne, json!("x"))], None));
+    }
+
+    #[tokio::test]
+    async fn delivery_is_tenant_scoped_bounded_and_rate_limited() {
+        let hub = Arc::new(LiveTail::new(TailConfig { buffer: 2, max_rows_per_sec: 100, ..TailConfig::default() }));
+        let mut sub = hub.subscribe("t1", TailSignal::Logs, TailFilter::new(vec![], None)).unwrap();
+        let batch = SpoolBatch::Logs(vec![
+            log("t2", "a", "other tenant"),
+            log("t1", "a", "one"),
+            log("t1", "a", "two"),
+            log("t1", "a", "three"),
+        ]);
+        hub.publish(&batch);
+
+        let Some(TailEvent::Dropped(1)) = sub.next().await else { panic!("expected a drop notice") };
+        for want in ["one", "two"] {
+            let Some(TailEvent::Row(row)) = sub.next().await else { panic!("expected a row") };
+            assert_eq!(serde_json::from_str::<serde_json::Value>(&row).unwrap()["Body"], want);
+        }
+
+        assert_eq!(hub.subscribers(), 1);
+        drop(sub);
+        assert_eq!(hub.subscribers(), 0);
+    }
+}
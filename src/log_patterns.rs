@@ -0,0 +1,223 @@
+//! Drain-style log template mining for `/api/v1/logs/patterns`.
+//!
+//! Follows Drain (He et al., ICWS 2017): a line is tokenized on whitespace,
+//! tokens containing a digit are treated as parameters (`<*>`), and the line
+//! is routed through a fixed-depth tree keyed on token count and the first
+//! token (Drain's default depth of 4). Within that leaf it joins the most
+//! similar template if at least `SIM_THRESHOLD` of the positions agree,
+//! widening differing positions to `<*>`; otherwise it starts a new template.
+//!
+//! One miner is kept per (tenant, service) for `MINER_TTL` after its last use,
+//! so template ids stay stable across requests and later requests start from
+//! the templates already learned. Counts are not kept in the miner — each
+//! request counts its own window.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+pub const WILDCARD: &str = "<*>";
+
+/// Minimum fraction of matching token positions to join a template.
+const SIM_THRESHOLD: f64 = 0.4;
+/// Templates per miner; past this, lines only join existing templates or the
+/// overflow template for their token count (at most `MAX_TOKENS + 1` more).
+const MAX_TEMPLATES: usize = 5_000;
+/// Lines are mined on their first `MAX_TOKENS` tokens.
+const MAX_TOKENS: usize = 64;
+
+const MINER_TTL: Duration = Duration::from_secs(3600);
+const MAX_MINERS: usize = 10_000;
+
+type MinerEntry = (Arc<Mutex<Drain>>, Instant);
+
+/// (tenant, service) → miner and its last use.
+static MINERS: LazyLock<DashMap<(String, String), MinerEntry>> = LazyLock::new(DashMap::new);
+
+/// The cached miner for a tenant's service. When the cache is full, a fresh
+/// uncached miner is returned (template ids are then only stable within the
+/// request).
+pub fn miner(tenant_id: &str, service: &str) -> Arc<Mutex<Drain>> {
+    let now = Instant::now();
+    let key = (tenant_id.to_string(), service.to_string());
+    if let Some(mut entry) = MINERS.get_mut(&key) {
+        entry.1 = now;
+        return entry.0.clone();
+    }
+    if MINERS.len() >= MAX_MINERS {
+        MINERS.retain(|_, (_, used)| now.duration_since(*used) < MINER_TTL);
+        if MINERS.len() >= MAX_MINERS {
+            return Arc::new(Mutex::new(Drain::default()));
+        }
+    }
+    MINERS.entry(key).or_insert_with(|| (Arc::new(Mutex::new(Drain::default())), now)).0.clone()
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub id: u32,
+    pub tokens: Vec<String>,
+}
+
+impl Template {
+    pub fn text(&self) -> String {
+        self.tokens.join(" ")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Drain {
+    templates: Vec<Template>,
+    /// (token count, first token) → template ids.
+    leaves: HashMap<(usize, String), Vec<u32>>,
+    /// Token count → the all-wildcard template absorbing lines past `MAX_TEMPLATES`.
+    overflow: HashMap<usize, u32>,
+}
+
+fn is_param(token: &str) -> bool {
+    token == WILDCARD || token.bytes().any(|b| b.is_ascii_digit())
+}
+
+fn tokenize(line: &str) -> Vec<String> {
+    line.split_whitespace()
+        .take(MAX_TOKENS)
+        .map(|t| if is_param(t) { WILDCARD.to_string() } else { t.to_string() })
+        .collect()
+}
+
+/// Share of positions where the template has the same literal token, and
+/// the template's wildcard count (more wildcards wins a tie in Drain).
+fn similarity(template: &[String], tokens: &[String]) -> (f64, usize) {
+    let mut same = 0;
+    let mut params = 0;
+    for (t, tok) in template.iter().zip(tokens) {
+        if t == WILDCARD {
+            params += 1;
+        } else if t == tok {
+            same += 1;
+        }
+    }
+    (same as f64 / tokens.len().max(1) as f64, params)
+}
+
+impl Drain {
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn template(&self, id: u32) -> Option<&Template> {
+        self.templates.get(id as usize)
+    }
+
+    /// Mine one line; returns the id of the template it now belongs to.
+    pub fn add(&mut self, line: &str) -> u32 {
+        let tokens = tokenize(line);
+        let key = (tokens.len(), tokens.first().cloned().unwrap_or_default());
+        let leaf = self.leaves.entry(key).or_default();
+
+        let best = leaf
+            .iter()
+            .map(|&id| {
+                let (sim, params) = similarity(&self.templates[id as usize].tokens, &tokens);
+                (id, sim, params)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(a.2.cmp(&b.2)));
+
+        let at_cap = self.templates.len() >= MAX_TEMPLATES;
+        match best {
+            Some((id, sim, _)) if sim >= SIM_THRESHOLD || at_cap => {
+                let template = &mut self.templates[id as usize].tokens;
+                for (t, tok) in template.iter_mut().zip(&tokens) {
+                    if t != tok {
+                        *t = WILDCARD.to_string();
+                    }
+                }
+                id
+            }
+            _ if at_cap => {
+                // No template of this shape: one all-wildcard template per token
+                // count absorbs the overflow, so the miner stays bounded.
+                let len = tokens.len();
+                *self.overflow.entry(len).or_insert_with(|| {
+                    let id = self.templates.len() as u32;
+                    self.templates.push(Template { id, tokens: vec![WILDCARD.to_string(); len] });
+                    id
+                })
+            }
+            _ => {
+                let id = self.templates.len() as u32;
+                self.templates.push(Template { id, tokens });
+                leaf.push(id);
+                id
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_lines_share_a_template() {
+        let mut d = Drain::default();
+        let a = d.add("connected to db-7 in 12ms");
+        let b = d.add("connected to db-9 in 48ms");
+        let c = d.add("user alice logged in");
+        let e = d.add("user bob logged in");
+        let f = d.add("cache miss for key session");
+
+        assert_eq!(a, b);
+        assert_eq!(c, e);
+        assert_ne!(a, c);
+        assert_ne!(f, c);
+        assert_eq!(d.template(a).unwrap().text(), "connected to <*> in <*>");
+        assert_eq!(d.template(c).unwrap().text(), "user <*> logged in");
+        assert_eq!(d.len(), 3);
+    }
+
+    #[test]
+    fn dissimilar_lines_of_same_shape_stay_apart() {
+        let mut d = Drain::default();
+        let a = d.add("GET request served ok");
+        let b = d.add("GET upstream timed out");
+        assert_ne!(a, b);
+        assert_eq!(d.template(a).unwrap().text(), "GET request served ok");
+    }
+
+    #[test]
+    fn templates_stop_growing_at_the_cap() {
+        // Digit-free, distinct first tokens: every line starts its own template.
+        let word = |mut i: usize| {
+            let mut w = String::new();
+            loop {
+                w.push((b'a' + (i % 26) as u8) as char);
+                i /= 26;
+                if i == 0 {
+                    return w;
+                }
+            }
+        };
+        let mut d = Drain::default();
+        for i in 0..MAX_TEMPLATES {
+            d.add(&format!("{} line", word(i)));
+        }
+        assert_eq!(d.len(), MAX_TEMPLATES);
+
+        // New first tokens of an existing length share one overflow template.
+        let a = d.add("brand new line");
+        let b = d.add("another fresh line");
+        assert_eq!(a, b);
+        assert_eq!(d.template(a).unwrap().text(), "<*> <*> <*>");
+        for i in 0..1000 {
+            d.add(&format!("novel{} {}", word(i), word(i + 1)));
+        }
+        assert_eq!(d.len(), MAX_TEMPLATES + 2);
+    }
+}
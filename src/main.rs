@@ -258,6 +258,56 @@ async fn build_object_store_buffer(max_bytes: u64) -> anyhow::Result<IngestBuffe
     Ok(IngestBuffer::ObjectStore(s))
 }
 
+/// An S3/MinIO store from `{var}_S3_BUCKET`, `_S3_ENDPOINT`, `_S3_PREFIX`,
+/// `_S3_REGION`, `_S3_ACCESS_KEY` and `_S3_SECRET_KEY` (credentials fall
+/// back to `AWS_*`, as for the ingest buffer). `None` without a bucket or if
+/// the store can't be built.
+fn s3_store_from_env(var: &str, default_prefix: &str) -> Option<(Arc<dyn object_store::ObjectStore>, String)> {
+    let env = |name: &str| std::env::var(format!("{var}_S3_{name}"));
+    let bucket = env("BUCKET").ok().filter(|b| !b.is_empty())?;
+    let endpoint = env("ENDPOINT").unwrap_or_default();
+    let prefix = env("PREFIX").unwrap_or_else(|_| default_prefix.to_string());
+    let region = env("REGION").unwrap_or_else(|_| "us-east-1".to_string());
+    let access = env("ACCESS_KEY").or_else(|_| std::env::var("AWS_ACCESS_KEY_ID")).unwrap_or_default();
+    let secret = env("SECRET_KEY").or_else(|_| std::env::var("AWS_SECRET_ACCESS_KEY")).unwrap_or_default();
+    match rush_api::object_store_spool::s3_store(&endpoint, &bucket, &region, &access, &secret) {
+        Ok(store) => Some((store, prefix)),
+        Err(e) => {
+            tracing::warn!(error = %e, bucket = %bucket, "{var}_S3_* object store unavailable");
+            None
+        }
+    }
+}
+
+/// Build the range-query result cache from `RUSH_QUERY_CACHE_*` env. With
+/// `RUSH_QUERY_CACHE_S3_BUCKET` set, chunks are also written to S3/MinIO.
+/// How often cached tenant config (firewall rules, cardinality limits, naming
+/// modes, query limits) is reloaded, picking up changes made on other replicas.
+const CONFIG_REFRESH: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Run `refresh` every `interval` in the background. A failed run is logged and
+/// the previous state kept until the next one.
+fn spawn_refresh<F, Fut>(interval: std::time::Duration, name: &'static str, mut refresh: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = anyhow::Result<()>> + Send,
+{
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            if let Err(e) = refresh().await {
+                tracing::warn!(error = %e, task = name, "config refresh failed");
+            }
+        }
+    });
+}
+
+fn build_query_cache() -> rush_api::query_cache::QueryCache {
+    use rush_api::query_cache::{CacheConfig, QueryCache};
+    QueryCache::new(CacheConfig::from_env(), s3_store_from_env("RUSH_QUERY_CACHE", "query-cache/"))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
@@ -343,6 +393,8 @@ async fn main() -> anyhow::Result<()> {
     if rush_api::row_policy_supported() {
         migrations::apply_row_policies(&ch).await;
     }
+    // Timeouts are only reported as the tenant's limit when it is tighter than the profile's.
+    rush_api::query_limits::probe_profile_timeout(&ch).await;
 
     let config_db = Arc::new(
         ConfigDb::open(&clickhouse_url, &clickhouse_user, &clickhouse_password).await?
@@ -425,7 +477,7 @@ async fn main() -> anyhow::Result<()> {
 
     // Spawn the Datadog-style monitor engine (v2 alerting)
     if engine_enabled("RUSH_RUN_MONITOR_ENGINE") {
-        monitor_engine::spawn(ch.clone(), config_db.clone(), smtp_config);
+        monitor_engine::spawn(ch.clone(), config_db.clone(), smtp_config.clone());
     } else {
         tracing::info!("in-process monitor engine disabled (RUSH_RUN_MONITOR_ENGINE=false); expecting a dedicated monitor-engine deployment");
     }
@@ -437,6 +489,24 @@ async fn main() -> anyhow::Result<()> {
     } else {
         tracing::info!("in-process siem engine disabled (RUSH_RUN_SIEM_ENGINE=false); expecting a dedicated siem-engine deployment");
     }
+
+    // Scheduled exports (saved query → object storage on a cron schedule).
+    if engine_enabled("RUSH_RUN_EXPORT_SCHEDULER") {
+        let target = s3_store_from_env("RUSH_EXPORT", "exports/")
+            .map(|(store, prefix)| rush_api::export_scheduler::ExportTarget { store, prefix });
+        if target.is_none() {
+            tracing::warn!("RUSH_EXPORT_S3_BUCKET not set; scheduled export runs will fail until it is");
+        }
+        rush_api::export_scheduler::spawn(
+            ch.clone(),
+            config_db.clone(),
+            smtp_config.clone(),
+            target,
+            rush_api::export_scheduler::ExportSchedulerConfig::from_env(),
+        );
+    } else {
+        tracing::info!("in-process export scheduler disabled (RUSH_RUN_EXPORT_SCHEDULER=false)");
+    }
     } // end `if !drain_only` (background engines)
 
     // ── Durable write path: spool + writer ──
@@ -467,7 +537,29 @@ async fn main() -> anyhow::Result<()> {
         let spool = Spool::open(&spool_dir, spool_max_bytes).expect("failed to open spool directory");
         std::sync::Arc::new(IngestBuffer::Disk(spool))
     };
-    let writer = ChWriter::new(ch.clone(), buffer);
+    buffer.set_tenant_policy(rush_api::spool::TenantPolicy::from_env());
+    let mut writer = ChWriter::new(ch.clone(), buffer);
+    // Durable-ack mode: batched rows go through a local WAL before the 200.
+    // Leftovers from a previous run are re-written before we start serving.
+    // Without batching every insert completes before the ack, so there is
+    // nothing for the WAL to cover; its leftovers are still replayed.
+    if !drain_only && let Some(wal_cfg) = rush_api::wal::WalConfig::from_env() {
+        let (wal, leftovers) = rush_api::wal::Wal::open(&wal_cfg).expect("failed to open ingest WAL directory");
+        if writer.batch_config().disabled() {
+            tracing::warn!("RUSH_INGEST_DURABILITY=wal ignored: insert batching is disabled, inserts are synchronous");
+        } else {
+            writer = writer.with_wal(wal);
+            tracing::info!(
+                dir = %wal_cfg.dir.display(),
+                commit_ms = wal_cfg.commit_interval.as_millis() as u64,
+                "ingest durability: wal"
+            );
+        }
+        if !leftovers.is_empty() {
+            tracing::info!(segments = leftovers.len(), "replaying ingest WAL");
+            writer.replay_wal(leftovers).await;
+        }
+    }
     if drain_only || run_replayer {
         writer.clone().spawn_replayer();
     }
@@ -483,9 +575,19 @@ async fn main() -> anyhow::Result<()> {
             "ingest insert batching configured"
         );
     }
-    // Stats engine (emits ingest-buffer depth/age/drain metrics). API process only.
+    // Kubernetes metadata enrichment (opt-in, API process only): pod index fed
+    // by a watch.
+    let k8s = rush_api::k8s_enrich::K8sEnrichConfig::from_env()
+        .filter(|_| !drain_only)
+        .map(|cfg| {
+            let enricher = std::sync::Arc::new(rush_api::k8s_enrich::K8sEnricher::new(cfg));
+            enricher.spawn_watcher();
+            enricher
+        });
+    // Stats engine (emits ingest-buffer depth/age/drain and k8s enrichment
+    // metrics). API process only.
     if !drain_only {
-        stats_engine::spawn_stats_engine(ch.clone(), writer.buffer.clone());
+        stats_engine::spawn_stats_engine(ch.clone(), writer.buffer.clone(), k8s.clone());
     }
 
     // Drain-worker-only: this process exists solely to drain the ingest buffer
@@ -506,13 +608,12 @@ async fn main() -> anyhow::Result<()> {
     {
         let fw_handle = writer.firewall.clone();
         let cdb = config_db.clone();
-        tokio::spawn(async move {
-            let mut tick = tokio::time::interval(std::time::Duration::from_secs(30));
-            loop {
-                tick.tick().await;
-                if let Ok(fw) = cdb.compiled_metric_firewall().await {
-                    if let Ok(mut g) = fw_handle.write() { *g = Arc::new(fw); }
-                }
+        spawn_refresh(CONFIG_REFRESH, "metric firewall", move || {
+            let (fw_handle, cdb) = (fw_handle.clone(), cdb.clone());
+            async move {
+                let fw = cdb.compiled_metric_firewall().await?;
+                if let Ok(mut g) = fw_handle.write() { *g = Arc::new(fw); }
+                Ok(())
             }
         });
     }
@@ -520,6 +621,69 @@ async fn main() -> anyhow::Result<()> {
     // Spawn usage tracker (fire-and-forget signal usage tracking)
     let usage = usage_tracker::spawn(ch.clone());
 
+    // Cardinality limiter: same load-now-then-refresh pattern as the firewall.
+    // The refresh tick also garbage-collects expired series. Limited batches
+    // are reported as usage events and notified on the limit's channels.
+    if let Ok(cfg) = config_db.compiled_cardinality_limits().await {
+        writer.cardinality.set_config(cfg);
+    }
+    writer.cardinality.spawn_notifier(config_db.clone(), usage.clone(), smtp_config.clone());
+    {
+        let limiter = writer.cardinality.clone();
+        let cdb = config_db.clone();
+        spawn_refresh(CONFIG_REFRESH, "cardinality limits", move || {
+            let (limiter, cdb) = (limiter.clone(), cdb.clone());
+            async move {
+                let loaded = cdb.compiled_cardinality_limits().await.map(|cfg| limiter.set_config(cfg));
+                limiter.gc(rush_api::cardinality_limiter::now_secs());
+                loaded
+            }
+        });
+    }
+
+    // Per-tenant metric naming mode (OTel vs Prometheus-normalized): load now,
+    // then refresh so changes made on another replica are picked up.
+    if let Ok(rows) = config_db.list_tenant_metric_naming().await {
+        rush_api::metric_naming::set_modes(&rows);
+    }
+    {
+        let cdb = config_db.clone();
+        spawn_refresh(CONFIG_REFRESH, "metric naming modes", move || {
+            let cdb = cdb.clone();
+            async move {
+                rush_api::metric_naming::set_modes(&cdb.list_tenant_metric_naming().await?);
+                Ok(())
+            }
+        });
+    }
+
+    // Per-tenant query limit overrides: same load-then-refresh as naming modes.
+    if let Ok(rows) = config_db.list_tenant_query_limits().await {
+        rush_api::query_limits::set_overrides(rows);
+    }
+    {
+        let cdb = config_db.clone();
+        spawn_refresh(CONFIG_REFRESH, "query limit overrides", move || {
+            let cdb = cdb.clone();
+            async move {
+                rush_api::query_limits::set_overrides(cdb.list_tenant_query_limits().await?);
+                Ok(())
+            }
+        });
+    }
+
+    // Delta → cumulative state: forget series whose exporters went quiet.
+    if writer.temporality.enabled() {
+        let temporality = writer.temporality.clone();
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                tick.tick().await;
+                temporality.gc(rush_api::cardinality_limiter::now_secs());
+            }
+        });
+    }
+
     // Spawn usage accumulator (per-tenant ingest metering)
     let usage_accumulator = UsageAccumulator::new();
     usage_accumulator.spawn_flusher(ch.clone());
@@ -588,25 +752,54 @@ async fn main() -> anyhow::Result<()> {
         config: wide_config,
         login_limiter,
         api_key_cache,
+        k8s,
+        jobs: Arc::new(rush_api::query_jobs::QueryJobs::new(rush_api::query_jobs::JobsConfig::from_env())),
+        query_cache: Arc::new(build_query_cache()),
     };
 
+    // `dry_run=true` on trace, query, logs, export and PromQL requests returns the
+    // generated SQL and its cost estimate instead of results.
+    let dry_run = axum::middleware::from_fn_with_state(state.clone(), handlers::explain::dry_run);
+
+    // Per-tenant concurrency and rate limits on the query endpoints (scan
+    // limits are applied per statement in `tenant_query`).
+    let budget = axum::middleware::from_fn(rush_api::query_limits::enforce);
+
     let app = Router::new()
         // Trace endpoints
-        .route("/api/v1/traces/{trace_id}", get(handlers::traces::get_trace))
+        .route("/api/v1/traces/{trace_id}", get(handlers::traces::get_trace).route_layer(dry_run.clone()).route_layer(budget.clone()))
         // Query endpoints
-        .route("/api/v1/query", post(handlers::query::execute_query))
-        .route("/api/v1/query/count", post(handlers::query::count_query))
-        .route("/api/v1/query/group", post(handlers::query::group_query))
-        .route("/api/v1/query/timeseries", post(handlers::query::timeseries_query))
-        // Export current query + results (CSV/JSON), capped by export_max_rows
-        .route("/api/v1/query/export", post(handlers::query::export_query))
+        .route("/api/v1/query", post(handlers::query::execute_query).route_layer(dry_run.clone()).route_layer(budget.clone()))
+        .route("/api/v1/query/count", post(handlers::query::count_query).route_layer(budget.clone()))
+        .route("/api/v1/query/group", post(handlers::query::group_query).route_layer(budget.clone()))
+        .route("/api/v1/query/timeseries", post(handlers::query::timeseries_query).route_layer(budget.clone()))
+        // Export current query + results (CSV/JSON/NDJSON/Arrow/Parquet), capped by export_max_rows
+        .route("/api/v1/query/export", post(handlers::query::export_query).route_layer(dry_run.clone()).route_layer(budget.clone()))
+        // Range-query result cache size and hit ratio
+        .route("/api/v1/query/cache", get(handlers::query::cache_stats))
+        // Generated SQL and ClickHouse cost estimate for any query endpoint
+        .route("/api/v1/query/explain", post(handlers::explain::explain_query).route_layer(budget.clone()))
+        // Live tail of ingested spans (SSE or WebSocket)
+        .route("/api/v1/query/tail", get(handlers::query::tail_query))
+        // Pipe query language over spans or logs
+        .route("/api/v1/query/pipe", post(handlers::pipe_query::pipe_query).route_layer(budget.clone()))
+        // Asynchronous query jobs (a submitted job holds its budget slot until it finishes)
+        .route(
+            "/api/v1/jobs",
+            get(handlers::jobs::list_jobs).merge(post(handlers::jobs::submit_job).route_layer(budget.clone())),
+        )
+        .route("/api/v1/jobs/{id}", get(handlers::jobs::get_job).delete(handlers::jobs::cancel_job))
+        .route("/api/v1/jobs/{id}/results", get(handlers::jobs::job_results))
         // BubbleUp comparison analysis
-        .route("/api/v1/bubbleup", post(handlers::bubbleup::bubbleup))
+        .route("/api/v1/bubbleup", post(handlers::bubbleup::bubbleup).route_layer(budget.clone()))
         // Log endpoints
-        .route("/api/v1/logs", post(handlers::logs::query_logs))
-        .route("/api/v1/logs/count", post(handlers::logs::count_logs))
-        .route("/api/v1/logs/group", post(handlers::logs::group_logs))
-        .route("/api/v1/logs/export", post(handlers::logs::export_logs))
+        .route("/api/v1/logs", post(handlers::logs::query_logs).route_layer(dry_run.clone()).route_layer(budget.clone()))
+        .route("/api/v1/logs/count", post(handlers::logs::count_logs).route_layer(budget.clone()))
+        .route("/api/v1/logs/group", post(handlers::logs::group_logs).route_layer(budget.clone()))
+        .route("/api/v1/logs/export", post(handlers::logs::export_logs).route_layer(dry_run.clone()).route_layer(budget.clone()))
+        .route("/api/v1/logs/tail", get(handlers::logs::tail_logs))
+        .route("/api/v1/logs/patterns", post(handlers::logs::log_patterns).route_layer(budget.clone()))
+        .route("/api/v1/logs/context", post(handlers::logs::log_context).route_layer(budget.clone()))
         // Service catalog
         .route("/api/v1/services", get(handlers::services::list_services))
         .route("/api/v1/services/graph", get(handlers::services::service_graph))
@@ -648,6 +841,33 @@ async fn main() -> anyhow::Result<()> {
             "/api/v1/dashboards/{id}/export",
             get(handlers::dashboards::export_dashboard),
         )
+        // Saved queries (named Explore queries)
+        .route(
+            "/api/v1/saved-queries",
+            get(handlers::saved_queries::list_saved_queries).post(handlers::saved_queries::create_saved_query),
+        )
+        .route(
+            "/api/v1/saved-queries/{id}",
+            get(handlers::saved_queries::get_saved_query)
+                .put(handlers::saved_queries::update_saved_query)
+                .delete(handlers::saved_queries::delete_saved_query),
+        )
+        .route(
+            "/api/v1/saved-queries/{id}/execute",
+            post(handlers::saved_queries::execute_saved_query).route_layer(budget.clone()),
+        )
+        // Scheduled exports of saved queries to object storage
+        .route(
+            "/api/v1/scheduled-exports",
+            get(handlers::scheduled_exports::list_scheduled_exports).post(handlers::scheduled_exports::create_scheduled_export),
+        )
+        .route(
+            "/api/v1/scheduled-exports/{id}",
+            get(handlers::scheduled_exports::get_scheduled_export)
+                .put(handlers::scheduled_exports::update_scheduled_export)
+                .delete(handlers::scheduled_exports::delete_scheduled_export),
+        )
+        .route("/api/v1/scheduled-exports/{id}/runs", get(handlers::scheduled_exports::list_export_runs))
         // Dashboard template endpoints
         .route(
             "/api/v1/dashboard-templates",
@@ -802,23 +1022,33 @@ async fn main() -> anyhow::Result<()> {
         // Prometheus-compatible metrics API (for Grafana)
         .route(
             "/prom/api/v1/query",
-            get(handlers::metrics::prom_query).post(handlers::metrics::prom_query_post),
+            get(handlers::metrics::prom_query)
+                .post(handlers::metrics::prom_query_post)
+                .route_layer(dry_run.clone())
+                .route_layer(budget.clone()),
         )
         .route(
             "/prom/api/v1/query_range",
-            get(handlers::metrics::prom_query_range).post(handlers::metrics::prom_query_range_post),
+            get(handlers::metrics::prom_query_range)
+                .post(handlers::metrics::prom_query_range_post)
+                .route_layer(dry_run.clone())
+                .route_layer(budget.clone()),
         )
         .route(
             "/prom/api/v1/series",
-            get(handlers::metrics::prom_series).post(handlers::metrics::prom_series_post),
+            get(handlers::metrics::prom_series)
+                .post(handlers::metrics::prom_series_post)
+                .route_layer(budget.clone()),
         )
         .route(
             "/prom/api/v1/labels",
-            get(handlers::metrics::prom_labels).post(handlers::metrics::prom_labels),
+            get(handlers::metrics::prom_labels)
+                .post(handlers::metrics::prom_labels)
+                .route_layer(budget.clone()),
         )
         .route(
             "/prom/api/v1/label/{name}/values",
-            get(handlers::metrics::prom_label_values),
+            get(handlers::metrics::prom_label_values).route_layer(budget.clone()),
         )
         // Prometheus remote write
         .route(
@@ -891,6 +1121,16 @@ async fn main() -> anyhow::Result<()> {
             "/api/v1/tenants/{id}/auth",
             put(handlers::tenants::set_auth_required),
         )
+        .route(
+            "/api/v1/tenants/{id}/metric-naming",
+            get(handlers::tenants::get_metric_naming).put(handlers::tenants::set_metric_naming),
+        )
+        .route(
+            "/api/v1/tenants/{id}/query-limits",
+            get(handlers::tenants::get_query_limits)
+                .put(handlers::tenants::set_query_limits)
+                .delete(handlers::tenants::delete_query_limits),
+        )
         // Global retention caps (default + per-signal maximums)
         .route(
             "/api/v1/retention/global",
@@ -899,7 +1139,14 @@ async fn main() -> anyhow::Result<()> {
         )
         // Ingest buffer status (durable spool depth + backend)
         .route("/api/v1/ingest/buffer", get(handlers::ingest_buffer::buffer_status))
-        // Metric firewall (ingest-time block / drop-label rules)
+        // Dead-letter (quarantined spool records): list / inspect / re-drive / delete
+        .route("/api/v1/ingest/deadletter", get(handlers::ingest_buffer::list_dead_letters))
+        .route(
+            "/api/v1/ingest/deadletter/{id}",
+            get(handlers::ingest_buffer::get_dead_letter).delete(handlers::ingest_buffer::delete_dead_letter),
+        )
+        .route("/api/v1/ingest/deadletter/{id}/redrive", post(handlers::ingest_buffer::redrive_dead_letter))
+        // Metric firewall (ingest-time block / drop-label / relabel rules)
         .route(
             "/api/v1/metric-firewall",
             get(handlers::metric_firewall::list)
@@ -910,6 +1157,16 @@ async fn main() -> anyhow::Result<()> {
             put(handlers::metric_firewall::update)
                 .delete(handlers::metric_firewall::delete),
         )
+        // Per-tenant cardinality limits (metric `*` = tenant default)
+        .route(
+            "/api/v1/tenants/{id}/cardinality-limits",
+            get(handlers::cardinality_limits::list),
+        )
+        .route(
+            "/api/v1/tenants/{id}/cardinality-limits/{metric}",
+            put(handlers::cardinality_limits::upsert)
+                .delete(handlers::cardinality_limits::delete),
+        )
         // Tenant retention overrides
         .route(
             "/api/v1/tenants/{id}/retention",
@@ -976,7 +1233,7 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/v1/argocd/applications/{name}", get(handlers::argocd::get_application))
         .route("/api/v1/argocd/applicationsets", get(handlers::argocd::list_applicationsets))
         // Stats
-        .route("/api/v1/stats", post(handlers::stats::get_stats))
+        .route("/api/v1/stats", post(handlers::stats::get_stats).route_layer(budget.clone()))
         // Signal usage
         .route("/api/v1/usage", get(handlers::usage::get_usage))
         .route("/api/v1/usage/cardinality/{metric}", get(handlers::usage::get_label_breakdown))
@@ -1182,8 +1439,14 @@ async fn main() -> anyhow::Result<()> {
     // Graceful shutdown: on SIGINT/SIGTERM, stop accepting new connections, let
     // in-flight requests finish, then flush any buffered ingest rows so the
     // cross-request batcher never drops in-memory rows on a clean shutdown.
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
+    // Connect info lets ingest handlers key Kubernetes enrichment on the peer IP.
+    // Open live tails never finish on their own, so they are closed on the signal.
+    let tail = shutdown_writer.tail.clone();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            tail.close_all();
+        })
         .await?;
     tracing::info!("graceful shutdown: flushing buffered ingest batches");
     shutdown_writer.flush_all().await;
@@ -8,25 +8,56 @@
 //!   - **blocks** the datapoint (drops the whole series), matched by metric name
 //!     (literal/regex) and/or a label key+value (literal/regex), or
 //!   - **drops labels** from the series whose key matches a literal/regex,
-//!     optionally scoped to a metric/label match.
+//!     optionally scoped to a metric/label match, or
+//!   - **relabels** the series with Prometheus `metric_relabel_configs`
+//!     semantics: `replace` (regex replace into a target label; targeting
+//!     `__name__` renames the metric), `labelmap`, `labelkeep` and `hashmod`.
 //!
-//! Precedence is fixed: allow → block. Drop-label rules always apply, even to
-//! allowed series — allow exempts from blocking, not from label stripping.
+//! Precedence is fixed: allow → block, evaluated against the series as
+//! received. Drop-label and relabel rules then run in rule order (each sees
+//! the output of the previous one) and always apply, even to allowed series —
+//! allow exempts from blocking, not from rewriting.
+//!
+//! Relabel regexes are fully anchored like Prometheus (`^(?:re)$`); the
+//! allow/block/drop-label matchers keep their unanchored `is_match` semantics.
 //!
 //! Compiled rules live behind `Arc<RwLock<Arc<MetricFirewall>>>` in `ChWriter`
 //! and are hot-swapped by a background refresher / on config change, so the
 //! ingest hot path only takes a brief read lock + cheap Arc clone.
 
+use md5::{Digest, Md5};
 use regex::Regex;
 
+/// Pseudo-label addressing the metric name in relabel source/target labels.
+const METRIC_NAME_LABEL: &str = "__name__";
+
 /// A metric row the firewall can inspect/mutate. Implemented by the metric
 /// insert row structs in `models::ingest`.
 pub trait MetricRow {
+    fn fw_tenant_id(&self) -> &str;
     fn fw_metric_name(&self) -> &str;
+    fn fw_set_metric_name(&mut self, name: &str);
     fn fw_attributes(&self) -> &[(String, String)];
     fn fw_attributes_mut(&mut self) -> &mut Vec<(String, String)>;
 }
 
+/// Actions that rewrite a series in rule order (as opposed to allow/block,
+/// which only decide whether it is kept).
+pub fn is_rewrite_action(action: &str) -> bool {
+    matches!(action, "drop_label" | "replace" | "labelmap" | "labelkeep" | "hashmod")
+}
+
+/// Compile a Prometheus-style relabel regex (fully anchored).
+fn anchored_regex(pattern: &str) -> Option<Regex> {
+    match Regex::new(&format!("^(?:{pattern})$")) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            tracing::warn!(pattern = %pattern, error = %e, "metric firewall: invalid relabel regex, rule disabled");
+            None
+        }
+    }
+}
+
 /// A name/value matcher: match anything, an exact string, or a regex.
 #[derive(Clone)]
 enum Matcher {
@@ -69,6 +100,28 @@ enum CompiledAction {
     Block,
     /// Strip label KEYS matching the inner matcher from matching series.
     DropLabel(Matcher),
+    /// Join `source_labels` with `separator`; if `regex` matches, write the
+    /// expanded `replacement` into the (expanded) `target_label`. An empty
+    /// result deletes the target label.
+    Replace {
+        source_labels: Vec<String>,
+        separator: String,
+        regex: Regex,
+        target_label: String,
+        replacement: String,
+    },
+    /// Set `target_label` to md5(joined source values) mod `modulus`.
+    HashMod {
+        source_labels: Vec<String>,
+        separator: String,
+        target_label: String,
+        modulus: u64,
+    },
+    /// Copy every label whose KEY matches `regex` to the key produced by
+    /// expanding `replacement` (original label is kept).
+    LabelMap { regex: Regex, replacement: String },
+    /// Remove every label whose KEY does not match the regex.
+    LabelKeep(Regex),
 }
 
 #[derive(Clone)]
@@ -100,35 +153,148 @@ impl CompiledRule {
     }
 }
 
+/// Join the values of `labels` with `sep`; `__name__` reads the metric name
+/// and a missing label contributes an empty string (Prometheus semantics).
+fn source_value<T: MetricRow>(row: &T, labels: &[String], sep: &str) -> String {
+    let mut out = String::new();
+    for (i, l) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push_str(sep);
+        }
+        if l == METRIC_NAME_LABEL {
+            out.push_str(row.fw_metric_name());
+        } else if let Some((_, v)) = row.fw_attributes().iter().find(|(k, _)| k == l) {
+            out.push_str(v);
+        }
+    }
+    out
+}
+
+/// Set (or, for an empty value, delete) a label. `__name__` renames the
+/// metric; an empty metric name is ignored rather than producing a nameless row.
+fn set_label<T: MetricRow>(row: &mut T, key: &str, value: String) {
+    if key == METRIC_NAME_LABEL {
+        if !value.is_empty() && value != row.fw_metric_name() {
+            row.fw_set_metric_name(&value);
+        }
+        return;
+    }
+    let attrs = row.fw_attributes_mut();
+    if value.is_empty() {
+        attrs.retain(|(k, _)| k != key);
+    } else if let Some(slot) = attrs.iter_mut().find(|(k, _)| k == key) {
+        slot.1 = value;
+    } else {
+        attrs.push((key.to_string(), value));
+    }
+}
+
+impl CompiledAction {
+    /// Apply a rewrite action to a row whose predicate already matched.
+    fn rewrite<T: MetricRow>(&self, row: &mut T) {
+        match self {
+            CompiledAction::DropLabel(dl) => {
+                row.fw_attributes_mut().retain(|(k, _)| !dl.matches(k));
+            }
+            CompiledAction::Replace { source_labels, separator, regex, target_label, replacement } => {
+                let val = source_value(row, source_labels, separator);
+                let Some(caps) = regex.captures(&val) else { return };
+                let mut target = String::new();
+                caps.expand(target_label, &mut target);
+                if target.is_empty() {
+                    return;
+                }
+                let mut res = String::new();
+                caps.expand(replacement, &mut res);
+                set_label(row, &target, res);
+            }
+            CompiledAction::HashMod { source_labels, separator, target_label, modulus } => {
+                let val = source_value(row, source_labels, separator);
+                let hash = Md5::digest(val.as_bytes());
+                let mut tail = [0u8; 8];
+                tail.copy_from_slice(&hash[8..]);
+                let m = u64::from_be_bytes(tail) % modulus;
+                set_label(row, target_label, m.to_string());
+            }
+            CompiledAction::LabelMap { regex, replacement } => {
+                let mapped: Vec<(String, String)> = row
+                    .fw_attributes()
+                    .iter()
+                    .filter(|(k, _)| regex.is_match(k))
+                    .map(|(k, v)| (regex.replace(k, replacement.as_str()).into_owned(), v.clone()))
+                    .collect();
+                for (k, v) in mapped {
+                    set_label(row, &k, v);
+                }
+            }
+            CompiledAction::LabelKeep(re) => {
+                row.fw_attributes_mut().retain(|(k, _)| re.is_match(k));
+            }
+            CompiledAction::Allow | CompiledAction::Block => {}
+        }
+    }
+}
+
 /// A set of rules for one action, with a literal-metric-name fast path: rules
 /// whose metric matcher is an exact string are bucketed by name so a row only
 /// pays a HashMap probe for them; regex/any-metric rules stay in a scan list.
+/// Each rule carries its position in the original rule list so ordered
+/// (rewrite) sets can replay rules in sequence across both lists.
 #[derive(Clone, Default)]
 struct RuleSet {
-    by_literal: std::collections::HashMap<String, Vec<CompiledRule>>,
-    scan: Vec<CompiledRule>,
+    by_literal: std::collections::HashMap<String, Vec<(usize, CompiledRule)>>,
+    scan: Vec<(usize, CompiledRule)>,
+    len: usize,
 }
 
 impl RuleSet {
     fn is_empty(&self) -> bool {
-        self.by_literal.is_empty() && self.scan.is_empty()
+        self.len == 0
     }
 
     fn push(&mut self, rule: CompiledRule) {
+        let seq = self.len;
+        self.len += 1;
         if let Matcher::Literal(name) = &rule.metric {
-            self.by_literal.entry(name.clone()).or_default().push(rule);
+            self.by_literal.entry(name.clone()).or_default().push((seq, rule));
         } else {
-            self.scan.push(rule);
+            self.scan.push((seq, rule));
         }
     }
 
     fn matches(&self, name: &str, attrs: &[(String, String)]) -> bool {
         if let Some(rules) = self.by_literal.get(name) {
-            if rules.iter().any(|r| r.predicate_matches(name, attrs)) {
+            if rules.iter().any(|(_, r)| r.predicate_matches(name, attrs)) {
                 return true;
             }
         }
-        self.scan.iter().any(|r| r.predicate_matches(name, attrs))
+        self.scan.iter().any(|(_, r)| r.predicate_matches(name, attrs))
+    }
+
+    /// Apply every matching rewrite rule in rule order. The literal bucket is
+    /// re-probed after each step because a `replace` into `__name__` can move
+    /// the series into a different bucket for the rules that follow.
+    fn rewrite<T: MetricRow>(&self, row: &mut T) {
+        fn next_from(rules: &[(usize, CompiledRule)], seq: usize) -> Option<&(usize, CompiledRule)> {
+            rules.get(rules.partition_point(|(s, _)| *s < seq))
+        }
+        let mut seq = 0;
+        loop {
+            let lit = self
+                .by_literal
+                .get(row.fw_metric_name())
+                .and_then(|rules| next_from(rules, seq));
+            let scan = next_from(&self.scan, seq);
+            let (s, rule) = match (lit, scan) {
+                (Some(a), Some(b)) => if a.0 < b.0 { a } else { b },
+                (Some(a), None) | (None, Some(a)) => a,
+                (None, None) => return,
+            };
+            if rule.predicate_matches(row.fw_metric_name(), row.fw_attributes()) {
+                rule.action.rewrite(row);
+            }
+            seq = s + 1;
+        }
     }
 }
 
@@ -138,13 +304,15 @@ impl RuleSet {
 pub struct MetricFirewall {
     allow: RuleSet,
     block: RuleSet,
-    drop: Vec<CompiledRule>,
+    /// Drop-label + relabel rules, applied in rule order.
+    rewrite: RuleSet,
 }
 
 /// Raw rule fields as stored in `config_metric_firewall` (also the API shape).
+#[derive(Default)]
 pub struct RawRule {
     pub enabled: bool,
-    pub action: String, // "allow" | "block" | "drop_label"
+    pub action: String, // "allow" | "block" | "drop_label" | "replace" | "labelmap" | "labelkeep" | "hashmod"
     pub metric_pattern: String,
     pub metric_regex: bool,
     pub match_label_key: String,
@@ -152,6 +320,13 @@ pub struct RawRule {
     pub match_label_value_regex: bool,
     pub drop_label_pattern: String,
     pub drop_label_regex: bool,
+    // Relabel fields (Prometheus `relabel_config` names).
+    pub source_labels: Vec<String>,
+    pub separator: String,
+    pub regex: String,
+    pub target_label: String,
+    pub replacement: String,
+    pub modulus: u64,
 }
 
 impl MetricFirewall {
@@ -190,6 +365,40 @@ impl MetricFirewall {
                         None => continue,
                     }
                 }
+                "replace" => {
+                    if r.target_label.is_empty() {
+                        tracing::warn!("metric firewall: replace rule has no target label, skipping");
+                        continue;
+                    }
+                    let Some(regex) = anchored_regex(&r.regex) else { continue };
+                    CompiledAction::Replace {
+                        source_labels: r.source_labels.clone(),
+                        separator: r.separator.clone(),
+                        regex,
+                        target_label: r.target_label.clone(),
+                        replacement: r.replacement.clone(),
+                    }
+                }
+                "hashmod" => {
+                    if r.target_label.is_empty() || r.modulus == 0 {
+                        tracing::warn!("metric firewall: hashmod rule needs a target label and non-zero modulus, skipping");
+                        continue;
+                    }
+                    CompiledAction::HashMod {
+                        source_labels: r.source_labels.clone(),
+                        separator: r.separator.clone(),
+                        target_label: r.target_label.clone(),
+                        modulus: r.modulus,
+                    }
+                }
+                "labelmap" => {
+                    let Some(regex) = anchored_regex(&r.regex) else { continue };
+                    CompiledAction::LabelMap { regex, replacement: r.replacement.clone() }
+                }
+                "labelkeep" => {
+                    let Some(regex) = anchored_regex(&r.regex) else { continue };
+                    CompiledAction::LabelKeep(regex)
+                }
                 // Unknown action strings have always compiled as block; keep that.
                 _ => CompiledAction::Block,
             };
@@ -198,20 +407,21 @@ impl MetricFirewall {
             match &rule.action {
                 CompiledAction::Allow => fw.allow.push(rule),
                 CompiledAction::Block => fw.block.push(rule),
-                CompiledAction::DropLabel(_) => fw.drop.push(rule),
+                _ => fw.rewrite.push(rule),
             }
         }
         fw
     }
 
     pub fn is_empty(&self) -> bool {
-        self.allow.is_empty() && self.block.is_empty() && self.drop.is_empty()
+        self.allow.is_empty() && self.block.is_empty() && self.rewrite.is_empty()
     }
 
     /// Apply all rules to a batch of metric rows in place. Returns the number of
     /// datapoints dropped (for logging/metrics). Rules are pre-partitioned by
     /// action, and literal-name rules are probed via HashMap, so each row scans
-    /// only regex/any-metric rules of the relevant action.
+    /// only regex/any-metric rules of the relevant action. Rewrite rules run
+    /// after the keep/drop decision, in rule order.
     pub fn apply<T: MetricRow>(&self, rows: &mut Vec<T>) -> usize {
         if self.is_empty() {
             return 0;
@@ -227,12 +437,8 @@ impl MetricFirewall {
                     return false; // drop the whole datapoint
                 }
             }
-            for rule in &self.drop {
-                if rule.predicate_matches(row.fw_metric_name(), row.fw_attributes()) {
-                    if let CompiledAction::DropLabel(dl) = &rule.action {
-                        row.fw_attributes_mut().retain(|(k, _)| !dl.matches(k));
-                    }
-                }
+            if !self.rewrite.is_empty() {
+                self.rewrite.rewrite(row);
             }
             true
         });
@@ -246,7 +452,9 @@ mod tests {
 
     struct Row { name: String, attrs: Vec<(String, String)> }
     impl MetricRow for Row {
+        fn fw_tenant_id(&self) -> &str { "t" }
         fn fw_metric_name(&self) -> &str { &self.name }
+        fn fw_set_metric_name(&mut self, name: &str) { self.name = name.into(); }
         fn fw_attributes(&self) -> &[(String, String)] { &self.attrs }
         fn fw_attributes_mut(&mut self) -> &mut Vec<(String, String)> { &mut self.attrs }
     }
@@ -259,8 +467,25 @@ mod tests {
             metric_pattern: mp.into(), metric_regex: mre,
             match_label_key: lk.into(), match_label_value: lv.into(), match_label_value_regex: lvre,
             drop_label_pattern: dl.into(), drop_label_regex: dlre,
+            ..Default::default()
         }
     }
+    /// A relabel rule with Prometheus' defaults (separator `;`, regex `(.*)`,
+    /// replacement `$1`), unscoped (applies to every metric).
+    fn relabel(action: &str, src: &[&str], target: &str) -> RawRule {
+        RawRule {
+            enabled: true, action: action.into(),
+            source_labels: src.iter().map(|s| s.to_string()).collect(),
+            separator: ";".into(), regex: "(.*)".into(),
+            target_label: target.into(), replacement: "$1".into(),
+            ..Default::default()
+        }
+    }
+    fn sorted(attrs: &[(String, String)]) -> Vec<(&str, &str)> {
+        let mut v: Vec<_> = attrs.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        v.sort();
+        v
+    }
 
     #[test]
     fn block_by_metric_literal_and_regex() {
@@ -370,4 +595,178 @@ mod tests {
         assert_eq!(fw.apply(&mut rows), 1);
         assert_eq!(rows[0].name, "http_x");
     }
+
+    // The relabel cases below mirror Prometheus' model/relabel/relabel_test.go.
+
+    #[test]
+    fn replace_with_capture_groups() {
+        let fw = MetricFirewall::compile(&[RawRule {
+            regex: "f(.*)".into(),
+            replacement: "ch${1}-ch${1}".into(),
+            ..relabel("replace", &["a"], "d")
+        }]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b", "bar")])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("a", "foo"), ("b", "bar"), ("d", "choo-choo")]);
+    }
+
+    #[test]
+    fn replace_joins_sources_and_is_anchored() {
+        let fw = MetricFirewall::compile(&[
+            RawRule {
+                regex: "f(.*);(.*)r".into(),
+                replacement: "$1-$2".into(),
+                ..relabel("replace", &["a", "b"], "a")
+            },
+            // Unanchored this would match "boo" inside "xbooy"; anchored it must not.
+            RawRule { regex: "boo".into(), replacement: "hit".into(), ..relabel("replace", &["c"], "e") },
+        ]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b", "bar"), ("c", "xbooy")])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("a", "oo-ba"), ("b", "bar"), ("c", "xbooy")]);
+    }
+
+    #[test]
+    fn replace_missing_source_and_empty_result() {
+        let fw = MetricFirewall::compile(&[
+            // Missing source label reads as "" which matches "(.*)" → replacement
+            // "$1" expands to "" → target label deleted.
+            relabel("replace", &["missing"], "a"),
+            // Regex doesn't match → untouched.
+            RawRule { regex: "nomatch".into(), ..relabel("replace", &["b"], "b") },
+        ]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b", "bar")])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("b", "bar")]);
+    }
+
+    #[test]
+    fn replace_into_name_renames_metric_and_later_rules_see_new_name() {
+        let fw = MetricFirewall::compile(&[
+            RawRule {
+                regex: "old_(.*)".into(),
+                ..relabel("replace", &["__name__"], "__name__")
+            },
+            // Literal-name rule for the NEW name must fire after the rename.
+            RawRule { metric_pattern: "requests".into(), replacement: "yes".into(), ..relabel("replace", &[], "renamed") },
+            // Literal-name rule for the OLD name must not.
+            RawRule { metric_pattern: "old_requests".into(), replacement: "yes".into(), ..relabel("replace", &[], "stale") },
+        ]);
+        let mut rows = vec![row("old_requests", &[]), row("other", &[])];
+        fw.apply(&mut rows);
+        assert_eq!(rows[0].name, "requests");
+        assert_eq!(sorted(&rows[0].attrs), vec![("renamed", "yes")]);
+        assert_eq!(rows[1].name, "other");
+        assert!(rows[1].attrs.is_empty());
+    }
+
+    #[test]
+    fn replace_copies_name_into_label() {
+        let fw = MetricFirewall::compile(&[relabel("replace", &["__name__"], "metric")]);
+        let mut rows = vec![row("http_requests_total", &[])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("metric", "http_requests_total")]);
+    }
+
+    #[test]
+    fn hashmod_matches_prometheus() {
+        // md5("baz") lower 8 bytes (big-endian) % 1000 == 976 in Prometheus.
+        let fw = MetricFirewall::compile(&[RawRule { modulus: 1000, ..relabel("hashmod", &["c"], "d") }]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b", "bar"), ("c", "baz")])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("a", "foo"), ("b", "bar"), ("c", "baz"), ("d", "976")]);
+    }
+
+    #[test]
+    fn hashmod_without_modulus_is_refused_at_compile() {
+        let fw = MetricFirewall::compile(&[relabel("hashmod", &["c"], "d")]);
+        assert!(fw.is_empty());
+    }
+
+    #[test]
+    fn labelmap_copies_matching_keys() {
+        let fw = MetricFirewall::compile(&[RawRule {
+            regex: "(b.*)".into(),
+            replacement: "${1}_new".into(),
+            ..relabel("labelmap", &[], "")
+        }]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b1", "bar"), ("b2", "baz")])];
+        fw.apply(&mut rows);
+        assert_eq!(
+            sorted(&rows[0].attrs),
+            vec![("a", "foo"), ("b1", "bar"), ("b1_new", "bar"), ("b2", "baz"), ("b2_new", "baz")]
+        );
+    }
+
+    #[test]
+    fn labelmap_strips_prefix() {
+        let fw = MetricFirewall::compile(&[RawRule {
+            regex: "__meta_(.+)".into(),
+            ..relabel("labelmap", &[], "")
+        }]);
+        let mut rows = vec![row("m", &[("__meta_pod", "p1"), ("job", "x")])];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("__meta_pod", "p1"), ("job", "x"), ("pod", "p1")]);
+    }
+
+    #[test]
+    fn labelkeep_removes_unmatched_keys() {
+        let fw = MetricFirewall::compile(&[RawRule { regex: "(b.*)".into(), ..relabel("labelkeep", &[], "") }]);
+        let mut rows = vec![row("m", &[("a", "foo"), ("b1", "bar"), ("b2", "baz")])];
+        assert_eq!(fw.apply(&mut rows), 0); // keeps the series, only trims labels
+        assert_eq!(sorted(&rows[0].attrs), vec![("b1", "bar"), ("b2", "baz")]);
+    }
+
+    #[test]
+    fn rewrite_rules_run_in_rule_order() {
+        // replace then labelkeep: the new label survives because it matches.
+        let keep_after = MetricFirewall::compile(&[
+            RawRule { regex: "(.*)".into(), ..relabel("replace", &["a"], "b_copy") },
+            RawRule { regex: "b.*".into(), ..relabel("labelkeep", &[], "") },
+        ]);
+        let mut rows = vec![row("m", &[("a", "foo")])];
+        keep_after.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("b_copy", "foo")]);
+
+        // labelkeep first: source label is gone, so the replace yields "" → no label.
+        let keep_before = MetricFirewall::compile(&[
+            RawRule { regex: "b.*".into(), ..relabel("labelkeep", &[], "") },
+            RawRule { regex: "(.*)".into(), ..relabel("replace", &["a"], "b_copy") },
+        ]);
+        let mut rows = vec![row("m", &[("a", "foo")])];
+        keep_before.apply(&mut rows);
+        assert!(rows[0].attrs.is_empty());
+    }
+
+    #[test]
+    fn relabel_scoped_by_metric_and_label_match() {
+        let fw = MetricFirewall::compile(&[RawRule {
+            metric_pattern: "^http_.*".into(),
+            metric_regex: true,
+            match_label_key: "env".into(),
+            match_label_value: "prod".into(),
+            replacement: "1".into(),
+            ..relabel("replace", &[], "tier")
+        }]);
+        let mut rows = vec![
+            row("http_x", &[("env", "prod")]),
+            row("http_x", &[("env", "dev")]),
+            row("cpu", &[("env", "prod")]),
+        ];
+        fw.apply(&mut rows);
+        assert_eq!(sorted(&rows[0].attrs), vec![("env", "prod"), ("tier", "1")]);
+        assert_eq!(rows[1].attrs.len(), 1);
+        assert_eq!(rows[2].attrs.len(), 1);
+    }
+
+    #[test]
+    fn block_sees_series_before_relabel() {
+        let fw = MetricFirewall::compile(&[
+            RawRule { regex: "old_(.*)".into(), ..relabel("replace", &["__name__"], "__name__") },
+            raw("block", "old_cpu", false, "", "", false, "", false),
+        ]);
+        let mut rows = vec![row("old_cpu", &[]), row("old_mem", &[])];
+        assert_eq!(fw.apply(&mut rows), 1);
+        assert_eq!(rows[0].name, "mem");
+    }
 }
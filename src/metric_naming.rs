@@ -0,0 +1,450 @@
+//! OTel → Prometheus metric name and label normalization.
+//!
+//! OTLP metrics are stored as the SDK named them (`http.server.request.duration`,
+//! dotted attribute keys), while `remote_write` sources use Prometheus names
+//! (`http_server_request_duration_seconds`). A tenant can opt into the
+//! `prometheus` naming mode, which does two things:
+//!
+//!   - at ingest, `ingest_otlp_metrics` rewrites names and label keys following
+//!     the OTel spec's Prometheus compatibility rules (unit suffix, `_ratio`
+//!     for unit `1` gauges, `_total` for monotonic sums, invalid characters →
+//!     `_`);
+//!   - at query time, `promql::sql::matchers_to_sql` also matches the OTel
+//!     spelling a queried name was generated from (given the stored unit and
+//!     type), so series ingested before the switch still match.
+//!
+//! The default `otel` mode stores names as received and matches exactly.
+//! Modes live in `config_tenant_metric_naming` and are cached here process-wide
+//! (refreshed by a background task, and immediately on change).
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, LazyLock, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetricNaming {
+    #[default]
+    Otel,
+    Prometheus,
+}
+
+impl MetricNaming {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetricNaming::Otel => "otel",
+            MetricNaming::Prometheus => "prometheus",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "otel" => Some(MetricNaming::Otel),
+            "prometheus" => Some(MetricNaming::Prometheus),
+            _ => None,
+        }
+    }
+
+    /// Normalize attribute keys in prometheus mode; unchanged otherwise.
+    pub fn attrs(self, attrs: Vec<(String, String)>) -> Vec<(String, String)> {
+        match self {
+            MetricNaming::Otel => attrs,
+            MetricNaming::Prometheus => prometheus_attrs(attrs),
+        }
+    }
+}
+
+/// Tenants in `prometheus` mode (everyone else is `otel`).
+static PROMETHEUS_TENANTS: LazyLock<RwLock<Arc<HashSet<String>>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(HashSet::new())));
+
+/// The naming mode of a tenant.
+pub fn for_tenant(tenant_id: &str) -> MetricNaming {
+    match PROMETHEUS_TENANTS.read() {
+        Ok(g) if g.contains(tenant_id) => MetricNaming::Prometheus,
+        _ => MetricNaming::Otel,
+    }
+}
+
+/// Replace the cached modes with `(tenant_id, mode)` rows from the config store.
+pub fn set_modes(rows: &[(String, String)]) {
+    let set: HashSet<String> = rows
+        .iter()
+        .filter(|(_, m)| MetricNaming::parse(m) == Some(MetricNaming::Prometheus))
+        .map(|(t, _)| t.clone())
+        .collect();
+    if let Ok(mut g) = PROMETHEUS_TENANTS.write() {
+        *g = Arc::new(set);
+    }
+}
+
+/// Update one tenant's cached mode (after a config change).
+pub fn set_tenant_mode(tenant_id: &str, mode: MetricNaming) {
+    if let Ok(mut g) = PROMETHEUS_TENANTS.write() {
+        let mut set = (**g).clone();
+        match mode {
+            MetricNaming::Prometheus => set.insert(tenant_id.to_string()),
+            MetricNaming::Otel => set.remove(tenant_id),
+        };
+        *g = Arc::new(set);
+    }
+}
+
+/// The OTLP metric type, as far as naming is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Gauge,
+    /// Monotonic sum — gets `_total`.
+    Counter,
+    /// Non-monotonic sum, histograms, summaries.
+    Other,
+}
+
+// ─── Names ──────────────────────────────────────────────────────────────────
+
+/// UCUM unit → Prometheus unit word.
+const UNITS: &[(&str, &str)] = &[
+    ("d", "days"),
+    ("h", "hours"),
+    ("min", "minutes"),
+    ("s", "seconds"),
+    ("ms", "milliseconds"),
+    ("us", "microseconds"),
+    ("ns", "nanoseconds"),
+    ("By", "bytes"),
+    ("KiBy", "kibibytes"),
+    ("MiBy", "mebibytes"),
+    ("GiBy", "gibibytes"),
+    ("TiBy", "tebibytes"),
+    ("KBy", "kilobytes"),
+    ("MBy", "megabytes"),
+    ("GBy", "gigabytes"),
+    ("TBy", "terabytes"),
+    ("m", "meters"),
+    ("V", "volts"),
+    ("A", "amperes"),
+    ("J", "joules"),
+    ("W", "watts"),
+    ("g", "grams"),
+    ("Cel", "celsius"),
+    ("Hz", "hertz"),
+    ("%", "percent"),
+];
+
+/// Denominator unit (after `/`) → word used in `_per_<word>`.
+const PER_UNITS: &[(&str, &str)] = &[
+    ("s", "second"),
+    ("m", "minute"),
+    ("h", "hour"),
+    ("d", "day"),
+    ("w", "week"),
+    ("mo", "month"),
+    ("y", "year"),
+];
+
+/// Replace characters outside `[a-zA-Z0-9_:]` with `_`, collapse runs of `_`
+/// and prefix a leading digit with `_`.
+pub fn sanitize_metric_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 1);
+    for c in name.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == ':' { c } else { '_' };
+        if c == '_' && out.ends_with('_') {
+            continue;
+        }
+        out.push(c);
+    }
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Unit suffix words for a UCUM unit: `("bytes", Some("second"))` for `By/s`.
+/// Annotations in braces (`{requests}`) carry no unit and are dropped.
+fn unit_words(unit: &str) -> (Option<String>, Option<String>) {
+    let word = |u: &str, table: &[(&str, &str)]| -> Option<String> {
+        let u = u.trim();
+        if u.is_empty() || u.starts_with('{') || u == "1" {
+            return None;
+        }
+        let w = table.iter().find(|(k, _)| *k == u).map(|(_, v)| v.to_string()).unwrap_or_else(|| sanitize_metric_name(u));
+        let w = w.trim_matches('_').to_string();
+        (!w.is_empty()).then_some(w)
+    };
+    // Strip a trailing annotation such as `By{packets}`.
+    let unit = unit.split('{').next().unwrap_or("");
+    match unit.split_once('/') {
+        Some((main, per)) => (word(main, UNITS), word(per, PER_UNITS)),
+        None => (word(unit, UNITS), None),
+    }
+}
+
+/// The Prometheus-compatible name for an OTLP metric.
+pub fn prometheus_metric_name(name: &str, unit: &str, kind: MetricKind) -> String {
+    let mut out = sanitize_metric_name(name);
+    if kind == MetricKind::Counter && let Some(base) = out.strip_suffix("_total") {
+        out = base.to_string();
+    }
+    let (main, per) = unit_words(unit);
+    let mut suffix = String::new();
+    if let Some(m) = main {
+        suffix.push('_');
+        suffix.push_str(&m);
+    }
+    if let Some(p) = per {
+        suffix.push_str("_per_");
+        suffix.push_str(&p);
+    }
+    if !suffix.is_empty() && !out.ends_with(&suffix) {
+        out.push_str(&suffix);
+    }
+    if kind == MetricKind::Gauge && unit.trim() == "1" && !out.ends_with("_ratio") {
+        out.push_str("_ratio");
+    }
+    if kind == MetricKind::Counter {
+        out.push_str("_total");
+    }
+    out
+}
+
+/// Most `_`s in a queried base name whose OTel spellings are spelled out
+/// (2^n variants).
+const MAX_CANDIDATE_SEPARATORS: u32 = 8;
+
+/// The OTel name a queried Prometheus name may have been generated from
+/// (`prometheus_metric_name` in reverse): the base before the unit suffix and
+/// `_total`, and what the stored unit and type must be for the base to carry
+/// that suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtelOrigin {
+    /// Sanitized base name.
+    pub base: String,
+    /// Unit word and `_per_` word split off the query.
+    main: Option<String>,
+    per: Option<String>,
+    ratio: bool,
+    counter: bool,
+}
+
+impl OtelOrigin {
+    pub fn parse(query: &str) -> Self {
+        let mut base = sanitize_metric_name(query);
+        let counter = match base.strip_suffix("_total") {
+            Some(b) if !b.is_empty() => {
+                base = b.to_string();
+                true
+            }
+            _ => false,
+        };
+        if let Some(b) = base.strip_suffix("_ratio").filter(|b| !b.is_empty()) {
+            return OtelOrigin { base: b.to_string(), main: None, per: None, ratio: true, counter };
+        }
+        match split_unit_suffix(&base) {
+            Some((b, main, per)) => OtelOrigin { base: b, main, per, ratio: false, counter },
+            None => OtelOrigin { base, main: None, per: None, ratio: false, counter },
+        }
+    }
+
+    /// The unit stems (the stored unit before any `{annotation}`) with which
+    /// a metric of `kind` named `base` gets the queried name. Empty if no
+    /// metric of that kind can: `_total` is only added to counters, `_ratio`
+    /// only to gauges.
+    pub fn unit_stems(&self, kind: MetricKind) -> Vec<String> {
+        if self.counter != (kind == MetricKind::Counter) {
+            return Vec::new();
+        }
+        if self.ratio {
+            return if kind == MetricKind::Gauge { vec!["1".to_string()] } else { Vec::new() };
+        }
+        let stems = |word: &Option<String>, table: &'static [(&'static str, &'static str)]| -> Vec<&'static str> {
+            match word {
+                Some(w) => table.iter().filter(|(_, v)| v == w).map(|(u, _)| *u).collect(),
+                None => vec!["", "1"],
+            }
+        };
+        let mains = stems(&self.main, UNITS);
+        let mut out = Vec::new();
+        match &self.per {
+            Some(_) => {
+                for p in stems(&self.per, PER_UNITS) {
+                    out.extend(mains.iter().map(|m| format!("{m}/{p}")));
+                }
+            }
+            // A bare `1` gauge is a `_ratio`.
+            None if self.main.is_none() && kind == MetricKind::Gauge => out.push(String::new()),
+            None => out.extend(mains.iter().map(|m| m.to_string())),
+        }
+        out
+    }
+
+    /// The stored OTel spellings of the base, as a list the primary key on
+    /// `MetricName` can use: every `_` read as `_` or `.`. Names using other
+    /// separators (`-`, `/`) only match by their sanitized form. `None` when
+    /// the base has more than `MAX_CANDIDATE_SEPARATORS` `_`s.
+    pub fn spellings(&self) -> Option<Vec<String>> {
+        let separators: Vec<usize> = self.base.match_indices('_').map(|(i, _)| i).collect();
+        if separators.len() as u32 > MAX_CANDIDATE_SEPARATORS {
+            return None;
+        }
+        let names = (0..1u32 << separators.len()).map(|mask| {
+            let mut dotted = self.base.clone().into_bytes();
+            for (bit, &i) in separators.iter().enumerate() {
+                if mask & (1 << bit) != 0 {
+                    dotted[i] = b'.';
+                }
+            }
+            String::from_utf8(dotted).unwrap_or_default()
+        });
+        Some(names.collect())
+    }
+}
+
+/// Split a known unit suffix (`_bytes`, `_bytes_per_second`, `_per_second`)
+/// off a sanitized name, as `(rest, unit word, per word)`.
+fn split_unit_suffix(name: &str) -> Option<(String, Option<String>, Option<String>)> {
+    let mut rest = name;
+    let mut per = None;
+    for (_, p) in PER_UNITS {
+        if let Some(b) = rest.strip_suffix(&format!("_per_{p}")) {
+            per = Some(p.to_string());
+            rest = b;
+            break;
+        }
+    }
+    let mut main = None;
+    for (_, w) in UNITS {
+        if let Some(b) = rest.strip_suffix(&format!("_{w}")) {
+            main = Some(w.to_string());
+            rest = b;
+            break;
+        }
+    }
+    if (main.is_none() && per.is_none()) || rest.is_empty() {
+        return None;
+    }
+    Some((rest.to_string(), main, per))
+}
+
+// ─── Labels ─────────────────────────────────────────────────────────────────
+
+/// The Prometheus-compatible label name for an attribute key: characters
+/// outside `[a-zA-Z0-9_]` become `_`; a leading digit gets a `key_` prefix and
+/// a single leading `_` a `key` prefix (`__` is reserved).
+pub fn prometheus_label(key: &str) -> String {
+    let mut out: String = key.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert_str(0, "key_");
+    } else if out.starts_with('_') && !out.starts_with("__") {
+        out.insert_str(0, "key");
+    }
+    out
+}
+
+/// Normalize attribute keys. Keys that collide after normalization have their
+/// values joined with `;`, ordered by original key (per the OTel spec).
+pub fn prometheus_attrs(attrs: Vec<(String, String)>) -> Vec<(String, String)> {
+    if attrs.iter().all(|(k, _)| prometheus_label(k) == *k) {
+        return attrs;
+    }
+    let mut merged: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (k, v) in attrs {
+        merged.entry(prometheus_label(&k)).or_default().push((k, v));
+    }
+    merged
+        .into_iter()
+        .map(|(k, mut vs)| {
+            vs.sort();
+            (k, vs.into_iter().map(|(_, v)| v).collect::<Vec<_>>().join(";"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metric_names_follow_the_otel_prometheus_rules() {
+        let cases = [
+            ("http.server.request.duration", "s", MetricKind::Other, "http_server_request_duration_seconds"),
+            ("http.server.requests", "{request}", MetricKind::Counter, "http_server_requests_total"),
+            ("system.network.io", "By", MetricKind::Counter, "system_network_io_bytes_total"),
+            ("process.cpu.utilization", "1", MetricKind::Gauge, "process_cpu_utilization_ratio"),
+            ("throughput", "By/s", MetricKind::Gauge, "throughput_bytes_per_second"),
+            ("jobs_total", "", MetricKind::Counter, "jobs_total"),
+            ("queue_size_bytes", "By", MetricKind::Gauge, "queue_size_bytes"),
+            ("2xx..count", "", MetricKind::Gauge, "_2xx_count"),
+        ];
+        for (name, unit, kind, want) in cases {
+            assert_eq!(prometheus_metric_name(name, unit, kind), want, "{name} [{unit}]");
+        }
+    }
+
+    #[test]
+    fn label_keys_are_normalized_and_merged() {
+        assert_eq!(prometheus_label("http.request.method"), "http_request_method");
+        assert_eq!(prometheus_label("0zone"), "key_0zone");
+        assert_eq!(prometheus_label("_private"), "key_private");
+        assert_eq!(prometheus_label("__reserved"), "__reserved");
+        let attrs = vec![
+            ("a.b".to_string(), "1".to_string()),
+            ("a_b".to_string(), "2".to_string()),
+            ("c".to_string(), "3".to_string()),
+        ];
+        assert_eq!(
+            prometheus_attrs(attrs),
+            vec![("a_b".to_string(), "1;2".to_string()), ("c".to_string(), "3".to_string())]
+        );
+    }
+
+    #[test]
+    fn otel_origin_inverts_the_prometheus_name() {
+        let cases = [
+            ("http.server.request.duration", "s", MetricKind::Other),
+            ("system.network.io", "By", MetricKind::Counter),
+            ("process.cpu.utilization", "1", MetricKind::Gauge),
+            ("throughput", "By/s", MetricKind::Gauge),
+            ("http.server.requests", "{request}", MetricKind::Counter),
+            ("queue.depth", "", MetricKind::Gauge),
+        ];
+        for (name, unit, kind) in cases {
+            let origin = OtelOrigin::parse(&prometheus_metric_name(name, unit, kind));
+            assert!(origin.spellings().unwrap().contains(&name.to_string()), "{name}");
+            let stem = unit.split('{').next().unwrap();
+            assert!(origin.unit_stems(kind).contains(&stem.to_string()), "{name} [{unit}]");
+        }
+    }
+
+    #[test]
+    fn otel_origin_requires_the_queried_unit_and_type() {
+        // No unit suffix: only unitless metrics, never any unit.
+        let plain = OtelOrigin::parse("http_requests_total");
+        assert_eq!(plain.base, "http_requests");
+        assert_eq!(plain.unit_stems(MetricKind::Counter), vec!["", "1"]);
+        assert!(plain.unit_stems(MetricKind::Gauge).is_empty());
+        assert!(plain.unit_stems(MetricKind::Other).is_empty());
+
+        let cpu = OtelOrigin::parse("process_cpu_seconds_total");
+        assert_eq!(cpu.base, "process_cpu");
+        assert_eq!(cpu.unit_stems(MetricKind::Counter), vec!["s"]);
+
+        let gauge = OtelOrigin::parse("queue_depth");
+        assert_eq!(gauge.unit_stems(MetricKind::Gauge), vec![""]);
+        assert_eq!(gauge.unit_stems(MetricKind::Other), vec!["", "1"]);
+        assert!(gauge.unit_stems(MetricKind::Counter).is_empty());
+
+        let ratio = OtelOrigin::parse("process_cpu_utilization_ratio");
+        assert_eq!(ratio.unit_stems(MetricKind::Gauge), vec!["1"]);
+        assert!(ratio.unit_stems(MetricKind::Other).is_empty());
+
+        let rate = OtelOrigin::parse("net_bytes_per_second");
+        assert_eq!(rate.base, "net");
+        assert_eq!(rate.unit_stems(MetricKind::Gauge), vec!["By/s"]);
+
+        let spellings = OtelOrigin::parse("http_server_request_duration_seconds").spellings().unwrap();
+        assert_eq!(spellings.len(), 8);
+        assert!(spellings.contains(&"http.server.request.duration".to_string()));
+        assert!(spellings.contains(&"http_server_request_duration".to_string()));
+        assert!(!spellings.iter().any(|s| s.contains("seconds")));
+
+        assert!(OtelOrigin::parse("a_b_c_d_e_f_g_h_i_j").spellings().is_none());
+    }
+}
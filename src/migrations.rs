@@ -489,6 +489,40 @@ ORDER BY (tenant_id, session_id, chunk_idx)
 TTL toDateTime(chunk_ts) + INTERVAL 7 DAY DELETE
 SETTINGS index_granularity = 8192, ttl_only_drop_parts = 1",
 
+    // ── Query job results (rows of /api/v1/jobs results, kept for a day) ──
+    r"CREATE TABLE IF NOT EXISTS observability.query_job_results
+(
+    `tenant_id` LowCardinality(String),
+    `job_id` String,
+    `seq` UInt32,
+    `row` String CODEC(ZSTD(1)),
+    `created_at` DateTime DEFAULT now()
+)
+ENGINE = MergeTree
+ORDER BY (tenant_id, job_id, seq)
+TTL created_at + INTERVAL 1 DAY DELETE
+SETTINGS index_granularity = 8192, ttl_only_drop_parts = 1",
+
+    // ── Query job status (one row per state change; FINAL reads the latest) ──
+    r"CREATE TABLE IF NOT EXISTS observability.query_jobs
+(
+    `tenant_id` LowCardinality(String),
+    `job_id` String,
+    `kind` LowCardinality(String),
+    `status` LowCardinality(String),
+    `error` String CODEC(ZSTD(1)),
+    `rows` UInt64,
+    `meta` String CODEC(ZSTD(1)),
+    `submitted_at` DateTime64(3),
+    `finished_at` DateTime64(3),
+    `deadline` DateTime64(3),
+    `version` UInt64
+)
+ENGINE = ReplacingMergeTree(version)
+ORDER BY (tenant_id, job_id)
+TTL toDateTime(submitted_at) + INTERVAL 2 DAY DELETE
+SETTINGS index_granularity = 8192",
+
     // ── Tenant usage metering (per-tenant ingest volume tracking) ──
     r"CREATE TABLE IF NOT EXISTS observability.tenant_usage
 (
@@ -714,6 +748,8 @@ const ROW_POLICY_TABLES: &[&str] = &[
     "metrics_sum_1h",
     "rum",
     "rum_replay",
+    "query_job_results",
+    "query_jobs",
 ];
 
 /// Create row policies on all tenant-scoped tables. Only safe to call when
@@ -444,11 +444,14 @@ pub struct SummaryRow {
 
 // ═══ Metric firewall integration ═══
 // All metric row types expose metric_name + attributes for the ingest-time
-// metric firewall (see crate::metric_firewall).
+// metric firewall (see crate::metric_firewall). Renaming (relabel into
+// `__name__`) swaps in a fresh Arc for that row only; its siblings keep sharing.
 macro_rules! impl_metric_row {
     ($t:ty) => {
         impl crate::metric_firewall::MetricRow for $t {
+            fn fw_tenant_id(&self) -> &str { &self.tenant_id }
             fn fw_metric_name(&self) -> &str { &self.metric_name }
+            fn fw_set_metric_name(&mut self, name: &str) { self.metric_name = Arc::from(name); }
             fn fw_attributes(&self) -> &[(String, String)] { &self.attributes }
             fn fw_attributes_mut(&mut self) -> &mut Vec<(String, String)> { &mut self.attributes }
         }
@@ -17,6 +17,81 @@ pub struct MetricSample {
     pub value: f64,
 }
 
+/// Raw data point from ClickHouse metrics_histogram (explicit bucket bounds)
+#[derive(Debug, Clone, Row, Deserialize)]
+pub struct HistogramSample {
+    #[serde(rename = "MetricName")]
+    pub metric_name: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(rename = "Attributes")]
+    pub attributes: Vec<(String, String)>,
+    #[serde(rename = "ts_ms")]
+    pub ts_ms: i64,
+    #[serde(rename = "Count")]
+    pub count: u64,
+    #[serde(rename = "Sum")]
+    pub sum: f64,
+    #[serde(rename = "BucketCounts")]
+    pub bucket_counts: Vec<u64>,
+    #[serde(rename = "ExplicitBounds")]
+    pub explicit_bounds: Vec<f64>,
+}
+
+/// Raw data point from ClickHouse metrics_exp_histogram (base-2 exponential buckets)
+#[derive(Debug, Clone, Row, Deserialize)]
+pub struct ExpHistogramSample {
+    #[serde(rename = "MetricName")]
+    pub metric_name: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(rename = "Attributes")]
+    pub attributes: Vec<(String, String)>,
+    #[serde(rename = "ts_ms")]
+    pub ts_ms: i64,
+    #[serde(rename = "Count")]
+    pub count: u64,
+    #[serde(rename = "Sum")]
+    pub sum: f64,
+    #[serde(rename = "Scale")]
+    pub scale: i32,
+    #[serde(rename = "ZeroCount")]
+    pub zero_count: u64,
+    #[serde(rename = "PositiveOffset")]
+    pub positive_offset: i32,
+    #[serde(rename = "PositiveBucketCounts")]
+    pub positive_bucket_counts: Vec<u64>,
+    #[serde(rename = "NegativeOffset")]
+    pub negative_offset: i32,
+    #[serde(rename = "NegativeBucketCounts")]
+    pub negative_bucket_counts: Vec<u64>,
+}
+
+/// One merged bucket from a metrics_gauge_1m/1h or metrics_sum_1m/1h rollup.
+/// `ts_ms` is the bucket start; `Avg` is NaN for the sum rollups, which keep no
+/// avg state.
+#[derive(Debug, Clone, Row, Deserialize)]
+pub struct RollupSample {
+    #[serde(rename = "MetricName")]
+    pub metric_name: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(rename = "Attributes")]
+    pub attributes: Vec<(String, String)>,
+    #[serde(rename = "ts_ms")]
+    pub ts_ms: i64,
+    #[serde(rename = "Last")]
+    pub last: f64,
+    #[serde(rename = "Min")]
+    pub min: f64,
+    #[serde(rename = "Max")]
+    pub max: f64,
+    #[serde(rename = "Count")]
+    pub count: u64,
+    #[serde(rename = "Avg")]
+    pub avg: f64,
+}
+
 /// Row for label discovery queries
 #[derive(Debug, Clone, Row, Deserialize)]
 pub struct LabelNameRow {
@@ -64,6 +139,10 @@ pub struct MatrixData {
     #[serde(rename = "resultType")]
     pub result_type: &'static str,
     pub result: Vec<MatrixResult>,
+    /// Set when an approximate query served at least one range function from a
+    /// rollup (see `promql::rollup`); omitted otherwise.
+    #[serde(rename = "rollupUsed", skip_serializing_if = "std::ops::Not::not")]
+    pub rollup_used: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -10,6 +10,8 @@ pub mod metrics;
 pub mod monitor;
 pub mod query;
 pub mod rum;
+pub mod saved_query;
+pub mod scheduled_export;
 pub mod service_link;
 pub mod slo;
 pub mod trace;
@@ -91,7 +91,7 @@ fn default_interval() -> String {
 }
 
 /// Count result — time bucketed counts (ClickHouse Row).
-#[derive(Debug, Serialize, Deserialize, Row)]
+#[derive(Debug, Clone, Serialize, Deserialize, Row)]
 pub struct CountBucket {
     pub bucket: String,
     pub count: u64,
@@ -125,7 +125,7 @@ pub struct TimeseriesRequest {
 }
 
 /// A single timeseries bucket with RED metrics.
-#[derive(Debug, Serialize, Deserialize, Row)]
+#[derive(Debug, Clone, Serialize, Deserialize, Row)]
 pub struct TimeseriesBucket {
     pub bucket: String,
     pub count: u64,
@@ -137,7 +137,7 @@ pub struct TimeseriesBucket {
 }
 
 /// A grouped timeseries bucket (with a group key).
-#[derive(Debug, Serialize, Deserialize, Row)]
+#[derive(Debug, Clone, Serialize, Deserialize, Row)]
 pub struct GroupedTimeseriesBucket {
     pub bucket: String,
     pub group_key: String,
@@ -148,3 +148,21 @@ pub struct GroupedTimeseriesBucket {
     pub p95_ms: f64,
     pub p99_ms: f64,
 }
+
+impl crate::query_cache::Bucketed for CountBucket {
+    fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+impl crate::query_cache::Bucketed for TimeseriesBucket {
+    fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
+
+impl crate::query_cache::Bucketed for GroupedTimeseriesBucket {
+    fn bucket(&self) -> &str {
+        &self.bucket
+    }
+}
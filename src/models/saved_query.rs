@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+use crate::models::query::TimeRange;
+
+/// A named Explore query. `filters`, `search`, `group_by` and `aggregation`
+/// have the same meaning as in `/api/v1/query` (spans) or `/api/v1/logs`
+/// (logs); `columns` is the column set the Explore table shows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub tenant_id: String,
+    pub owner_id: String,
+    /// `private` (owner only) or `tenant`.
+    pub visibility: String,
+    /// `spans` or `logs`.
+    pub signal: String,
+    pub filters: serde_json::Value,
+    pub search: String,
+    pub group_by: Vec<String>,
+    pub aggregation: String,
+    pub columns: Vec<String>,
+    /// Window used when an execute request gives no time range, e.g. `1h`.
+    pub default_range: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Body of both create (POST) and update (PUT).
+#[derive(Debug, Deserialize)]
+pub struct SavedQueryRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_visibility")]
+    pub visibility: String,
+    #[serde(default = "default_signal")]
+    pub signal: String,
+    #[serde(default = "default_empty_array")]
+    pub filters: serde_json::Value,
+    #[serde(default)]
+    pub search: String,
+    #[serde(default)]
+    pub group_by: Vec<String>,
+    #[serde(default = "default_aggregation")]
+    pub aggregation: String,
+    #[serde(default)]
+    pub columns: Vec<String>,
+    #[serde(default = "default_range")]
+    pub default_range: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExecuteSavedQueryRequest {
+    /// Overrides the saved `default_range`.
+    #[serde(default)]
+    pub time_range: Option<TimeRange>,
+    #[serde(default)]
+    pub limit: Option<u64>,
+    #[serde(default)]
+    pub offset: Option<u64>,
+}
+
+fn default_visibility() -> String {
+    "tenant".to_string()
+}
+
+fn default_signal() -> String {
+    "spans".to_string()
+}
+
+fn default_aggregation() -> String {
+    "count".to_string()
+}
+
+fn default_range() -> String {
+    "1h".to_string()
+}
+
+fn default_empty_array() -> serde_json::Value {
+    serde_json::Value::Array(Vec::new())
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use crate::handlers::export::ExportFormat;
+
+/// A saved query exported to object storage on a cron schedule (see
+/// `export_scheduler`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledExport {
+    pub id: String,
+    pub tenant_id: String,
+    pub owner_id: String,
+    pub name: String,
+    pub saved_query_id: String,
+    /// Five-field cron expression, evaluated in UTC.
+    pub schedule: String,
+    /// `csv`, `ndjson`, `arrow` or `parquet`.
+    pub format: String,
+    /// Key prefix under the tenant's directory in the export bucket.
+    pub destination: String,
+    /// Channel ids notified when a run fails.
+    pub notification_channels: Vec<String>,
+    pub enabled: bool,
+    /// Scheduled time of the latest run, successful or not.
+    pub last_run_at: String,
+    /// `success` or `failed`; empty before the first run.
+    pub last_status: String,
+    pub last_error: String,
+    /// End of the time window the last successful run covered; the next run
+    /// starts here so failed runs leave no gap.
+    pub exported_until: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// Body of both create (POST) and update (PUT).
+#[derive(Debug, Deserialize)]
+pub struct ScheduledExportRequest {
+    pub name: String,
+    pub saved_query_id: String,
+    pub schedule: String,
+    #[serde(default = "default_format")]
+    pub format: ExportFormat,
+    #[serde(default)]
+    pub destination: String,
+    #[serde(default)]
+    pub notification_channels: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+/// One run of a scheduled export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportRun {
+    pub id: String,
+    pub export_id: String,
+    pub tenant_id: String,
+    /// The cron tick this run was for.
+    pub scheduled_at: String,
+    pub started_at: String,
+    pub finished_at: String,
+    /// Exported time window.
+    pub window_from: String,
+    pub window_to: String,
+    /// `success` or `failed`.
+    pub status: String,
+    pub rows: u64,
+    pub bytes: u64,
+    /// Object key written (successful runs).
+    pub object_path: String,
+    pub error: String,
+}
+
+fn default_format() -> ExportFormat {
+    ExportFormat::Csv
+}
+
+fn default_enabled() -> bool {
+    true
+}
@@ -1,16 +1,24 @@
 //! Object-store backend for the durable ingest buffer (PRD Phase 2).
 //!
 //! Design (deliberately simple — see docs/PRD-object-store-ingest-buffer.md):
-//! one object per spilled batch under a prefix, keyed by a sortable
-//! `{unix_millis:013}-{seq:08}.batch` so listing yields oldest-first. Drain =
-//! list → get oldest → (caller inserts) → delete. No shared manifest, no CAS:
+//! one object per spilled batch under a per-tenant sub-prefix, keyed by a
+//! sortable `{hex tenant}/{unix_millis:013}-{seq:08}.batch` so listing yields
+//! each tenant's batches oldest-first (objects directly under the prefix, from
+//! before tenants were tracked, belong to tenant ""). Drain = list → pick a
+//! tenant by weighted round-robin → get its oldest → (caller inserts) →
+//! delete. No shared manifest, no CAS:
 //! at-least-once is the target (ClickHouse async inserts aren't transactional),
 //! and a single drain worker removes the only concurrent-duplicate window.
 //!
 //! Object body = `{table}\n{payload}` (table names have no newline; the payload
 //! is the same serde_json batch bytes the disk spool stores).
+//!
+//! Quarantined records live under a sibling `{prefix}-deadletter/` prefix (not
+//! nested, so the drain listing never sees them) as `{millis}-{seq}.dead`
+//! objects in the shared dead-letter format (`crate::spool::encode_dead_letter`).
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -20,7 +28,31 @@ use object_store::{ObjectStore, PutPayload};
 use object_store::aws::AmazonS3Builder;
 use object_store::path::Path as OsPath;
 
-use crate::spool::SpoolFull;
+use crate::spool::{
+    DeadLetterEntry, DeadLetterMeta, FairScheduler, SpoolFull, TenantPolicy, TenantUsage, active_tenants,
+    encode_dead_letter, valid_dead_letter_id,
+};
+
+/// An S3/MinIO client for `bucket` (an empty `endpoint` means AWS).
+pub fn s3_store(
+    endpoint: &str,
+    bucket: &str,
+    region: &str,
+    access_key: &str,
+    secret_key: &str,
+) -> anyhow::Result<Arc<dyn ObjectStore>> {
+    let mut b = AmazonS3Builder::new()
+        .with_bucket_name(bucket)
+        .with_region(region)
+        .with_access_key_id(access_key)
+        .with_secret_access_key(secret_key);
+    if !endpoint.is_empty() {
+        b = b.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
+    }
+    // Path-style addressing for MinIO/S3-compatibles.
+    b = b.with_virtual_hosted_style_request(false);
+    Ok(Arc::new(b.build()?))
+}
 
 pub struct ObjectStoreSpool {
     store: Arc<dyn ObjectStore>,
@@ -30,6 +62,13 @@ pub struct ObjectStoreSpool {
     count: AtomicUsize,
     seq: AtomicU64,
     committed: AtomicU64,
+    dead_prefix: String,
+    dead_bytes: AtomicU64,
+    dead_count: AtomicUsize,
+    /// Pending (bytes, objects) per tenant.
+    tenants: Mutex<HashMap<String, (u64, usize)>>,
+    policy: Mutex<TenantPolicy>,
+    scheduler: Mutex<FairScheduler>,
 }
 
 impl ObjectStoreSpool {
@@ -44,17 +83,7 @@ impl ObjectStoreSpool {
         secret_key: &str,
         max_bytes: u64,
     ) -> anyhow::Result<Self> {
-        let mut b = AmazonS3Builder::new()
-            .with_bucket_name(bucket)
-            .with_region(region)
-            .with_access_key_id(access_key)
-            .with_secret_access_key(secret_key);
-        if !endpoint.is_empty() {
-            b = b.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
-        }
-        // Path-style addressing for MinIO/S3-compatibles.
-        b = b.with_virtual_hosted_style_request(false);
-        let store: Arc<dyn ObjectStore> = Arc::new(b.build()?);
+        let store = s3_store(endpoint, bucket, region, access_key, secret_key)?;
         Self::open(store, prefix, max_bytes).await
     }
 
@@ -67,6 +96,7 @@ impl ObjectStoreSpool {
         } else {
             format!("{prefix}/")
         };
+        let dead_prefix = format!("{}-deadletter/", prefix.trim_end_matches('/'));
         let s = ObjectStoreSpool {
             store,
             prefix,
@@ -75,21 +105,35 @@ impl ObjectStoreSpool {
             count: AtomicUsize::new(0),
             seq: AtomicU64::new(0),
             committed: AtomicU64::new(0),
+            dead_prefix,
+            dead_bytes: AtomicU64::new(0),
+            dead_count: AtomicUsize::new(0),
+            tenants: Mutex::new(HashMap::new()),
+            policy: Mutex::new(TenantPolicy::default()),
+            scheduler: Mutex::new(FairScheduler::default()),
         };
         // Seed counters from existing objects so the cap + metrics survive restarts.
         let metas = s.list_sorted().await?;
         let mut total = 0u64;
         for m in &metas {
             total += m.1;
+            s.account(&s.tenant_of(&m.0), m.1 as i64, 1);
         }
         s.bytes.store(total, Ordering::Relaxed);
         s.count.store(metas.len(), Ordering::Relaxed);
+        let dead = s.list_prefix(&s.dead_prefix).await?;
+        s.dead_bytes.store(dead.iter().map(|m| m.1).sum(), Ordering::Relaxed);
+        s.dead_count.store(dead.len(), Ordering::Relaxed);
         Ok(s)
     }
 
     /// All buffered objects as (key, size), sorted oldest-first by key.
     async fn list_sorted(&self) -> anyhow::Result<Vec<(OsPath, u64)>> {
-        let pfx = OsPath::from(self.prefix.trim_end_matches('/'));
+        self.list_prefix(&self.prefix).await
+    }
+
+    async fn list_prefix(&self, prefix: &str) -> anyhow::Result<Vec<(OsPath, u64)>> {
+        let pfx = OsPath::from(prefix.trim_end_matches('/'));
         let mut out: Vec<(OsPath, u64)> = Vec::new();
         let mut stream = self.store.list(Some(&pfx));
         while let Some(meta) = stream.next().await {
@@ -100,16 +144,26 @@ impl ObjectStoreSpool {
         Ok(out)
     }
 
-    /// Spill one batch as a new object. Returns `SpoolFull` if over the cap or
-    /// the put fails (so the caller applies 429 backpressure rather than dropping).
-    pub async fn append(&self, table: &str, payload: &[u8]) -> Result<(), SpoolFull> {
+    /// Spill one batch as a new object. Returns `SpoolFull` if over the global
+    /// or the tenant's cap, or the put fails (so the caller applies 429
+    /// backpressure rather than dropping).
+    pub async fn append(&self, tenant: &str, table: &str, payload: &[u8]) -> Result<(), SpoolFull> {
         let rec_len = (table.len() + 1 + payload.len()) as u64;
         if self.bytes.load(Ordering::Relaxed) + rec_len > self.max_bytes {
             return Err(SpoolFull);
         }
+        let active = active_tenants(self.tenants.lock().unwrap().keys().map(String::as_str), tenant);
+        let tenant_cap = self.policy.lock().unwrap().tenant_max_bytes(self.max_bytes, active);
+        if self.tenant_bytes(tenant) + rec_len > tenant_cap {
+            return Err(SpoolFull);
+        }
         let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
         let seq = self.seq.fetch_add(1, Ordering::Relaxed);
-        let key = OsPath::from(format!("{}{:013}-{:08}.batch", self.prefix, millis, seq));
+        let key = if tenant.is_empty() {
+            OsPath::from(format!("{}{:013}-{:08}.batch", self.prefix, millis, seq))
+        } else {
+            OsPath::from(format!("{}{}/{:013}-{:08}.batch", self.prefix, hex::encode(tenant), millis, seq))
+        };
 
         let mut body = Vec::with_capacity(rec_len as usize);
         body.extend_from_slice(table.as_bytes());
@@ -120,6 +174,7 @@ impl ObjectStoreSpool {
             Ok(_) => {
                 self.bytes.fetch_add(rec_len, Ordering::Relaxed);
                 self.count.fetch_add(1, Ordering::Relaxed);
+                self.account(tenant, rec_len as i64, 1);
                 Ok(())
             }
             Err(e) => {
@@ -129,15 +184,30 @@ impl ObjectStoreSpool {
         }
     }
 
-    /// Oldest spilled batch + its object key (handle), or None if empty.
+    /// Next spilled batch as (tenant, key, size, records), or None if empty.
+    /// The tenant is picked by weighted round-robin; within it, oldest first.
     /// Corrupt objects are deleted and skipped.
-    pub async fn next_batch(&self) -> Option<(OsPath, Vec<(String, Vec<u8>)>)> {
+    pub async fn next_batch(&self) -> Option<(String, OsPath, u64, Vec<(String, Vec<u8>)>)> {
         loop {
             let metas = match self.list_sorted().await {
                 Ok(m) => m,
                 Err(e) => { tracing::warn!(error = %e, "object-store buffer: list failed"); return None; }
             };
-            let (key, size) = metas.into_iter().next()?;
+            // Oldest object per tenant (the listing is sorted by key).
+            let mut oldest: Vec<(String, OsPath, u64)> = Vec::new();
+            for (key, size) in metas {
+                let tenant = self.tenant_of(&key);
+                if !oldest.iter().any(|(t, _, _)| *t == tenant) {
+                    oldest.push((tenant, key, size));
+                }
+            }
+            oldest.sort_by(|a, b| a.0.cmp(&b.0));
+            let picked = {
+                let ready: Vec<&str> = oldest.iter().map(|(t, _, _)| t.as_str()).collect();
+                let policy = self.policy.lock().unwrap();
+                self.scheduler.lock().unwrap().pick(&ready, &policy)?.to_string()
+            };
+            let (tenant, key, size) = oldest.into_iter().find(|(t, _, _)| *t == picked)?;
             let data = match self.store.get(&key).await {
                 Ok(r) => match r.bytes().await {
                     Ok(b) => b,
@@ -146,42 +216,144 @@ impl ObjectStoreSpool {
                 Err(e) => { tracing::warn!(error = %e, key = %key, "buffer: get failed"); return None; }
             };
             match split_record(&data) {
-                Some((table, payload)) => return Some((key, vec![(table, payload)])),
+                Some((table, payload)) => return Some((tenant, key, size, vec![(table, payload)])),
                 None => {
                     tracing::error!(key = %key, "object-store buffer: corrupt object — discarding");
                     let _ = self.store.delete(&key).await;
                     self.bytes.fetch_sub(size.min(self.bytes.load(Ordering::Relaxed)), Ordering::Relaxed);
                     self.count.fetch_sub(self.count.load(Ordering::Relaxed).min(1), Ordering::Relaxed);
+                    self.account(&tenant, -(size as i64), -1);
                     continue; // try the next object
                 }
             }
         }
     }
 
-    /// Delete a successfully-drained object.
-    pub async fn commit(&self, key: &OsPath) {
-        // Best-effort size accounting: re-deriving exact size isn't worth a HEAD;
-        // recompute totals lazily on next open. Decrement count; bytes via list drift
-        // is corrected on restart. Here we just delete + decrement count.
+    /// Delete a successfully-drained object (`size` as listed by `next_batch`).
+    pub async fn commit(&self, tenant: &str, key: &OsPath, size: u64) {
         if let Err(e) = self.store.delete(key).await {
             tracing::warn!(error = %e, key = %key, "object-store buffer: delete failed");
             return;
         }
+        self.bytes.fetch_sub(size.min(self.bytes.load(Ordering::Relaxed)), Ordering::Relaxed);
         self.count.fetch_sub(self.count.load(Ordering::Relaxed).min(1), Ordering::Relaxed);
+        self.account(tenant, -(size as i64), -1);
         self.committed.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn set_tenant_policy(&self, policy: TenantPolicy) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    pub fn tenant_usage(&self) -> Vec<TenantUsage> {
+        let mut out: Vec<TenantUsage> = self
+            .tenants
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(t, (b, c))| TenantUsage { tenant: t.clone(), pending_bytes: *b, pending_count: *c })
+            .collect();
+        out.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        out
+    }
+
+    fn tenant_bytes(&self, tenant: &str) -> u64 {
+        self.tenants.lock().unwrap().get(tenant).map_or(0, |t| t.0)
+    }
+
+    /// Adjust a tenant's pending (bytes, objects), dropping it at zero objects.
+    fn account(&self, tenant: &str, bytes: i64, count: i64) {
+        let mut g = self.tenants.lock().unwrap();
+        let e = g.entry(tenant.to_string()).or_insert((0, 0));
+        e.0 = e.0.saturating_add_signed(bytes);
+        e.1 = e.1.saturating_add_signed(count as isize);
+        if e.1 == 0 {
+            g.remove(tenant);
+        }
+    }
+
+    /// Owning tenant of a batch key: the hex sub-prefix, or "" for legacy keys.
+    fn tenant_of(&self, key: &OsPath) -> String {
+        let rel = key.as_ref().strip_prefix(self.prefix.as_str()).unwrap_or(key.as_ref());
+        rel.split_once('/')
+            .and_then(|(h, _)| hex::decode(h).ok())
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Write one record to the dead-letter prefix.
+    pub async fn quarantine(&self, table: &str, payload: &[u8], meta: &DeadLetterMeta) -> anyhow::Result<()> {
+        let body = encode_dead_letter(meta, table, payload);
+        let len = body.len() as u64;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        let key = OsPath::from(format!("{}{:013}-{:08}.dead", self.dead_prefix, millis, seq));
+        self.store.put(&key, PutPayload::from_bytes(Bytes::from(body))).await?;
+        self.dead_bytes.fetch_add(len, Ordering::Relaxed);
+        self.dead_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// All dead-letter entries, oldest first.
+    pub async fn dead_letters(&self) -> anyhow::Result<Vec<DeadLetterEntry>> {
+        let mut out = Vec::new();
+        for (key, _) in self.list_prefix(&self.dead_prefix).await? {
+            let Some(id) = key.filename().map(str::to_string) else { continue };
+            let data = self.store.get(&key).await?.bytes().await?;
+            out.push(DeadLetterEntry::from_body(id, &data));
+        }
+        Ok(out)
+    }
+
+    /// Raw dead-letter body by id. `Ok(None)` when it doesn't exist.
+    pub async fn read_dead_letter(&self, id: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        if !valid_dead_letter_id(id) {
+            return Ok(None);
+        }
+        let key = OsPath::from(format!("{}{}", self.dead_prefix, id));
+        match self.store.get(&key).await {
+            Ok(r) => Ok(Some(r.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Delete a dead-letter entry. Returns false when it doesn't exist.
+    pub async fn delete_dead_letter(&self, id: &str) -> anyhow::Result<bool> {
+        if !valid_dead_letter_id(id) {
+            return Ok(false);
+        }
+        let key = OsPath::from(format!("{}{}", self.dead_prefix, id));
+        let size = match self.store.head(&key).await {
+            Ok(m) => m.size,
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        self.store.delete(&key).await?;
+        self.dead_bytes.fetch_sub(size.min(self.dead_bytes.load(Ordering::Relaxed)), Ordering::Relaxed);
+        self.dead_count.fetch_sub(self.dead_count.load(Ordering::Relaxed).min(1), Ordering::Relaxed);
+        Ok(true)
+    }
+
+    pub fn dead_letter_count(&self) -> usize { self.dead_count.load(Ordering::Relaxed) }
+    pub fn dead_letter_bytes(&self) -> u64 { self.dead_bytes.load(Ordering::Relaxed) }
+
     pub fn total_bytes(&self) -> u64 { self.bytes.load(Ordering::Relaxed) }
     pub fn segment_count(&self) -> usize { self.count.load(Ordering::Relaxed) }
     pub fn max_bytes(&self) -> u64 { self.max_bytes }
     pub fn committed_total(&self) -> u64 { self.committed.load(Ordering::Relaxed) }
 
-    /// Age (seconds) of the oldest pending object, parsed from its `{millis}-{seq}.batch` key.
+    /// Age (seconds) of the oldest pending object across all tenants, parsed
+    /// from its `{millis}-{seq}.batch` key.
     pub async fn oldest_age_secs(&self) -> Option<u64> {
         let metas = self.list_sorted().await.ok()?;
-        let (key, _) = metas.into_iter().next()?;
-        let fname = key.as_ref().rsplit('/').next().unwrap_or("");
-        let ms = fname.split('-').next().and_then(|s| s.parse::<u64>().ok())?;
+        let ms = metas
+            .iter()
+            .filter_map(|(key, _)| {
+                let fname = key.as_ref().rsplit('/').next().unwrap_or("");
+                fname.split('-').next().and_then(|s| s.parse::<u64>().ok())
+            })
+            .min()?;
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
         Some(now.saturating_sub(ms) / 1000)
     }
@@ -204,29 +376,32 @@ mod tests {
     #[tokio::test]
     async fn append_drain_commit_roundtrip() {
         let s = ObjectStoreSpool::open(store(), "ingest", 1_000_000).await.unwrap();
-        s.append("logs", b"alpha").await.unwrap();
-        s.append("spans", b"beta").await.unwrap();
+        s.append("t", "logs", b"alpha").await.unwrap();
+        s.append("t", "spans", b"beta").await.unwrap();
         assert_eq!(s.segment_count(), 2);
 
         // oldest first
-        let (k1, recs1) = s.next_batch().await.unwrap();
+        let (t1, k1, n1, recs1) = s.next_batch().await.unwrap();
+        assert_eq!(t1, "t");
         assert_eq!(recs1, vec![("logs".to_string(), b"alpha".to_vec())]);
-        s.commit(&k1).await;
+        s.commit(&t1, &k1, n1).await;
 
-        let (k2, recs2) = s.next_batch().await.unwrap();
+        let (t2, k2, n2, recs2) = s.next_batch().await.unwrap();
         assert_eq!(recs2, vec![("spans".to_string(), b"beta".to_vec())]);
-        s.commit(&k2).await;
+        s.commit(&t2, &k2, n2).await;
 
         assert!(s.next_batch().await.is_none());
+        assert_eq!((s.total_bytes(), s.segment_count()), (0, 0));
+        assert!(s.tenant_usage().is_empty());
     }
 
     #[tokio::test]
     async fn cap_triggers_spoolfull() {
         let s = ObjectStoreSpool::open(store(), "ingest", 30).await.unwrap();
         // rec_len = table+1+payload; "m"+1+10 = 12
-        s.append("m", &[0u8; 10]).await.unwrap();
-        s.append("m", &[0u8; 10]).await.unwrap(); // 24
-        let full = s.append("m", &[0u8; 10]).await; // 36 > 30
+        s.append("t", "m", &[0u8; 10]).await.unwrap();
+        s.append("t", "m", &[0u8; 10]).await.unwrap(); // 24
+        let full = s.append("t", "m", &[0u8; 10]).await; // 36 > 30
         assert!(full.is_err());
     }
 
@@ -235,11 +410,57 @@ mod tests {
         let st = store();
         {
             let s = ObjectStoreSpool::open(st.clone(), "ingest", 1_000_000).await.unwrap();
-            s.append("logs", b"x").await.unwrap();
+            s.append("t", "logs", b"x").await.unwrap();
         }
         // Re-open over the same store → must see the leftover object.
         let s2 = ObjectStoreSpool::open(st, "ingest", 1_000_000).await.unwrap();
         assert_eq!(s2.segment_count(), 1);
-        assert!(s2.next_batch().await.is_some());
+        assert_eq!(s2.tenant_usage()[0].tenant, "t");
+        assert_eq!(s2.next_batch().await.unwrap().0, "t");
+    }
+
+    #[tokio::test]
+    async fn drain_round_robins_tenants_and_caps_per_tenant() {
+        let s = ObjectStoreSpool::open(store(), "ingest", 1_000).await.unwrap();
+        s.set_tenant_policy(TenantPolicy { max_pct: 50, weights: Default::default() });
+        // "big" fills its 500-byte share; "small" can still spool.
+        s.append("small", "logs", b"hi").await.unwrap();
+        for _ in 0..4 {
+            s.append("big", "logs", &[0u8; 100]).await.unwrap();
+        }
+        assert!(s.append("big", "logs", &[0u8; 100]).await.is_err(), "big is over its share");
+        assert_eq!(s.tenant_usage()[0].pending_bytes, 420);
+        s.append("small", "logs", b"fresh").await.unwrap();
+
+        // Equal weights alternate, even though all of big's data is older.
+        let mut order = Vec::new();
+        while let Some((t, k, n, _)) = s.next_batch().await {
+            s.commit(&t, &k, n).await;
+            order.push(t);
+        }
+        assert_eq!(order, ["big", "small", "big", "small", "big", "big"]);
+    }
+
+    #[tokio::test]
+    async fn dead_letters_are_kept_out_of_the_drain() {
+        let st = store();
+        let s = ObjectStoreSpool::open(st.clone(), "ingest", 1_000_000).await.unwrap();
+        let meta = DeadLetterMeta { tenant: "t".into(), reason: "Code: 53".into(), attempts: 3, source: "k".into(), quarantined_at: 1 };
+        s.quarantine("logs", b"[1]", &meta).await.unwrap();
+        assert!(s.next_batch().await.is_none(), "dead letters must not be replayed");
+        assert_eq!(s.dead_letter_count(), 1);
+
+        // Survives a restart, lists with metadata, decodes, deletes.
+        let s = ObjectStoreSpool::open(st, "ingest", 1_000_000).await.unwrap();
+        assert_eq!(s.dead_letter_count(), 1);
+        let list = s.dead_letters().await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].table, "logs");
+        assert_eq!(list[0].meta.as_ref(), Some(&meta));
+        let raw = s.read_dead_letter(&list[0].id).await.unwrap().unwrap();
+        assert_eq!(crate::spool::decode_dead_letter(&raw).unwrap().2, b"[1]".to_vec());
+        assert!(s.delete_dead_letter(&list[0].id).await.unwrap());
+        assert!(!s.delete_dead_letter(&list[0].id).await.unwrap());
+        assert_eq!((s.dead_letter_count(), s.dead_letter_bytes()), (0, 0));
     }
 }
@@ -0,0 +1,133 @@
+//! Syntax tree for pipe queries. Every node keeps its byte span in the
+//! source so compile errors can point at the exact text.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub search: Option<Search>,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    pub fn sql(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "=",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+}
+
+/// The leading search section, before the first `|`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Search {
+    /// Free text (a word or a quoted phrase).
+    Term { text: String, span: Span },
+    /// `field:value` (`:` is equality; `*` in the value is a wildcard) or
+    /// `field >= value` style comparisons.
+    Field { field: Ident, op: CmpOp, value: String, quoted: bool, span: Span },
+    Not(Box<Search>),
+    And(Vec<Search>),
+    Or(Vec<Search>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Where(Expr),
+    Eval(Vec<(Ident, Expr)>),
+    Stats { aggs: Vec<Agg>, by: Vec<ByItem>, span: Span },
+    Sort(Vec<SortKey>),
+    Head { n: u64, span: Span },
+    Fields { exclude: bool, names: Vec<Ident> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Agg {
+    pub func: Ident,
+    pub arg: Option<Expr>,
+    pub alias: Option<Ident>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ByItem {
+    pub expr: Expr,
+    pub alias: Option<Ident>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub field: Ident,
+    pub desc: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Cmp(CmpOp),
+    Like,
+    NotLike,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Field(String),
+    Str(String),
+    /// Numeric literal, kept as written (already validated).
+    Num(String),
+    /// Duration literal (`500ms`, `5m`), in nanoseconds.
+    Duration(u64),
+    Bool(bool),
+    Call(Ident, Vec<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    In { expr: Box<Expr>, list: Vec<Expr>, negated: bool },
+}
@@ -0,0 +1,724 @@
+//! Compile a parsed pipe query to ClickHouse SQL.
+//!
+//! Commands accumulate into a `Stage` (one SELECT). A command that must see
+//! the previous stage's output — `where` after `head`, anything after
+//! `fields`, a second `stats` — closes the stage and opens a new one over it
+//! as a subquery. `where` right after `stats` becomes HAVING instead.
+//!
+//! `eval` columns are inlined wherever they are referenced in the same stage,
+//! so they may not reuse the name of an existing column.
+
+use super::ast::*;
+use super::{PipeError, parse};
+use crate::handlers::logs::{build_log_where, resolve_log_field};
+use crate::query_builder::{build_log_search_sql, build_span_search_sql, build_where_clause, escape_string_literal, resolve_field};
+
+/// Row cap when the query has no `head` / `limit`.
+pub const DEFAULT_LIMIT: u64 = 1_000;
+pub const MAX_LIMIT: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Spans,
+    Logs,
+}
+
+impl Signal {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "spans" | "traces" => Some(Signal::Spans),
+            "logs" => Some(Signal::Logs),
+            _ => None,
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            Signal::Spans => "spans",
+            Signal::Logs => "logs",
+        }
+    }
+
+    fn time_column(self) -> &'static str {
+        match self {
+            Signal::Spans => "timestamp",
+            Signal::Logs => "Timestamp",
+        }
+    }
+
+    /// The columns a query without `stats` / `fields` returns.
+    fn default_columns(self) -> &'static [(&'static str, Ty)] {
+        match self {
+            Signal::Spans => &[
+                ("timestamp", Ty::Time),
+                ("trace_id", Ty::Str),
+                ("span_id", Ty::Str),
+                ("parent_span_id", Ty::Str),
+                ("service_name", Ty::Str),
+                ("span_name", Ty::Str),
+                ("kind", Ty::Str),
+                ("status", Ty::Str),
+                ("duration_ns", Ty::Num),
+                ("http_method", Ty::Str),
+                ("http_path", Ty::Str),
+                ("http_status_code", Ty::Num),
+                ("attributes", Ty::Str),
+            ],
+            Signal::Logs => &[
+                ("Timestamp", Ty::Time),
+                ("TraceId", Ty::Str),
+                ("SpanId", Ty::Str),
+                ("SeverityText", Ty::Str),
+                ("SeverityNumber", Ty::Num),
+                ("ServiceName", Ty::Str),
+                ("Body", Ty::Str),
+                ("ResourceAttributes", Ty::Str),
+                ("ScopeName", Ty::Str),
+                ("LogAttributes", Ty::Str),
+            ],
+        }
+    }
+
+    /// Short names accepted for common fields.
+    fn canonical(self, name: &str) -> &str {
+        match (self, name) {
+            (Signal::Spans, "duration") => "duration_ns",
+            (Signal::Spans, "service") => "service_name",
+            (Signal::Spans, "name" | "operation") => "span_name",
+            (Signal::Spans, "method") => "http_method",
+            (Signal::Spans, "path" | "route") => "http_path",
+            (Signal::Spans, "status_code") => "http_status_code",
+            (Signal::Logs, "timestamp") => "Timestamp",
+            (Signal::Logs, "service" | "service_name") => "ServiceName",
+            (Signal::Logs, "level" | "severity" | "severity_text") => "SeverityText",
+            (Signal::Logs, "severity_number") => "SeverityNumber",
+            (Signal::Logs, "message" | "msg" | "body") => "Body",
+            (Signal::Logs, "trace_id") => "TraceId",
+            (Signal::Logs, "span_id") => "SpanId",
+            (Signal::Logs, "scope_name") => "ScopeName",
+            _ => name,
+        }
+    }
+
+    /// A field of the underlying table.
+    fn field(self, name: &str) -> Val {
+        let name = self.canonical(name);
+        if let Some((col, ty)) = self.default_columns().iter().find(|(c, _)| *c == name) {
+            return Val::new(col.to_string(), *ty);
+        }
+        match self {
+            Signal::Spans if name == "level" => Val::new(resolve_field(name), Ty::Str),
+            Signal::Spans if name.starts_with("attributes.") => Val::new(resolve_field(name), Ty::Str),
+            Signal::Spans => Val::new(resolve_field(&format!("attributes.{name}")), Ty::Str),
+            Signal::Logs => Val::new(resolve_log_field(name), Ty::Str),
+        }
+    }
+
+    /// Case-insensitive form used by `field:value` search on enum-like
+    /// fields, so `status:error` matches `STATUS_CODE_ERROR` and `Error`.
+    fn normalized(self, name: &str) -> Option<&'static str> {
+        match (self, self.canonical(name)) {
+            (Signal::Spans, "status" | "level") => Some("lower(replaceOne(status, 'STATUS_CODE_', ''))"),
+            (Signal::Spans, "kind") => Some("lower(replaceOne(kind, 'SPAN_KIND_', ''))"),
+            (Signal::Logs, "SeverityText") => Some("lower(SeverityText)"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Num,
+    Str,
+    Bool,
+    Time,
+}
+
+#[derive(Debug, Clone)]
+struct Val {
+    sql: String,
+    ty: Ty,
+}
+
+impl Val {
+    fn new(sql: String, ty: Ty) -> Self {
+        Val { sql, ty }
+    }
+
+    /// Numeric view: string attributes are parsed, NULL when not a number.
+    fn num(&self) -> String {
+        match self.ty {
+            Ty::Str => format!("toFloat64OrNull({})", self.sql),
+            _ => self.sql.clone(),
+        }
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+fn string_lit(s: &str) -> String {
+    format!("'{}'", escape_string_literal(s))
+}
+
+/// A search value that can be emitted as a numeric literal as-is.
+fn numeric_literal(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))
+        && s.parse::<f64>().is_ok()
+}
+
+enum Source {
+    Table,
+    /// A previous stage's output columns.
+    Sub(Vec<(String, Ty)>),
+}
+
+struct Stage {
+    from: String,
+    source: Source,
+    prewhere: String,
+    evals: Vec<(String, Val)>,
+    wheres: Vec<String>,
+    /// Explicit projection from `stats` / `fields`, as (value, output name).
+    select: Option<Vec<(Val, String)>>,
+    group_by: Vec<String>,
+    aggregated: bool,
+    having: Vec<String>,
+    order_by: Vec<String>,
+    limit: Option<u64>,
+}
+
+impl Stage {
+    fn new(from: String, source: Source, prewhere: String) -> Self {
+        Stage {
+            from,
+            source,
+            prewhere,
+            evals: Vec::new(),
+            wheres: Vec::new(),
+            select: None,
+            group_by: Vec::new(),
+            aggregated: false,
+            having: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+        }
+    }
+
+    /// Output columns without an explicit projection.
+    fn input_columns(&self, signal: Signal) -> Vec<(String, Ty)> {
+        match &self.source {
+            Source::Table => signal.default_columns().iter().map(|(c, t)| (c.to_string(), *t)).collect(),
+            Source::Sub(cols) => cols.clone(),
+        }
+    }
+
+    fn outputs(&self, signal: Signal) -> Vec<(String, Ty)> {
+        match &self.select {
+            Some(sel) => sel.iter().map(|(v, name)| (name.clone(), v.ty)).collect(),
+            None => {
+                let mut cols = self.input_columns(signal);
+                cols.extend(self.evals.iter().map(|(n, v)| (n.clone(), v.ty)));
+                cols
+            }
+        }
+    }
+
+    fn render(&self, signal: Signal, limit: Option<u64>) -> String {
+        let projection: Vec<String> = match &self.select {
+            Some(sel) => sel.iter().map(|(v, name)| format!("{} AS {}", v.sql, quote_ident(name))).collect(),
+            None => {
+                let mut cols: Vec<String> = match &self.source {
+                    Source::Table => signal.default_columns().iter().map(|(c, _)| c.to_string()).collect(),
+                    Source::Sub(cols) => cols.iter().map(|(c, _)| quote_ident(c)).collect(),
+                };
+                cols.extend(self.evals.iter().map(|(n, v)| format!("{} AS {}", v.sql, quote_ident(n))));
+                cols
+            }
+        };
+        let mut sql = format!("SELECT {} FROM {}", projection.join(", "), self.from);
+        if !self.prewhere.is_empty() {
+            sql.push_str(&format!(" PREWHERE {}", self.prewhere));
+        }
+        if !self.wheres.is_empty() {
+            sql.push_str(&format!(" WHERE {}", self.wheres.join(" AND ")));
+        }
+        if !self.group_by.is_empty() {
+            sql.push_str(&format!(" GROUP BY {}", self.group_by.join(", ")));
+        }
+        if !self.having.is_empty() {
+            sql.push_str(&format!(" HAVING {}", self.having.join(" AND ")));
+        }
+        if !self.order_by.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", self.order_by.join(", ")));
+        }
+        if let Some(n) = limit.or(self.limit) {
+            sql.push_str(&format!(" LIMIT {n}"));
+        }
+        sql
+    }
+}
+
+struct Compiler {
+    signal: Signal,
+    stage: Stage,
+}
+
+/// A compiled query: one SELECT and the names of its output columns, in order.
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub sql: String,
+    pub columns: Vec<String>,
+}
+
+/// Compile `src` over `signal` for one tenant and time range.
+pub fn compile(src: &str, signal: Signal, from: &str, to: &str, tenant_id: &str) -> Result<Compiled, PipeError> {
+    let query = parse(src)?;
+    let escaped_tenant = escape_string_literal(tenant_id);
+    let prewhere = match signal {
+        Signal::Spans => build_where_clause(&[], from, to).with_prewhere_prefix(&format!("tenant_id = '{escaped_tenant}'")).prewhere,
+        Signal::Logs => build_log_where(&[], from, to, None, tenant_id).prewhere,
+    };
+    let mut c = Compiler { signal, stage: Stage::new(signal.table().to_string(), Source::Table, prewhere) };
+    if let Some(search) = &query.search {
+        let cond = c.search(search)?;
+        c.stage.wheres.push(cond);
+    }
+    for command in &query.commands {
+        c.command(command)?;
+    }
+    let limit = c.stage.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let columns = c.stage.outputs(signal).into_iter().map(|(name, _)| name).collect();
+    Ok(Compiled { sql: c.stage.render(signal, Some(limit)), columns })
+}
+
+impl Compiler {
+    /// Close the current stage and continue over its output.
+    fn wrap(&mut self) {
+        let cols = self.stage.outputs(self.signal);
+        let inner = self.stage.render(self.signal, None);
+        self.stage = Stage::new(format!("({inner})"), Source::Sub(cols), String::new());
+    }
+
+    fn lookup(&self, ident: &Ident) -> Result<Val, PipeError> {
+        let name = ident.name.as_str();
+        if name.contains(['\'', '\\']) {
+            return Err(PipeError::new("field names may not contain quotes or backslashes", ident.span));
+        }
+        if let Some(sel) = &self.stage.select {
+            return sel
+                .iter()
+                .find(|(_, n)| n == name)
+                .map(|(v, n)| Val::new(quote_ident(n), v.ty))
+                .ok_or_else(|| self.unknown(ident));
+        }
+        if let Some((_, v)) = self.stage.evals.iter().find(|(n, _)| n == name) {
+            return Ok(Val::new(format!("({})", v.sql), v.ty));
+        }
+        match &self.stage.source {
+            Source::Table => Ok(self.signal.field(name)),
+            Source::Sub(cols) => {
+                let canonical = self.signal.canonical(name);
+                cols.iter()
+                    .find(|(c, _)| c == name || c == canonical)
+                    .map(|(c, t)| Val::new(quote_ident(c), *t))
+                    .ok_or_else(|| self.unknown(ident))
+            }
+        }
+    }
+
+    fn unknown(&self, ident: &Ident) -> PipeError {
+        let available: Vec<String> = self.stage.outputs(self.signal).into_iter().map(|(n, _)| n).collect();
+        PipeError::new(format!("unknown field `{}` (available: {})", ident.name, available.join(", ")), ident.span)
+    }
+
+    fn command(&mut self, command: &Command) -> Result<(), PipeError> {
+        match command {
+            Command::Where(e) => {
+                if self.stage.select.is_some() && self.stage.aggregated && self.stage.limit.is_none() {
+                    let cond = self.bool_expr(e)?;
+                    self.stage.having.push(cond);
+                    return Ok(());
+                }
+                if self.stage.select.is_some() || self.stage.limit.is_some() {
+                    self.wrap();
+                }
+                let cond = self.bool_expr(e)?;
+                self.stage.wheres.push(cond);
+            }
+            Command::Eval(assigns) => {
+                if self.stage.select.is_some() || self.stage.limit.is_some() {
+                    self.wrap();
+                }
+                for (name, e) in assigns {
+                    let v = self.expr(e)?;
+                    let is_eval = self.stage.evals.iter().any(|(n, _)| *n == name.name);
+                    if !is_eval && self.stage.input_columns(self.signal).iter().any(|(n, _)| *n == name.name) {
+                        return Err(PipeError::new(
+                            format!("eval cannot overwrite column `{}`; choose a new name", name.name),
+                            name.span,
+                        ));
+                    }
+                    self.stage.evals.retain(|(n, _)| *n != name.name);
+                    self.stage.evals.push((name.name.clone(), v));
+                }
+            }
+            Command::Stats { aggs, by, span } => {
+                if self.stage.select.is_some() || self.stage.limit.is_some() || !self.stage.order_by.is_empty() {
+                    self.wrap();
+                }
+                if aggs.is_empty() {
+                    return Err(PipeError::new("stats needs at least one aggregation", *span));
+                }
+                let mut select: Vec<(Val, String)> = Vec::new();
+                let mut push = |v: Val, name: String, span: Span| {
+                    if select.iter().any(|(_, n)| *n == name) {
+                        return Err(PipeError::new(format!("duplicate column `{name}`; rename one with `as`"), span));
+                    }
+                    select.push((v, name));
+                    Ok(())
+                };
+                let mut group_by = Vec::new();
+                for item in by {
+                    let v = self.expr(&item.expr)?;
+                    let name = match (&item.alias, &item.expr.kind) {
+                        (Some(a), _) => a.name.clone(),
+                        (None, ExprKind::Field(f)) => f.clone(),
+                        (None, ExprKind::Call(f, _)) if f.name.eq_ignore_ascii_case("bin") => "time".to_string(),
+                        _ => return Err(PipeError::new("name this group with `as`", item.expr.span)),
+                    };
+                    group_by.push(quote_ident(&name));
+                    push(v, name, item.expr.span)?;
+                }
+                for agg in aggs {
+                    let (v, default_name) = self.aggregation(agg)?;
+                    let name = agg.alias.as_ref().map(|a| a.name.clone()).unwrap_or(default_name);
+                    push(v, name, agg.func.span)?;
+                }
+                self.stage.select = Some(select);
+                self.stage.group_by = group_by;
+                self.stage.aggregated = true;
+            }
+            Command::Sort(keys) => {
+                if self.stage.limit.is_some() {
+                    self.wrap();
+                }
+                let mut order = Vec::new();
+                for key in keys {
+                    let v = self.lookup(&key.field)?;
+                    order.push(format!("{} {}", v.sql, if key.desc { "DESC" } else { "ASC" }));
+                }
+                self.stage.order_by = order;
+            }
+            Command::Head { n, .. } => {
+                let n = (*n).min(MAX_LIMIT);
+                self.stage.limit = Some(self.stage.limit.map_or(n, |l| l.min(n)));
+            }
+            Command::Fields { exclude, names } => {
+                if self.stage.select.is_some() {
+                    self.wrap();
+                }
+                let mut select = Vec::new();
+                if *exclude {
+                    let outputs = self.stage.outputs(self.signal);
+                    for name in names {
+                        if !outputs.iter().any(|(n, _)| *n == name.name) {
+                            return Err(self.unknown(name));
+                        }
+                    }
+                    for (col, _) in outputs.into_iter().filter(|(n, _)| !names.iter().any(|x| x.name == *n)) {
+                        let v = self.lookup(&Ident { name: col.clone(), span: Span::new(0, 0) })?;
+                        select.push((v, col));
+                    }
+                } else {
+                    for name in names {
+                        select.push((self.lookup(name)?, name.name.clone()));
+                    }
+                }
+                self.stage.select = Some(select);
+                self.stage.aggregated = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// An aggregation and its default output name.
+    fn aggregation(&self, agg: &Agg) -> Result<(Val, String), PipeError> {
+        let func = agg.func.name.to_ascii_lowercase();
+        let arg = agg.arg.as_ref().map(|e| self.expr(e)).transpose()?;
+        let need_arg = || PipeError::new(format!("{func}() needs a field"), agg.func.span);
+        let sql = match func.as_str() {
+            "count" => match &arg {
+                None => "count()".to_string(),
+                Some(v) => format!("count({})", v.sql),
+            },
+            "sum" | "avg" | "min" | "max" => format!("{func}({})", arg.as_ref().ok_or_else(need_arg)?.num()),
+            "uniq" | "dc" | "distinct_count" => format!("uniq({})", arg.as_ref().ok_or_else(need_arg)?.sql),
+            "median" => format!("quantile(0.5)({})", arg.as_ref().ok_or_else(need_arg)?.num()),
+            p if p.starts_with('p') && p.len() > 1 => {
+                let digits = &p[1..];
+                let q = format!("0.{}", digits.replace('.', ""));
+                let valid = digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+                    && digits.starts_with(|c: char| c.is_ascii_digit())
+                    && q.parse::<f64>().is_ok_and(|q| q > 0.0 && q < 1.0)
+                    && (digits.len() == 2 || digits.contains('.'));
+                if !valid {
+                    return Err(PipeError::new(format!("unknown aggregation `{}`", agg.func.name), agg.func.span));
+                }
+                format!("quantile({q})({})", arg.as_ref().ok_or_else(need_arg)?.num())
+            }
+            _ => {
+                return Err(PipeError::new(
+                    format!(
+                        "unknown aggregation `{}` (expected count, sum, avg, min, max, uniq, median or pNN)",
+                        agg.func.name
+                    ),
+                    agg.func.span,
+                ));
+            }
+        };
+        Ok((Val::new(sql, Ty::Num), func))
+    }
+
+    fn bool_expr(&self, e: &Expr) -> Result<String, PipeError> {
+        Ok(self.expr(e)?.sql)
+    }
+
+    fn expr(&self, e: &Expr) -> Result<Val, PipeError> {
+        Ok(match &e.kind {
+            ExprKind::Field(name) => self.lookup(&Ident { name: name.clone(), span: e.span })?,
+            ExprKind::Str(s) => Val::new(string_lit(s), Ty::Str),
+            ExprKind::Num(n) => Val::new(n.clone(), Ty::Num),
+            ExprKind::Duration(ns) => Val::new(ns.to_string(), Ty::Num),
+            ExprKind::Bool(b) => Val::new(b.to_string(), Ty::Bool),
+            ExprKind::Neg(x) => Val::new(format!("(-{})", self.expr(x)?.num()), Ty::Num),
+            ExprKind::Not(x) => Val::new(format!("(NOT {})", self.expr(x)?.sql), Ty::Bool),
+            ExprKind::Binary(op, a, b) => {
+                let (a, b) = (self.expr(a)?, self.expr(b)?);
+                match op {
+                    BinOp::Or => Val::new(format!("({} OR {})", a.sql, b.sql), Ty::Bool),
+                    BinOp::And => Val::new(format!("({} AND {})", a.sql, b.sql), Ty::Bool),
+                    BinOp::Cmp(op) => {
+                        let (a, b) = coerce(a, b);
+                        Val::new(format!("({} {} {})", a, op.sql(), b), Ty::Bool)
+                    }
+                    BinOp::Like => Val::new(format!("({} LIKE {})", a.sql, b.sql), Ty::Bool),
+                    BinOp::NotLike => Val::new(format!("({} NOT LIKE {})", a.sql, b.sql), Ty::Bool),
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => {
+                        let sym = match op {
+                            BinOp::Add => "+",
+                            BinOp::Sub => "-",
+                            BinOp::Mul => "*",
+                            BinOp::Div => "/",
+                            _ => "%",
+                        };
+                        Val::new(format!("({} {sym} {})", a.num(), b.num()), Ty::Num)
+                    }
+                }
+            }
+            ExprKind::In { expr, list, negated } => {
+                let lhs = self.expr(expr)?;
+                let mut items = Vec::new();
+                for item in list {
+                    let (_, item) = coerce(lhs.clone(), self.expr(item)?);
+                    items.push(item);
+                }
+                let not = if *negated { "NOT " } else { "" };
+                Val::new(format!("({} {not}IN ({}))", lhs.sql, items.join(", ")), Ty::Bool)
+            }
+            ExprKind::Call(func, args) => self.call(func, args, e.span)?,
+        })
+    }
+
+    fn call(&self, func: &Ident, args: &[Expr], span: Span) -> Result<Val, PipeError> {
+        let name = func.name.to_ascii_lowercase();
+        let arity = |n: std::ops::RangeInclusive<usize>| {
+            if n.contains(&args.len()) {
+                Ok(())
+            } else {
+                Err(PipeError::new(format!("wrong number of arguments to {name}()"), span))
+            }
+        };
+        if name == "bin" {
+            arity(1..=2)?;
+            return self.bin(args, span);
+        }
+        let vals = args.iter().map(|a| self.expr(a)).collect::<Result<Vec<_>, _>>()?;
+        let v = |i: usize| &vals[i];
+        Ok(match name.as_str() {
+            "lower" | "upper" => {
+                arity(1..=1)?;
+                Val::new(format!("{name}(toString({}))", v(0).sql), Ty::Str)
+            }
+            "len" | "length" => {
+                arity(1..=1)?;
+                Val::new(format!("lengthUTF8(toString({}))", v(0).sql), Ty::Num)
+            }
+            "abs" | "floor" | "ceil" => {
+                arity(1..=1)?;
+                Val::new(format!("{name}({})", v(0).num()), Ty::Num)
+            }
+            "round" => {
+                arity(1..=2)?;
+                match vals.get(1) {
+                    Some(d) => Val::new(format!("round({}, {})", v(0).num(), d.sql), Ty::Num),
+                    None => Val::new(format!("round({})", v(0).num()), Ty::Num),
+                }
+            }
+            "if" => {
+                arity(3..=3)?;
+                Val::new(format!("if({}, {}, {})", v(0).sql, v(1).sql, v(2).sql), v(1).ty)
+            }
+            "coalesce" => {
+                arity(1..=16)?;
+                let parts: Vec<&str> = vals.iter().map(|v| v.sql.as_str()).collect();
+                Val::new(format!("coalesce({})", parts.join(", ")), v(0).ty)
+            }
+            "concat" => {
+                arity(1..=16)?;
+                let parts: Vec<String> = vals.iter().map(|v| format!("toString({})", v.sql)).collect();
+                Val::new(format!("concat({})", parts.join(", ")), Ty::Str)
+            }
+            "contains" => {
+                arity(2..=2)?;
+                Val::new(format!("(position(toString({}), {}) > 0)", v(0).sql, v(1).sql), Ty::Bool)
+            }
+            "tostring" => {
+                arity(1..=1)?;
+                Val::new(format!("toString({})", v(0).sql), Ty::Str)
+            }
+            "tonumber" => {
+                arity(1..=1)?;
+                Val::new(format!("toFloat64OrNull(toString({}))", v(0).sql), Ty::Num)
+            }
+            "count" | "sum" | "avg" | "min" | "max" | "uniq" | "median" => {
+                return Err(PipeError::new(format!("{name}() is an aggregation; use it in `stats`"), func.span));
+            }
+            _ => return Err(PipeError::new(format!("unknown function `{}`", func.name), func.span)),
+        })
+    }
+
+    /// `bin(5m)` / `bin(field, 5m)`: start of the time bucket.
+    fn bin(&self, args: &[Expr], span: Span) -> Result<Val, PipeError> {
+        let (field, width) = match args {
+            [w] => (self.lookup(&Ident { name: self.signal.time_column().to_string(), span })?, w),
+            [f, w] => (self.expr(f)?, w),
+            _ => unreachable!("arity checked"),
+        };
+        if field.ty != Ty::Time {
+            return Err(PipeError::new("bin() needs a timestamp field", args[0].span));
+        }
+        let secs = match width.kind {
+            ExprKind::Duration(ns) if ns >= 1_000_000_000 && ns % 1_000_000_000 == 0 => ns / 1_000_000_000,
+            _ => return Err(PipeError::new("bin width must be a whole number of seconds, like 30s or 5m", width.span)),
+        };
+        Ok(Val::new(format!("toStartOfInterval({}, INTERVAL {secs} SECOND)", field.sql), Ty::Time))
+    }
+
+    // ── search section ──
+
+    fn search(&self, s: &Search) -> Result<String, PipeError> {
+        Ok(match s {
+            Search::Term { text, .. } => {
+                let term = format!("\"{}\"", text.replace('"', ""));
+                let sql = match self.signal {
+                    Signal::Spans => build_span_search_sql(&term),
+                    Signal::Logs => build_log_search_sql(&term),
+                };
+                sql.unwrap_or_else(|| "1".to_string())
+            }
+            Search::Field { field, op, value, quoted, .. } => self.search_field(field, *op, value, *quoted)?,
+            Search::Not(inner) => format!("(NOT {})", self.search(inner)?),
+            Search::And(parts) => {
+                let parts = parts.iter().map(|p| self.search(p)).collect::<Result<Vec<_>, _>>()?;
+                format!("({})", parts.join(" AND "))
+            }
+            Search::Or(parts) => {
+                let parts = parts.iter().map(|p| self.search(p)).collect::<Result<Vec<_>, _>>()?;
+                format!("({})", parts.join(" OR "))
+            }
+        })
+    }
+
+    fn search_field(&self, field: &Ident, op: CmpOp, value: &str, quoted: bool) -> Result<String, PipeError> {
+        let col = self.lookup(field)?;
+        if let (Some(norm), CmpOp::Eq | CmpOp::Ne) = (self.signal.normalized(&field.name), op) {
+            return Ok(format!("{norm} {} {}", op.sql(), string_lit(&value.to_lowercase())));
+        }
+        if !quoted && value.contains('*') && matches!(op, CmpOp::Eq | CmpOp::Ne) {
+            let pattern = escape_string_literal(value).replace('%', "\\%").replace('_', "\\_").replace('*', "%");
+            let not = if op == CmpOp::Ne { "NOT " } else { "" };
+            return Ok(format!("toString({}) {not}LIKE '{pattern}'", col.sql));
+        }
+        let numeric = !quoted && numeric_literal(value);
+        Ok(match col.ty {
+            Ty::Num if numeric => format!("{} {} {value}", col.sql, op.sql()),
+            Ty::Str if numeric && !matches!(op, CmpOp::Eq | CmpOp::Ne) => {
+                format!("{} {} {value}", col.num(), op.sql())
+            }
+            Ty::Time => format!("{} {} parseDateTime64BestEffort({}, 9)", col.sql, op.sql(), string_lit(value)),
+            _ => format!("toString({}) {} {}", col.sql, op.sql(), string_lit(value)),
+        })
+    }
+}
+
+/// Make both sides of a comparison the same type: strings compared with a
+/// number are parsed as numbers, strings compared with a time as times.
+fn coerce(a: Val, b: Val) -> (String, String) {
+    match (a.ty, b.ty) {
+        (Ty::Num, Ty::Str) => (a.sql, b.num()),
+        (Ty::Str, Ty::Num) => (a.num(), b.sql),
+        (Ty::Time, Ty::Str) => (a.sql, format!("parseDateTime64BestEffort({}, 9)", b.sql)),
+        (Ty::Str, Ty::Time) => (format!("parseDateTime64BestEffort({}, 9)", a.sql), b.sql),
+        _ => (a.sql, b.sql),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(q: &str) -> String {
+        compile(q, Signal::Spans, "2026-01-01T00:00:00Z", "2026-01-01T01:00:00Z", "acme").unwrap().sql
+    }
+
+    #[test]
+    fn stats_sort_head_compile_to_one_select() {
+        let sql = spans("service:checkout status:error | stats p95(duration) by http_path | sort -p95 | head 10");
+        assert!(sql.starts_with("SELECT http_path AS `http_path`, quantile(0.95)(duration_ns) AS `p95` FROM spans PREWHERE tenant_id = 'acme' AND "), "{sql}");
+        assert!(sql.contains("WHERE (toString(service_name) = 'checkout' AND lower(replaceOne(status, 'STATUS_CODE_', '')) = 'error')"), "{sql}");
+        assert!(sql.ends_with("GROUP BY `http_path` ORDER BY `p95` DESC LIMIT 10"), "{sql}");
+    }
+
+    #[test]
+    fn later_stages_nest_and_where_after_stats_is_having() {
+        let sql = spans("| eval ms = duration / 1000000 | where ms > 5 | stats count() as n by bin(1m) | where n > 3 | head 5 | where n < 100");
+        assert!(sql.starts_with("SELECT `time`, `n` FROM (SELECT toStartOfInterval(timestamp, INTERVAL 60 SECOND) AS `time`, count() AS `n` FROM spans"), "{sql}");
+        assert!(sql.contains("WHERE (((duration_ns / 1000000)) > 5) GROUP BY `time` HAVING (`n` > 3) LIMIT 5) WHERE (`n` < 100) LIMIT 1000"), "{sql}");
+    }
+
+    #[test]
+    fn logs_fields_resolve_like_structured_filters() {
+        let sql = compile("level:error | where user.id = \"42\" | fields Timestamp, service, Body", Signal::Logs, "a", "b", "t").unwrap().sql;
+        assert!(sql.starts_with("SELECT Timestamp AS `Timestamp`, ServiceName AS `service`, Body AS `Body` FROM logs PREWHERE tenant_id = 't'"), "{sql}");
+        assert!(sql.contains("WHERE lower(SeverityText) = 'error' AND (if(LogAttributes['user.id'] != ''"), "{sql}");
+    }
+
+    #[test]
+    fn compile_errors_carry_spans() {
+        let src = "| stats count() by service | where latency > 1";
+        let e = compile(src, Signal::Spans, "a", "b", "t").unwrap_err();
+        assert_eq!(&src[e.start..e.end], "latency");
+        assert!(e.message.starts_with("unknown field `latency` (available: service, count)"), "{}", e.message);
+
+        let src = "| eval duration_ns = 1";
+        let e = compile(src, Signal::Spans, "a", "b", "t").unwrap_err();
+        assert_eq!(&src[e.start..e.end], "duration_ns");
+
+        let src = "| stats p101(duration)";
+        let e = compile(src, Signal::Spans, "a", "b", "t").unwrap_err();
+        assert_eq!(&src[e.start..e.end], "p101");
+    }
+}
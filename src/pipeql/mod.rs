@@ -0,0 +1,47 @@
+//! Pipe query language for Explore, over `spans` and `logs`.
+//!
+//! ```text
+//! service:checkout status:error | stats p95(duration) by http_path | sort -p95 | head 10
+//! ```
+//!
+//! The part before the first `|` is a search in the Explore search syntax
+//! (free text, `field:value`, `field>=value`, AND / OR / NOT, parentheses);
+//! free-text terms go through the same index-backed builders as the search
+//! box (`build_span_search_sql` / `build_log_search_sql`). Each command then
+//! transforms the result: `where`, `eval`, `stats … by …` (with `bin(5m)`
+//! time bucketing), `sort`, `head` / `limit` and `fields`.
+//!
+//! `parser` builds an `ast::Query`; `compile` turns it into one ClickHouse
+//! statement, nesting a subquery whenever a command has to see the output of
+//! the previous stage (e.g. `where` after `head`). Field names resolve through
+//! `resolve_field` / `resolve_log_field`, so they mean what they mean in the
+//! structured filters. Errors carry the byte span of the offending text.
+
+pub mod ast;
+pub mod compile;
+pub mod parser;
+
+use serde::Serialize;
+
+pub use compile::{Compiled, Signal, compile};
+pub use parser::parse;
+
+/// A parse or compile error, with the byte range of the source it refers to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipeError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl PipeError {
+    pub fn new(message: impl Into<String>, span: ast::Span) -> Self {
+        PipeError { message: message.into(), start: span.start, end: span.end }
+    }
+}
+
+impl std::fmt::Display for PipeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {}..{})", self.message, self.start, self.end)
+    }
+}
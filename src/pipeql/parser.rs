@@ -0,0 +1,663 @@
+//! Recursive-descent parser for pipe queries.
+//!
+//! ```text
+//! query    := search? ('|' command)*
+//! search   := or ; or := and ("OR" and)* ; and := not (("AND")? not)*
+//! not      := ("NOT" | "-") not | '(' or ')' | field (':' | cmp) value | term
+//! command  := "where" expr
+//!           | "eval" ident '=' expr (',' ident '=' expr)*
+//!           | "stats" agg (',' agg)* ("by" expr ("as" ident)? (',' ...)*)?
+//!           | "sort" ('-' | '+')? ident (',' ...)*
+//!           | "head" int? | "limit" int
+//!           | "fields" '-'? ident (',' ident)*
+//! agg      := ident '(' expr? ')' ("as" ident)?
+//! expr     := or ; or := and ("or" and)* ; and := not ("and" not)*
+//! not      := "not" not | cmp
+//! cmp      := add ((cmp-op | "like" | "not like") add | "not"? "in" '(' expr, ... ')')?
+//! add      := mul (('+' | '-') mul)* ; mul := unary (('*' | '/' | '%') unary)*
+//! unary    := '-' unary | primary
+//! primary  := number | duration | string | "true" | "false"
+//!           | ident '(' (expr (',' expr)*)? ')' | ident | '(' expr ')'
+//! ```
+//!
+//! Keywords are case-insensitive. Identifiers may contain dots
+//! (`http.route`); anything else can be written in backticks. Parentheses,
+//! `not`/`-` and call arguments nest at most `MAX_DEPTH` levels deep.
+
+use super::PipeError;
+use super::ast::*;
+
+pub fn parse(src: &str) -> Result<Query, PipeError> {
+    let mut p = Parser { src, pos: 0, depth: 0 };
+    p.skip_ws();
+    let search = if p.at_end() || p.peek() == Some('|') { None } else { Some(p.search_or()?) };
+    let mut commands = Vec::new();
+    loop {
+        p.skip_ws();
+        if p.at_end() {
+            break;
+        }
+        if !p.eat("|") {
+            return Err(p.err_here("expected `|` or end of query"));
+        }
+        commands.push(p.command()?);
+    }
+    Ok(Query { search, commands })
+}
+
+const KEYWORDS: &[&str] = &["and", "or", "not", "by", "as", "in", "like"];
+
+/// Deepest nesting the parser descends into before giving up, so hostile
+/// input gets an error instead of exhausting the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '@'
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.src.len()
+    }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+
+    fn err(&self, message: impl Into<String>, span: Span) -> PipeError {
+        PipeError::new(message, span)
+    }
+
+    fn err_here(&self, message: impl Into<String>) -> PipeError {
+        let len = self.peek().map(char::len_utf8).unwrap_or(0);
+        self.err(message, Span::new(self.pos, self.pos + len))
+    }
+
+    /// Consume `s` literally (after whitespace).
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Run `f` one nesting level deeper; `start` is where the nesting token began.
+    fn nested<T>(&mut self, start: usize, f: impl FnOnce(&mut Self) -> Result<T, PipeError>) -> Result<T, PipeError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.err(format!("query nests too deeply (max {MAX_DEPTH} levels)"), Span::new(start, self.pos)));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), PipeError> {
+        if self.eat(s) { Ok(()) } else { Err(self.err_here(format!("expected `{s}`"))) }
+    }
+
+    /// Whether a case-insensitive keyword is next, without consuming it.
+    fn at_keyword(&mut self, kw: &str) -> bool {
+        self.skip_ws();
+        let rest = self.rest();
+        rest.len() >= kw.len()
+            && rest.is_char_boundary(kw.len())
+            && rest[..kw.len()].eq_ignore_ascii_case(kw)
+            && !rest[kw.len()..].chars().next().is_some_and(is_ident_char)
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        if self.at_keyword(kw) {
+            self.pos += kw.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Option<Ident> {
+        self.skip_ws();
+        let start = self.pos;
+        if self.peek() == Some('`') {
+            let end = self.rest()[1..].find('`')?;
+            let name = self.rest()[1..1 + end].to_string();
+            self.pos += end + 2;
+            return Some(Ident { name, span: Span::new(start, self.pos) });
+        }
+        if !self.peek().is_some_and(is_ident_start) {
+            return None;
+        }
+        let len = self.rest().find(|c: char| !is_ident_char(c)).unwrap_or(self.rest().len());
+        self.pos += len;
+        Some(Ident { name: self.src[start..self.pos].to_string(), span: Span::new(start, self.pos) })
+    }
+
+    fn expect_ident(&mut self, what: &str) -> Result<Ident, PipeError> {
+        self.ident().ok_or_else(|| self.err_here(format!("expected {what}")))
+    }
+
+    /// A quoted string at the cursor, unescaped.
+    fn string(&mut self) -> Result<Option<(String, Span)>, PipeError> {
+        self.skip_ws();
+        let start = self.pos;
+        let Some(quote) = self.peek().filter(|c| *c == '"' || *c == '\'') else { return Ok(None) };
+        let mut out = String::new();
+        let mut chars = self.rest()[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, e)) => out.push(e),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.pos += i + 2;
+                    return Ok(Some((out, Span::new(start, self.pos))));
+                }
+                c => out.push(c),
+            }
+        }
+        Err(self.err("unterminated string", Span::new(start, self.src.len())))
+    }
+
+    /// A bare search word: everything up to whitespace, `|`, `(` or `)`.
+    fn word(&mut self) -> Option<(String, Span)> {
+        self.skip_ws();
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c: char| c.is_whitespace() || matches!(c, '|' | '(' | ')'))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return None;
+        }
+        self.pos += len;
+        Some((self.src[start..self.pos].to_string(), Span::new(start, self.pos)))
+    }
+
+    fn cmp_op(&mut self) -> Option<CmpOp> {
+        self.skip_ws();
+        for (s, op) in [
+            ("==", CmpOp::Eq),
+            ("!=", CmpOp::Ne),
+            ("<=", CmpOp::Le),
+            (">=", CmpOp::Ge),
+            ("=", CmpOp::Eq),
+            ("<", CmpOp::Lt),
+            (">", CmpOp::Gt),
+        ] {
+            if self.rest().starts_with(s) {
+                self.pos += s.len();
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    // ── search section ──
+
+    fn search_end(&mut self) -> bool {
+        self.skip_ws();
+        self.at_end() || matches!(self.peek(), Some('|' | ')'))
+    }
+
+    fn search_or(&mut self) -> Result<Search, PipeError> {
+        let mut parts = vec![self.search_and()?];
+        while self.keyword("or") {
+            parts.push(self.search_and()?);
+        }
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Search::Or(parts) })
+    }
+
+    fn search_and(&mut self) -> Result<Search, PipeError> {
+        let mut parts = vec![self.search_not()?];
+        loop {
+            if self.search_end() || self.at_keyword("or") {
+                break;
+            }
+            self.keyword("and");
+            parts.push(self.search_not()?);
+        }
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Search::And(parts) })
+    }
+
+    fn search_not(&mut self) -> Result<Search, PipeError> {
+        self.skip_ws();
+        if self.search_end() {
+            return Err(self.err_here("expected a search term"));
+        }
+        let open = self.pos;
+        if self.keyword("not") {
+            return Ok(Search::Not(Box::new(self.nested(open, Self::search_not)?)));
+        }
+        if self.rest().starts_with('-') && self.rest()[1..].starts_with(|c: char| !c.is_whitespace()) {
+            self.pos += 1;
+            return Ok(Search::Not(Box::new(self.nested(open, Self::search_not)?)));
+        }
+        if self.eat("(") {
+            let inner = self.nested(open, Self::search_or)?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        if let Some((text, span)) = self.string()? {
+            return Ok(Search::Term { text, span });
+        }
+
+        // `field:value` / `field op value`, else a bare word.
+        let start = self.pos;
+        if let Some(field) = self.ident() {
+            let op = if self.rest().starts_with(':') {
+                self.pos += 1;
+                Some(CmpOp::Eq)
+            } else {
+                self.cmp_op()
+            };
+            if let Some(op) = op {
+                let (value, quoted, vspan) = match self.string()? {
+                    Some((v, s)) => (v, true, s),
+                    None => match self.word() {
+                        Some((v, s)) => (v, false, s),
+                        None => return Err(self.err_here(format!("expected a value for `{}`", field.name))),
+                    },
+                };
+                let span = Span::new(start, vspan.end);
+                return Ok(Search::Field { field, op, value, quoted, span });
+            }
+            self.pos = start;
+        }
+        let (text, span) = self.word().ok_or_else(|| self.err_here("expected a search term"))?;
+        Ok(Search::Term { text, span })
+    }
+
+    // ── commands ──
+
+    fn command(&mut self) -> Result<Command, PipeError> {
+        self.skip_ws();
+        let Some(name) = self.ident() else { return Err(self.err_here("expected a command after `|`")) };
+        match name.name.to_ascii_lowercase().as_str() {
+            "where" => Ok(Command::Where(self.expr()?)),
+            "eval" => {
+                let mut assigns = Vec::new();
+                loop {
+                    let target = self.expect_ident("a field name")?;
+                    self.skip_ws();
+                    if !self.rest().starts_with('=') || self.rest().starts_with("==") {
+                        return Err(self.err_here("expected `=`"));
+                    }
+                    self.pos += 1;
+                    assigns.push((target, self.expr()?));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                Ok(Command::Eval(assigns))
+            }
+            "stats" => {
+                let mut aggs = Vec::new();
+                loop {
+                    aggs.push(self.agg()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                let mut by = Vec::new();
+                if self.keyword("by") {
+                    loop {
+                        let expr = self.expr()?;
+                        let alias = if self.keyword("as") { Some(self.expect_ident("an alias")?) } else { None };
+                        by.push(ByItem { expr, alias });
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                }
+                let span = name.span.to(Span::new(self.pos, self.pos));
+                Ok(Command::Stats { aggs, by, span })
+            }
+            "sort" => {
+                let mut keys = Vec::new();
+                loop {
+                    let desc = if self.eat("-") {
+                        true
+                    } else {
+                        self.eat("+");
+                        false
+                    };
+                    let field = self.expect_ident("a field to sort by")?;
+                    let desc = if self.keyword("desc") {
+                        true
+                    } else if self.keyword("asc") {
+                        false
+                    } else {
+                        desc
+                    };
+                    keys.push(SortKey { field, desc });
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                Ok(Command::Sort(keys))
+            }
+            "head" | "limit" => {
+                self.skip_ws();
+                let start = self.pos;
+                let len = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+                if len == 0 {
+                    if name.name.eq_ignore_ascii_case("head") {
+                        return Ok(Command::Head { n: 10, span: name.span });
+                    }
+                    return Err(self.err_here("expected a row count"));
+                }
+                self.pos += len;
+                let span = Span::new(start, self.pos);
+                let n = self.src[start..self.pos].parse().map_err(|_| self.err("row count too large", span))?;
+                Ok(Command::Head { n, span: name.span.to(span) })
+            }
+            "fields" => {
+                let exclude = self.eat("-");
+                let mut names = Vec::new();
+                loop {
+                    names.push(self.expect_ident("a field name")?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                Ok(Command::Fields { exclude, names })
+            }
+            other => Err(self.err(
+                format!("unknown command `{other}` (expected where, eval, stats, sort, head, limit or fields)"),
+                name.span,
+            )),
+        }
+    }
+
+    fn agg(&mut self) -> Result<Agg, PipeError> {
+        let func = self.expect_ident("an aggregation like count() or p95(duration)")?;
+        self.expect("(")?;
+        let arg = if self.eat(")") {
+            None
+        } else {
+            let e = self.expr()?;
+            self.expect(")")?;
+            Some(e)
+        };
+        let alias = if self.keyword("as") { Some(self.expect_ident("an alias")?) } else { None };
+        Ok(Agg { func, arg, alias })
+    }
+
+    // ── expressions ──
+
+    fn expr(&mut self) -> Result<Expr, PipeError> {
+        let mut lhs = self.expr_and()?;
+        while self.keyword("or") {
+            let rhs = self.expr_and()?;
+            lhs = binary(BinOp::Or, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn expr_and(&mut self) -> Result<Expr, PipeError> {
+        let mut lhs = self.expr_not()?;
+        while self.keyword("and") {
+            let rhs = self.expr_not()?;
+            lhs = binary(BinOp::And, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn expr_not(&mut self) -> Result<Expr, PipeError> {
+        self.skip_ws();
+        let start = self.pos;
+        if self.keyword("not") {
+            let inner = self.nested(start, Self::expr_not)?;
+            let span = Span::new(start, inner.span.end);
+            return Ok(Expr { kind: ExprKind::Not(Box::new(inner)), span });
+        }
+        self.expr_cmp()
+    }
+
+    fn expr_cmp(&mut self) -> Result<Expr, PipeError> {
+        let lhs = self.expr_add()?;
+        if let Some(op) = self.cmp_op() {
+            let rhs = self.expr_add()?;
+            return Ok(binary(BinOp::Cmp(op), lhs, rhs));
+        }
+        if self.keyword("like") {
+            let rhs = self.expr_add()?;
+            return Ok(binary(BinOp::Like, lhs, rhs));
+        }
+        let save = self.pos;
+        let negated = self.keyword("not");
+        if negated && self.keyword("like") {
+            let rhs = self.expr_add()?;
+            return Ok(binary(BinOp::NotLike, lhs, rhs));
+        }
+        if self.keyword("in") {
+            self.expect("(")?;
+            let mut list = Vec::new();
+            loop {
+                list.push(self.expr_add()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+            let span = Span::new(lhs.span.start, self.pos);
+            return Ok(Expr { kind: ExprKind::In { expr: Box::new(lhs), list, negated }, span });
+        }
+        self.pos = save;
+        Ok(lhs)
+    }
+
+    fn expr_add(&mut self) -> Result<Expr, PipeError> {
+        let mut lhs = self.expr_mul()?;
+        loop {
+            let op = if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else {
+                break;
+            };
+            let rhs = self.expr_mul()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn expr_mul(&mut self) -> Result<Expr, PipeError> {
+        let mut lhs = self.expr_unary()?;
+        loop {
+            let op = if self.eat("*") {
+                BinOp::Mul
+            } else if self.eat("/") {
+                BinOp::Div
+            } else if self.eat("%") {
+                BinOp::Mod
+            } else {
+                break;
+            };
+            let rhs = self.expr_unary()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn expr_unary(&mut self) -> Result<Expr, PipeError> {
+        self.skip_ws();
+        let start = self.pos;
+        if self.eat("-") {
+            let inner = self.nested(start, Self::expr_unary)?;
+            let span = Span::new(start, inner.span.end);
+            return Ok(Expr { kind: ExprKind::Neg(Box::new(inner)), span });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, PipeError> {
+        self.skip_ws();
+        let start = self.pos;
+        if self.eat("(") {
+            let inner = self.nested(start, Self::expr)?;
+            self.expect(")")?;
+            return Ok(Expr { kind: inner.kind, span: Span::new(start, self.pos) });
+        }
+        if let Some((s, span)) = self.string()? {
+            return Ok(Expr { kind: ExprKind::Str(s), span });
+        }
+        if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            return self.number();
+        }
+        let Some(ident) = self.ident() else { return Err(self.err_here("expected an expression")) };
+        let lower = ident.name.to_ascii_lowercase();
+        if self.rest().starts_with('(') {
+            self.pos += 1;
+            let mut args = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    args.push(self.nested(start, Self::expr)?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+            }
+            return Ok(Expr { kind: ExprKind::Call(ident, args), span: Span::new(start, self.pos) });
+        }
+        let kind = match lower.as_str() {
+            "true" => ExprKind::Bool(true),
+            "false" => ExprKind::Bool(false),
+            kw if KEYWORDS.contains(&kw) => {
+                return Err(self.err(format!("unexpected keyword `{}`", ident.name), ident.span));
+            }
+            _ => ExprKind::Field(ident.name),
+        };
+        Ok(Expr { kind, span: ident.span })
+    }
+
+    /// A number, or a duration when a unit follows directly (`250ms`, `5m`).
+    fn number(&mut self) -> Result<Expr, PipeError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let mut len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if rest[len..].starts_with('.') && rest[len + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+            len += 1 + rest[len + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - len - 1);
+        }
+        let digits = &rest[..len];
+        let unit_len = rest[len..].find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len() - len);
+        let unit = &rest[len..len + unit_len];
+        self.pos += len + unit_len;
+        let span = Span::new(start, self.pos);
+        if self.peek().is_some_and(is_ident_char) {
+            return Err(self.err("invalid number", Span::new(start, self.pos + 1)));
+        }
+        if unit.is_empty() {
+            return Ok(Expr { kind: ExprKind::Num(digits.to_string()), span });
+        }
+        let scale: f64 = match unit {
+            "ns" => 1.0,
+            "us" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            "d" => 86400e9,
+            _ => {
+                return Err(self.err(format!("unknown duration unit `{unit}` (use ns, us, ms, s, m, h or d)"), span));
+            }
+        };
+        let value: f64 = digits.parse().map_err(|_| self.err("invalid number", span))?;
+        Ok(Expr { kind: ExprKind::Duration((value * scale) as u64), span })
+    }
+}
+
+fn binary(op: BinOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span.to(rhs.span);
+    Expr { kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_search_and_pipeline() {
+        let q = parse("service:checkout status:error | stats p95(duration) by http_path | sort -p95 | head 10").unwrap();
+        let Some(Search::And(parts)) = &q.search else { panic!("expected AND search") };
+        assert!(matches!(&parts[0], Search::Field { field, value, .. } if field.name == "service" && value == "checkout"));
+        assert_eq!(q.commands.len(), 3);
+        let Command::Stats { aggs, by, .. } = &q.commands[0] else { panic!("expected stats") };
+        assert_eq!(aggs[0].func.name, "p95");
+        assert!(matches!(&by[0].expr.kind, ExprKind::Field(f) if f == "http_path"));
+        assert!(matches!(&q.commands[1], Command::Sort(k) if k[0].desc && k[0].field.name == "p95"));
+        assert!(matches!(&q.commands[2], Command::Head { n: 10, .. }));
+    }
+
+    #[test]
+    fn expression_precedence_and_durations() {
+        let q = parse("| where duration > 250ms and not kind in (\"client\", \"producer\") or a + b * 2 >= 3").unwrap();
+        let Command::Where(e) = &q.commands[0] else { panic!() };
+        let ExprKind::Binary(BinOp::Or, lhs, rhs) = &e.kind else { panic!("or binds loosest: {e:?}") };
+        let ExprKind::Binary(BinOp::And, cmp, _) = &lhs.kind else { panic!() };
+        assert!(matches!(&cmp.kind, ExprKind::Binary(BinOp::Cmp(CmpOp::Gt), _, d) if d.kind == ExprKind::Duration(250_000_000)));
+        let ExprKind::Binary(BinOp::Cmp(CmpOp::Ge), sum, _) = &rhs.kind else { panic!() };
+        assert!(matches!(&sum.kind, ExprKind::Binary(BinOp::Add, _, m) if matches!(m.kind, ExprKind::Binary(BinOp::Mul, ..))));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_text() {
+        let src = "error | stats count() by svc | frobnicate 3";
+        let e = parse(src).unwrap_err();
+        assert_eq!(&src[e.start..e.end], "frobnicate");
+
+        let src = "| where duration > 5parsecs";
+        let e = parse(src).unwrap_err();
+        assert_eq!(&src[e.start..e.end], "5parsecs");
+
+        let e = parse("| where (a = 1").unwrap_err();
+        assert_eq!(e.message, "expected `)`");
+        assert_eq!(e.start, 14);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        let n = 100_000;
+        for src in [
+            format!("| where {}a{}", "(".repeat(n), ")".repeat(n)),
+            format!("| where {}a", "not ".repeat(n)),
+            format!("| where {}1", "-".repeat(n)),
+            format!("| where {}1{}", "abs(".repeat(n), ")".repeat(n)),
+            format!("{}error{}", "(".repeat(n), ")".repeat(n)),
+            format!("{}error", "-".repeat(n)),
+        ] {
+            let e = parse(&src).unwrap_err();
+            assert!(e.message.contains("nests too deeply"), "{}", e.message);
+            assert!(e.start < e.end && e.end <= src.len());
+        }
+
+        // Just under the limit still parses.
+        let ok = format!("| where {}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse(&ok).is_ok());
+    }
+}
@@ -15,8 +15,8 @@ pub fn aggregate_series(
     step_timestamps: &[f64],
     param: Option<f64>,
 ) -> Vec<TimeSeries> {
-    // topk/bottomk are special: they select series rather than combining values
-    if matches!(op, AggOp::Topk | AggOp::Bottomk) {
+    // topk/bottomk/limitk are special: they select series rather than combining values
+    if matches!(op, AggOp::Topk | AggOp::Bottomk | AggOp::Limitk) {
         return aggregate_topk_bottomk(series, op, param, by_labels, without);
     }
 
@@ -92,7 +92,7 @@ pub fn aggregate_series(
                             unique.dedup();
                             unique.len() as f64
                         }
-                        AggOp::Topk | AggOp::Bottomk => unreachable!(),
+                        AggOp::Topk | AggOp::Bottomk | AggOp::Limitk => unreachable!(),
                     };
 
                     Some((t, result))
@@ -128,7 +128,9 @@ pub fn build_group_key(
     }
 }
 
-/// Select top-k or bottom-k series by their latest value.
+/// Select top-k or bottom-k series by their latest value. limitk takes the
+/// first k series of each group in label-set order — Prometheus only promises
+/// *some* k series, so any deterministic choice is conformant.
 fn aggregate_topk_bottomk(
     series: Vec<TimeSeries>,
     op: AggOp,
@@ -150,6 +152,12 @@ fn aggregate_topk_bottomk(
 
     let mut result = Vec::new();
     for (_, mut members) in groups {
+        if op == AggOp::Limitk {
+            members.sort_by(|a, b| a.labels.cmp(&b.labels));
+            result.extend(members.into_iter().take(k));
+            continue;
+        }
+
         // Sort by latest value
         members.sort_by(|a, b| {
             let a_val = a.samples.last().map(|(_, v)| *v).unwrap_or(0.0);
@@ -317,4 +325,22 @@ mod tests {
         assert!(values.contains(&100.0));
         assert!(values.contains(&200.0));
     }
+
+    #[test]
+    fn test_limitk_per_group() {
+        let series: Vec<TimeSeries> = ["a", "b", "c"]
+            .iter()
+            .flat_map(|instance| {
+                ["x", "y"].iter().map(move |job| TimeSeries {
+                    labels: [("instance".into(), instance.to_string()), ("job".into(), job.to_string())].into(),
+                    samples: vec![(10.0, 1.0)],
+                })
+            })
+            .collect();
+        let result = aggregate_series(series, AggOp::Limitk, &["job".to_string()], false, &[10.0], Some(2.0));
+        assert_eq!(result.len(), 4);
+        let picked: Vec<(&str, &str)> =
+            result.iter().map(|s| (s.labels["job"].as_str(), s.labels["instance"].as_str())).collect();
+        assert_eq!(picked, vec![("x", "a"), ("x", "b"), ("y", "a"), ("y", "b")]);
+    }
 }
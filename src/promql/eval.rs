@@ -5,19 +5,22 @@ use std::sync::LazyLock;
 use std::time::{Duration, Instant};
 use clickhouse::Client;
 use dashmap::DashMap;
-use promql_parser::parser::{self, Expr};
+use promql_parser::parser::{self, AtModifier, Expr, Offset, SubqueryExpr};
 
 use super::types::TimeSeries;
-use super::{aggregate, binary, compute, scalar, sql, translate, types};
-use crate::models::metrics::MetricSample;
+use super::{aggregate, binary, compute, histogram, rollup, scalar, sql, translate, types, vector};
+use crate::models::metrics::{ExpHistogramSample, HistogramSample, MetricSample, RollupSample};
 
 /// Which metrics table(s) a metric name lives in. A metric is either a gauge or a
 /// sum in practice, so half the per-selector scans always return 0 rows.
+/// `Histogram` marks a `_bucket` / `_sum` / `_count` name served by the OTLP
+/// histogram tables rather than the float ones.
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum MetricTable {
     Gauge,
     Sum,
     Both,
+    Histogram,
 }
 
 /// (tenant_id, metric_name) → which table(s) returned rows last time. Tenant-scoped:
@@ -28,6 +31,33 @@ static METRIC_TABLE_CACHE: LazyLock<DashMap<(String, String), (MetricTable, Inst
 const METRIC_TABLE_TTL: Duration = Duration::from_secs(300);
 const METRIC_TABLE_CACHE_MAX: usize = 10_000;
 
+fn cached_metric_table(key: &(String, String)) -> Option<MetricTable> {
+    METRIC_TABLE_CACHE.get(key).filter(|e| e.1.elapsed() < METRIC_TABLE_TTL).map(|e| e.0)
+}
+
+fn remember_metric_table(key: (String, String), table: MetricTable) {
+    if METRIC_TABLE_CACHE.len() > METRIC_TABLE_CACHE_MAX {
+        // Evict only expired entries; clear() would also wipe hot ones.
+        METRIC_TABLE_CACHE.retain(|_, v| v.1.elapsed() < METRIC_TABLE_TTL);
+        if METRIC_TABLE_CACHE.len() > METRIC_TABLE_CACHE_MAX {
+            METRIC_TABLE_CACHE.clear(); // backstop: still over cap after pruning
+        }
+    }
+    METRIC_TABLE_CACHE.insert(key, (table, Instant::now()));
+}
+
+/// Instant-vector lookback for a selector pinned with `@` — matches the 5m
+/// default `extract_lookback` assumes for bare selectors.
+const DEFAULT_LOOKBACK_SECS: f64 = 300.0;
+
+/// Resolution of `expr[range:]` when the subquery omits its step — Prometheus
+/// falls back to the global evaluation interval, which defaults to 1m.
+const DEFAULT_SUBQUERY_STEP_SECS: f64 = 60.0;
+/// Same ceiling Prometheus puts on range-query resolution, applied per subquery
+/// window: more steps than this in one `range` is almost certainly a typo'd step
+/// (e.g. `[30d:1s]`). The outer query's length doesn't count against it.
+const MAX_SUBQUERY_STEPS: usize = 11_000;
+
 // ═══════════════════════════════════════════════════════════════════
 // Public API
 // ═══════════════════════════════════════════════════════════════════
@@ -42,7 +72,14 @@ pub async fn evaluate_instant_query(
 ) -> Result<Vec<TimeSeries>, String> {
     let expr = parser::parse(query).map_err(|e| format!("{e}"))?;
     let step_timestamps = vec![eval_time];
-    evaluate(&expr, ch, eval_time - lookback, eval_time, &step_timestamps, tenant_id).await
+    let window = EvalWindow {
+        query_start: eval_time - lookback,
+        query_end: eval_time,
+        step_timestamps: &step_timestamps,
+        bounds: AtBounds { start: eval_time, end: eval_time },
+        tenant_id,
+    };
+    evaluate(&expr, ch, &window).await
 }
 
 /// Evaluate a range query (multiple points across a time range).
@@ -57,35 +94,86 @@ pub async fn evaluate_range_query(
     let expr = parser::parse(query).map_err(|e| format!("{e}"))?;
     let lookback = extract_lookback(&expr);
     let step_timestamps = generate_steps(start, end, step);
-    evaluate(&expr, ch, start - lookback, end, &step_timestamps, tenant_id).await
+    let window = EvalWindow {
+        query_start: start - lookback,
+        query_end: end,
+        step_timestamps: &step_timestamps,
+        bounds: AtBounds { start, end: step_timestamps.last().copied().unwrap_or(end) },
+        tenant_id,
+    };
+    evaluate(&expr, ch, &window).await
 }
 
 // ═══════════════════════════════════════════════════════════════════
 // Recursive evaluator
 // ═══════════════════════════════════════════════════════════════════
 
-/// Recursively evaluate a promql-parser Expr tree.
-fn evaluate<'a>(
-    expr: &'a Expr,
-    ch: &'a Client,
+/// What an expression is evaluated over: the sample range to read, the step
+/// grid, the outer query's bounds for `@ start()` / `@ end()` and the tenant.
+#[derive(Debug, Clone, Copy)]
+struct EvalWindow<'a> {
+    /// Earliest sample time read (first step minus lookback / range).
     query_start: f64,
     query_end: f64,
     step_timestamps: &'a [f64],
+    bounds: AtBounds,
     tenant_id: &'a str,
+}
+
+/// Recursively evaluate a promql-parser Expr tree.
+fn evaluate<'a>(
+    expr: &'a Expr,
+    ch: &'a Client,
+    w: &'a EvalWindow<'a>,
 ) -> Pin<Box<dyn Future<Output = Result<Vec<TimeSeries>, String>> + Send + 'a>> {
     Box::pin(async move {
+    let EvalWindow { query_start, query_end, step_timestamps, bounds, tenant_id } = *w;
     match expr {
-        Expr::VectorSelector(vs) => {
-            query_clickhouse(ch, vs, query_start, query_end, step_timestamps, true, tenant_id).await
-        }
+        Expr::VectorSelector(vs) => match selector_shift(vs, bounds) {
+            TimeShift::None => {
+                query_clickhouse(ch, vs, query_start, query_end, step_timestamps, true, tenant_id).await
+            }
+            TimeShift::Offset(d) => {
+                // Evaluate on the grid moved back by the offset, then move the samples
+                // forward again so they line up with the caller's steps.
+                let shifted: Vec<f64> = step_timestamps.iter().map(|&t| t - d).collect();
+                let mut series =
+                    query_clickhouse(ch, vs, query_start - d, query_end - d, &shifted, true, tenant_id).await?;
+                shift_series(&mut series, d);
+                Ok(series)
+            }
+            TimeShift::Pinned(at) => {
+                // One instant lookup at the pinned time, repeated across every step.
+                let series =
+                    query_clickhouse(ch, vs, at - DEFAULT_LOOKBACK_SECS, at, &[at], true, tenant_id).await?;
+                Ok(pin_to_steps(series, step_timestamps))
+            }
+        },
 
         Expr::MatrixSelector(ms) => {
             // MatrixSelector wraps a VectorSelector with a range duration.
             // We query the full range needed and keep ALL raw samples
             // so that range functions (rate, increase, etc.) have enough data.
             let range_secs = ms.range.as_secs_f64();
-            let adjusted_start = query_start - range_secs;
-            query_clickhouse(ch, &ms.vs, adjusted_start, query_end, step_timestamps, false, tenant_id).await
+            match selector_shift(&ms.vs, bounds) {
+                TimeShift::None => {
+                    let adjusted_start = query_start - range_secs;
+                    query_clickhouse(ch, &ms.vs, adjusted_start, query_end, step_timestamps, false, tenant_id).await
+                }
+                TimeShift::Offset(d) => {
+                    let shifted: Vec<f64> = step_timestamps.iter().map(|&t| t - d).collect();
+                    let mut series = query_clickhouse(
+                        ch, &ms.vs, query_start - range_secs - d, query_end - d, &shifted, false, tenant_id,
+                    )
+                    .await?;
+                    shift_series(&mut series, d);
+                    Ok(series)
+                }
+                TimeShift::Pinned(at) => {
+                    // Only the single window ending at the pinned time is ever read.
+                    query_clickhouse(ch, &ms.vs, at - range_secs, at, &[at], false, tenant_id).await
+                }
+            }
         }
 
         Expr::Call(call) => {
@@ -93,18 +181,17 @@ fn evaluate<'a>(
 
             // Check if it's a range function
             if let Some(range_func) = translate::to_range_func(func_name) {
-                return evaluate_range_call(
-                    &call.args.args, range_func, func_name, ch, query_start, query_end, step_timestamps, tenant_id,
-                )
-                .await;
+                return evaluate_range_call(&call.args.args, range_func, func_name, ch, w).await;
             }
 
             // Check if it's a scalar function
             if let Some(scalar_func) = translate::to_scalar_func(func_name) {
-                return evaluate_scalar_call(
-                    &call.args.args, scalar_func, func_name, ch, query_start, query_end, step_timestamps, tenant_id,
-                )
-                .await;
+                return evaluate_scalar_call(&call.args.args, scalar_func, func_name, ch, w).await;
+            }
+
+            // Label manipulation, ordering, absent, date and histogram helpers
+            if let Some(vector_func) = translate::to_vector_func(func_name) {
+                return evaluate_vector_call(&call.args.args, vector_func, ch, w).await;
             }
 
             Err(format!("unsupported function: {func_name}"))
@@ -114,7 +201,7 @@ fn evaluate<'a>(
             let op = translate::to_agg_op(agg.op)?;
             let (by_labels, without) = translate::extract_label_modifier(&agg.modifier);
 
-            let inner = evaluate(&agg.expr, ch, query_start, query_end, step_timestamps, tenant_id).await?;
+            let inner = evaluate(&agg.expr, ch, w).await?;
 
             // Extract param (e.g., for quantile, topk, bottomk)
             let param = match &agg.param {
@@ -135,8 +222,8 @@ fn evaluate<'a>(
         Expr::Binary(bin) => {
             // Evaluate both sides in parallel
             let (lhs_result, rhs_result) = tokio::join!(
-                evaluate(&bin.lhs, ch, query_start, query_end, step_timestamps, tenant_id),
-                evaluate(&bin.rhs, ch, query_start, query_end, step_timestamps, tenant_id),
+                evaluate(&bin.lhs, ch, w),
+                evaluate(&bin.rhs, ch, w),
             );
 
             let lhs = lhs_result?;
@@ -152,7 +239,7 @@ fn evaluate<'a>(
         }
 
         Expr::Unary(unary) => {
-            let mut inner = evaluate(&unary.expr, ch, query_start, query_end, step_timestamps, tenant_id).await?;
+            let mut inner = evaluate(&unary.expr, ch, w).await?;
             // Negate all values
             for ts in &mut inner {
                 for sample in &mut ts.samples {
@@ -163,7 +250,7 @@ fn evaluate<'a>(
         }
 
         Expr::Paren(paren) => {
-            evaluate(&paren.expr, ch, query_start, query_end, step_timestamps, tenant_id).await
+            evaluate(&paren.expr, ch, w).await
         }
 
         Expr::NumberLiteral(num) => {
@@ -182,8 +269,11 @@ fn evaluate<'a>(
             Err("string literals are not supported in evaluation".to_string())
         }
 
-        Expr::Subquery(_) => {
-            Err("subqueries are not yet supported".to_string())
+        Expr::Subquery(sq) => {
+            // A bare subquery is a range vector, like a MatrixSelector: hand back the
+            // inner samples on the subquery's own grid.
+            let eval_times = subquery_eval_times(step_timestamps, bounds, &sq.offset, &sq.at);
+            evaluate_subquery(sq, ch, &eval_times, bounds, tenant_id).await
         }
 
         Expr::Extension(_) => {
@@ -199,11 +289,9 @@ async fn evaluate_range_call(
     range_func: types::RangeFunc,
     func_name: &str,
     ch: &Client,
-    query_start: f64,
-    query_end: f64,
-    step_timestamps: &[f64],
-    tenant_id: &str,
+    w: &EvalWindow<'_>,
 ) -> Result<Vec<TimeSeries>, String> {
+    let EvalWindow { step_timestamps, bounds, tenant_id, .. } = *w;
     // Range functions expect their first arg to be a MatrixSelector (or nested expr).
     // Some have an additional numeric parameter (quantile_over_time, predict_linear).
 
@@ -225,22 +313,52 @@ async fn evaluate_range_call(
         format!("{func_name} requires a matrix argument")
     })?;
 
+    // Subqueries carry their own step grid and offset/@ — evaluate the inner
+    // expression on that grid, then window it per outer step.
+    if let Expr::Subquery(sq) = matrix_arg.as_ref() {
+        let eval_times = subquery_eval_times(step_timestamps, bounds, &sq.offset, &sq.at);
+        let inner = evaluate_subquery(sq, ch, &eval_times, bounds, tenant_id).await?;
+        return Ok(subquery_range_at_steps(
+            &inner,
+            range_func,
+            sq.range.as_secs_f64(),
+            step_timestamps,
+            &eval_times,
+            param,
+        ));
+    }
+
     // Extract the range duration from the matrix selector
     let range_secs = match matrix_arg.as_ref() {
         Expr::MatrixSelector(ms) => ms.range.as_secs_f64(),
         _ => 300.0, // default 5m
     };
 
+    // `@` pins the window: compute the function once at the pinned time and
+    // report that value at every step.
+    if let Expr::MatrixSelector(ms) = matrix_arg.as_ref()
+        && let TimeShift::Pinned(at) = selector_shift(&ms.vs, bounds)
+    {
+        let raw_series = evaluate(matrix_arg, ch, w).await?;
+        let pinned = evaluate_range_at_steps(&raw_series, range_func, range_secs, &[at], param);
+        return Ok(pin_to_steps(pinned, step_timestamps));
+    }
+
+    // Approximate mode: windows that tile whole rollup buckets merge the bucket
+    // states instead of reading raw samples (see `promql::rollup`).
+    if let Expr::MatrixSelector(ms) = matrix_arg.as_ref()
+        && matches!(selector_shift(&ms.vs, bounds), TimeShift::None)
+        && ms.vs.name.as_deref().and_then(histogram::split_name).is_none()
+        && let Some(source) = rollup::plan(range_func, range_secs, step_timestamps)
+        && let Some(series) =
+            query_rollup_tables(ch, &ms.vs, range_func, source, range_secs, step_timestamps, tenant_id).await?
+    {
+        rollup::mark_used();
+        return Ok(series);
+    }
+
     // Evaluate the inner expression to get raw series
-    let raw_series = evaluate(
-        matrix_arg,
-        ch,
-        query_start - range_secs,
-        query_end,
-        step_timestamps,
-        tenant_id,
-    )
-    .await?;
+    let raw_series = evaluate(matrix_arg, ch, &EvalWindow { query_start: w.query_start - range_secs, ..*w }).await?;
 
     // Apply range function at each step
     Ok(evaluate_range_at_steps(
@@ -252,16 +370,35 @@ async fn evaluate_range_call(
     ))
 }
 
+/// Evaluate a subquery's inner expression on its own step-aligned grid, covering
+/// every `(eval_time - range, eval_time]` window the outer steps will ask for.
+async fn evaluate_subquery(
+    sq: &SubqueryExpr,
+    ch: &Client,
+    eval_times: &[f64],
+    bounds: AtBounds,
+    tenant_id: &str,
+) -> Result<Vec<TimeSeries>, String> {
+    let step = sq.step.map(|d| d.as_secs_f64()).unwrap_or(DEFAULT_SUBQUERY_STEP_SECS);
+    if step <= 0.0 {
+        return Err("subquery step must be positive".to_string());
+    }
+    let inner_steps = subquery_steps(eval_times, sq.range.as_secs_f64(), step)?;
+    let (Some(&first), Some(&last)) = (inner_steps.first(), inner_steps.last()) else {
+        return Ok(Vec::new());
+    };
+    let lookback = extract_lookback(&sq.expr);
+    let window = EvalWindow { query_start: first - lookback, query_end: last, step_timestamps: &inner_steps, bounds, tenant_id };
+    evaluate(&sq.expr, ch, &window).await
+}
+
 /// Evaluate a Call node that wraps a scalar function (abs, ceil, histogram_quantile, etc.).
 async fn evaluate_scalar_call(
     args: &[Box<Expr>],
     scalar_func: types::ScalarFunc,
     func_name: &str,
     ch: &Client,
-    query_start: f64,
-    query_end: f64,
-    step_timestamps: &[f64],
-    tenant_id: &str,
+    w: &EvalWindow<'_>,
 ) -> Result<Vec<TimeSeries>, String> {
     // Classify based on function argument patterns
     let (inner_expr, extra_args): (&Expr, Vec<f64>) = match func_name {
@@ -315,10 +452,56 @@ async fn evaluate_scalar_call(
         }
     };
 
-    let inner_series = evaluate(inner_expr, ch, query_start, query_end, step_timestamps, tenant_id).await?;
+    let inner_series = evaluate(inner_expr, ch, w).await?;
     Ok(scalar::apply_scalar_func(inner_series, scalar_func, &extra_args))
 }
 
+/// Evaluate a Call node for a vector function (label_replace, sort, absent, ...).
+async fn evaluate_vector_call(
+    args: &[Box<Expr>],
+    vector_func: types::VectorFunc,
+    ch: &Client,
+    w: &EvalWindow<'_>,
+) -> Result<Vec<TimeSeries>, String> {
+    use types::VectorFunc;
+
+    // histogram_count/sum/avg read the classic `_count` / `_sum` series that sit
+    // next to the `_bucket` series named in the argument.
+    if matches!(vector_func, VectorFunc::HistogramCount | VectorFunc::HistogramSum | VectorFunc::HistogramAvg) {
+        let arg = args.first().ok_or("histogram functions require a vector argument")?;
+        let component = |suffix: &str| {
+            vector::rename_bucket_selectors(arg, suffix)
+                .ok_or_else(|| "histogram_count/histogram_sum/histogram_avg expect a classic histogram _bucket selector".to_string())
+        };
+        return match vector_func {
+            VectorFunc::HistogramCount => {
+                let counts = component("_count")?;
+                let series = evaluate(&counts, ch, w).await?;
+                Ok(vector::histogram_component(series))
+            }
+            VectorFunc::HistogramSum => {
+                let sums = component("_sum")?;
+                let series = evaluate(&sums, ch, w).await?;
+                Ok(vector::histogram_component(series))
+            }
+            _ => {
+                let (sums, counts) = (component("_sum")?, component("_count")?);
+                let (sums, counts) = tokio::join!(
+                    evaluate(&sums, ch, w),
+                    evaluate(&counts, ch, w),
+                );
+                Ok(vector::histogram_avg(sums?, counts?, w.step_timestamps))
+            }
+        };
+    }
+
+    let inner = match vector::vector_arg_index(vector_func, args) {
+        Some(idx) => evaluate(&args[idx], ch, w).await?,
+        None => Vec::new(),
+    };
+    vector::apply_vector_func(vector_func, args, inner, w.step_timestamps)
+}
+
 // ═══════════════════════════════════════════════════════════════════
 // ClickHouse query
 // ═══════════════════════════════════════════════════════════════════
@@ -326,6 +509,12 @@ async fn evaluate_scalar_call(
 /// Query ClickHouse for a VectorSelector, returning TimeSeries.
 /// When `align` is true, step-align samples to step_timestamps (for instant vectors).
 /// When false, return all raw samples (for range vectors used by rate/increase/etc).
+///
+/// `<name>_bucket` / `_sum` / `_count` selectors fall back to the OTLP
+/// histogram tables for `<name>` (see `promql::histogram`) when the float
+/// tables have nothing for them. One source answers a selector, never both, so
+/// a name present in both can't yield duplicate label sets; which source that
+/// is gets remembered in `METRIC_TABLE_CACHE` like the gauge/sum routing.
 async fn query_clickhouse(
     ch: &Client,
     vs: &promql_parser::parser::VectorSelector,
@@ -335,35 +524,148 @@ async fn query_clickhouse(
     align: bool,
     tenant_id: &str,
 ) -> Result<Vec<TimeSeries>, String> {
-    let escaped_tenant = crate::query_builder::escape_string_literal(&tenant_id);
+    let Some((name, (base, component))) =
+        vs.name.as_deref().and_then(|n| Some((n, histogram::split_name(n)?)))
+    else {
+        return query_metric_tables(ch, vs, start_secs, end_secs, step_timestamps, align, tenant_id).await;
+    };
+    let key = (tenant_id.to_string(), name.to_string());
+    let cached = cached_metric_table(&key);
+    if cached != Some(MetricTable::Histogram) {
+        let floats = query_metric_tables(ch, vs, start_secs, end_secs, step_timestamps, align, tenant_id).await?;
+        // Known float metric (e.g. remote_write classic histograms), or it had rows.
+        if cached.is_some() || !floats.is_empty() {
+            return Ok(floats);
+        }
+    }
+    let mut series =
+        query_histogram_tables(ch, vs, base, component, start_secs, end_secs, step_timestamps, align, tenant_id)
+            .await?;
+    if cached.is_none() && !series.is_empty() {
+        remember_metric_table(key, MetricTable::Histogram);
+    }
+    series.sort_by(|a, b| a.labels.cmp(&b.labels));
+    Ok(series)
+}
+
+/// Synthetic classic-histogram series for a `<base>_bucket` / `_sum` / `_count`
+/// selector, read from metrics_histogram and metrics_exp_histogram. Histogram
+/// points are expanded in Rust, so alignment uses `step_align_series` rather
+/// than the SQL bucketing of the float path.
+#[allow(clippy::too_many_arguments)]
+async fn query_histogram_tables(
+    ch: &Client,
+    vs: &promql_parser::parser::VectorSelector,
+    base: &str,
+    component: histogram::Component,
+    start_secs: f64,
+    end_secs: f64,
+    step_timestamps: &[f64],
+    align: bool,
+    tenant_id: &str,
+) -> Result<Vec<TimeSeries>, String> {
+    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
+    let naming = crate::metric_naming::for_tenant(tenant_id);
     let mut where_parts = vec![
         format!("tenant_id = '{escaped_tenant}'"),
         format!("TimeUnix >= toDateTime64({}, 9)", start_secs as i64),
         format!("TimeUnix <= toDateTime64({}, 9)", end_secs as i64),
+        sql::metric_name_sql(base, naming, sql::NameTable::Histogram),
     ];
-
-    // Extract metric name from matchers
-    if let Some(name) = &vs.name {
-        if !name.is_empty() {
-            where_parts.push(format!(
-                "MetricName = '{}'",
-                crate::query_builder::escape_string_literal(&name)
-            ));
+    // `le` only exists on the expanded series, so it is matched after expansion.
+    let mut le_matchers = Vec::new();
+    for m in &vs.matchers.matchers {
+        match m.name.as_str() {
+            "__name__" => {}
+            "le" => le_matchers.push(m.clone()),
+            _ => where_parts.extend(sql::matchers_to_sql(std::slice::from_ref(m), naming, sql::NameTable::Histogram)),
         }
     }
+    let where_clause = where_parts.join(" AND ");
 
-    // Also check for __name__ matcher
-    for m in &vs.matchers.matchers {
-        if m.name == "__name__" {
-            // Already handled by vs.name for Equal matches,
-            // but handle regex and other ops here
-            where_parts.extend(sql::matchers_to_sql(&[m.clone()]));
-            continue;
-        }
-        where_parts.extend(sql::matchers_to_sql(&[m.clone()]));
+    // Only `_bucket` needs the bucket arrays; skip shipping them otherwise.
+    let buckets = component == histogram::Component::Bucket;
+    let explicit_cols = if buckets {
+        "BucketCounts, ExplicitBounds"
+    } else {
+        "emptyArrayUInt64() AS BucketCounts, emptyArrayFloat64() AS ExplicitBounds"
+    };
+    let exponential_cols = if buckets {
+        "Scale, ZeroCount, PositiveOffset, PositiveBucketCounts, NegativeOffset, NegativeBucketCounts"
+    } else {
+        "Scale, ZeroCount, PositiveOffset, emptyArrayUInt64() AS PositiveBucketCounts, \
+         NegativeOffset, emptyArrayUInt64() AS NegativeBucketCounts"
+    };
+    let make_sql = |table: &str, cols: &str| {
+        format!(
+            "SELECT MetricName, ServiceName, Attributes, \
+             toInt64(toUnixTimestamp64Milli(TimeUnix)) AS ts_ms, Count, Sum, {cols} \
+             FROM {table} \
+             WHERE {where_clause} \
+             ORDER BY MetricName, ServiceName, Attributes, TimeUnix"
+        )
+    };
+
+    let (explicit, exponential) = tokio::join!(
+        crate::tenant_query(ch, &make_sql("metrics_histogram", explicit_cols), tenant_id)
+            .fetch_all::<HistogramSample>(),
+        crate::tenant_query(ch, &make_sql("metrics_exp_histogram", exponential_cols), tenant_id)
+            .fetch_all::<ExpHistogramSample>(),
+    );
+    let explicit = selector_rows(explicit, tenant_id)?;
+    let exponential = selector_rows(exponential, tenant_id)?;
+
+    let series = histogram::expand(&explicit, &exponential, component, &le_matchers);
+    if align {
+        Ok(step_align_series(series, step_timestamps, end_secs - start_secs))
+    } else {
+        Ok(series)
     }
+}
 
-    let where_clause = where_parts.join(" AND ");
+/// Query the float metric tables (metrics_gauge / metrics_sum) for a VectorSelector.
+async fn query_metric_tables(
+    ch: &Client,
+    vs: &promql_parser::parser::VectorSelector,
+    start_secs: f64,
+    end_secs: f64,
+    step_timestamps: &[f64],
+    align: bool,
+    tenant_id: &str,
+) -> Result<Vec<TimeSeries>, String> {
+    let escaped_tenant = crate::query_builder::escape_string_literal(&tenant_id);
+    let where_parts = vec![
+        format!("tenant_id = '{escaped_tenant}'"),
+        format!("TimeUnix >= toDateTime64({}, 9)", start_secs as i64),
+        format!("TimeUnix <= toDateTime64({}, 9)", end_secs as i64),
+    ];
+
+    // OTel vs Prometheus naming: prometheus-mode tenants also match the OTel
+    // spelling, which depends on what the table records.
+    let naming = crate::metric_naming::for_tenant(tenant_id);
+
+    let where_clause = |table: sql::NameTable| {
+        let mut parts = where_parts.clone();
+        // Extract metric name from matchers
+        if let Some(name) = vs.name.as_deref().filter(|n| !n.is_empty()) {
+            parts.push(sql::metric_name_sql(name, naming, table));
+        }
+
+        // Also check for __name__ matcher
+        for m in &vs.matchers.matchers {
+            if m.name == "__name__" {
+                // Already handled by vs.name for Equal matches,
+                // but handle regex and other ops here
+                parts.extend(sql::matchers_to_sql(std::slice::from_ref(m), naming, table));
+                continue;
+            }
+            parts.extend(sql::matchers_to_sql(std::slice::from_ref(m), naming, table));
+        }
+        parts.join(" AND ")
+    };
+    let names_of = |table: &str| {
+        if table == "metrics_sum" { sql::NameTable::Sum } else { sql::NameTable::Gauge }
+    };
 
     // ── SQL-side step bucketing for the align=true (instant-vector) path ──
     //
@@ -395,6 +697,7 @@ async fn query_clickhouse(
 
     // Raw-streaming SQL (align=false): all samples, ordered by series then time.
     let make_raw_sql = |table: &str| {
+        let where_clause = where_clause(names_of(table));
         format!(
             "SELECT MetricName, ServiceName, Attributes, \
              toInt64(toUnixTimestamp64Milli(TimeUnix)) AS ts_ms, Value \
@@ -423,6 +726,7 @@ async fn query_clickhouse(
     // hs and grid_start/grid_step are f64s we control (never user SQL).
     let hs = grid_step / 2.0;
     let make_bucketed_sql = |table: &str| {
+        let where_clause = where_clause(names_of(table));
         format!(
             "SELECT MetricName, ServiceName, Attributes, k AS ts_ms, \
              argMax(Value, TimeUnix) AS Value FROM ( \
@@ -453,26 +757,25 @@ async fn query_clickhouse(
         .as_ref()
         .filter(|n| !n.is_empty())
         .map(|n| (tenant_id.to_string(), n.clone()));
-    let cached_choice = cache_key.as_ref().and_then(|k| {
-        METRIC_TABLE_CACHE
-            .get(k)
-            .filter(|e| e.1.elapsed() < METRIC_TABLE_TTL)
-            .map(|e| e.0)
-    });
+    let cached_choice = cache_key.as_ref().and_then(cached_metric_table);
 
     let (gauge_rows, sum_rows) = match cached_choice {
         Some(MetricTable::Gauge) => {
-            let rows = crate::tenant_query(ch, &make_sql("metrics_gauge"), tenant_id)
-                .fetch_all::<MetricSample>()
-                .await
-                .unwrap_or_default();
+            let rows = selector_rows(
+                crate::tenant_query(ch, &make_sql("metrics_gauge"), tenant_id)
+                    .fetch_all::<MetricSample>()
+                    .await,
+                tenant_id,
+            )?;
             (rows, Vec::new())
         }
         Some(MetricTable::Sum) => {
-            let rows = crate::tenant_query(ch, &make_sql("metrics_sum"), tenant_id)
-                .fetch_all::<MetricSample>()
-                .await
-                .unwrap_or_default();
+            let rows = selector_rows(
+                crate::tenant_query(ch, &make_sql("metrics_sum"), tenant_id)
+                    .fetch_all::<MetricSample>()
+                    .await,
+                tenant_id,
+            )?;
             (Vec::new(), rows)
         }
         _ => {
@@ -483,8 +786,8 @@ async fn query_clickhouse(
                 crate::tenant_query(ch, &make_sql("metrics_sum"), tenant_id)
                     .fetch_all::<MetricSample>(),
             );
-            let gauge_rows = gauge_res.unwrap_or_default();
-            let sum_rows = sum_res.unwrap_or_default();
+            let gauge_rows = selector_rows(gauge_res, tenant_id)?;
+            let sum_rows = selector_rows(sum_res, tenant_id)?;
 
             // Record which table(s) actually had data (only on a true miss, and only
             // when at least one table returned rows — an empty result tells us nothing).
@@ -497,14 +800,7 @@ async fn query_clickhouse(
                         (false, false) => None,
                     };
                     if let Some(observed) = observed {
-                        if METRIC_TABLE_CACHE.len() > METRIC_TABLE_CACHE_MAX {
-                            // Evict only expired entries; clear() would also wipe hot ones.
-                            METRIC_TABLE_CACHE.retain(|_, v| v.1.elapsed() < METRIC_TABLE_TTL);
-                            if METRIC_TABLE_CACHE.len() > METRIC_TABLE_CACHE_MAX {
-                                METRIC_TABLE_CACHE.clear(); // backstop: still over cap after pruning
-                            }
-                        }
-                        METRIC_TABLE_CACHE.insert(key, (observed, Instant::now()));
+                        remember_metric_table(key, observed);
                     }
                 }
             }
@@ -524,6 +820,89 @@ async fn query_clickhouse(
     }
 }
 
+/// Evaluate a range function over a MatrixSelector from the gauge/sum rollups
+/// picked by `rollup::plan`. `None` when the rollup can't answer it — avg-based
+/// functions over series from the sum rollups, which keep no avg state — so the
+/// caller falls back to raw.
+async fn query_rollup_tables(
+    ch: &Client,
+    vs: &promql_parser::parser::VectorSelector,
+    func: types::RangeFunc,
+    source: crate::rollup::Source,
+    range_secs: f64,
+    step_timestamps: &[f64],
+    tenant_id: &str,
+) -> Result<Option<Vec<TimeSeries>>, String> {
+    let (Some(interval), Some(&first), Some(&last)) =
+        (source.interval_secs(), step_timestamps.first(), step_timestamps.last())
+    else {
+        return Ok(None);
+    };
+    let escaped_tenant = crate::query_builder::escape_string_literal(tenant_id);
+    let naming = crate::metric_naming::for_tenant(tenant_id);
+    // Every window's buckets, plus the one before the first window for rate/increase.
+    let where_parts = vec![
+        format!("tenant_id = '{escaped_tenant}'"),
+        format!("bucket >= toDateTime64({}, 9)", (first - range_secs) as i64 - interval),
+        format!("bucket < toDateTime64({}, 9)", last as i64),
+    ];
+    let where_clause = |table: sql::NameTable| {
+        let mut parts = where_parts.clone();
+        if let Some(name) = vs.name.as_deref().filter(|n| !n.is_empty()) {
+            parts.push(sql::metric_name_sql(name, naming, table));
+        }
+        parts.extend(sql::matchers_to_sql(&vs.matchers.matchers, naming, table));
+        parts.join(" AND ")
+    };
+
+    let make_sql = |table: &str, names: sql::NameTable, avg: &str| {
+        let where_clause = where_clause(names);
+        format!(
+            "SELECT MetricName, ServiceName, Attributes, \
+             toInt64(toUnixTimestamp64Milli(bucket)) AS ts_ms, \
+             argMaxMerge(last_state) AS Last, minMerge(min_state) AS Min, maxMerge(max_state) AS Max, \
+             toUInt64(countMerge(cnt_state)) AS Count, {avg} AS Avg \
+             FROM {table}{suffix} \
+             WHERE {where_clause} \
+             GROUP BY MetricName, ServiceName, Attributes, bucket \
+             ORDER BY MetricName, ServiceName, Attributes, bucket",
+            suffix = source.suffix(),
+        )
+    };
+    let (gauge_res, sum_res) = tokio::join!(
+        crate::tenant_query(ch, &make_sql("metrics_gauge", sql::NameTable::GaugeRollup, "avgMerge(avg_state)"), tenant_id)
+            .fetch_all::<RollupSample>(),
+        crate::tenant_query(ch, &make_sql("metrics_sum", sql::NameTable::SumRollup, "nan"), tenant_id).fetch_all::<RollupSample>(),
+    );
+    let gauge_rows = selector_rows(gauge_res, tenant_id)?;
+    let sum_rows = selector_rows(sum_res, tenant_id)?;
+    if rollup::needs_avg(func) && !sum_rows.is_empty() {
+        return Ok(None);
+    }
+
+    let series = rollup::group_buckets(&gauge_rows, &sum_rows);
+    Ok(Some(rollup::range_at_steps(&series, func, range_secs, interval as f64, step_timestamps)))
+}
+
+/// Rows of one selector query. A failed query reads as no data, except when a
+/// tenant query limit stopped it: that fails the evaluation with the limit.
+/// Either way the result is not cached (`query_cache::note_failure`).
+fn selector_rows<T>(
+    res: Result<Vec<T>, clickhouse::error::Error>,
+    tenant_id: &str,
+) -> Result<Vec<T>, String> {
+    match res {
+        Ok(rows) => Ok(rows),
+        Err(e) => {
+            crate::query_cache::note_failure();
+            match crate::query_limits::exceeded(tenant_id, &e) {
+                Some(limit) => Err(limit.to_string()),
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+}
+
 /// Convert SQL-bucketed rows (one row per series per step bucket, `ts_ms` reused as the
 /// integer bucket index k, `value` = argMax-by-time within the bucket) into TimeSeries
 /// with samples placed at `step_timestamps[k]`. Bucket indices outside `[0, n)` (samples
@@ -641,10 +1020,10 @@ fn rows_to_series(gauge_rows: &[MetricSample], sum_rows: &[MetricSample]) -> Vec
 
 /// Snap raw series to step timestamps, picking the latest sample within tolerance.
 ///
-/// Retained as the executable reference for the SQL-side bucketing rewrite: the new
-/// `align=true` path computes the same assignment server-side (see `query_clickhouse`),
-/// and tests pin the SQL bucket-index formula against this function.
-#[cfg_attr(not(test), allow(dead_code))]
+/// The executable reference for the SQL-side bucketing rewrite: the float
+/// `align=true` path computes the same assignment server-side (see
+/// `query_metric_tables`), and tests pin the SQL bucket-index formula against this
+/// function. Synthetic histogram series, expanded in Rust, are aligned with it directly.
 fn step_align_series(
     series: Vec<TimeSeries>,
     step_timestamps: &[f64],
@@ -689,7 +1068,7 @@ fn step_align_series(
 // ═══════════════════════════════════════════════════════════════════
 
 /// Evaluate a range function at each step timestamp over a sliding window.
-fn evaluate_range_at_steps(
+pub(super) fn evaluate_range_at_steps(
     raw_series: &[TimeSeries],
     func: types::RangeFunc,
     range_secs: f64,
@@ -721,6 +1100,159 @@ fn evaluate_range_at_steps(
         .collect()
 }
 
+/// Apply a range function over subquery output: for each outer step, the window is
+/// the inner samples in `(eval_time - range, eval_time]`, reported at the outer step.
+fn subquery_range_at_steps(
+    inner: &[TimeSeries],
+    func: types::RangeFunc,
+    range_secs: f64,
+    step_timestamps: &[f64],
+    eval_times: &[f64],
+    param: Option<f64>,
+) -> Vec<TimeSeries> {
+    inner
+        .iter()
+        .map(|ts| {
+            let samples: Vec<(f64, f64)> = step_timestamps
+                .iter()
+                .zip(eval_times)
+                .filter_map(|(&t, &e)| {
+                    let window: Vec<(f64, f64)> = ts
+                        .samples
+                        .iter()
+                        .filter(|(st, _)| *st > e - range_secs && *st <= e)
+                        .copied()
+                        .collect();
+
+                    compute::evaluate_range_func(func, &window, param).map(|v| (t, v))
+                })
+                .collect();
+
+            TimeSeries {
+                labels: ts.labels.clone(),
+                samples,
+            }
+        })
+        .collect()
+}
+
+/// Seconds an `offset` modifier moves evaluation back in time (negative for
+/// `offset -5m`, which looks forward).
+fn offset_secs(offset: &Option<Offset>) -> f64 {
+    match offset {
+        Some(Offset::Pos(d)) => d.as_secs_f64(),
+        Some(Offset::Neg(d)) => -d.as_secs_f64(),
+        None => 0.0,
+    }
+}
+
+/// The outer query's first and last step. `@ start()` and `@ end()` mean these
+/// at any depth — inside a subquery the step grid is the subquery's own, so
+/// they can't be read off the grid being evaluated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AtBounds {
+    start: f64,
+    end: f64,
+}
+
+/// Evaluation time pinned by an `@` modifier, if any.
+fn at_secs(at: &Option<AtModifier>, bounds: AtBounds) -> Option<f64> {
+    match at {
+        Some(AtModifier::Start) => Some(bounds.start),
+        Some(AtModifier::End) => Some(bounds.end),
+        Some(AtModifier::At(t)) => Some(match t.duration_since(std::time::UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        }),
+        None => None,
+    }
+}
+
+/// How a selector's `offset` / `@` modifiers move its evaluation time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeShift {
+    None,
+    /// Every step looks this many seconds into the past (negative: the future).
+    Offset(f64),
+    /// Every step evaluates at this one absolute time (`@`, minus any offset).
+    Pinned(f64),
+}
+
+fn selector_shift(vs: &parser::VectorSelector, bounds: AtBounds) -> TimeShift {
+    let offset = offset_secs(&vs.offset);
+    match at_secs(&vs.at, bounds) {
+        Some(at) => TimeShift::Pinned(at - offset),
+        None if offset != 0.0 => TimeShift::Offset(offset),
+        None => TimeShift::None,
+    }
+}
+
+/// Move every sample timestamp forward by `secs`, undoing an offset so the
+/// samples land back on the query grid.
+fn shift_series(series: &mut [TimeSeries], secs: f64) {
+    for ts in series {
+        for sample in &mut ts.samples {
+            sample.0 += secs;
+        }
+    }
+}
+
+/// Spread a single-instant result (at most one sample per series) across every
+/// step. Series with no sample at that instant are dropped.
+fn pin_to_steps(series: Vec<TimeSeries>, step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    series
+        .into_iter()
+        .filter_map(|ts| {
+            let &(_, v) = ts.samples.last()?;
+            Some(TimeSeries {
+                labels: ts.labels,
+                samples: step_timestamps.iter().map(|&t| (t, v)).collect(),
+            })
+        })
+        .collect()
+}
+
+/// The time each outer step actually evaluates a subquery at: the step itself (or
+/// the `@` time), shifted back by the offset.
+fn subquery_eval_times(
+    step_timestamps: &[f64],
+    bounds: AtBounds,
+    offset: &Option<Offset>,
+    at: &Option<AtModifier>,
+) -> Vec<f64> {
+    let offset = offset_secs(offset);
+    let pinned = at_secs(at, bounds);
+    step_timestamps.iter().map(|&t| pinned.unwrap_or(t) - offset).collect()
+}
+
+/// Inner step grid for a subquery: absolute multiples of `step` (so results don't
+/// shift with the outer query's start, as in Prometheus) spanning from just after
+/// the earliest window start to the latest eval time.
+fn subquery_steps(eval_times: &[f64], range_secs: f64, step: f64) -> Result<Vec<f64>, String> {
+    let per_window = (range_secs / step).ceil();
+    if per_window > MAX_SUBQUERY_STEPS as f64 {
+        return Err(format!(
+            "subquery would evaluate {per_window} steps per window, exceeding the maximum of \
+             {MAX_SUBQUERY_STEPS}; increase its step"
+        ));
+    }
+    let lo = eval_times.iter().copied().fold(f64::INFINITY, f64::min);
+    let hi = eval_times.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if !lo.is_finite() || !hi.is_finite() {
+        return Ok(Vec::new());
+    }
+    let mut first = ((lo - range_secs) / step).floor() * step;
+    if first <= lo - range_secs {
+        first += step;
+    }
+    let last = (hi / step).floor() * step;
+    if last < first {
+        return Ok(Vec::new());
+    }
+    let n = ((last - first) / step).round() as usize + 1;
+    Ok((0..n).map(|i| first + i as f64 * step).collect())
+}
+
 /// Extract a number literal from an Expr node.
 fn extract_number_literal(expr: &Expr) -> Option<f64> {
     match expr {
@@ -743,7 +1275,8 @@ pub fn extract_lookback(expr: &Expr) -> f64 {
         }
         Expr::Unary(u) => extract_lookback(&u.expr),
         Expr::Paren(p) => extract_lookback(&p.expr),
-        _ => 300.0, // default 5m
+        Expr::Subquery(sq) => sq.range.as_secs_f64() + offset_secs(&sq.offset).max(0.0),
+        _ => DEFAULT_LOOKBACK_SECS,
     }
 }
 
@@ -757,6 +1290,32 @@ fn generate_steps(start: f64, end: f64, step: f64) -> Vec<f64> {
     timestamps
 }
 
+/// Whether any selector or subquery in `query` carries an `@` modifier or a
+/// negative offset. Such queries can't be evaluated chunk by chunk: `@ start()`
+/// / `@ end()` name the whole query's bounds, not a chunk's, and `offset -1h`
+/// makes a chunk read data past its own end, so an old chunk may still depend
+/// on recent data. Unparseable queries report false.
+pub fn uses_at_or_negative_offset(query: &str) -> bool {
+    parser::parse(query).is_ok_and(|expr| reads_outside_steps(&expr))
+}
+
+fn reads_outside_steps(expr: &Expr) -> bool {
+    let shifts = |at: &Option<AtModifier>, offset: &Option<Offset>| {
+        at.is_some() || matches!(offset, Some(Offset::Neg(_)))
+    };
+    match expr {
+        Expr::VectorSelector(vs) => shifts(&vs.at, &vs.offset),
+        Expr::MatrixSelector(ms) => shifts(&ms.vs.at, &ms.vs.offset),
+        Expr::Subquery(sq) => shifts(&sq.at, &sq.offset) || reads_outside_steps(&sq.expr),
+        Expr::Call(call) => call.args.args.iter().any(|a| reads_outside_steps(a)),
+        Expr::Aggregate(agg) => reads_outside_steps(&agg.expr) || agg.param.as_deref().is_some_and(reads_outside_steps),
+        Expr::Binary(bin) => reads_outside_steps(&bin.lhs) || reads_outside_steps(&bin.rhs),
+        Expr::Unary(u) => reads_outside_steps(&u.expr),
+        Expr::Paren(p) => reads_outside_steps(&p.expr),
+        _ => false,
+    }
+}
+
 /// Walk a promql-parser Expr tree and extract all metric names from VectorSelectors.
 pub fn extract_metrics_from_expr(expr: &Expr) -> Vec<String> {
     let mut names = Vec::new();
@@ -799,6 +1358,7 @@ fn collect_metrics(expr: &Expr, names: &mut Vec<String>) {
         }
         Expr::Unary(u) => collect_metrics(&u.expr, names),
         Expr::Paren(p) => collect_metrics(&p.expr, names),
+        Expr::Subquery(sq) => collect_metrics(&sq.expr, names),
         _ => {}
     }
 }
@@ -899,6 +1459,177 @@ mod tests {
         assert!(got[0].samples.is_empty());
     }
 
+    // ── subqueries ──
+
+    fn parse_subquery(query: &str) -> SubqueryExpr {
+        match parser::parse(query).unwrap() {
+            Expr::Subquery(sq) => sq,
+            other => panic!("expected subquery, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn subquery_steps_are_aligned_to_absolute_multiples() {
+        // Windows (35, 95] and (65, 125] → every multiple of 10 in (35, 125].
+        let got = subquery_steps(&[95.0, 125.0], 60.0, 10.0).unwrap();
+        let want: Vec<f64> = (4..=12).map(|k| k as f64 * 10.0).collect();
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn subquery_steps_exclude_window_start() {
+        // (40, 100] holds six 10s steps, not seven.
+        let got = subquery_steps(&[100.0], 60.0, 10.0).unwrap();
+        assert_eq!(got, vec![50.0, 60.0, 70.0, 80.0, 90.0, 100.0]);
+    }
+
+    #[test]
+    fn subquery_steps_reject_oversized_grid() {
+        let err = subquery_steps(&[1_000_000.0], 1_000_000.0, 1.0).unwrap_err();
+        assert!(err.contains("increase its step"), "{err}");
+    }
+
+    #[test]
+    fn subquery_step_cap_ignores_outer_range() {
+        // max_over_time(rate(x[5m])[1h:1m]) over 30 days at a 1h outer step:
+        // 60 steps per window, ~43k across the whole range.
+        let day = 86_400.0;
+        let eval_times: Vec<f64> = (0..=30 * 24).map(|i| 1_700_000_000.0 + i as f64 * 3600.0).collect();
+        let got = subquery_steps(&eval_times, 3600.0, 60.0).unwrap();
+        assert!(got.len() > MAX_SUBQUERY_STEPS, "{}", got.len());
+        assert_eq!(got.len(), (30.0 * day / 60.0) as usize + 60);
+    }
+
+    #[test]
+    fn subquery_eval_times_apply_offset_and_at() {
+        let steps = [1000.0, 1060.0, 1120.0];
+        let bounds = AtBounds { start: 1000.0, end: 1120.0 };
+
+        let sq = parse_subquery("x[5m:1m]");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![1000.0, 1060.0, 1120.0]);
+
+        let sq = parse_subquery("x[5m:1m] offset 2m");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![880.0, 940.0, 1000.0]);
+
+        let sq = parse_subquery("x[5m:1m] offset -1m");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![1060.0, 1120.0, 1180.0]);
+
+        let sq = parse_subquery("x[5m:1m] @ 500 offset 1m");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![440.0, 440.0, 440.0]);
+
+        let sq = parse_subquery("x[5m:1m] @ end()");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![1120.0, 1120.0, 1120.0]);
+
+        let sq = parse_subquery("x[5m:1m] @ start()");
+        assert_eq!(subquery_eval_times(&steps, bounds, &sq.offset, &sq.at), vec![1000.0, 1000.0, 1000.0]);
+
+        // Inside a subquery the grid is the subquery's own; `@` still names the
+        // outer query's bounds.
+        let inner = [900.0, 960.0];
+        let sq = parse_subquery("x[5m:1m] @ end()");
+        assert_eq!(subquery_eval_times(&inner, bounds, &sq.offset, &sq.at), vec![1120.0, 1120.0]);
+        let sq = parse_subquery("x[5m:1m] @ start()");
+        assert_eq!(subquery_eval_times(&inner, bounds, &sq.offset, &sq.at), vec![1000.0, 1000.0]);
+    }
+
+    #[test]
+    fn at_modifier_detection_reaches_nested_selectors() {
+        assert!(!uses_at_or_negative_offset("rate(up[5m])"));
+        assert!(uses_at_or_negative_offset("up @ 500"));
+        assert!(uses_at_or_negative_offset("sum(rate(up[5m] @ end()))"));
+        assert!(uses_at_or_negative_offset("max_over_time((up @ start())[10m:1m])"));
+        assert!(uses_at_or_negative_offset("max_over_time(up[10m:1m] @ end())"));
+        assert!(uses_at_or_negative_offset("topk(3, up) / on() group_left up @ start()"));
+        assert!(!uses_at_or_negative_offset("not ( valid"));
+        assert!(!uses_at_or_negative_offset("rate(up[5m] offset 1h)"));
+        assert!(uses_at_or_negative_offset("up offset -1h"));
+        assert!(uses_at_or_negative_offset("sum(rate(up[5m] offset -30m))"));
+        assert!(uses_at_or_negative_offset("max_over_time(up[10m:1m] offset -5m)"));
+        assert!(uses_at_or_negative_offset("max_over_time((up offset -1m)[10m:1m])"));
+    }
+
+    #[test]
+    fn subquery_range_windows_follow_eval_times() {
+        // Inner grid every 10s with value == timestamp.
+        let inner = series_with((0..=20).map(|k| (k as f64 * 10.0, k as f64 * 10.0)).collect());
+        let steps = [150.0, 200.0];
+
+        // No offset: (120, 150] and (170, 200].
+        let got = subquery_range_at_steps(&inner, types::RangeFunc::SumOverTime, 30.0, &steps, &[150.0, 200.0], None);
+        assert_eq!(got[0].samples, vec![(150.0, 130.0 + 140.0 + 150.0), (200.0, 180.0 + 190.0 + 200.0)]);
+
+        // offset 100s: windows move back, results still land on the outer steps.
+        let got = subquery_range_at_steps(&inner, types::RangeFunc::MaxOverTime, 30.0, &steps, &[50.0, 100.0], None);
+        assert_eq!(got[0].samples, vec![(150.0, 50.0), (200.0, 100.0)]);
+
+        // Windows before the first inner sample produce no point.
+        let got = subquery_range_at_steps(&inner, types::RangeFunc::CountOverTime, 30.0, &steps, &[-100.0, 10.0], None);
+        assert_eq!(got[0].samples, vec![(200.0, 2.0)]);
+    }
+
+    #[test]
+    fn subquery_lookback_and_metrics() {
+        let expr = parser::parse("max_over_time(rate(http_requests_total[5m])[30m:1m] offset 10m)").unwrap();
+        assert_eq!(extract_lookback(&expr), 2400.0);
+        assert_eq!(extract_metrics_from_expr(&expr), vec!["http_requests_total".to_string()]);
+    }
+
+    // ── offset / @ on selectors ──
+
+    fn parse_selector(query: &str) -> parser::VectorSelector {
+        match parser::parse(query).unwrap() {
+            Expr::VectorSelector(vs) => vs,
+            Expr::MatrixSelector(ms) => ms.vs,
+            other => panic!("expected selector, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn selector_shift_reads_offset_and_at() {
+        let bounds = AtBounds { start: 1000.0, end: 1060.0 };
+        assert_eq!(selector_shift(&parse_selector("up"), bounds), TimeShift::None);
+        assert_eq!(selector_shift(&parse_selector("up offset 1w"), bounds), TimeShift::Offset(604_800.0));
+        assert_eq!(selector_shift(&parse_selector("up[5m] offset -2m"), bounds), TimeShift::Offset(-120.0));
+        assert_eq!(selector_shift(&parse_selector("up @ 500"), bounds), TimeShift::Pinned(500.0));
+        assert_eq!(selector_shift(&parse_selector("up[5m] @ 500 offset 1m"), bounds), TimeShift::Pinned(440.0));
+        assert_eq!(selector_shift(&parse_selector("up @ start()"), bounds), TimeShift::Pinned(1000.0));
+        assert_eq!(selector_shift(&parse_selector("up @ end() offset 10s"), bounds), TimeShift::Pinned(1050.0));
+    }
+
+    #[test]
+    fn offset_results_land_back_on_query_grid() {
+        // `up offset 1m` evaluated for steps [120, 180] reads the grid [60, 120];
+        // shifting forward must put the samples back on [120, 180].
+        let steps = [120.0, 180.0];
+        let shifted: Vec<f64> = steps.iter().map(|&t| t - 60.0).collect();
+        let mut got = step_align_series(series_with(vec![(55.0, 1.0), (118.0, 2.0)]), &shifted, 300.0);
+        shift_series(&mut got, 60.0);
+        assert_eq!(got[0].samples, vec![(120.0, 1.0), (180.0, 2.0)]);
+    }
+
+    #[test]
+    fn offset_range_function_matches_unshifted_data() {
+        // rate(x[1m] offset 1h) over shifted data must equal rate(x[1m]) over the
+        // same data one hour later.
+        let samples: Vec<(f64, f64)> = (0..10).map(|k| (k as f64 * 15.0, k as f64 * 30.0)).collect();
+        let steps = [90.0, 120.0];
+        let want = evaluate_range_at_steps(&series_with(samples.clone()), types::RangeFunc::Rate, 60.0, &steps, None);
+
+        let mut raw = series_with(samples.iter().map(|&(t, v)| (t - 3600.0, v)).collect());
+        shift_series(&mut raw, 3600.0);
+        let got = evaluate_range_at_steps(&raw, types::RangeFunc::Rate, 60.0, &steps, None);
+        assert_eq!(got[0].samples, want[0].samples);
+    }
+
+    #[test]
+    fn pin_to_steps_repeats_value_and_drops_empty() {
+        let mut series = series_with(vec![(500.0, 7.0)]);
+        series.extend(series_with(vec![]));
+        let got = pin_to_steps(series, &[1000.0, 1060.0, 1120.0]);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].samples, vec![(1000.0, 7.0), (1060.0, 7.0), (1120.0, 7.0)]);
+    }
+
     // ── rows_to_series ──
 
     fn sample(metric: &str, service: &str, attrs: &[(&str, &str)], ts_ms: i64, value: f64) -> MetricSample {
@@ -1094,4 +1825,62 @@ mod tests {
         let got = bucketed_rows_to_series(&[], &[], &steps);
         assert!(got.is_empty());
     }
+
+    /// RowBinary for one `MetricSample`.
+    fn row_binary(name: &str, ts_ms: i64, value: f64) -> Vec<u8> {
+        let mut out = Vec::new();
+        for text in [name, "api"] {
+            out.push(text.len() as u8);
+            out.extend_from_slice(text.as_bytes());
+        }
+        out.push(0); // no attributes
+        out.extend_from_slice(&ts_ms.to_le_bytes());
+        out.extend_from_slice(&value.to_le_bytes());
+        out
+    }
+
+    /// A stand-in ClickHouse HTTP endpoint that records every statement and
+    /// answers gauge reads with one `foo_count` sample, anything else with no rows.
+    async fn fake_clickhouse() -> (Client, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::sync::{Arc, Mutex};
+        let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = seen.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri, body: String| {
+            let log = log.clone();
+            async move {
+                let sql = if body.is_empty() {
+                    url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+                        .find(|(k, _)| k == "query")
+                        .map(|(_, v)| v.into_owned())
+                        .unwrap_or_default()
+                } else {
+                    body
+                };
+                let rows = if sql.contains("FROM metrics_gauge") { row_binary("foo_count", 60_000, 7.0) } else { Vec::new() };
+                log.lock().unwrap().push(sql);
+                rows
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = Client::default().with_url(url).with_compression(clickhouse::Compression::None);
+        (client, seen)
+    }
+
+    #[tokio::test]
+    async fn float_only_count_series_skips_the_histogram_tables() {
+        let (ch, seen) = fake_clickhouse().await;
+        let Ok(Expr::VectorSelector(vs)) = parser::parse("foo_count") else { panic!("selector") };
+        for _ in 0..2 {
+            let series = query_clickhouse(&ch, &vs, 0.0, 120.0, &[60.0], false, "t-float-count").await.unwrap();
+            assert_eq!(series.len(), 1);
+            assert_eq!(series[0].samples, vec![(60.0, 7.0)]);
+        }
+        let statements = seen.lock().unwrap();
+        assert!(!statements.is_empty());
+        assert!(statements.iter().all(|q| !q.contains("histogram")), "{statements:?}");
+        // The second evaluation went straight to the table the name lives in.
+        assert_eq!(statements.iter().filter(|q| q.contains("FROM metrics_sum")).count(), 1);
+    }
 }
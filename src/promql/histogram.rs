@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use promql_parser::label::Matcher;
+
+use super::types::{self, TimeSeries};
+use crate::models::metrics::{ExpHistogramSample, HistogramSample};
+
+// ═══════════════════════════════════════════════════════════════════
+// Synthetic classic-histogram series over OTLP histogram tables
+// ═══════════════════════════════════════════════════════════════════
+//
+// OTLP histograms land one row per data point in `metrics_histogram` (explicit
+// bounds + per-bucket counts) and `metrics_exp_histogram` (base-2 exponential
+// buckets). PromQL only knows float series, so a selector for `<name>_bucket`,
+// `<name>_sum` or `<name>_count` is answered from those rows as if they had been
+// scraped from a Prometheus classic histogram: cumulative `le`-labelled buckets
+// ending in `+Inf`, plus the sum and count. That is exactly the shape
+// `histogram_quantile` and `histogram_fraction` expect.
+//
+// Points are exposed with their stored temporality: cumulative histograms behave
+// like Prometheus counters; delta histograms read as per-interval counts.
+
+/// Which classic-histogram series a selector asks for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Component {
+    Bucket,
+    Sum,
+    Count,
+}
+
+impl Component {
+    fn suffix(self) -> &'static str {
+        match self {
+            Component::Bucket => "_bucket",
+            Component::Sum => "_sum",
+            Component::Count => "_count",
+        }
+    }
+}
+
+/// Split `http_server_duration_bucket` into (`http_server_duration`, Bucket).
+pub fn split_name(name: &str) -> Option<(&str, Component)> {
+    [Component::Bucket, Component::Sum, Component::Count]
+        .into_iter()
+        .find_map(|c| name.strip_suffix(c.suffix()).filter(|base| !base.is_empty()).map(|base| (base, c)))
+}
+
+/// Prometheus-style `le` value: shortest round-trip float, `+Inf` for the
+/// overflow bucket.
+fn format_le(le: f64) -> String {
+    if le == f64::INFINITY { "+Inf".to_string() } else { format!("{le}") }
+}
+
+/// Cumulative `(le, count)` buckets for an explicit-bounds data point. OTLP
+/// carries `bounds.len() + 1` counts; the last is the overflow bucket.
+pub fn explicit_buckets(bounds: &[f64], counts: &[u64], total: u64) -> Vec<(f64, f64)> {
+    let mut cumulative = 0u64;
+    let mut out: Vec<(f64, f64)> = bounds
+        .iter()
+        .zip(counts)
+        .map(|(&le, &c)| {
+            cumulative += c;
+            (le, cumulative as f64)
+        })
+        .collect();
+    let overflow: u64 = counts.iter().skip(bounds.len()).sum();
+    out.push((f64::INFINITY, total.max(cumulative + overflow) as f64));
+    out
+}
+
+/// Cumulative `(le, count)` buckets for an exponential data point. At scale `s`
+/// the base is `2^(2^-s)`; positive bucket `i` (counting from `offset`) holds
+/// `(base^i, base^(i+1)]` and negative bucket `i` mirrors it below zero. The zero
+/// bucket is reported as `le="0"`, and negative/zero buckets only when they hold
+/// observations, so all-positive latency histograms look like ordinary ones.
+pub fn exponential_buckets(p: &ExpHistogramSample) -> Vec<(f64, f64)> {
+    let bound = |index: i64| (index as f64 * (-p.scale as f64).exp2()).exp2();
+    let mut out = Vec::new();
+    let mut cumulative = 0u64;
+
+    let has_below_or_zero = p.zero_count > 0 || p.negative_bucket_counts.iter().any(|&c| c > 0);
+    if has_below_or_zero {
+        // Most negative first: bucket i covers [-base^(i+1), -base^i).
+        for (j, &c) in p.negative_bucket_counts.iter().enumerate().rev() {
+            cumulative += c;
+            out.push((-bound(p.negative_offset as i64 + j as i64), cumulative as f64));
+        }
+        cumulative += p.zero_count;
+        out.push((0.0, cumulative as f64));
+    }
+    for (j, &c) in p.positive_bucket_counts.iter().enumerate() {
+        cumulative += c;
+        out.push((bound(p.positive_offset as i64 + j as i64 + 1), cumulative as f64));
+    }
+    out.push((f64::INFINITY, p.count.max(cumulative) as f64));
+    out
+}
+
+/// One expanded data point: identity plus the values for every emitted series.
+struct Point<'a> {
+    metric_name: &'a str,
+    service_name: &'a str,
+    attributes: &'a [(String, String)],
+    ts_ms: i64,
+    count: u64,
+    sum: f64,
+    buckets: Vec<(f64, f64)>,
+}
+
+/// Expand histogram rows into the series for `component`, applying any `le`
+/// matchers (which can't be pushed into SQL — `le` isn't a stored attribute).
+/// Output is label-sorted with time-sorted samples, like the float path.
+pub fn expand(
+    explicit: &[HistogramSample],
+    exponential: &[ExpHistogramSample],
+    component: Component,
+    le_matchers: &[Matcher],
+) -> Vec<TimeSeries> {
+    let want_buckets = component == Component::Bucket;
+    let points = explicit
+        .iter()
+        .map(|p| Point {
+            metric_name: &p.metric_name,
+            service_name: &p.service_name,
+            attributes: &p.attributes,
+            ts_ms: p.ts_ms,
+            count: p.count,
+            sum: p.sum,
+            buckets: if want_buckets { explicit_buckets(&p.explicit_bounds, &p.bucket_counts, p.count) } else { Vec::new() },
+        })
+        .chain(exponential.iter().map(|p| Point {
+            metric_name: &p.metric_name,
+            service_name: &p.service_name,
+            attributes: &p.attributes,
+            ts_ms: p.ts_ms,
+            count: p.count,
+            sum: p.sum,
+            buckets: if want_buckets { exponential_buckets(p) } else { Vec::new() },
+        }));
+
+    let mut samples: Vec<(BTreeMap<String, String>, f64, f64)> = Vec::new();
+    for p in points {
+        let name = format!("{}{}", p.metric_name, component.suffix());
+        let labels = types::build_label_set(&name, p.service_name, p.attributes);
+        let t = p.ts_ms as f64 / 1000.0;
+        match component {
+            Component::Sum => samples.push((labels, t, p.sum)),
+            Component::Count => samples.push((labels, t, p.count as f64)),
+            Component::Bucket => {
+                for (le, v) in p.buckets {
+                    let le = format_le(le);
+                    if le_matchers.iter().all(|m| m.is_match(&le)) {
+                        let mut labels = labels.clone();
+                        labels.insert("le".to_string(), le);
+                        samples.push((labels, t, v));
+                    }
+                }
+            }
+        }
+    }
+    types::group_into_series(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use promql_parser::label::MatchOp;
+
+    fn exp_point(scale: i32, zero: u64, pos: (i32, Vec<u64>), neg: (i32, Vec<u64>)) -> ExpHistogramSample {
+        let count = zero + pos.1.iter().sum::<u64>() + neg.1.iter().sum::<u64>();
+        ExpHistogramSample {
+            metric_name: "latency".to_string(),
+            service_name: "api".to_string(),
+            attributes: vec![],
+            ts_ms: 60_000,
+            count,
+            sum: 0.0,
+            scale,
+            zero_count: zero,
+            positive_offset: pos.0,
+            positive_bucket_counts: pos.1,
+            negative_offset: neg.0,
+            negative_bucket_counts: neg.1,
+        }
+    }
+
+    #[test]
+    fn split_name_recognizes_components() {
+        assert_eq!(split_name("http_duration_bucket"), Some(("http_duration", Component::Bucket)));
+        assert_eq!(split_name("http.duration_sum"), Some(("http.duration", Component::Sum)));
+        assert_eq!(split_name("http_duration_count"), Some(("http_duration", Component::Count)));
+        assert_eq!(split_name("http_duration"), None);
+        assert_eq!(split_name("_count"), None);
+    }
+
+    #[test]
+    fn explicit_buckets_are_cumulative_with_inf() {
+        let got = explicit_buckets(&[0.1, 0.5, 1.0], &[2, 3, 4, 1], 10);
+        assert_eq!(got, vec![(0.1, 2.0), (0.5, 5.0), (1.0, 9.0), (f64::INFINITY, 10.0)]);
+        // No bounds at all: a single +Inf bucket holding the count.
+        assert_eq!(explicit_buckets(&[], &[7], 7), vec![(f64::INFINITY, 7.0)]);
+    }
+
+    #[test]
+    fn exponential_buckets_follow_scale_and_offset() {
+        // Scale 0 → base 2. Offset 1: buckets (2,4], (4,8], (8,16].
+        let got = exponential_buckets(&exp_point(0, 0, (1, vec![1, 2, 3]), (0, vec![])));
+        assert_eq!(got, vec![(4.0, 1.0), (8.0, 3.0), (16.0, 6.0), (f64::INFINITY, 6.0)]);
+
+        // Scale 1 → base √2. Offset -2: buckets (0.5, 0.707], (0.707, 1].
+        let got = exponential_buckets(&exp_point(1, 0, (-2, vec![1, 1]), (0, vec![])));
+        assert!((got[0].0 - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+        assert_eq!(got[1], (1.0, 2.0));
+
+        // Scale -1 → base 4. Offset 0: bucket (1, 4].
+        assert_eq!(exponential_buckets(&exp_point(-1, 0, (0, vec![5]), (0, vec![])))[0], (4.0, 5.0));
+    }
+
+    #[test]
+    fn exponential_negative_and_zero_buckets_come_first() {
+        // Negative offset 0 at scale 0: bucket 0 = [-2,-1), bucket 1 = [-4,-2).
+        let got = exponential_buckets(&exp_point(0, 2, (0, vec![1]), (0, vec![3, 4])));
+        assert_eq!(got, vec![(-2.0, 4.0), (-1.0, 7.0), (0.0, 9.0), (2.0, 10.0), (f64::INFINITY, 10.0)]);
+    }
+
+    #[test]
+    fn expand_builds_le_series_and_components() {
+        let explicit = vec![HistogramSample {
+            metric_name: "http_duration".to_string(),
+            service_name: "api".to_string(),
+            attributes: vec![("route".to_string(), "/".to_string())],
+            ts_ms: 60_000,
+            count: 4,
+            sum: 1.5,
+            bucket_counts: vec![1, 3, 0],
+            explicit_bounds: vec![0.25, 1.0],
+        }];
+
+        let buckets = expand(&explicit, &[], Component::Bucket, &[]);
+        let les: Vec<(&str, &str, f64)> = buckets
+            .iter()
+            .map(|ts| (ts.labels["__name__"].as_str(), ts.labels["le"].as_str(), ts.samples[0].1))
+            .collect();
+        assert_eq!(
+            les,
+            vec![("http_duration_bucket", "+Inf", 4.0), ("http_duration_bucket", "0.25", 1.0), ("http_duration_bucket", "1", 4.0)]
+        );
+        assert_eq!(buckets[0].labels["route"], "/");
+        assert_eq!(buckets[0].samples[0].0, 60.0);
+
+        let only_inf = expand(&explicit, &[], Component::Bucket, &[Matcher::new(MatchOp::Equal, "le", "+Inf")]);
+        assert_eq!(only_inf.len(), 1);
+
+        let sum = expand(&explicit, &[], Component::Sum, &[]);
+        assert_eq!((sum[0].labels["__name__"].as_str(), sum[0].samples[0].1), ("http_duration_sum", 1.5));
+        let count = expand(&[], &[exp_point(0, 0, (0, vec![2, 2]), (0, vec![]))], Component::Count, &[]);
+        assert_eq!((count[0].labels["__name__"].as_str(), count[0].samples[0].1), ("latency_count", 4.0));
+    }
+
+    #[test]
+    fn expanded_buckets_feed_histogram_quantile() {
+        // 100 observations: 50 in (0, 0.1], 50 in (0.1, 0.2] → p50 = 0.1, p75 = 0.15.
+        let explicit = vec![HistogramSample {
+            metric_name: "rpc".to_string(),
+            service_name: String::new(),
+            attributes: vec![],
+            ts_ms: 0,
+            count: 100,
+            sum: 0.0,
+            bucket_counts: vec![50, 50, 0],
+            explicit_bounds: vec![0.1, 0.2],
+        }];
+        let buckets = expand(&explicit, &[], Component::Bucket, &[]);
+        let q = |phi: f64| {
+            crate::promql::scalar::apply_scalar_func(buckets.clone(), types::ScalarFunc::HistogramQuantile, &[phi])[0].samples[0].1
+        };
+        assert!((q(0.5) - 0.1).abs() < 1e-9);
+        assert!((q(0.75) - 0.15).abs() < 1e-9);
+    }
+}
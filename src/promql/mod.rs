@@ -2,10 +2,13 @@ pub mod aggregate;
 pub mod binary;
 pub mod compute;
 pub mod eval;
+pub mod histogram;
+pub mod rollup;
 pub mod scalar;
 pub mod sql;
 pub mod translate;
 pub mod types;
+pub mod vector;
 
 // Re-export the public API
 pub use eval::{evaluate_instant_query, evaluate_range_query, extract_metrics_from_expr};
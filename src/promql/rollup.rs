@@ -0,0 +1,407 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::types::{self, RangeFunc, TimeSeries};
+use crate::models::metrics::RollupSample;
+use crate::rollup::Source;
+
+// ═══════════════════════════════════════════════════════════════════
+// Approximate range functions over the metric rollups
+// ═══════════════════════════════════════════════════════════════════
+//
+// A 30-day `rate(x[1h])` at a 1h step reads every raw sample in the range. In
+// approximate mode the evaluator instead reads the `metrics_*_1m` / `_1h` bucket
+// states and merges them per window, whenever the step grid and range line up
+// with whole buckets (`crate::rollup::select_range_source`). The error bounds
+// against the raw evaluation are documented in `crate::rollup`.
+//
+// Approximate mode is a task-local scope around one query evaluation, like the
+// dry-run capture in `query_explain`; the scope also records whether any range
+// function was actually served from a rollup, for the response flag.
+
+tokio::task_local! {
+    static APPROXIMATE: Arc<AtomicBool>;
+}
+
+/// Evaluate `fut` in approximate mode; returns its output and whether a rollup
+/// served any part of it.
+pub async fn approximate<F: Future>(fut: F) -> (F::Output, bool) {
+    let used = Arc::new(AtomicBool::new(false));
+    let out = APPROXIMATE.scope(used.clone(), fut).await;
+    (out, used.load(Ordering::Relaxed))
+}
+
+/// Record that a rollup served part of the current approximate evaluation.
+pub fn mark_used() {
+    let _ = APPROXIMATE.try_with(|used| used.store(true, Ordering::Relaxed));
+}
+
+/// Range functions that can be computed from merged bucket states.
+fn supports(func: RangeFunc) -> bool {
+    matches!(
+        func,
+        RangeFunc::Rate
+            | RangeFunc::Increase
+            | RangeFunc::SumOverTime
+            | RangeFunc::AvgOverTime
+            | RangeFunc::MinOverTime
+            | RangeFunc::MaxOverTime
+            | RangeFunc::CountOverTime
+            | RangeFunc::LastOverTime
+            | RangeFunc::PresentOverTime
+    )
+}
+
+/// Whether `func` needs the avg state, which only the gauge rollups keep.
+pub fn needs_avg(func: RangeFunc) -> bool {
+    matches!(func, RangeFunc::SumOverTime | RangeFunc::AvgOverTime)
+}
+
+/// The rollup to serve `func` over `range_secs` windows on `step_timestamps`
+/// from, or `None` to read raw: outside approximate mode, for functions that
+/// need individual samples, and for grids the rollup buckets don't tile.
+pub fn plan(func: RangeFunc, range_secs: f64, step_timestamps: &[f64]) -> Option<Source> {
+    if APPROXIMATE.try_with(|_| ()).is_err() || !supports(func) {
+        return None;
+    }
+    let [first, second, ..] = step_timestamps else {
+        return None;
+    };
+    match crate::rollup::select_range_source(range_secs, second - first, *first) {
+        Source::Raw => None,
+        source => Some(source),
+    }
+}
+
+/// Merged states of one rollup bucket `[start, start + interval)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub start: f64,
+    pub last: f64,
+    pub min: f64,
+    pub max: f64,
+    pub count: f64,
+    pub avg: f64,
+}
+
+/// One series' buckets, ordered by start.
+#[derive(Debug, Clone)]
+pub struct BucketSeries {
+    pub labels: BTreeMap<String, String>,
+    pub buckets: Vec<Bucket>,
+}
+
+/// (MetricName, ServiceName, Attributes) of a row: one series before label building.
+type RowKey<'a> = (&'a str, &'a str, &'a [(String, String)]);
+
+/// Group rollup rows (ordered by series then bucket, as the SQL returns them)
+/// into label-sorted series, merging label sets that appear in both tables.
+pub fn group_buckets(gauge_rows: &[RollupSample], sum_rows: &[RollupSample]) -> Vec<BucketSeries> {
+    let mut series: Vec<BucketSeries> = Vec::new();
+    for rows in [gauge_rows, sum_rows] {
+        let mut prev_key: Option<RowKey> = None;
+        for r in rows {
+            let key = (r.metric_name.as_str(), r.service_name.as_str(), r.attributes.as_slice());
+            if prev_key != Some(key) {
+                series.push(BucketSeries {
+                    labels: types::build_label_set(&r.metric_name, &r.service_name, &r.attributes),
+                    buckets: Vec::new(),
+                });
+                prev_key = Some(key);
+            }
+            series.last_mut().expect("series pushed above").buckets.push(Bucket {
+                start: r.ts_ms as f64 / 1000.0,
+                last: r.last,
+                min: r.min,
+                max: r.max,
+                count: r.count as f64,
+                avg: r.avg,
+            });
+        }
+    }
+
+    series.sort_by(|a, b| a.labels.cmp(&b.labels));
+    let mut merged: Vec<BucketSeries> = Vec::with_capacity(series.len());
+    for s in series {
+        match merged.last_mut() {
+            Some(last) if last.labels == s.labels => {
+                last.buckets.extend(s.buckets);
+                last.buckets.sort_by(|a, b| a.start.total_cmp(&b.start));
+            }
+            _ => merged.push(s),
+        }
+    }
+    merged
+}
+
+/// Apply `func` at each step over the buckets tiling `[t - range, t)`. Rate and
+/// increase also read the bucket just before the window, whose last value is
+/// the counter as the window opens. A sample stamped exactly at `t - range` is
+/// inside the window (see the error bounds in `crate::rollup`).
+pub fn range_at_steps(
+    series: &[BucketSeries],
+    func: RangeFunc,
+    range_secs: f64,
+    interval_secs: f64,
+    step_timestamps: &[f64],
+) -> Vec<TimeSeries> {
+    series
+        .iter()
+        .map(|s| {
+            let samples = step_timestamps
+                .iter()
+                .filter_map(|&t| {
+                    let from = t - range_secs;
+                    let inside: Vec<Bucket> = s
+                        .buckets
+                        .iter()
+                        .filter(|b| b.start >= from && b.start + interval_secs <= t)
+                        .copied()
+                        .collect();
+                    let before = s.buckets.iter().find(|b| b.start == from - interval_secs);
+                    window_value(func, &inside, before).map(|v| (t, v))
+                })
+                .collect();
+            TimeSeries { labels: s.labels.clone(), samples }
+        })
+        .collect()
+}
+
+fn window_value(func: RangeFunc, inside: &[Bucket], before: Option<&Bucket>) -> Option<f64> {
+    let last = inside.last()?;
+    let count: f64 = inside.iter().map(|b| b.count).sum();
+    let sum = || inside.iter().map(|b| b.avg * b.count).sum::<f64>();
+    match func {
+        RangeFunc::Rate | RangeFunc::Increase => {
+            let points: Vec<&Bucket> = before.into_iter().chain(inside).collect();
+            let (first, end) = (points.first()?, points.last()?);
+            let dt = end.start - first.start;
+            if dt <= 0.0 {
+                return None;
+            }
+            let increase = counter_increase(&points);
+            Some(if func == RangeFunc::Rate { increase / dt } else { increase })
+        }
+        RangeFunc::CountOverTime => Some(count),
+        RangeFunc::SumOverTime => Some(sum()),
+        RangeFunc::AvgOverTime => Some(sum() / count),
+        RangeFunc::MinOverTime => inside.iter().map(|b| b.min).reduce(f64::min),
+        RangeFunc::MaxOverTime => inside.iter().map(|b| b.max).reduce(f64::max),
+        RangeFunc::LastOverTime => Some(last.last),
+        RangeFunc::PresentOverTime => Some(1.0),
+        _ => None,
+    }
+}
+
+/// Counter growth across consecutive bucket lasts. When a bucket's last is
+/// below the previous one the counter reset inside it: the growth up to the
+/// reset is taken from the bucket's max, and the last counts up from zero, as
+/// the raw evaluation does for the first sample after a reset.
+fn counter_increase(points: &[&Bucket]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| {
+            let (prev, cur) = (w[0], w[1]);
+            if cur.last >= prev.last {
+                cur.last - prev.last
+            } else {
+                (cur.max - prev.last).max(0.0) + cur.last
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::promql::eval::evaluate_range_at_steps;
+
+    /// Day-aligned origin so every rollup interval tiles the grid.
+    const T0: f64 = 1_699_920_000.0;
+    /// Scrape interval of the synthetic series, offset so no sample sits on a
+    /// bucket boundary.
+    const SCRAPE: f64 = 15.0;
+    const SCRAPE_OFFSET: f64 = 7.0;
+
+    fn labels() -> BTreeMap<String, String> {
+        BTreeMap::from([("__name__".to_string(), "synthetic".to_string())])
+    }
+
+    /// `n` scrapes of `value(t)`.
+    fn scrape(n: usize, value: impl Fn(f64) -> f64) -> TimeSeries {
+        let samples = (0..n)
+            .map(|i| {
+                let t = T0 + SCRAPE_OFFSET + i as f64 * SCRAPE;
+                (t, value(t - T0))
+            })
+            .collect();
+        TimeSeries { labels: labels(), samples }
+    }
+
+    /// What the rollup materialized views store for `raw`.
+    fn roll_up(raw: &TimeSeries, interval: f64) -> BucketSeries {
+        let mut buckets: Vec<Bucket> = Vec::new();
+        for &(t, v) in &raw.samples {
+            let start = (t / interval).floor() * interval;
+            match buckets.last_mut() {
+                Some(b) if b.start == start => {
+                    b.avg = (b.avg * b.count + v) / (b.count + 1.0);
+                    b.count += 1.0;
+                    b.min = b.min.min(v);
+                    b.max = b.max.max(v);
+                    b.last = v;
+                }
+                _ => buckets.push(Bucket { start, last: v, min: v, max: v, count: 1.0, avg: v }),
+            }
+        }
+        BucketSeries { labels: raw.labels.clone(), buckets }
+    }
+
+    fn steps(first: f64, step: f64, n: usize) -> Vec<f64> {
+        (0..n).map(|i| first + i as f64 * step).collect()
+    }
+
+    /// Raw and rollup results for the same function, step by step.
+    fn compare(raw: &TimeSeries, func: RangeFunc, range: f64, interval: f64, grid: &[f64]) -> Vec<(f64, f64)> {
+        let exact = evaluate_range_at_steps(std::slice::from_ref(raw), func, range, grid, None);
+        let approx = range_at_steps(&[roll_up(raw, interval)], func, range, interval, grid);
+        assert_eq!(exact[0].labels, approx[0].labels);
+        let (e, a) = (&exact[0].samples, &approx[0].samples);
+        assert_eq!(e.len(), a.len(), "{func:?}: raw {e:?} vs rollup {a:?}");
+        e.iter()
+            .zip(a)
+            .map(|(&(te, ve), &(ta, va))| {
+                assert_eq!(te, ta, "{func:?}");
+                (ve, va)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn over_time_functions_match_raw() {
+        // Two days of a wavy gauge with a 10-minute gap every 5 hours.
+        let mut gauge = scrape(2 * 86400 / SCRAPE as usize, |t| 50.0 + 40.0 * (t / 5000.0).sin() + (t % 97.0));
+        gauge.samples.retain(|(t, _)| (t - T0) % 18_000.0 >= 600.0);
+
+        let cases = [
+            (3600.0, 3600.0, 3600.0), // 1h windows at a 1h step from the 1h rollup
+            (7200.0, 3600.0, 3600.0), // 2h windows at a 1h step
+            (900.0, 300.0, 60.0),     // 15m windows at a 5m step from the 1m rollup
+        ];
+        let funcs = [
+            RangeFunc::CountOverTime,
+            RangeFunc::SumOverTime,
+            RangeFunc::AvgOverTime,
+            RangeFunc::MinOverTime,
+            RangeFunc::MaxOverTime,
+            RangeFunc::LastOverTime,
+            RangeFunc::PresentOverTime,
+        ];
+        for (range, step, interval) in cases {
+            let grid = steps(T0 + range, step, ((2.0 * 86400.0 - range) / step) as usize);
+            for func in funcs {
+                for (exact, approx) in compare(&gauge, func, range, interval, &grid) {
+                    assert!((exact - approx).abs() <= 1e-9 * exact.abs().max(1.0), "{func:?}: {exact} vs {approx}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sample_on_window_start_is_counted() {
+        // One sample exactly on the start of the window ending at T0 + 1h, one
+        // inside it and one exactly on its end.
+        let raw = TimeSeries { labels: labels(), samples: vec![(T0, 1.0), (T0 + 1800.0, 2.0), (T0 + 3600.0, 4.0)] };
+        let rollup = range_at_steps(&[roll_up(&raw, 60.0)], RangeFunc::CountOverTime, 3600.0, 60.0, &[T0 + 3600.0]);
+        assert_eq!(rollup[0].samples, vec![(T0 + 3600.0, 2.0)]);
+        let sum = range_at_steps(&[roll_up(&raw, 60.0)], RangeFunc::SumOverTime, 3600.0, 60.0, &[T0 + 3600.0]);
+        assert_eq!(sum[0].samples, vec![(T0 + 3600.0, 3.0)]);
+        // The raw evaluation also counts the start sample, and the end one too.
+        let exact = evaluate_range_at_steps(&[raw], RangeFunc::CountOverTime, 3600.0, &[T0 + 3600.0], None);
+        assert_eq!(exact[0].samples, vec![(T0 + 3600.0, 3.0)]);
+    }
+
+    #[test]
+    fn rate_matches_raw_for_steady_counter() {
+        let counter = scrape(86400 / SCRAPE as usize, |t| 3.0 * t);
+        let grid = steps(T0 + 2.0 * 3600.0, 3600.0, 22);
+        for (exact, approx) in compare(&counter, RangeFunc::Rate, 3600.0, 3600.0, &grid) {
+            assert!((exact - 3.0).abs() < 1e-9 && (approx - 3.0).abs() < 1e-9, "{exact} vs {approx}");
+        }
+    }
+
+    #[test]
+    fn increase_stays_within_documented_bound() {
+        // Rate 1 + sin(t/3000) swings between 0 and 2/s; the counter resets
+        // mid-bucket at 30h.
+        let growth = |t: f64| t + 3000.0 * (1.0 - (t / 3000.0).cos());
+        let reset_at = 30.0 * 3600.0 + 1234.0;
+        let counter = scrape(2 * 86400 / SCRAPE as usize, |t| {
+            if t < reset_at { growth(t) } else { growth(t) - growth(reset_at) }
+        });
+        // One scrape interval of growth at each end of the window.
+        let bound = 2.0 * SCRAPE * 2.0;
+
+        for (range, step, interval) in [(3600.0, 3600.0, 3600.0), (600.0, 300.0, 60.0)] {
+            // Start one step in, so every window has a bucket before it.
+            let grid = steps(T0 + range + step, step, ((2.0 * 86400.0 - range) / step) as usize - 1);
+            for (exact, approx) in compare(&counter, RangeFunc::Increase, range, interval, &grid) {
+                assert!((exact - approx).abs() <= bound, "{exact} vs {approx}");
+            }
+            for (exact, approx) in compare(&counter, RangeFunc::Rate, range, interval, &grid) {
+                // Both rates are averages of a rate within [0, 2] over nearly the same span.
+                assert!((exact - approx).abs() <= bound / (range - 2.0 * SCRAPE), "{exact} vs {approx}");
+            }
+        }
+    }
+
+    #[test]
+    fn reset_inside_bucket_is_bridged_through_max() {
+        let b = |start: f64, last: f64, max: f64| Bucket { start, last, min: 0.0, max, count: 4.0, avg: 0.0 };
+        // 100 → peaked at 130 → reset → 20: growth is 30 before and 20 after the reset.
+        assert_eq!(counter_increase(&[&b(0.0, 100.0, 100.0), &b(60.0, 20.0, 130.0)]), 50.0);
+        assert_eq!(counter_increase(&[&b(0.0, 100.0, 100.0), &b(60.0, 110.0, 110.0)]), 10.0);
+    }
+
+    #[tokio::test]
+    async fn plan_needs_approximate_mode_and_a_tiling_grid() {
+        let hourly = steps(T0 + 3600.0, 3600.0, 24);
+        assert_eq!(plan(RangeFunc::Rate, 3600.0, &hourly), None);
+
+        let (planned, used) = approximate(async {
+            [
+                plan(RangeFunc::Rate, 3600.0, &hourly),
+                plan(RangeFunc::AvgOverTime, 300.0, &hourly),
+                plan(RangeFunc::Rate, 3600.0, &steps(T0 + 3600.0, 30.0, 24)),
+                plan(RangeFunc::Rate, 3600.0, &hourly[..1]),
+                plan(RangeFunc::QuantileOverTime, 3600.0, &hourly),
+            ]
+        })
+        .await;
+        assert_eq!(planned, [Some(Source::Rollup1h), Some(Source::Rollup1m), None, None, None]);
+        assert!(!used);
+
+        let ((), used) = approximate(async { mark_used() }).await;
+        assert!(used);
+    }
+
+    #[test]
+    fn group_buckets_merges_tables_and_orders_buckets() {
+        let row = |name: &str, ts_ms: i64, last: f64| RollupSample {
+            metric_name: name.to_string(),
+            service_name: "api".to_string(),
+            attributes: vec![],
+            ts_ms,
+            last,
+            min: last,
+            max: last,
+            count: 1,
+            avg: f64::NAN,
+        };
+        let got = group_buckets(&[row("b", 0, 1.0), row("a", 60_000, 2.0)], &[row("a", 0, 3.0)]);
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].labels["__name__"], "a");
+        assert_eq!(got[0].buckets.iter().map(|b| (b.start, b.last)).collect::<Vec<_>>(), vec![(0.0, 3.0), (60.0, 2.0)]);
+    }
+}
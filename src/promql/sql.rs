@@ -1,13 +1,46 @@
 use promql_parser::label::{MatchOp, Matcher};
 
+use crate::metric_naming::{self, MetricKind, MetricNaming, OtelOrigin};
+
+/// Sanitized stored name, for comparing against either naming form.
+const SANITIZED_NAME: &str = "replaceRegexpAll(replaceRegexpAll(MetricName, '[^a-zA-Z0-9_:]', '_'), '_+', '_')";
+
+/// Stored unit before any `{annotation}`, as `OtelOrigin::unit_stems` lists it.
+const UNIT_STEM: &str = "splitByChar('{', MetricUnit)[1]";
+
+/// The table a name condition runs against, which decides what it can check
+/// about a stored OTel spelling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameTable {
+    Gauge,
+    /// `IsMonotonic` tells counters (`_total`) from other sums.
+    Sum,
+    Histogram,
+    /// The rollups keep the unit as an aggregate state and no `IsMonotonic`,
+    /// so OTel spellings are looked up in the raw table.
+    GaugeRollup,
+    SumRollup,
+}
+
 /// Build ClickHouse WHERE clause fragments from promql-parser label matchers.
-pub fn matchers_to_sql(matchers: &[Matcher]) -> Vec<String> {
+///
+/// With `MetricNaming::Prometheus` a queried metric name matches exactly, and
+/// also the OTel spelling it was generated from when the stored unit and type
+/// give that suffix: `http_server_request_duration_seconds` matches series
+/// stored as `http.server.request.duration` in `s`, but not in `ms`. Label
+/// `http_method` also matches attribute `http.method` (and vice versa). Regex
+/// name matchers test the sanitized stored name as well.
+pub fn matchers_to_sql(matchers: &[Matcher], naming: MetricNaming, table: NameTable) -> Vec<String> {
     let mut conditions = Vec::new();
     for m in matchers {
+        if m.name == "__name__" && naming == MetricNaming::Prometheus {
+            conditions.push(resolving_name_sql(&m.op, &m.value, table));
+            continue;
+        }
         let col = match m.name.as_str() {
             "__name__" => "MetricName".to_string(),
             "service_name" | "job" => "ServiceName".to_string(),
-            _ => format!("Attributes['{}']", crate::query_builder::escape_string_literal(&m.name)),
+            _ => label_column(&m.name, naming),
         };
 
         let escaped = crate::query_builder::escape_string_literal(&m.value);
@@ -21,3 +54,164 @@ pub fn matchers_to_sql(matchers: &[Matcher]) -> Vec<String> {
     }
     conditions
 }
+
+/// Condition for a selector's bare metric name (`foo{...}`).
+pub fn metric_name_sql(name: &str, naming: MetricNaming, table: NameTable) -> String {
+    match naming {
+        MetricNaming::Otel => format!("MetricName = '{}'", crate::query_builder::escape_string_literal(name)),
+        MetricNaming::Prometheus => resolving_name_sql(&MatchOp::Equal, name, table),
+    }
+}
+
+fn resolving_name_sql(op: &MatchOp, value: &str, table: NameTable) -> String {
+    let escaped = crate::query_builder::escape_string_literal(value);
+    match op {
+        MatchOp::Equal | MatchOp::NotEqual => {
+            let cond = match otel_origin_sql(value, table) {
+                Some(otel) => format!("(MetricName = '{escaped}' OR {otel})"),
+                None => format!("MetricName = '{escaped}'"),
+            };
+            if matches!(op, MatchOp::NotEqual) { format!("NOT {cond}") } else { cond }
+        }
+        MatchOp::Re(_) => format!("(match(MetricName, '{escaped}') OR match({SANITIZED_NAME}, '{escaped}'))"),
+        MatchOp::NotRe(_) => format!("NOT (match(MetricName, '{escaped}') OR match({SANITIZED_NAME}, '{escaped}'))"),
+    }
+}
+
+/// Series in `table` stored under an OTel spelling of `query` whose unit and
+/// type give the queried suffix; `None` if none can.
+fn otel_origin_sql(query: &str, table: NameTable) -> Option<String> {
+    let kinds: &[(MetricKind, &str)] = match table {
+        NameTable::Gauge | NameTable::GaugeRollup => &[(MetricKind::Gauge, "")],
+        NameTable::Sum | NameTable::SumRollup => {
+            &[(MetricKind::Counter, " AND IsMonotonic"), (MetricKind::Other, " AND NOT IsMonotonic")]
+        }
+        NameTable::Histogram => &[(MetricKind::Other, "")],
+    };
+    let quote = |names: &[String]| {
+        names
+            .iter()
+            .map(|n| format!("'{}'", crate::query_builder::escape_string_literal(n)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let origin = OtelOrigin::parse(query);
+    let name = match origin.spellings() {
+        Some(names) => format!("MetricName IN ({})", quote(&names)),
+        // Too many separators to spell out: compare the sanitized stored name,
+        // which scans the tenant's metrics.
+        None => format!("{SANITIZED_NAME} = '{}'", crate::query_builder::escape_string_literal(&origin.base)),
+    };
+    let cond = kinds
+        .iter()
+        .filter_map(|(kind, monotonic)| {
+            let stems = origin.unit_stems(*kind);
+            (!stems.is_empty()).then(|| format!("{name} AND {UNIT_STEM} IN ({}){monotonic}", quote(&stems)))
+        })
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    if cond.is_empty() {
+        return None;
+    }
+    Some(match table {
+        NameTable::GaugeRollup => {
+            format!("(tenant_id, MetricName) IN (SELECT tenant_id, MetricName FROM metrics_gauge WHERE {cond})")
+        }
+        NameTable::SumRollup => {
+            format!("(tenant_id, MetricName) IN (SELECT tenant_id, MetricName FROM metrics_sum WHERE {cond})")
+        }
+        _ => format!("({cond})"),
+    })
+}
+
+/// Attribute lookup for a label. In prometheus mode a dotted key falls back to
+/// its normalized spelling, and a normalized key falls back to whichever
+/// stored key normalizes to it.
+fn label_column(key: &str, naming: MetricNaming) -> String {
+    let escaped = crate::query_builder::escape_string_literal(key);
+    if naming == MetricNaming::Otel {
+        return format!("Attributes['{escaped}']");
+    }
+    let normalized = metric_naming::prometheus_label(key);
+    if normalized != key {
+        let norm = crate::query_builder::escape_string_literal(&normalized);
+        format!("if(mapContains(Attributes, '{escaped}'), Attributes['{escaped}'], Attributes['{norm}'])")
+    } else if key.contains('_') {
+        format!(
+            "if(mapContains(Attributes, '{escaped}'), Attributes['{escaped}'], \
+             arrayElement(mapValues(mapFilter((k, v) -> replaceRegexpAll(k, '[^a-zA-Z0-9_]', '_') = '{escaped}', Attributes)), 1))"
+        )
+    } else {
+        format!("Attributes['{escaped}']")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eq(name: &str, value: &str) -> Matcher {
+        Matcher::new(MatchOp::Equal, name, value)
+    }
+
+    #[test]
+    fn otel_mode_matches_exactly() {
+        let got = matchers_to_sql(&[eq("__name__", "up"), eq("http.method", "GET")], MetricNaming::Otel, NameTable::Gauge);
+        assert_eq!(got, vec!["MetricName = 'up'".to_string(), "Attributes['http.method'] = 'GET'".to_string()]);
+    }
+
+    #[test]
+    fn prometheus_mode_resolves_either_form() {
+        let got = matchers_to_sql(
+            &[eq("__name__", "http_server_request_duration_seconds"), eq("http.method", "GET"), eq("code", "200")],
+            MetricNaming::Prometheus,
+            NameTable::Histogram,
+        );
+        assert!(got[0].starts_with("(MetricName = 'http_server_request_duration_seconds' OR (MetricName IN ("));
+        assert!(got[0].contains("'http.server.request.duration'"));
+        assert!(got[0].contains(&format!("{UNIT_STEM} IN ('s')")));
+        assert!(!got[0].contains("_total"));
+        assert_eq!(
+            got[1],
+            "if(mapContains(Attributes, 'http.method'), Attributes['http.method'], Attributes['http_method']) = 'GET'"
+        );
+        assert_eq!(got[2], "Attributes['code'] = '200'");
+
+        // Too many separators to spell out: compare the sanitized stored name.
+        let long = metric_name_sql("a_b_c_d_e_f_g_h_i_j", MetricNaming::Prometheus, NameTable::Gauge);
+        assert!(long.contains(&format!("{SANITIZED_NAME} = 'a_b_c_d_e_f_g_h_i_j'")));
+    }
+
+    #[test]
+    fn otel_spellings_need_the_queried_unit_and_monotonicity() {
+        let counter = metric_name_sql("process_cpu_seconds_total", MetricNaming::Prometheus, NameTable::Sum);
+        assert_eq!(
+            counter,
+            format!(
+                "(MetricName = 'process_cpu_seconds_total' OR (MetricName IN ('process_cpu', 'process.cpu') \
+                 AND {UNIT_STEM} IN ('s') AND IsMonotonic))"
+            )
+        );
+        // Unit-less query: no unit suffix is accepted, and no OTel spelling of
+        // another name (`process_cpu_total`) is listed.
+        let plain = metric_name_sql("http_requests_total", MetricNaming::Prometheus, NameTable::Sum);
+        assert!(plain.contains(&format!("{UNIT_STEM} IN ('', '1') AND IsMonotonic")));
+        assert!(!plain.contains("bytes") && !plain.contains("seconds"));
+        // Gauges and histograms are never `_total`.
+        assert_eq!(
+            metric_name_sql("http_requests_total", MetricNaming::Prometheus, NameTable::Gauge),
+            "MetricName = 'http_requests_total'"
+        );
+        assert_eq!(
+            metric_name_sql("queue_depth", MetricNaming::Prometheus, NameTable::Sum),
+            format!(
+                "(MetricName = 'queue_depth' OR (MetricName IN ('queue_depth', 'queue.depth') \
+                 AND {UNIT_STEM} IN ('', '1') AND NOT IsMonotonic))"
+            )
+        );
+        // Rollups check the raw table, which records unit and monotonicity.
+        let rollup = metric_name_sql("net_io_bytes_total", MetricNaming::Prometheus, NameTable::SumRollup);
+        assert!(rollup.contains("(tenant_id, MetricName) IN (SELECT tenant_id, MetricName FROM metrics_sum WHERE "));
+        assert!(rollup.contains("IN ('By') AND IsMonotonic)"));
+    }
+}
@@ -1,5 +1,5 @@
 use promql_parser::parser::token::{self, TokenType};
-use super::types::{AggOp, RangeFunc, ScalarFunc};
+use super::types::{AggOp, RangeFunc, ScalarFunc, VectorFunc};
 
 /// Map a function name (from promql-parser `Call.func.name`) to our internal RangeFunc.
 pub fn to_range_func(name: &str) -> Option<RangeFunc> {
@@ -63,6 +63,35 @@ pub fn to_scalar_func(name: &str) -> Option<ScalarFunc> {
     }
 }
 
+/// Map a function name to our internal VectorFunc.
+pub fn to_vector_func(name: &str) -> Option<VectorFunc> {
+    match name {
+        "label_replace" => Some(VectorFunc::LabelReplace),
+        "label_join" => Some(VectorFunc::LabelJoin),
+        "sort" => Some(VectorFunc::Sort),
+        "sort_desc" => Some(VectorFunc::SortDesc),
+        "sort_by_label" => Some(VectorFunc::SortByLabel),
+        "sort_by_label_desc" => Some(VectorFunc::SortByLabelDesc),
+        "absent" => Some(VectorFunc::Absent),
+        "vector" => Some(VectorFunc::Vector),
+        "scalar" => Some(VectorFunc::Scalar),
+        "time" => Some(VectorFunc::Time),
+        "minute" => Some(VectorFunc::Minute),
+        "hour" => Some(VectorFunc::Hour),
+        "day_of_week" => Some(VectorFunc::DayOfWeek),
+        "day_of_month" => Some(VectorFunc::DayOfMonth),
+        "day_of_year" => Some(VectorFunc::DayOfYear),
+        "days_in_month" => Some(VectorFunc::DaysInMonth),
+        "month" => Some(VectorFunc::Month),
+        "year" => Some(VectorFunc::Year),
+        "histogram_count" => Some(VectorFunc::HistogramCount),
+        "histogram_sum" => Some(VectorFunc::HistogramSum),
+        "histogram_avg" => Some(VectorFunc::HistogramAvg),
+        "histogram_fraction" => Some(VectorFunc::HistogramFraction),
+        _ => None,
+    }
+}
+
 /// Map a promql-parser aggregation TokenType to our internal AggOp.
 pub fn to_agg_op(tt: TokenType) -> Result<AggOp, String> {
     let t = tt.id();
@@ -78,6 +107,7 @@ pub fn to_agg_op(tt: TokenType) -> Result<AggOp, String> {
     if t == token::T_BOTTOMK { return Ok(AggOp::Bottomk); }
     if t == token::T_GROUP { return Ok(AggOp::Group); }
     if t == token::T_COUNT_VALUES { return Ok(AggOp::CountValues); }
+    if t == token::T_LIMITK { return Ok(AggOp::Limitk); }
     Err(format!("unsupported aggregation token: {tt:?}"))
 }
 
@@ -49,6 +49,7 @@ pub enum AggOp {
     Bottomk,
     Group,
     CountValues,
+    Limitk,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -85,12 +86,45 @@ pub enum ScalarFunc {
     Timestamp,
 }
 
+/// Functions that reshape or relabel their input vector rather than mapping each
+/// value independently — see `promql::vector`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorFunc {
+    // Label manipulation
+    LabelReplace,
+    LabelJoin,
+    // Ordering
+    Sort,
+    SortDesc,
+    SortByLabel,
+    SortByLabelDesc,
+    // Presence / type conversion
+    Absent,
+    Vector,
+    Scalar,
+    Time,
+    // Date parts (UTC)
+    Minute,
+    Hour,
+    DayOfWeek,
+    DayOfMonth,
+    DayOfYear,
+    DaysInMonth,
+    Month,
+    Year,
+    // Classic histogram helpers
+    HistogramCount,
+    HistogramSum,
+    HistogramAvg,
+    HistogramFraction,
+}
+
 // ═══════════════════════════════════════════════════════════════════
 // Evaluation types
 // ═══════════════════════════════════════════════════════════════════
 
 /// A single time-series identified by its label set, with ordered samples.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TimeSeries {
     pub labels: BTreeMap<String, String>,
     pub samples: Vec<(f64, f64)>, // (timestamp_secs, value)
@@ -0,0 +1,1000 @@
+use std::collections::{BTreeMap, BTreeSet};
+use chrono::{DateTime, Datelike, NaiveDate, Timelike};
+use promql_parser::label::MatchOp;
+use promql_parser::parser::Expr;
+
+use super::types::{TimeSeries, VectorFunc};
+
+// ═══════════════════════════════════════════════════════════════════
+// Vector function dispatch
+// ═══════════════════════════════════════════════════════════════════
+
+/// Index of the instant-vector argument in a call, or None when the function
+/// takes no vector (or it is optional and absent).
+pub fn vector_arg_index(func: VectorFunc, args: &[Box<Expr>]) -> Option<usize> {
+    let idx = match func {
+        VectorFunc::Time => return None,
+        VectorFunc::HistogramFraction => 2,
+        _ => 0,
+    };
+    (idx < args.len()).then_some(idx)
+}
+
+/// Apply a vector function to its already-evaluated vector argument. `args` is
+/// the raw call argument list, for string/number parameters and for `absent`'s
+/// label inference. Functions whose vector argument is optional (the date parts)
+/// receive `vector(time())` when the caller omits it.
+///
+/// HistogramCount/Sum/Avg re-read the `_count`/`_sum` series and are dispatched
+/// by the evaluator, not here.
+pub fn apply_vector_func(
+    func: VectorFunc,
+    args: &[Box<Expr>],
+    inner: Vec<TimeSeries>,
+    step_timestamps: &[f64],
+) -> Result<Vec<TimeSeries>, String> {
+    match func {
+        VectorFunc::LabelReplace => {
+            let dst = string_arg(args, 1, "label_replace")?;
+            let replacement = string_arg(args, 2, "label_replace")?;
+            let src = string_arg(args, 3, "label_replace")?;
+            let regex = string_arg(args, 4, "label_replace")?;
+            label_replace(inner, dst, replacement, src, regex)
+        }
+        VectorFunc::LabelJoin => {
+            let dst = string_arg(args, 1, "label_join")?;
+            let sep = string_arg(args, 2, "label_join")?;
+            let srcs = (3..args.len())
+                .map(|i| string_arg(args, i, "label_join"))
+                .collect::<Result<Vec<_>, _>>()?;
+            label_join(inner, dst, sep, &srcs)
+        }
+        VectorFunc::Sort => Ok(sort_by_value(inner, false)),
+        VectorFunc::SortDesc => Ok(sort_by_value(inner, true)),
+        VectorFunc::SortByLabel | VectorFunc::SortByLabelDesc => {
+            let labels = (1..args.len())
+                .map(|i| string_arg(args, i, "sort_by_label"))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(sort_by_label(inner, &labels, func == VectorFunc::SortByLabelDesc))
+        }
+        VectorFunc::Absent => {
+            let labels = args.first().map(|a| absent_labels(a)).unwrap_or_default();
+            Ok(absent(&inner, labels, step_timestamps))
+        }
+        // A scalar argument is already a label-less series.
+        VectorFunc::Vector => Ok(inner),
+        VectorFunc::Scalar => Ok(scalar(&inner, step_timestamps)),
+        VectorFunc::Time => Ok(time(step_timestamps)),
+        VectorFunc::Minute
+        | VectorFunc::Hour
+        | VectorFunc::DayOfWeek
+        | VectorFunc::DayOfMonth
+        | VectorFunc::DayOfYear
+        | VectorFunc::DaysInMonth
+        | VectorFunc::Month
+        | VectorFunc::Year => {
+            let inner = if vector_arg_index(func, args).is_some() { inner } else { time(step_timestamps) };
+            Ok(date_part(inner, func))
+        }
+        VectorFunc::HistogramFraction => {
+            let lower = number_arg(args, 0, "histogram_fraction")?;
+            let upper = number_arg(args, 1, "histogram_fraction")?;
+            Ok(histogram_fraction(lower, upper, &inner, step_timestamps))
+        }
+        VectorFunc::HistogramCount | VectorFunc::HistogramSum | VectorFunc::HistogramAvg => {
+            Err("histogram_count/histogram_sum/histogram_avg are evaluated from _count/_sum series".to_string())
+        }
+    }
+}
+
+fn string_arg<'a>(args: &'a [Box<Expr>], idx: usize, func_name: &str) -> Result<&'a str, String> {
+    match args.get(idx).map(|a| a.as_ref()) {
+        Some(Expr::StringLiteral(s)) => Ok(s.val.as_str()),
+        Some(Expr::Paren(p)) => string_arg(std::slice::from_ref(&p.expr), 0, func_name),
+        _ => Err(format!("{func_name}: argument {} must be a string literal", idx + 1)),
+    }
+}
+
+fn number_arg(args: &[Box<Expr>], idx: usize, func_name: &str) -> Result<f64, String> {
+    fn literal(expr: &Expr) -> Option<f64> {
+        match expr {
+            Expr::NumberLiteral(n) => Some(n.val),
+            Expr::Unary(u) => literal(&u.expr).map(|v| -v),
+            Expr::Paren(p) => literal(&p.expr),
+            _ => None,
+        }
+    }
+    args.get(idx)
+        .and_then(|a| literal(a))
+        .ok_or_else(|| format!("{func_name}: argument {} must be a number literal", idx + 1))
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Label manipulation
+// ═══════════════════════════════════════════════════════════════════
+
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Relabelling can collapse several series onto one label set, e.g. an
+/// instance whose port changed mid-range. Like Prometheus, which checks per
+/// step, merge them into one series and only reject two samples landing on
+/// the same timestamp.
+fn merge_same_labelsets(series: Vec<TimeSeries>, func_name: &str) -> Result<Vec<TimeSeries>, String> {
+    let mut index: BTreeMap<BTreeMap<String, String>, usize> = BTreeMap::new();
+    let mut out: Vec<TimeSeries> = Vec::with_capacity(series.len());
+    for ts in series {
+        match index.get(&ts.labels) {
+            Some(&i) => out[i].samples.extend(ts.samples),
+            None => {
+                index.insert(ts.labels.clone(), out.len());
+                out.push(ts);
+            }
+        }
+    }
+    for ts in &mut out {
+        ts.samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if ts.samples.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(format!("{func_name}: vector cannot contain metrics with the same labelset"));
+        }
+    }
+    Ok(out)
+}
+
+fn set_or_remove(labels: &mut BTreeMap<String, String>, name: &str, value: String) {
+    if value.is_empty() {
+        labels.remove(name);
+    } else {
+        labels.insert(name.to_string(), value);
+    }
+}
+
+/// `label_replace(v, dst, replacement, src, regex)`: when the fully-anchored
+/// `regex` matches the `src` value (missing labels read as ""), set `dst` to
+/// `replacement` with `$1` / `${name}` expanded. An empty result removes `dst`.
+pub fn label_replace(
+    series: Vec<TimeSeries>,
+    dst: &str,
+    replacement: &str,
+    src: &str,
+    regex: &str,
+) -> Result<Vec<TimeSeries>, String> {
+    let re = regex::Regex::new(&format!("^(?s:{regex})$"))
+        .map_err(|e| format!("label_replace: invalid regular expression {regex:?}: {e}"))?;
+    if !is_valid_label_name(dst) {
+        return Err(format!("label_replace: invalid destination label name {dst:?}"));
+    }
+    let out: Vec<TimeSeries> = series
+        .into_iter()
+        .map(|mut ts| {
+            let value = ts.labels.get(src).map(String::as_str).unwrap_or("");
+            if let Some(caps) = re.captures(value) {
+                let mut expanded = String::new();
+                caps.expand(replacement, &mut expanded);
+                set_or_remove(&mut ts.labels, dst, expanded);
+            }
+            ts
+        })
+        .collect();
+    merge_same_labelsets(out, "label_replace")
+}
+
+/// `label_join(v, dst, separator, src...)`: join the `src` values (missing
+/// labels read as "") with `separator` into `dst`. An empty result removes `dst`.
+pub fn label_join(
+    series: Vec<TimeSeries>,
+    dst: &str,
+    separator: &str,
+    srcs: &[&str],
+) -> Result<Vec<TimeSeries>, String> {
+    if !is_valid_label_name(dst) {
+        return Err(format!("label_join: invalid destination label name {dst:?}"));
+    }
+    let out: Vec<TimeSeries> = series
+        .into_iter()
+        .map(|mut ts| {
+            let joined = srcs
+                .iter()
+                .map(|s| ts.labels.get(*s).map(String::as_str).unwrap_or(""))
+                .collect::<Vec<_>>()
+                .join(separator);
+            set_or_remove(&mut ts.labels, dst, joined);
+            ts
+        })
+        .collect();
+    merge_same_labelsets(out, "label_join")
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Ordering
+// ═══════════════════════════════════════════════════════════════════
+
+/// `sort` / `sort_desc` by each series' latest value. NaN sorts last in both
+/// directions, as in Prometheus. Ordering only matters for instant queries.
+pub fn sort_by_value(mut series: Vec<TimeSeries>, desc: bool) -> Vec<TimeSeries> {
+    let latest = |ts: &TimeSeries| ts.samples.last().map(|(_, v)| *v).unwrap_or(f64::NAN);
+    series.sort_by(|a, b| {
+        let (va, vb) = (latest(a), latest(b));
+        match (va.is_nan(), vb.is_nan()) {
+            (true, true) => std::cmp::Ordering::Equal,
+            (true, false) => std::cmp::Ordering::Greater,
+            (false, true) => std::cmp::Ordering::Less,
+            _ if desc => vb.total_cmp(&va),
+            _ => va.total_cmp(&vb),
+        }
+    });
+    series
+}
+
+/// `sort_by_label` / `sort_by_label_desc`: natural order on the given label
+/// values, ties broken by the full label set.
+pub fn sort_by_label(mut series: Vec<TimeSeries>, labels: &[&str], desc: bool) -> Vec<TimeSeries> {
+    series.sort_by(|a, b| {
+        let ord = labels
+            .iter()
+            .map(|l| {
+                natural_cmp(
+                    a.labels.get(*l).map(String::as_str).unwrap_or(""),
+                    b.labels.get(*l).map(String::as_str).unwrap_or(""),
+                )
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.labels.cmp(&b.labels));
+        if desc { ord.reverse() } else { ord }
+    });
+    series
+}
+
+/// Compare strings treating runs of ASCII digits as numbers, so `host10`
+/// sorts after `host9`.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    fn chunks(s: &str) -> Vec<&str> {
+        let mut out = Vec::new();
+        let mut start = 0;
+        let bytes = s.as_bytes();
+        for i in 1..=bytes.len() {
+            if i == bytes.len() || bytes[i].is_ascii_digit() != bytes[i - 1].is_ascii_digit() {
+                out.push(&s[start..i]);
+                start = i;
+            }
+        }
+        out
+    }
+    let (ca, cb) = (chunks(a), chunks(b));
+    for (x, y) in ca.iter().zip(&cb) {
+        let both_numeric = x.as_bytes()[0].is_ascii_digit() && y.as_bytes()[0].is_ascii_digit();
+        let ord = if both_numeric {
+            let (xt, yt) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            xt.len().cmp(&yt.len()).then_with(|| xt.cmp(yt)).then_with(|| x.len().cmp(&y.len()))
+        } else {
+            x.cmp(y)
+        };
+        if ord.is_ne() {
+            return ord;
+        }
+    }
+    ca.len().cmp(&cb.len())
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Presence / type conversion
+// ═══════════════════════════════════════════════════════════════════
+
+fn half_step(step_timestamps: &[f64]) -> f64 {
+    if step_timestamps.len() >= 2 {
+        (step_timestamps[1] - step_timestamps[0]) / 2.0
+    } else {
+        5.0
+    }
+}
+
+/// Latest sample of `ts` within half a step of `t` — the same snapping
+/// `binary` and `aggregate` use.
+fn value_at(ts: &TimeSeries, t: f64, half_step: f64) -> Option<f64> {
+    ts.samples
+        .iter()
+        .rev()
+        .find(|(st, _)| *st <= t + half_step && *st >= t - half_step)
+        .map(|(_, v)| *v)
+}
+
+/// Labels `absent()` reports: the equality matchers of a plain selector
+/// argument, minus `__name__` and any label matched more than once.
+fn absent_labels(arg: &Expr) -> BTreeMap<String, String> {
+    let vs = match arg {
+        Expr::VectorSelector(vs) => vs,
+        Expr::MatrixSelector(ms) => &ms.vs,
+        Expr::Paren(p) => return absent_labels(&p.expr),
+        _ => return BTreeMap::new(),
+    };
+    // Mirrors Prometheus: a label matched by anything but a single equality
+    // matcher is dropped, e.g. `x{job="a",job="b"}` reports no `job`.
+    let mut labels = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for m in &vs.matchers.matchers {
+        if m.name == "__name__" {
+            continue;
+        }
+        if m.op == MatchOp::Equal && seen.insert(m.name.clone()) {
+            labels.insert(m.name.clone(), m.value.clone());
+        } else {
+            labels.remove(&m.name);
+        }
+    }
+    labels
+}
+
+/// `absent(v)`: 1 at every step where `v` has no sample, nothing elsewhere.
+pub fn absent(series: &[TimeSeries], labels: BTreeMap<String, String>, step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    let hs = half_step(step_timestamps);
+    let samples: Vec<(f64, f64)> = step_timestamps
+        .iter()
+        .filter(|&&t| series.iter().all(|ts| value_at(ts, t, hs).is_none()))
+        .map(|&t| (t, 1.0))
+        .collect();
+    if samples.is_empty() {
+        return Vec::new();
+    }
+    vec![TimeSeries { labels, samples }]
+}
+
+/// `scalar(v)`: the single element's value at each step, NaN when `v` has
+/// zero or several elements there.
+pub fn scalar(series: &[TimeSeries], step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    let hs = half_step(step_timestamps);
+    let samples = step_timestamps
+        .iter()
+        .map(|&t| {
+            let mut values = series.iter().filter_map(|ts| value_at(ts, t, hs));
+            match (values.next(), values.next()) {
+                (Some(v), None) => (t, v),
+                _ => (t, f64::NAN),
+            }
+        })
+        .collect();
+    vec![TimeSeries { labels: BTreeMap::new(), samples }]
+}
+
+/// `time()`: evaluation time in seconds at each step.
+pub fn time(step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    vec![TimeSeries {
+        labels: BTreeMap::new(),
+        samples: step_timestamps.iter().map(|&t| (t, t)).collect(),
+    }]
+}
+
+fn drop_metric_name(mut series: Vec<TimeSeries>) -> Vec<TimeSeries> {
+    for ts in &mut series {
+        ts.labels.remove("__name__");
+    }
+    series
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Date parts
+// ═══════════════════════════════════════════════════════════════════
+
+/// `minute()`, `hour()`, `day_of_week()` (Sunday = 0), `day_of_month()`,
+/// `day_of_year()`, `days_in_month()`, `month()` and `year()` of each value read
+/// as a Unix timestamp in UTC. Drops the metric name.
+pub fn date_part(series: Vec<TimeSeries>, func: VectorFunc) -> Vec<TimeSeries> {
+    let part = |v: f64| -> Option<f64> {
+        if !v.is_finite() {
+            return None;
+        }
+        let dt = DateTime::from_timestamp(v as i64, 0)?;
+        Some(match func {
+            VectorFunc::Minute => dt.minute() as f64,
+            VectorFunc::Hour => dt.hour() as f64,
+            VectorFunc::DayOfWeek => dt.weekday().num_days_from_sunday() as f64,
+            VectorFunc::DayOfMonth => dt.day() as f64,
+            VectorFunc::DayOfYear => dt.ordinal() as f64,
+            VectorFunc::DaysInMonth => days_in_month(dt.year(), dt.month())? as f64,
+            VectorFunc::Month => dt.month() as f64,
+            VectorFunc::Year => dt.year() as f64,
+            _ => return None,
+        })
+    };
+    drop_metric_name(series)
+        .into_iter()
+        .map(|mut ts| {
+            ts.samples = ts.samples.into_iter().filter_map(|(t, v)| part(v).map(|p| (t, p))).collect();
+            ts
+        })
+        .collect()
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<i64> {
+    let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)?
+    };
+    Some((next - first).num_days())
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Classic histograms
+// ═══════════════════════════════════════════════════════════════════
+
+/// Group `le`-labelled bucket series by their remaining labels (minus the metric
+/// name) into `(le, series)` lists, sorted by bound.
+fn bucket_groups(series: &[TimeSeries]) -> BTreeMap<BTreeMap<String, String>, Vec<(f64, &TimeSeries)>> {
+    let mut groups: BTreeMap<BTreeMap<String, String>, Vec<(f64, &TimeSeries)>> = BTreeMap::new();
+    for ts in series {
+        let mut labels = ts.labels.clone();
+        let Some(le) = labels.remove("le") else { continue };
+        let Ok(le) = le.parse::<f64>() else { continue };
+        labels.remove("__name__");
+        groups.entry(labels).or_default().push((le, ts));
+    }
+    for buckets in groups.values_mut() {
+        buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    groups
+}
+
+/// Estimated number of observations `<= x` from cumulative `(le, count)` buckets,
+/// interpolating linearly inside a bucket. The lowest bucket starts at 0 when its
+/// bound is positive (the same assumption `histogram_quantile` makes); nothing
+/// can be interpolated inside the `+Inf` bucket.
+fn cumulative_at(buckets: &[(f64, f64)], x: f64) -> f64 {
+    let mut prev_le = 0.0_f64;
+    let mut prev_count = 0.0_f64;
+    for (i, &(le, count)) in buckets.iter().enumerate() {
+        if i == 0 && le <= 0.0 {
+            prev_le = le;
+        }
+        if x < le {
+            if le.is_infinite() || x <= prev_le {
+                return prev_count;
+            }
+            return prev_count + (count - prev_count) * (x - prev_le) / (le - prev_le);
+        }
+        prev_le = le;
+        prev_count = count;
+    }
+    prev_count
+}
+
+/// `histogram_fraction(lower, upper, buckets)` over classic `le` buckets: the
+/// estimated share of observations in `[lower, upper]` at each step.
+pub fn histogram_fraction(lower: f64, upper: f64, series: &[TimeSeries], step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    let hs = half_step(step_timestamps);
+    bucket_groups(series)
+        .into_iter()
+        .filter_map(|(labels, buckets)| {
+            let samples: Vec<(f64, f64)> = step_timestamps
+                .iter()
+                .filter_map(|&t| {
+                    let counts: Vec<(f64, f64)> =
+                        buckets.iter().filter_map(|(le, ts)| value_at(ts, t, hs).map(|v| (*le, v))).collect();
+                    let total = counts.last().filter(|(le, _)| le.is_infinite())?.1;
+                    if total == 0.0 {
+                        return Some((t, f64::NAN));
+                    }
+                    let fraction = (cumulative_at(&counts, upper) - cumulative_at(&counts, lower)) / total;
+                    Some((t, fraction.max(0.0)))
+                })
+                .collect();
+            (!samples.is_empty()).then_some(TimeSeries { labels, samples })
+        })
+        .collect()
+}
+
+/// Rename the classic histogram `_bucket` selectors in `expr` to `suffix`
+/// (`_count` / `_sum`), so `histogram_count(rate(x_bucket[5m]))` reads
+/// `rate(x_count[5m])`. Returns None when no `_bucket` selector is present.
+pub fn rename_bucket_selectors(expr: &Expr, suffix: &str) -> Option<Expr> {
+    fn rename(expr: &mut Expr, suffix: &str) -> bool {
+        let vs = match expr {
+            Expr::VectorSelector(vs) => vs,
+            Expr::MatrixSelector(ms) => &mut ms.vs,
+            Expr::Call(call) => {
+                return call.args.args.iter_mut().fold(false, |found, a| rename(a, suffix) | found);
+            }
+            Expr::Aggregate(agg) => return rename(&mut agg.expr, suffix),
+            Expr::Binary(bin) => return rename(&mut bin.lhs, suffix) | rename(&mut bin.rhs, suffix),
+            Expr::Unary(u) => return rename(&mut u.expr, suffix),
+            Expr::Paren(p) => return rename(&mut p.expr, suffix),
+            Expr::Subquery(sq) => return rename(&mut sq.expr, suffix),
+            _ => return false,
+        };
+        let mut found = false;
+        if let Some(name) = &mut vs.name
+            && let Some(base) = name.strip_suffix("_bucket")
+        {
+            *name = format!("{base}{suffix}");
+            found = true;
+        }
+        for m in &mut vs.matchers.matchers {
+            if m.name == "__name__" && m.op == MatchOp::Equal && let Some(base) = m.value.strip_suffix("_bucket") {
+                m.value = format!("{base}{suffix}");
+                found = true;
+            }
+        }
+        // `le` only exists on the bucket series.
+        vs.matchers.matchers.retain(|m| m.name != "le");
+        found
+    }
+    let mut renamed = expr.clone();
+    rename(&mut renamed, suffix).then_some(renamed)
+}
+
+/// `histogram_count` / `histogram_sum`: the `_count` / `_sum` series without
+/// their metric name.
+pub fn histogram_component(series: Vec<TimeSeries>) -> Vec<TimeSeries> {
+    drop_metric_name(series)
+}
+
+/// `histogram_avg`: `_sum / _count` per label set and step.
+pub fn histogram_avg(sums: Vec<TimeSeries>, counts: Vec<TimeSeries>, step_timestamps: &[f64]) -> Vec<TimeSeries> {
+    let hs = half_step(step_timestamps);
+    let counts: BTreeMap<BTreeMap<String, String>, TimeSeries> =
+        drop_metric_name(counts).into_iter().map(|ts| (ts.labels.clone(), ts)).collect();
+    drop_metric_name(sums)
+        .into_iter()
+        .filter_map(|ts| {
+            let count = counts.get(&ts.labels)?;
+            let samples: Vec<(f64, f64)> = step_timestamps
+                .iter()
+                .filter_map(|&t| Some((t, value_at(&ts, t, hs)? / value_at(count, t, hs)?)))
+                .collect();
+            (!samples.is_empty()).then_some(TimeSeries { labels: ts.labels, samples })
+        })
+        .collect()
+}
+
+// ═══════════════════════════════════════════════════════════════════
+// Tests
+// ═══════════════════════════════════════════════════════════════════
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::promql::aggregate;
+    use crate::promql::translate;
+    use promql_parser::parser;
+
+    const EVAL_TIME: f64 = 3000.0;
+
+    /// Parse a Prometheus test-file series line (`metric{a="b"}` or `{}`) into a
+    /// label set.
+    fn labels_of(series: &str) -> BTreeMap<String, String> {
+        if series == "{}" {
+            return BTreeMap::new();
+        }
+        let vs = match parser::parse(series).unwrap() {
+            Expr::VectorSelector(vs) => vs,
+            other => panic!("not a series: {other:?}"),
+        };
+        let mut labels: BTreeMap<String, String> =
+            vs.matchers.matchers.iter().map(|m| (m.name.clone(), m.value.clone())).collect();
+        if let Some(name) = vs.name {
+            labels.insert("__name__".to_string(), name);
+        }
+        labels
+    }
+
+    fn load(fixture: &[(&str, f64)]) -> Vec<TimeSeries> {
+        fixture
+            .iter()
+            .map(|(s, v)| {
+                let (s, at) = s.split_once(" @ ").map_or((*s, EVAL_TIME), |(s, t)| (s, t.parse().unwrap()));
+                TimeSeries { labels: labels_of(s), samples: vec![(at, *v)] }
+            })
+            .collect()
+    }
+
+    /// Minimal in-memory evaluator for the conformance table: selectors read the
+    /// fixture, literals become scalars, and calls go through the same dispatch
+    /// the real evaluator uses.
+    fn eval(expr: &Expr, data: &[TimeSeries]) -> Vec<TimeSeries> {
+        let steps = [EVAL_TIME];
+        match expr {
+            Expr::VectorSelector(vs) => data
+                .iter()
+                .filter(|ts| {
+                    vs.name.as_ref().is_none_or(|n| ts.labels.get("__name__") == Some(n))
+                        && vs.matchers.matchers.iter().all(|m| {
+                            m.is_match(ts.labels.get(&m.name).map(String::as_str).unwrap_or(""))
+                        })
+                })
+                .cloned()
+                .collect(),
+            Expr::NumberLiteral(n) => vec![TimeSeries { labels: BTreeMap::new(), samples: vec![(EVAL_TIME, n.val)] }],
+            Expr::Paren(p) => eval(&p.expr, data),
+            Expr::Aggregate(agg) => {
+                let op = translate::to_agg_op(agg.op).unwrap();
+                let (by, without) = translate::extract_label_modifier(&agg.modifier);
+                aggregate::aggregate_series(eval(&agg.expr, data), op, &by, without, &steps, None)
+            }
+            Expr::Call(call) => {
+                let func = translate::to_vector_func(call.func.name).unwrap();
+                let args = &call.args.args;
+                let inner = vector_arg_index(func, args).map(|i| eval(&args[i], data)).unwrap_or_default();
+                apply_vector_func(func, args, inner, &steps).unwrap()
+            }
+            other => panic!("unsupported in conformance eval: {other:?}"),
+        }
+    }
+
+    fn render(series: &[TimeSeries]) -> Vec<(BTreeMap<String, String>, String)> {
+        series
+            .iter()
+            .filter_map(|ts| ts.samples.last().map(|(_, v)| (ts.labels.clone(), format!("{v}"))))
+            .collect()
+    }
+
+    /// Cases follow Prometheus' `promql/promqltest/testdata/functions.test`: same
+    /// fixtures, queries and expected series. `ordered` cases also compare output
+    /// order, like upstream `eval_ordered`. Fixture samples sit at `EVAL_TIME`
+    /// unless the series ends in ` @ <secs>`.
+    struct Case {
+        query: &'static str,
+        load: &'static [(&'static str, f64)],
+        want: &'static [(&'static str, f64)],
+        ordered: bool,
+    }
+
+    const LABEL_REPLACE_LOAD: &[(&str, f64)] = &[
+        (r#"testmetric{src="source-value-10",dst="original-destination-value"}"#, 0.0),
+        (r#"testmetric{src="source-value-20",dst="original-destination-value"}"#, 1.0),
+    ];
+    const LABEL_JOIN_LOAD: &[(&str, f64)] = &[
+        (r#"testmetric{src="a",src1="b",src2="c",dst="original-destination-value"}"#, 0.0),
+        (r#"testmetric{src="d",src1="e",src2="f",dst="original-destination-value"}"#, 1.0),
+    ];
+    const SORT_LOAD: &[(&str, f64)] = &[
+        (r#"http_requests{job="api-server", instance="0", group="production"}"#, 100.0),
+        (r#"http_requests{job="api-server", instance="1", group="production"}"#, 200.0),
+        (r#"http_requests{job="api-server", instance="0", group="canary"}"#, 300.0),
+        (r#"http_requests{job="api-server", instance="1", group="canary"}"#, 400.0),
+        (r#"http_requests{job="app-server", instance="0", group="production"}"#, 500.0),
+        (r#"http_requests{job="app-server", instance="1", group="production"}"#, 600.0),
+        (r#"http_requests{job="app-server", instance="0", group="canary"}"#, 700.0),
+        (r#"http_requests{job="app-server", instance="1", group="canary"}"#, 800.0),
+        (r#"http_requests{job="api-server", instance="2", group="canary"}"#, f64::NAN),
+    ];
+    const SORT_BY_LABEL_LOAD: &[(&str, f64)] = &[
+        (r#"node_uname_info{job="node_exporter", instance="4m600", release="1.2.3"}"#, 0.0),
+        (r#"node_uname_info{job="node_exporter", instance="4m5", release="1.11.3"}"#, 0.0),
+        (r#"node_uname_info{job="node_exporter", instance="4m1000", release="1.111.3"}"#, 0.0),
+    ];
+
+    const CASES: &[Case] = &[
+        Case {
+            query: r#"label_replace(testmetric, "dst", "destination-value-$1", "src", "source-value-(.*)")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[
+                (r#"testmetric{src="source-value-10",dst="destination-value-10"}"#, 0.0),
+                (r#"testmetric{src="source-value-20",dst="destination-value-20"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        // Regex must match the whole value.
+        Case {
+            query: r#"label_replace(testmetric, "dst", "destination-value-$1", "src", "value-(.*)")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[
+                (r#"testmetric{src="source-value-10",dst="original-destination-value"}"#, 0.0),
+                (r#"testmetric{src="source-value-20",dst="original-destination-value"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        Case {
+            query: r#"label_replace(testmetric, "dst", "$1-value-$2 $3", "src", "(.*)-value-(.*)")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[
+                (r#"testmetric{src="source-value-10",dst="source-value-10 "}"#, 0.0),
+                (r#"testmetric{src="source-value-20",dst="source-value-20 "}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        // Missing source label reads as "".
+        Case {
+            query: r#"label_replace(testmetric, "dst", "value-$1", "nonexistent-src", "source-value-(.*)")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[
+                (r#"testmetric{src="source-value-10",dst="original-destination-value"}"#, 0.0),
+                (r#"testmetric{src="source-value-20",dst="original-destination-value"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        Case {
+            query: r#"label_replace(testmetric, "dst", "value-$1", "nonexistent-src", "(.*)")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[
+                (r#"testmetric{src="source-value-10",dst="value-"}"#, 0.0),
+                (r#"testmetric{src="source-value-20",dst="value-"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        // An empty replacement removes the destination label.
+        Case {
+            query: r#"label_replace(testmetric, "dst", "", "dst", ".*")"#,
+            load: LABEL_REPLACE_LOAD,
+            want: &[(r#"testmetric{src="source-value-10"}"#, 0.0), (r#"testmetric{src="source-value-20"}"#, 1.0)],
+            ordered: false,
+        },
+        // The instance's port changed: both series collapse onto one label set,
+        // which is fine as long as they never report at the same time.
+        Case {
+            query: r#"label_replace(up, "instance", "$1", "instance", "(.*):.*")"#,
+            load: &[(r#"up{instance="host:9100"} @ 2940"#, 1.0), (r#"up{instance="host:9200"}"#, 0.0)],
+            want: &[(r#"up{instance="host"}"#, 0.0)],
+            ordered: false,
+        },
+        Case {
+            query: r#"label_join(testmetric, "dst", "-", "src", "src1", "src2")"#,
+            load: LABEL_JOIN_LOAD,
+            want: &[
+                (r#"testmetric{src="a",src1="b",src2="c",dst="a-b-c"}"#, 0.0),
+                (r#"testmetric{src="d",src1="e",src2="f",dst="d-e-f"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        Case {
+            query: r#"label_join(testmetric, "dst", "", "emptysrc", "emptysrc1", "emptysrc2")"#,
+            load: LABEL_JOIN_LOAD,
+            want: &[
+                (r#"testmetric{src="a",src1="b",src2="c"}"#, 0.0),
+                (r#"testmetric{src="d",src1="e",src2="f"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        Case {
+            query: r#"label_join(testmetric, "dst", ",", "src", "src", "src")"#,
+            load: LABEL_JOIN_LOAD,
+            want: &[
+                (r#"testmetric{src="a",src1="b",src2="c",dst="a,a,a"}"#, 0.0),
+                (r#"testmetric{src="d",src1="e",src2="f",dst="d,d,d"}"#, 1.0),
+            ],
+            ordered: false,
+        },
+        Case {
+            query: "sort(http_requests)",
+            load: SORT_LOAD,
+            want: &[
+                (r#"http_requests{group="production", instance="0", job="api-server"}"#, 100.0),
+                (r#"http_requests{group="production", instance="1", job="api-server"}"#, 200.0),
+                (r#"http_requests{group="canary", instance="0", job="api-server"}"#, 300.0),
+                (r#"http_requests{group="canary", instance="1", job="api-server"}"#, 400.0),
+                (r#"http_requests{group="production", instance="0", job="app-server"}"#, 500.0),
+                (r#"http_requests{group="production", instance="1", job="app-server"}"#, 600.0),
+                (r#"http_requests{group="canary", instance="0", job="app-server"}"#, 700.0),
+                (r#"http_requests{group="canary", instance="1", job="app-server"}"#, 800.0),
+                (r#"http_requests{group="canary", instance="2", job="api-server"}"#, f64::NAN),
+            ],
+            ordered: true,
+        },
+        Case {
+            query: "sort_desc(http_requests)",
+            load: SORT_LOAD,
+            want: &[
+                (r#"http_requests{group="canary", instance="1", job="app-server"}"#, 800.0),
+                (r#"http_requests{group="canary", instance="0", job="app-server"}"#, 700.0),
+                (r#"http_requests{group="production", instance="1", job="app-server"}"#, 600.0),
+                (r#"http_requests{group="production", instance="0", job="app-server"}"#, 500.0),
+                (r#"http_requests{group="canary", instance="1", job="api-server"}"#, 400.0),
+                (r#"http_requests{group="canary", instance="0", job="api-server"}"#, 300.0),
+                (r#"http_requests{group="production", instance="1", job="api-server"}"#, 200.0),
+                (r#"http_requests{group="production", instance="0", job="api-server"}"#, 100.0),
+                (r#"http_requests{group="canary", instance="2", job="api-server"}"#, f64::NAN),
+            ],
+            ordered: true,
+        },
+        Case {
+            query: r#"sort_by_label(http_requests, "instance")"#,
+            load: SORT_LOAD,
+            want: &[
+                (r#"http_requests{group="canary", instance="0", job="api-server"}"#, 300.0),
+                (r#"http_requests{group="canary", instance="0", job="app-server"}"#, 700.0),
+                (r#"http_requests{group="production", instance="0", job="api-server"}"#, 100.0),
+                (r#"http_requests{group="production", instance="0", job="app-server"}"#, 500.0),
+                (r#"http_requests{group="canary", instance="1", job="api-server"}"#, 400.0),
+                (r#"http_requests{group="canary", instance="1", job="app-server"}"#, 800.0),
+                (r#"http_requests{group="production", instance="1", job="api-server"}"#, 200.0),
+                (r#"http_requests{group="production", instance="1", job="app-server"}"#, 600.0),
+                (r#"http_requests{group="canary", instance="2", job="api-server"}"#, f64::NAN),
+            ],
+            ordered: true,
+        },
+        Case {
+            query: r#"sort_by_label_desc(http_requests, "instance", "group")"#,
+            load: SORT_LOAD,
+            want: &[
+                (r#"http_requests{group="canary", instance="2", job="api-server"}"#, f64::NAN),
+                (r#"http_requests{group="production", instance="1", job="app-server"}"#, 600.0),
+                (r#"http_requests{group="production", instance="1", job="api-server"}"#, 200.0),
+                (r#"http_requests{group="canary", instance="1", job="app-server"}"#, 800.0),
+                (r#"http_requests{group="canary", instance="1", job="api-server"}"#, 400.0),
+                (r#"http_requests{group="production", instance="0", job="app-server"}"#, 500.0),
+                (r#"http_requests{group="production", instance="0", job="api-server"}"#, 100.0),
+                (r#"http_requests{group="canary", instance="0", job="app-server"}"#, 700.0),
+                (r#"http_requests{group="canary", instance="0", job="api-server"}"#, 300.0),
+            ],
+            ordered: true,
+        },
+        // Natural sort: 4m5 < 4m600 < 4m1000.
+        Case {
+            query: r#"sort_by_label(node_uname_info, "instance")"#,
+            load: SORT_BY_LABEL_LOAD,
+            want: &[
+                (r#"node_uname_info{job="node_exporter", instance="4m5", release="1.11.3"}"#, 0.0),
+                (r#"node_uname_info{job="node_exporter", instance="4m600", release="1.2.3"}"#, 0.0),
+                (r#"node_uname_info{job="node_exporter", instance="4m1000", release="1.111.3"}"#, 0.0),
+            ],
+            ordered: true,
+        },
+        Case {
+            query: r#"sort_by_label(node_uname_info, "release")"#,
+            load: SORT_BY_LABEL_LOAD,
+            want: &[
+                (r#"node_uname_info{job="node_exporter", instance="4m600", release="1.2.3"}"#, 0.0),
+                (r#"node_uname_info{job="node_exporter", instance="4m5", release="1.11.3"}"#, 0.0),
+                (r#"node_uname_info{job="node_exporter", instance="4m1000", release="1.111.3"}"#, 0.0),
+            ],
+            ordered: true,
+        },
+        Case { query: "absent(nonexistent)", load: SORT_LOAD, want: &[("{}", 1.0)], ordered: false },
+        Case {
+            query: r#"absent(nonexistent{job="testjob", instance="testinstance", method=~".x"})"#,
+            load: SORT_LOAD,
+            want: &[(r#"{instance="testinstance", job="testjob"}"#, 1.0)],
+            ordered: false,
+        },
+        Case {
+            query: r#"absent(nonexistent{job="testjob",job="testjob2",foo="bar"})"#,
+            load: SORT_LOAD,
+            want: &[(r#"{foo="bar"}"#, 1.0)],
+            ordered: false,
+        },
+        Case {
+            query: r#"absent(nonexistent{job="testjob",job="testjob2",job="three",foo="bar"})"#,
+            load: SORT_LOAD,
+            want: &[(r#"{foo="bar"}"#, 1.0)],
+            ordered: false,
+        },
+        Case {
+            query: r#"absent(nonexistent{job="testjob",job=~"testjob2",foo="bar"})"#,
+            load: SORT_LOAD,
+            want: &[(r#"{foo="bar"}"#, 1.0)],
+            ordered: false,
+        },
+        Case { query: "absent(http_requests)", load: SORT_LOAD, want: &[], ordered: false },
+        Case {
+            query: r#"absent(sum(nonexistent{job="testjob", instance="testinstance"}))"#,
+            load: SORT_LOAD,
+            want: &[("{}", 1.0)],
+            ordered: false,
+        },
+        Case { query: "vector(1)", load: &[], want: &[("{}", 1.0)], ordered: false },
+        Case { query: "scalar(vector(1))", load: &[], want: &[("{}", 1.0)], ordered: false },
+        Case {
+            query: r#"scalar(http_requests{job="api-server"})"#,
+            load: SORT_LOAD,
+            want: &[("{}", f64::NAN)],
+            ordered: false,
+        },
+        Case { query: "time()", load: &[], want: &[("{}", EVAL_TIME)], ordered: false },
+        Case { query: "minute()", load: &[], want: &[("{}", 50.0)], ordered: false },
+        Case { query: "year()", load: &[], want: &[("{}", 1970.0)], ordered: false },
+        // 2006-01-02 22:04:05 UTC, a Monday.
+        Case { query: "minute(vector(1136239445))", load: &[], want: &[("{}", 4.0)], ordered: false },
+        Case { query: "hour(vector(1136239445))", load: &[], want: &[("{}", 22.0)], ordered: false },
+        Case { query: "day_of_week(vector(1136239445))", load: &[], want: &[("{}", 1.0)], ordered: false },
+        Case { query: "day_of_month(vector(1136239445))", load: &[], want: &[("{}", 2.0)], ordered: false },
+        Case { query: "day_of_year(vector(1136239445))", load: &[], want: &[("{}", 2.0)], ordered: false },
+        Case { query: "month(vector(1136239445))", load: &[], want: &[("{}", 1.0)], ordered: false },
+        Case { query: "year(vector(1136239445))", load: &[], want: &[("{}", 2006.0)], ordered: false },
+        // 2016-02-01 and 2016-12-31 00:00:00 UTC (leap year).
+        Case { query: "days_in_month(vector(1454284800))", load: &[], want: &[("{}", 29.0)], ordered: false },
+        Case { query: "day_of_year(vector(1483142400))", load: &[], want: &[("{}", 366.0)], ordered: false },
+        Case { query: "days_in_month(vector(1485907200))", load: &[], want: &[("{}", 28.0)], ordered: false },
+        Case {
+            query: r#"hour(http_requests{job="api-server", instance="0", group="production"})"#,
+            load: SORT_LOAD,
+            want: &[(r#"{group="production", instance="0", job="api-server"}"#, 0.0)],
+            ordered: false,
+        },
+    ];
+
+    #[test]
+    fn prometheus_conformance() {
+        for case in CASES {
+            let expr = parser::parse(case.query).unwrap_or_else(|e| panic!("{}: {e}", case.query));
+            let mut got = render(&eval(&expr, &load(case.load)));
+            let mut want: Vec<_> = case.want.iter().map(|(s, v)| (labels_of(s), format!("{v}"))).collect();
+            if !case.ordered {
+                got.sort();
+                want.sort();
+            }
+            assert_eq!(got, want, "{}", case.query);
+        }
+    }
+
+    #[test]
+    fn label_replace_rejects_bad_input() {
+        let series = load(LABEL_REPLACE_LOAD);
+        let err = label_replace(series.clone(), "dst", "", "src", "(.*").unwrap_err();
+        assert!(err.contains("invalid regular expression"), "{err}");
+        let err = label_replace(series.clone(), "\u{ff}invalid", "", "src", "(.*)").unwrap_err();
+        assert!(err.contains("invalid destination label name"), "{err}");
+        // Both series collapse onto testmetric{dst="original-destination-value"}.
+        let err = label_replace(series, "src", "", "", "").unwrap_err();
+        assert!(err.contains("same labelset"), "{err}");
+    }
+
+    #[test]
+    fn relabelled_series_merge_across_steps() {
+        let series = vec![
+            TimeSeries { labels: labels_of(r#"up{instance="host:9100"}"#), samples: vec![(10.0, 1.0), (20.0, 1.0)] },
+            TimeSeries { labels: labels_of(r#"up{instance="host:9200"}"#), samples: vec![(30.0, 0.0)] },
+        ];
+        let got = label_replace(series.clone(), "instance", "$1", "instance", "(.*):.*").unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].samples, vec![(10.0, 1.0), (20.0, 1.0), (30.0, 0.0)]);
+
+        let mut overlapping = series;
+        overlapping[1].samples.push((20.0, 0.0));
+        let err = label_replace(overlapping, "instance", "$1", "instance", "(.*):.*").unwrap_err();
+        assert!(err.contains("same labelset"), "{err}");
+    }
+
+    #[test]
+    fn absent_is_evaluated_per_step() {
+        let series = vec![TimeSeries { labels: BTreeMap::new(), samples: vec![(10.0, 1.0), (30.0, 1.0)] }];
+        let got = absent(&series, BTreeMap::new(), &[10.0, 20.0, 30.0, 40.0]);
+        assert_eq!(got[0].samples, vec![(20.0, 1.0), (40.0, 1.0)]);
+    }
+
+    fn buckets(values: &[(&str, f64)]) -> Vec<TimeSeries> {
+        values
+            .iter()
+            .map(|(le, v)| TimeSeries {
+                labels: [("__name__".to_string(), "x_bucket".to_string()), ("le".to_string(), le.to_string())].into(),
+                samples: vec![(EVAL_TIME, *v)],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn histogram_fraction_interpolates_classic_buckets() {
+        let series = buckets(&[("0.1", 10.0), ("0.5", 30.0), ("1", 40.0), ("+Inf", 40.0)]);
+        let at = |lo: f64, hi: f64| histogram_fraction(lo, hi, &series, &[EVAL_TIME])[0].samples[0].1;
+        assert_eq!(at(0.0, 0.1), 0.25);
+        assert_eq!(at(0.0, 0.3), 0.5);
+        assert_eq!(at(0.1, 0.5), 0.5);
+        assert_eq!(at(f64::NEG_INFINITY, f64::INFINITY), 1.0);
+        assert_eq!(at(0.5, 0.5), 0.0);
+        assert!(histogram_fraction(0.0, 1.0, &buckets(&[("1", 0.0), ("+Inf", 0.0)]), &[EVAL_TIME])[0].samples[0]
+            .1
+            .is_nan());
+        // Without a +Inf bucket there is no total to divide by.
+        assert!(histogram_fraction(0.0, 1.0, &buckets(&[("1", 5.0)]), &[EVAL_TIME]).is_empty());
+    }
+
+    #[test]
+    fn histogram_selectors_are_renamed_to_components() {
+        let expr = parser::parse(r#"sum by (le) (rate(http_duration_bucket{le="+Inf", job="api"}[5m]))"#).unwrap();
+        let renamed = rename_bucket_selectors(&expr, "_count").unwrap();
+        let renamed = renamed.to_string();
+        assert!(renamed.contains(r#"rate(http_duration_count{job="api"}[5m])"#), "{renamed}");
+        assert!(rename_bucket_selectors(&parser::parse("rate(http_duration[5m])").unwrap(), "_sum").is_none());
+    }
+
+    #[test]
+    fn histogram_avg_divides_sum_by_count() {
+        let series = |name: &str, v: f64| TimeSeries {
+            labels: [("__name__".to_string(), name.to_string()), ("job".to_string(), "api".to_string())].into(),
+            samples: vec![(EVAL_TIME, v)],
+        };
+        let got = histogram_avg(vec![series("x_sum", 12.0)], vec![series("x_count", 4.0)], &[EVAL_TIME]);
+        assert_eq!(render(&got), vec![([("job".to_string(), "api".to_string())].into(), "3".to_string())]);
+    }
+}
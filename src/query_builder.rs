@@ -493,6 +493,91 @@ fn log_term_match_sql(term: &str) -> String {
     format!("lower(Body) LIKE '%{inner}%'")
 }
 
+/// A parsed search string evaluated in memory (live tail) with the same
+/// term / `key=value` / AND / OR grouping as the SQL builders above. The
+/// caller decides what a single term or `key=value` pair matches.
+#[derive(Debug)]
+pub struct SearchMatcher(SearchExpr);
+
+impl SearchMatcher {
+    pub fn parse(search: &str) -> Option<Self> {
+        parse_search_expr(search).map(SearchMatcher)
+    }
+
+    pub fn matches(&self, term: &dyn Fn(&str) -> bool, kv: &dyn Fn(&str, &str) -> bool) -> bool {
+        fn eval(expr: &SearchExpr, term: &dyn Fn(&str) -> bool, kv: &dyn Fn(&str, &str) -> bool) -> bool {
+            match expr {
+                SearchExpr::Term(t) => term(t),
+                SearchExpr::KeyValue(k, v) => kv(k, v),
+                SearchExpr::And(exprs) => exprs.iter().all(|e| eval(e, term, kv)),
+                SearchExpr::Or(exprs) => exprs.iter().any(|e| eval(e, term, kv)),
+            }
+        }
+        eval(&self.0, term, kv)
+    }
+}
+
+/// Turn a free-text search term into the `LIKE` pattern the SQL path uses:
+/// lower-cased, `%`/`_` escaped, `*` as a wildcard, wrapped in `%…%`.
+pub fn search_term_pattern(term: &str) -> String {
+    let inner = term
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+        .replace('*', "%");
+    format!("%{inner}%")
+}
+
+/// In-memory ClickHouse `LIKE`: `%` matches any run, `_` one character and
+/// `\` escapes the next one. Case-sensitive, like the SQL operator.
+pub fn like_match(pattern: &str, text: &str) -> bool {
+    enum Tok {
+        Any,
+        One,
+        Lit(char),
+    }
+    let mut toks = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        toks.push(match c {
+            '%' => Tok::Any,
+            '_' => Tok::One,
+            '\\' => Tok::Lit(chars.next().unwrap_or('\\')),
+            c => Tok::Lit(c),
+        });
+    }
+    let text: Vec<char> = text.chars().collect();
+    // Greedy match with a single backtrack point at the last `%`.
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match toks.get(p) {
+            Some(Tok::Any) => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(Tok::One) => {
+                p += 1;
+                t += 1;
+            }
+            Some(Tok::Lit(c)) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    toks[p..].iter().all(|t| matches!(t, Tok::Any))
+}
+
 pub fn format_value(value: &serde_json::Value) -> String {
     match value {
         serde_json::Value::String(s) => {
@@ -646,24 +731,34 @@ const BUCKET_INTERVALS: &[(&str, u64)] = &[
     ("1d", 86400),
 ];
 
+/// Seconds in a whitelisted bucket interval token.
+pub(crate) fn bucket_interval_secs(interval: &str) -> Option<u64> {
+    BUCKET_INTERVALS.iter().find(|(tok, _)| *tok == interval).map(|(_, secs)| *secs)
+}
+
 /// Best-effort parse of the datetime formats accepted by the API (RFC3339, with or
 /// without an explicit offset, or a plain `YYYY-MM-DD HH:MM:SS`).
-fn parse_datetime_secs(s: &str) -> Option<i64> {
+pub(crate) fn parse_datetime(s: &str) -> Option<chrono::DateTime<chrono::Utc>> {
     let s = s.trim();
     if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
-        return Some(dt.timestamp());
+        return Some(dt.to_utc());
     }
     if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(&format!("{s}Z")) {
-        return Some(dt.timestamp());
+        return Some(dt.to_utc());
     }
     for fmt in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"] {
         if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(s, fmt) {
-            return Some(naive.and_utc().timestamp());
+            return Some(naive.and_utc());
         }
     }
     None
 }
 
+/// `parse_datetime` in whole seconds since the epoch.
+pub(crate) fn parse_datetime_secs(s: &str) -> Option<i64> {
+    parse_datetime(s).map(|dt| dt.timestamp())
+}
+
 /// Clamp a client-supplied bucket interval so the expected bucket count
 /// (time range / interval) stays <= `max_buckets`. The interval is untrusted:
 /// a `1s` interval over 30 days would otherwise produce ~2.6M GROUP BY buckets.
@@ -0,0 +1,612 @@
+//! Result cache for time-bucketed range queries.
+//!
+//! Dashboards re-run the same `count_query`, `timeseries_query` and PromQL
+//! `query_range` on every refresh while only the last few minutes of the
+//! answer change. `QueryCache::run` splits the requested range on a fixed grid
+//! of chunks (`chunk_secs`, aligned to the epoch and a multiple of the bucket
+//! interval / step, so no bucket straddles two chunks). Chunks that ended more
+//! than `freshness` ago are immutable and cached under
+//! (tenant, query key, chunk); everything else — the partial head, the fresh
+//! tail and any chunk not cached yet — is read from ClickHouse, with adjacent
+//! pieces merged into one query.
+//!
+//! Chunks to be cached are read with `result_overflow_mode=throw` (see
+//! `filling`): a read that hits the row cap, or that fails and is papered
+//! over by the caller (`note_failure`), is re-run normally and not cached, so
+//! a truncated answer is never kept.
+//!
+//! Entries are kept in memory under an LRU bounded by bytes, and optionally
+//! written through to an object store so other replicas and restarts start
+//! warm. Both expire after `ttl` to pick up very late data and retention
+//! deletes. Expired objects are deleted when a lookup finds them; chunks that
+//! are never asked for again stay until the bucket's lifecycle rule removes
+//! them, so give the prefix one (expire after a day or so). Bucket labels
+//! (`toString(toStartOf…(timestamp))`) are read as UTC, like the rest of
+//! `query_builder`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use object_store::ObjectStore;
+use object_store::path::Path as OsPath;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+use crate::config::env_or;
+use crate::promql::types::TimeSeries;
+
+tokio::task_local! {
+    /// Set while `QueryCache::run` reads chunks it means to cache; raised by
+    /// `note_failure`.
+    static FILL: Arc<std::sync::atomic::AtomicBool>;
+}
+
+/// Whether the current task is reading chunks for the cache. Its queries must
+/// fail at the row cap instead of truncating.
+pub fn filling() -> bool {
+    FILL.try_with(|_| ()).is_ok()
+}
+
+/// Record a failed read inside a cache fill whose caller carries on with
+/// partial data (the PromQL evaluator reads a failed selector as no data).
+/// The fill's chunks are then not cached.
+pub fn note_failure() {
+    let _ = FILL.try_with(|failed| failed.store(true, Ordering::Relaxed));
+}
+
+const DEFAULT_MAX_BYTES: usize = 256 * 1024 * 1024;
+const DEFAULT_FRESHNESS_SECS: i64 = 300;
+const DEFAULT_TTL_SECS: u64 = 6 * 3_600;
+
+/// A chunk holds at least this many buckets / steps…
+const BUCKETS_PER_CHUNK: i64 = 60;
+/// …and spans at least this long.
+const MIN_CHUNK_SECS: i64 = 3_600;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// Memory bound for cached chunks; 0 disables the cache.
+    pub max_bytes: usize,
+    /// Chunks ending less than this long ago are never cached (late data).
+    pub freshness_secs: i64,
+    /// How long a cached chunk is trusted.
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_bytes: DEFAULT_MAX_BYTES,
+            freshness_secs: DEFAULT_FRESHNESS_SECS,
+            ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_env() -> Self {
+        let d = CacheConfig::default();
+        CacheConfig {
+            max_bytes: env_or("RUSH_QUERY_CACHE_MAX_BYTES", d.max_bytes),
+            freshness_secs: env_or("RUSH_QUERY_CACHE_FRESHNESS_SECS", d.freshness_secs).max(0),
+            ttl: Duration::from_secs(env_or("RUSH_QUERY_CACHE_TTL_SECS", d.ttl.as_secs()).max(1)),
+        }
+    }
+}
+
+// ── Chunkable results ──
+
+/// A query result that can be cut at time boundaries and stitched back.
+pub trait Chunked: Sized {
+    /// The part of the result at times in `[from, to)`.
+    fn range(&self, from: i64, to: i64) -> Self;
+    /// Append the result for a later, adjacent time range.
+    fn append(&mut self, later: Self);
+    fn is_empty(&self) -> bool;
+}
+
+/// A row labelled with its bucket start (`YYYY-MM-DD HH:MM:SS`, UTC).
+pub trait Bucketed {
+    fn bucket(&self) -> &str;
+}
+
+fn bucket_secs(bucket: &str) -> Option<i64> {
+    chrono::NaiveDateTime::parse_from_str(bucket, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|t| t.and_utc().timestamp())
+}
+
+impl<R: Bucketed + Clone> Chunked for Vec<R> {
+    fn range(&self, from: i64, to: i64) -> Self {
+        self.iter()
+            .filter(|r| bucket_secs(r.bucket()).is_some_and(|t| t >= from && t < to))
+            .cloned()
+            .collect()
+    }
+
+    fn append(&mut self, later: Self) {
+        self.extend(later);
+    }
+
+    fn is_empty(&self) -> bool {
+        <[R]>::is_empty(self)
+    }
+}
+
+/// PromQL matrix: series keep their first-seen order; a series present in
+/// both halves gets the later samples appended.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Matrix(pub Vec<TimeSeries>);
+
+impl Chunked for Matrix {
+    fn range(&self, from: i64, to: i64) -> Self {
+        let (from, to) = (from as f64, to as f64);
+        Matrix(
+            self.0
+                .iter()
+                .filter_map(|ts| {
+                    let samples: Vec<(f64, f64)> =
+                        ts.samples.iter().copied().filter(|(t, _)| *t >= from && *t < to).collect();
+                    (!samples.is_empty()).then(|| TimeSeries { labels: ts.labels.clone(), samples })
+                })
+                .collect(),
+        )
+    }
+
+    fn append(&mut self, later: Self) {
+        let mut index: HashMap<BTreeMap<String, String>, usize> =
+            self.0.iter().enumerate().map(|(i, ts)| (ts.labels.clone(), i)).collect();
+        for ts in later.0 {
+            match index.get(&ts.labels) {
+                Some(&i) => self.0[i].samples.extend(ts.samples),
+                None => {
+                    index.insert(ts.labels.clone(), self.0.len());
+                    self.0.push(ts);
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Chunk length for a bucket interval / step, or `None` if `step_secs` is
+/// not a positive whole number of seconds.
+pub fn chunk_secs(step_secs: i64) -> Option<i64> {
+    (step_secs > 0).then(|| step_secs * BUCKETS_PER_CHUNK.max((MIN_CHUNK_SECS + step_secs - 1) / step_secs))
+}
+
+/// A request's `[from, to]` as unix seconds, when both parse.
+pub fn parse_range(from: &str, to: &str) -> Option<(i64, i64)> {
+    let from = crate::query_builder::parse_datetime_secs(from)?;
+    let to = crate::query_builder::parse_datetime_secs(to)?;
+    (to > from).then_some((from, to))
+}
+
+/// Unix seconds as an RFC 3339 UTC timestamp for a query's time range.
+pub fn format_time(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_default()
+}
+
+// ── LRU ──
+
+struct Entry {
+    value: Bytes,
+    stored: Instant,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<String, Entry>,
+    /// tick → key, oldest use first.
+    order: BTreeMap<u64, String>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl Lru {
+    fn get(&mut self, key: &str, ttl: Duration) -> Option<Bytes> {
+        let e = self.entries.get_mut(key)?;
+        if e.stored.elapsed() >= ttl {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        self.order.remove(&e.tick);
+        e.tick = self.tick;
+        self.order.insert(self.tick, key.to_string());
+        Some(e.value.clone())
+    }
+
+    fn put(&mut self, key: String, value: Bytes, max_bytes: usize) {
+        let size = key.len() + value.len();
+        if size > max_bytes {
+            return;
+        }
+        self.remove(&key);
+        while self.bytes + size > max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            if let Some(e) = self.entries.remove(&oldest) {
+                self.bytes -= oldest.len() + e.value.len();
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.bytes += size;
+        self.entries.insert(key, Entry { value, stored: Instant::now(), tick: self.tick });
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(e) = self.entries.remove(key) {
+            self.order.remove(&e.tick);
+            self.bytes -= key.len() + e.value.len();
+        }
+    }
+}
+
+// ── Cache ──
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    /// Chunk lookups answered from memory or the object store.
+    pub hits: u64,
+    pub misses: u64,
+    /// Of `hits`, those answered by the object store.
+    pub object_store_hits: u64,
+    pub hit_ratio: f64,
+    pub object_store: bool,
+}
+
+pub struct QueryCache {
+    config: CacheConfig,
+    lru: Mutex<Lru>,
+    store: Option<(Arc<dyn ObjectStore>, String)>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    store_hits: AtomicU64,
+}
+
+/// One piece of a split range: cached, or to be read (covering the listed
+/// chunk starts, which get cached afterwards).
+enum Segment<T> {
+    Hit(T),
+    Miss { from: i64, to: i64, chunks: Vec<i64> },
+}
+
+impl QueryCache {
+    /// `store` is an optional object store and key prefix to write through to.
+    pub fn new(config: CacheConfig, store: Option<(Arc<dyn ObjectStore>, String)>) -> Self {
+        QueryCache {
+            config,
+            lru: Mutex::new(Lru::default()),
+            store,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            store_hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = {
+            let lru = self.lru.lock().unwrap();
+            (lru.entries.len(), lru.bytes)
+        };
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            enabled: self.config.max_bytes > 0,
+            entries,
+            bytes,
+            max_bytes: self.config.max_bytes,
+            hits,
+            misses,
+            object_store_hits: self.store_hits.load(Ordering::Relaxed),
+            hit_ratio: if hits + misses > 0 { hits as f64 / (hits + misses) as f64 } else { 0.0 },
+            object_store: self.store.is_some(),
+        }
+    }
+
+    fn entry_key(tenant_id: &str, query_key: &str, chunk_secs: i64, start: i64) -> String {
+        let mut h = Sha256::new();
+        h.update(tenant_id.as_bytes());
+        h.update([0]);
+        h.update(query_key.as_bytes());
+        format!("{}/{chunk_secs}-{start}", hex::encode(h.finalize()))
+    }
+
+    async fn lookup(&self, key: &str) -> Option<Bytes> {
+        if let Some(v) = self.lru.lock().unwrap().get(key, self.config.ttl) {
+            return Some(v);
+        }
+        let (store, prefix) = self.store.as_ref()?;
+        let got = store.get(&OsPath::from(format!("{prefix}{key}"))).await.ok()?;
+        let age = chrono::Utc::now().signed_duration_since(got.meta.last_modified);
+        if !age.to_std().is_ok_and(|a| a < self.config.ttl) {
+            let (store, path) = (store.clone(), got.meta.location.clone());
+            tokio::spawn(async move {
+                if let Err(e) = store.delete(&path).await {
+                    tracing::debug!(error = %e, "query cache object store delete failed");
+                }
+            });
+            return None;
+        }
+        let value = got.bytes().await.ok()?;
+        self.store_hits.fetch_add(1, Ordering::Relaxed);
+        self.lru.lock().unwrap().put(key.to_string(), value.clone(), self.config.max_bytes);
+        Some(value)
+    }
+
+    fn insert(&self, key: String, value: Bytes) {
+        if let Some((store, prefix)) = &self.store {
+            let (store, path, payload) = (store.clone(), OsPath::from(format!("{prefix}{key}")), value.clone());
+            tokio::spawn(async move {
+                if let Err(e) = store.put(&path, payload.into()).await {
+                    tracing::warn!(error = %e, "query cache object store write failed");
+                }
+            });
+        }
+        self.lru.lock().unwrap().put(key, value, self.config.max_bytes);
+    }
+
+    /// Answer `[from, to]` (unix seconds, inclusive like the query API) from
+    /// cached chunks plus `run` for the rest. `run(a, b)` must return the
+    /// result for `[a, b]`; extra data it returns outside that range is cut
+    /// off. `query_key` must identify everything but the time range.
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        tenant_id: &str,
+        query_key: &str,
+        from: i64,
+        to: i64,
+        chunk_secs: i64,
+        run: F,
+    ) -> Result<T, E>
+    where
+        T: Chunked + Serialize + DeserializeOwned,
+        F: Fn(i64, i64) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if self.config.max_bytes == 0 || chunk_secs <= 0 || to <= from || crate::query_explain::active() {
+            return run(from, to).await;
+        }
+        let cutoff = chrono::Utc::now().timestamp() - self.config.freshness_secs;
+        let first = from.div_euclid(chunk_secs) * chunk_secs + if from.rem_euclid(chunk_secs) == 0 { 0 } else { chunk_secs };
+        let mut starts = Vec::new();
+        let mut b = first;
+        while b + chunk_secs <= to.min(cutoff) {
+            starts.push(b);
+            b += chunk_secs;
+        }
+        if starts.is_empty() {
+            return run(from, to).await;
+        }
+
+        // Split into cached chunks and runs of everything else.
+        let mut segments: Vec<Segment<T>> = Vec::new();
+        let push_miss = |segments: &mut Vec<Segment<T>>, a: i64, z: i64, chunk: Option<i64>| {
+            if let Some(Segment::Miss { to, chunks, .. }) = segments.last_mut()
+                && *to == a
+            {
+                *to = z;
+                chunks.extend(chunk);
+                return;
+            }
+            segments.push(Segment::Miss { from: a, to: z, chunks: chunk.into_iter().collect() });
+        };
+        if from < first {
+            push_miss(&mut segments, from, first, None);
+        }
+        for &start in &starts {
+            let key = Self::entry_key(tenant_id, query_key, chunk_secs, start);
+            let hit = match self.lookup(&key).await {
+                Some(bytes) => bincode::deserialize::<T>(&bytes).ok(),
+                None => None,
+            };
+            match hit {
+                Some(v) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    segments.push(Segment::Hit(v));
+                }
+                None => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    push_miss(&mut segments, start, start + chunk_secs, Some(start));
+                }
+            }
+        }
+        let last_end = starts[starts.len() - 1] + chunk_secs;
+        if last_end <= to {
+            push_miss(&mut segments, last_end, to, None);
+        }
+
+        let n = segments.len();
+        let mut out: Option<T> = None;
+        for (i, segment) in segments.into_iter().enumerate() {
+            let part = match segment {
+                Segment::Hit(v) => v,
+                Segment::Miss { from: a, to: z, chunks } => {
+                    // Pieces meet at chunk starts: keep [a, z) except at the ends
+                    // of the whole range (the head keeps its partial first bucket,
+                    // the tail its inclusive end).
+                    let lo = if i == 0 { i64::MIN } else { a };
+                    let hi = if i == n - 1 { i64::MAX } else { z };
+                    if chunks.is_empty() {
+                        run(a, z).await?.range(lo, hi)
+                    } else {
+                        // `run` is called inside the scope: it may build its
+                        // query before the first poll.
+                        let failed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+                        let filled = FILL.scope(failed.clone(), async { run(a, z).await }).await;
+                        match filled {
+                            Ok(v) if !failed.load(Ordering::Relaxed) => {
+                                let v = v.range(lo, hi);
+                                self.store_chunks(tenant_id, query_key, chunk_secs, &chunks, &v);
+                                v
+                            }
+                            // Over the row cap or partly failed: answer like an
+                            // uncached query would, and keep nothing.
+                            _ => run(a, z).await?.range(lo, hi),
+                        }
+                    }
+                }
+            };
+            match out.as_mut() {
+                Some(acc) => acc.append(part),
+                None => out = Some(part),
+            }
+        }
+        match out {
+            Some(v) => Ok(v),
+            None => run(from, to).await,
+        }
+    }
+
+    /// Cache the chunks starting at `chunks` out of a result read for them.
+    fn store_chunks<T: Chunked + Serialize>(
+        &self,
+        tenant_id: &str,
+        query_key: &str,
+        chunk_secs: i64,
+        chunks: &[i64],
+        v: &T,
+    ) {
+        for &start in chunks {
+            let chunk = v.range(start, start + chunk_secs);
+            // Empty chunks are cheap to re-read and not worth an entry.
+            if chunk.is_empty() {
+                continue;
+            }
+            if let Ok(bytes) = bincode::serialize(&chunk) {
+                self.insert(Self::entry_key(tenant_id, query_key, chunk_secs, start), bytes.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+    struct Row {
+        bucket: String,
+        n: u64,
+    }
+
+    impl Bucketed for Row {
+        fn bucket(&self) -> &str {
+            &self.bucket
+        }
+    }
+
+    /// One row per minute in `[a, b]`, like a GROUP BY toStartOfMinute.
+    fn minutes(a: i64, b: i64) -> Vec<Row> {
+        (a.div_euclid(60)..=b.div_euclid(60))
+            .map(|m| Row {
+                bucket: chrono::DateTime::from_timestamp(m * 60, 0).unwrap().format("%Y-%m-%d %H:%M:%S").to_string(),
+                n: m as u64,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn split_run_matches_a_single_query_and_reuses_past_chunks() {
+        let cache = QueryCache::new(CacheConfig::default(), None);
+        let chunk = chunk_secs(60).unwrap();
+        assert_eq!(chunk, 3_600);
+        let base = 1_700_000_000 - 1_700_000_000 % chunk;
+        let (from, to) = (base + 1_234, base + 4 * chunk + 90);
+        let calls = Mutex::new(Vec::new());
+        let run = |a: i64, b: i64| {
+            calls.lock().unwrap().push((a, b));
+            async move { Ok::<_, ()>(minutes(a, b)) }
+        };
+
+        let first = cache.run("t", "q", from, to, chunk, run).await.unwrap();
+        assert_eq!(first, minutes(from, to));
+        // Head through tail in one query: nothing was cached yet.
+        assert_eq!(*calls.lock().unwrap(), vec![(from, to)]);
+        assert_eq!(cache.stats().misses, 3);
+
+        calls.lock().unwrap().clear();
+        let second = cache.run("t", "q", from + 60, to + 60, chunk, run).await.unwrap();
+        assert_eq!(second, minutes(from + 60, to + 60));
+        // Only the head and the tail are read; the three full chunks are hits.
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![(from + 60, base + chunk), (base + 4 * chunk, to + 60)]
+        );
+        assert_eq!(cache.stats().hits, 3);
+
+        // A different tenant shares nothing.
+        calls.lock().unwrap().clear();
+        cache.run("other", "q", from, to, chunk, run).await.unwrap();
+        assert_eq!(calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_or_partial_fills_are_answered_but_not_cached() {
+        let cache = QueryCache::new(CacheConfig::default(), None);
+        let chunk = chunk_secs(60).unwrap();
+        let base = 1_700_000_000 - 1_700_000_000 % chunk;
+        let (from, to) = (base, base + 2 * chunk);
+        let calls = Mutex::new(0);
+
+        // Over the row cap: the strict read fails, the retry truncates.
+        let capped = |a: i64, _b: i64| {
+            *calls.lock().unwrap() += 1;
+            let strict = filling();
+            async move { if strict { Err("too many rows") } else { Ok(minutes(a, a + 60)) } }
+        };
+        assert_eq!(cache.run("t", "q", from, to, chunk, capped).await.unwrap(), minutes(from, from + 60));
+        assert_eq!(*calls.lock().unwrap(), 2);
+
+        // A swallowed failure: the data comes back, but it isn't kept.
+        let partial = |a: i64, b: i64| {
+            note_failure();
+            async move { Ok::<_, ()>(minutes(a, b)) }
+        };
+        cache.run("t", "q", from, to, chunk, partial).await.unwrap();
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used_within_budget() {
+        let mut lru = Lru::default();
+        let ttl = Duration::from_secs(60);
+        lru.put("a".into(), Bytes::from(vec![0; 9]), 25);
+        lru.put("b".into(), Bytes::from(vec![0; 9]), 25);
+        assert!(lru.get("a", ttl).is_some());
+        lru.put("c".into(), Bytes::from(vec![0; 9]), 25);
+        assert!(lru.get("b", ttl).is_none());
+        assert!(lru.get("a", ttl).is_some() && lru.get("c", ttl).is_some());
+        assert_eq!(lru.bytes, 20);
+    }
+
+    #[test]
+    fn matrix_chunks_stitch_back_together() {
+        let series = |name: &str, ts: &[f64]| TimeSeries {
+            labels: BTreeMap::from([("__name__".to_string(), name.to_string())]),
+            samples: ts.iter().map(|t| (*t, 1.0)).collect(),
+        };
+        let m = Matrix(vec![series("a", &[0.0, 15.0, 30.0, 45.0]), series("b", &[30.0])]);
+        let mut head = m.range(0, 30);
+        assert_eq!(head.0.len(), 1);
+        head.append(m.range(30, 60));
+        assert_eq!(head.0[0].samples.len(), 4);
+        assert_eq!(head.0[1].labels["__name__"], "b");
+    }
+}
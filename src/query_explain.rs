@@ -0,0 +1,233 @@
+//! Dry runs and cost estimates for read queries.
+//!
+//! A dry run executes a query handler inside a task-local capture scope.
+//! `tenant_query` records every statement the handler builds and runs it as
+//! `SELECT * FROM (…) LIMIT 0` instead, so the handler goes through its usual
+//! code path (including follow-up queries such as the logs full-range
+//! fallback) without reading data. Each recorded statement is then passed to
+//! ClickHouse `EXPLAIN ESTIMATE` (parts / rows / marks to read per table) and
+//! `EXPLAIN indexes = 1` (how far the primary key and each skip index, e.g.
+//! `idx_trace_id` or the ngram/text index, narrowed the read).
+//!
+//! Statements are recorded as `Client::query` templates (a literal `?` is
+//! `??`). Values bound through `tenant_query_bound` are filled in; one the
+//! handler `.bind()`s after `tenant_query` would show up as `?`, which
+//! ClickHouse cannot explain.
+
+use std::sync::{Arc, Mutex};
+
+use clickhouse::Client;
+use serde::Serialize;
+
+tokio::task_local! {
+    static CAPTURE: Arc<Mutex<Vec<String>>>;
+}
+
+/// Record `sql` if called inside a dry run. Returns false otherwise.
+pub fn capture(sql: &str) -> bool {
+    CAPTURE.try_with(|c| c.lock().unwrap().push(sql.to_string())).is_ok()
+}
+
+/// Whether the current task is inside a dry run.
+pub fn active() -> bool {
+    CAPTURE.try_with(|_| ()).is_ok()
+}
+
+/// Run `fut` as a dry run; returns its output and the statements it built.
+pub async fn dry_run<F: Future>(fut: F) -> (F::Output, Vec<String>) {
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let out = CAPTURE.scope(captured.clone(), fut).await;
+    let sql = std::mem::take(&mut *captured.lock().unwrap());
+    (out, sql)
+}
+
+/// One `EXPLAIN ESTIMATE` row.
+#[derive(Debug, Clone, Serialize, clickhouse::Row, serde::Deserialize)]
+pub struct TableEstimate {
+    pub database: String,
+    pub table: String,
+    pub parts: u64,
+    pub rows: u64,
+    pub marks: u64,
+}
+
+/// One index step from `EXPLAIN indexes = 1`: how many parts and granules
+/// were left after applying it, out of how many.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IndexUse {
+    pub table: String,
+    /// `MinMax`, `Partition`, `PrimaryKey` or `Skip`.
+    pub kind: String,
+    /// Skip index name (`idx_trace_id`, …); empty for the other kinds.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub parts_selected: u64,
+    pub parts_total: u64,
+    pub granules_selected: u64,
+    pub granules_total: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatementPlan {
+    pub sql: String,
+    pub estimate: Vec<TableEstimate>,
+    pub indexes: Vec<IndexUse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Totals {
+    pub parts: u64,
+    pub rows: u64,
+    pub marks: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub statements: Vec<StatementPlan>,
+    /// Sum of the estimates over all statements.
+    pub totals: Totals,
+}
+
+/// `EXPLAIN ESTIMATE` for one statement.
+pub async fn estimate(ch: &Client, sql: &str, tenant_id: &str) -> anyhow::Result<Vec<TableEstimate>> {
+    Ok(crate::tenant_query(ch, &format!("EXPLAIN ESTIMATE {sql}"), tenant_id)
+        .fetch_all::<TableEstimate>()
+        .await?)
+}
+
+/// Explain each statement of a dry run.
+pub async fn explain(ch: &Client, statements: Vec<String>, tenant_id: &str) -> Plan {
+    let mut totals = Totals::default();
+    let mut plans = Vec::with_capacity(statements.len());
+    for sql in statements {
+        let estimate = estimate(ch, &sql, tenant_id).await;
+        let indexes = crate::tenant_query(ch, &format!("EXPLAIN indexes = 1 {sql}"), tenant_id)
+            .fetch_all::<String>()
+            .await
+            .map_err(anyhow::Error::from);
+        let error = match (&estimate, &indexes) {
+            (Err(e), _) | (_, Err(e)) => Some(e.to_string()),
+            _ => None,
+        };
+        let estimate = estimate.unwrap_or_default();
+        for e in &estimate {
+            totals.parts += e.parts;
+            totals.rows += e.rows;
+            totals.marks += e.marks;
+        }
+        let indexes = indexes.map(|lines| parse_index_plan(&lines)).unwrap_or_default();
+        plans.push(StatementPlan { sql, estimate, indexes, error });
+    }
+    Plan { statements: plans, totals }
+}
+
+/// `a/b` → (a, b).
+fn ratio(s: &str) -> Option<(u64, u64)> {
+    let (a, b) = s.trim().split_once('/')?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+/// Pull the index steps out of `EXPLAIN indexes = 1` text, e.g.
+///
+/// ```text
+/// ReadFromMergeTree (observability.spans)
+///   Indexes:
+///     PrimaryKey
+///       Keys:
+///         tenant_id
+///       Parts: 4/12
+///       Granules: 40/900
+///     Skip
+///       Name: idx_trace_id
+///       Description: bloom_filter GRANULARITY 1
+///       Parts: 1/4
+///       Granules: 2/40
+/// ```
+fn parse_index_plan(lines: &[String]) -> Vec<IndexUse> {
+    let mut out: Vec<IndexUse> = Vec::new();
+    let mut table = String::new();
+    let mut current: Option<IndexUse> = None;
+    for line in lines {
+        let t = line.trim();
+        if let Some(rest) = t.strip_prefix("ReadFromMergeTree") {
+            out.extend(current.take());
+            table = rest.trim().trim_start_matches('(').trim_end_matches(')').to_string();
+        } else if matches!(t, "MinMax" | "Partition" | "PrimaryKey" | "Skip") {
+            out.extend(current.take());
+            current = Some(IndexUse {
+                table: table.clone(),
+                kind: t.to_string(),
+                name: String::new(),
+                parts_selected: 0,
+                parts_total: 0,
+                granules_selected: 0,
+                granules_total: 0,
+            });
+        } else if let Some(idx) = current.as_mut() {
+            if let Some(name) = t.strip_prefix("Name:") {
+                idx.name = name.trim().to_string();
+            } else if let Some((a, b)) = t.strip_prefix("Parts:").and_then(ratio) {
+                (idx.parts_selected, idx.parts_total) = (a, b);
+            } else if let Some((a, b)) = t.strip_prefix("Granules:").and_then(ratio) {
+                (idx.granules_selected, idx.granules_total) = (a, b);
+                out.extend(current.take());
+            }
+        }
+    }
+    out.extend(current);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_plan_lists_each_index_per_table() {
+        let text = "Expression ((Project names + Projection))
+  Limit (preliminary LIMIT (without OFFSET))
+    Expression
+      ReadFromMergeTree (observability.spans)
+      Indexes:
+        MinMax
+          Keys:
+            timestamp
+          Condition: and((timestamp in (-Inf, 1767229200]), (timestamp in [1767225600, +Inf)))
+          Parts: 6/20
+          Granules: 300/2000
+        PrimaryKey
+          Keys:
+            tenant_id
+          Condition: (tenant_id in ['acme', 'acme'])
+          Parts: 4/6
+          Granules: 40/300
+        Skip
+          Name: idx_trace_id
+          Description: bloom_filter GRANULARITY 1
+          Parts: 1/4
+          Granules: 2/40";
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let idx = parse_index_plan(&lines);
+        assert_eq!(idx.len(), 3);
+        assert_eq!(idx[0].kind, "MinMax");
+        assert_eq!((idx[1].parts_selected, idx[1].parts_total), (4, 6));
+        assert_eq!(idx[2].table, "observability.spans");
+        assert_eq!(idx[2].name, "idx_trace_id");
+        assert_eq!((idx[2].granules_selected, idx[2].granules_total), (2, 40));
+    }
+
+    #[tokio::test]
+    async fn dry_run_records_only_inside_its_scope() {
+        assert!(!capture("SELECT 1"));
+        let (n, sql) = dry_run(async {
+            capture("SELECT 2");
+            capture("SELECT 3");
+            7
+        })
+        .await;
+        assert_eq!(n, 7);
+        assert_eq!(sql, vec!["SELECT 2", "SELECT 3"]);
+    }
+}
@@ -0,0 +1,888 @@
+//! Asynchronous query jobs: run a query endpoint in the background, keep its
+//! result for `RESULT_TTL`, and serve it in pages.
+//!
+//! A job runs the ordinary handler (see `handlers::jobs`) inside a task-local
+//! `JobScope`. `tenant_query` picks the scope up and tags every ClickHouse
+//! query the handler issues with `query_id = <job id>-<n>`, lifts the
+//! interactive `max_execution_time`, and replaces the silent
+//! `result_overflow_mode = break` truncation with `throw`, so a job either
+//! returns the whole result or fails saying why. Progress is read from
+//! `system.processes` by that query id prefix and cancellation is
+//! `KILL QUERY` on the same prefix.
+//!
+//! The handler's response body is split into rows as it streams in
+//! (`RowSplitter`: the main array of a JSON body, or the lines of a text
+//! body) and written to `query_job_results` `INSERT_CHUNK` rows at a time, so
+//! a job never holds its whole result. ClickHouse expires the rows after a day.
+//!
+//! Job status lives in `query_jobs`, a ReplacingMergeTree with one row per
+//! state change, so any instance can answer status, results and cancel
+//! requests, and finished jobs survive a restart. Only the task is local: an
+//! instance that dies mid-job leaves a `running` row behind, which reads as
+//! failed once the job's deadline has passed. A cancel handled by another
+//! instance kills the job's queries; the task then finds the `cancelled` row
+//! and leaves it alone.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clickhouse::Client;
+use dashmap::DashMap;
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::config::env_or;
+
+const DEFAULT_MAX_PER_TENANT: usize = 4;
+const DEFAULT_MAX_EXECUTION_SECS: u64 = 3_600;
+const DEFAULT_MAX_RESULT_ROWS: u64 = 5_000_000;
+const DEFAULT_MAX_RESULT_BYTES: usize = 512 * 1024 * 1024;
+
+/// How long finished jobs and their results are kept. Matches the TTL on
+/// `query_job_results`.
+pub const RESULT_TTL: Duration = Duration::from_secs(86_400);
+
+/// Rows per INSERT when storing a result.
+const INSERT_CHUNK: usize = 10_000;
+
+/// A JSON array field this long is taken to be the rows and streamed; shorter
+/// ones are held until the body ends (see `RowSplitter`).
+const STREAM_AFTER: usize = INSERT_CHUNK;
+
+/// How much of a failed handler's body is kept as the job's error.
+const MAX_ERROR_BYTES: usize = 64 * 1024;
+
+/// Slack past a job's deadline before a `running` row is taken as lost, for
+/// the final status write of a job that just made it.
+const LOST_GRACE_MS: i64 = 60_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct JobsConfig {
+    /// Running jobs per tenant; further submissions get 429.
+    pub max_per_tenant: usize,
+    /// `max_execution_time` for each query a job runs, and the bound on the
+    /// whole job.
+    pub max_execution_secs: u64,
+    /// `max_result_rows` for each query a job runs (exceeding it fails the job).
+    pub max_result_rows: u64,
+    /// Largest handler response a job will store.
+    pub max_result_bytes: usize,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            max_per_tenant: DEFAULT_MAX_PER_TENANT,
+            max_execution_secs: DEFAULT_MAX_EXECUTION_SECS,
+            max_result_rows: DEFAULT_MAX_RESULT_ROWS,
+            max_result_bytes: DEFAULT_MAX_RESULT_BYTES,
+        }
+    }
+}
+
+impl JobsConfig {
+    /// `RUSH_JOB_MAX_PER_TENANT` (default 4), `RUSH_JOB_MAX_EXECUTION_SECS`
+    /// (3600), `RUSH_JOB_MAX_RESULT_ROWS` (5000000) and
+    /// `RUSH_JOB_MAX_RESULT_BYTES` (512 MiB).
+    pub fn from_env() -> Self {
+        let d = JobsConfig::default();
+        JobsConfig {
+            max_per_tenant: env_or("RUSH_JOB_MAX_PER_TENANT", d.max_per_tenant).max(1),
+            max_execution_secs: env_or("RUSH_JOB_MAX_EXECUTION_SECS", d.max_execution_secs).max(1),
+            max_result_rows: env_or("RUSH_JOB_MAX_RESULT_ROWS", d.max_result_rows).max(1),
+            max_result_bytes: env_or("RUSH_JOB_MAX_RESULT_BYTES", d.max_result_bytes).max(1),
+        }
+    }
+}
+
+// ── Query tagging ──
+
+struct JobScope {
+    id: String,
+    next_query: AtomicU32,
+    max_execution_secs: u64,
+    max_result_rows: u64,
+}
+
+tokio::task_local! {
+    static JOB_SCOPE: Arc<JobScope>;
+}
+
+/// ClickHouse settings for a query issued from inside a job.
+pub struct JobQuerySettings {
+    pub query_id: String,
+    pub max_execution_time: String,
+    pub max_result_rows: String,
+}
+
+/// Settings for the next query when called from a job's task; `None`
+/// everywhere else.
+pub fn current_query_settings() -> Option<JobQuerySettings> {
+    JOB_SCOPE
+        .try_with(|scope| JobQuerySettings {
+            query_id: format!("{}-{}", scope.id, scope.next_query.fetch_add(1, Ordering::Relaxed)),
+            max_execution_time: scope.max_execution_secs.to_string(),
+            max_result_rows: scope.max_result_rows.to_string(),
+        })
+        .ok()
+}
+
+// ── Jobs ──
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Live progress summed over the job's queries still in `system.processes`.
+#[derive(Debug, Clone, Default, Serialize, clickhouse::Row, serde::Deserialize)]
+pub struct JobProgress {
+    pub read_rows: u64,
+    pub read_bytes: u64,
+    /// ClickHouse's estimate of the rows the running queries will read.
+    pub total_rows_approx: u64,
+    pub elapsed_secs: f64,
+}
+
+/// One row of `query_jobs`. Times are unix milliseconds.
+#[derive(Debug, Clone, clickhouse::Row, Serialize, serde::Deserialize)]
+struct JobRecord {
+    tenant_id: String,
+    job_id: String,
+    kind: String,
+    status: String,
+    error: String,
+    /// Rows stored in `query_job_results`.
+    rows: u64,
+    /// The non-row part of a JSON response (totals, `truncated`, …), as JSON.
+    meta: String,
+    submitted_at: i64,
+    /// 0 while running.
+    finished_at: i64,
+    /// When a job still `running` must have been lost with its instance.
+    deadline: i64,
+    version: u64,
+}
+
+const JOB_COLUMNS: &str =
+    "tenant_id, job_id, kind, status, error, rows, meta, submitted_at, finished_at, deadline, version";
+
+fn now_ms() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn from_ms(ms: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_millis(ms).unwrap_or_default()
+}
+
+impl JobRecord {
+    fn is_lost(&self, now_ms: i64) -> bool {
+        self.status == JobStatus::Running.as_str() && now_ms > self.deadline + LOST_GRACE_MS
+    }
+
+    fn status(&self, now_ms: i64) -> JobStatus {
+        match self.status.as_str() {
+            "done" => JobStatus::Done,
+            "cancelled" => JobStatus::Cancelled,
+            "failed" => JobStatus::Failed,
+            _ if self.is_lost(now_ms) => JobStatus::Failed,
+            _ => JobStatus::Running,
+        }
+    }
+
+    fn info(&self, progress: Option<JobProgress>) -> JobInfo {
+        let now = now_ms();
+        let error = if self.is_lost(now) {
+            Some("job was lost: the instance running it stopped".to_string())
+        } else {
+            (!self.error.is_empty()).then(|| self.error.clone())
+        };
+        JobInfo {
+            id: self.job_id.clone(),
+            kind: self.kind.clone(),
+            status: self.status(now),
+            error,
+            rows: self.rows,
+            meta: serde_json::from_str(&self.meta).unwrap_or(serde_json::Value::Null),
+            progress,
+            submitted_at: from_ms(self.submitted_at),
+            finished_at: (self.finished_at > 0).then(|| from_ms(self.finished_at)),
+        }
+    }
+}
+
+/// A job's status as returned by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String,
+    pub status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub rows: u64,
+    pub meta: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+    pub submitted_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub struct QueryJobs {
+    cfg: JobsConfig,
+    /// Tasks of the jobs running on this instance, by job id.
+    local: DashMap<String, tokio::task::AbortHandle>,
+    /// Serialises this instance's submissions, so two can't both pass the
+    /// per-tenant check.
+    submit_lock: tokio::sync::Mutex<()>,
+}
+
+#[derive(clickhouse::Row, Serialize)]
+struct ResultRow<'a> {
+    tenant_id: &'a str,
+    job_id: &'a str,
+    seq: u32,
+    row: String,
+}
+
+async fn load(ch: &Client, tenant_id: &str, id: &str) -> anyhow::Result<Option<JobRecord>> {
+    let sql = format!("SELECT {JOB_COLUMNS} FROM query_jobs FINAL WHERE tenant_id = ? AND job_id = ? LIMIT 1");
+    Ok(crate::tenant_query_bound(ch, &sql, tenant_id, |q| q.bind(tenant_id).bind(id))
+        .fetch_optional::<JobRecord>()
+        .await?)
+}
+
+async fn save(ch: &Client, record: &JobRecord) -> anyhow::Result<()> {
+    let mut insert = ch.insert("query_jobs")?;
+    insert.write(record).await?;
+    insert.end().await?;
+    Ok(())
+}
+
+/// Move a running job to `status`, unless it already left `running` (a
+/// cancel, possibly on another instance, got there first). Returns the new
+/// record, or `None` if the job is missing or finished.
+async fn transition(
+    ch: &Client,
+    tenant_id: &str,
+    id: &str,
+    status: JobStatus,
+    update: impl FnOnce(&mut JobRecord),
+) -> anyhow::Result<Option<JobRecord>> {
+    let Some(mut record) = load(ch, tenant_id, id).await? else { return Ok(None) };
+    if record.status != JobStatus::Running.as_str() {
+        return Ok(None);
+    }
+    update(&mut record);
+    record.status = status.as_str().to_string();
+    record.finished_at = now_ms();
+    record.version = (record.finished_at as u64).max(record.version + 1);
+    save(ch, &record).await?;
+    Ok(Some(record))
+}
+
+impl QueryJobs {
+    pub fn new(cfg: JobsConfig) -> Self {
+        QueryJobs { cfg, local: DashMap::new(), submit_lock: tokio::sync::Mutex::new(()) }
+    }
+
+    pub fn config(&self) -> JobsConfig {
+        self.cfg
+    }
+
+    /// A job of the tenant's, unless its results have expired.
+    pub async fn get(&self, ch: &Client, tenant_id: &str, id: &str) -> anyhow::Result<Option<JobInfo>> {
+        let record = load(ch, tenant_id, id).await?;
+        Ok(record.filter(|r| !expired(r)).map(|r| r.info(None)))
+    }
+
+    /// The tenant's jobs, newest first.
+    pub async fn list(&self, ch: &Client, tenant_id: &str) -> anyhow::Result<Vec<JobInfo>> {
+        let sql = format!("SELECT {JOB_COLUMNS} FROM query_jobs FINAL WHERE tenant_id = ? ORDER BY submitted_at DESC");
+        let records = crate::tenant_query_bound(ch, &sql, tenant_id, |q| q.bind(tenant_id)).fetch_all::<JobRecord>().await?;
+        Ok(records.iter().filter(|r| !expired(r)).map(|r| r.info(None)).collect())
+    }
+
+    /// Start `run` as a job; its response is stored as the result. Returns
+    /// `None` when the tenant is at `max_per_tenant` running jobs.
+    pub async fn submit<F>(
+        self: &Arc<Self>,
+        ch: Client,
+        tenant_id: &str,
+        kind: &str,
+        run: F,
+    ) -> anyhow::Result<Option<JobInfo>>
+    where
+        F: Future<Output = axum::response::Response> + Send + 'static,
+    {
+        let _guard = self.submit_lock.lock().await;
+        let running = crate::tenant_query(
+            &ch,
+            "SELECT count() FROM query_jobs FINAL \
+             WHERE tenant_id = ? AND status = 'running' AND deadline > fromUnixTimestamp64Milli(?)",
+            tenant_id,
+        )
+        .bind(tenant_id)
+        .bind(now_ms() - LOST_GRACE_MS)
+        .fetch_one::<u64>()
+        .await?;
+        if running >= self.cfg.max_per_tenant as u64 {
+            return Ok(None);
+        }
+        let submitted_at = now_ms();
+        let record = JobRecord {
+            tenant_id: tenant_id.to_string(),
+            job_id: uuid::Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            status: JobStatus::Running.as_str().to_string(),
+            error: String::new(),
+            rows: 0,
+            meta: "null".to_string(),
+            submitted_at,
+            finished_at: 0,
+            deadline: submitted_at + (self.cfg.max_execution_secs * 1_000) as i64,
+            version: submitted_at as u64,
+        };
+        save(&ch, &record).await?;
+
+        let me = self.clone();
+        let task_record = record.clone();
+        // The task waits until its abort handle is recorded, so it can't
+        // finish (and deregister) first.
+        let (registered, wait_registered) = tokio::sync::oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            let _ = wait_registered.await;
+            me.execute(ch, &task_record, run).await;
+            me.local.remove(&task_record.job_id);
+        });
+        self.local.insert(record.job_id.clone(), handle.abort_handle());
+        let _ = registered.send(());
+        Ok(Some(record.info(None)))
+    }
+
+    /// Run a job's handler, store what it returns and record the outcome.
+    async fn execute<F>(&self, ch: Client, record: &JobRecord, run: F)
+    where
+        F: Future<Output = axum::response::Response>,
+    {
+        let scope = Arc::new(JobScope {
+            id: record.job_id.clone(),
+            next_query: AtomicU32::new(0),
+            max_execution_secs: self.cfg.max_execution_secs,
+            max_result_rows: self.cfg.max_result_rows,
+        });
+        let max_bytes = self.cfg.max_result_bytes;
+        // The body is read inside the scope too: streaming handlers run their
+        // queries while it is polled.
+        let work = JOB_SCOPE.scope(scope, async {
+            let response = run.await;
+            store_response(&ch, &record.tenant_id, &record.job_id, response, max_bytes).await
+        });
+        let limit = Duration::from_secs(self.cfg.max_execution_secs);
+        let outcome = match tokio::time::timeout(limit, work).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err(format!("job exceeded its time limit ({} s)", limit.as_secs())),
+        };
+        let (status, rows, meta, error) = match outcome {
+            Ok((rows, meta)) => (JobStatus::Done, rows, meta, String::new()),
+            Err(msg) => {
+                tracing::warn!(job_id = %record.job_id, error = %msg, "query job failed");
+                (JobStatus::Failed, 0, serde_json::Value::Null, msg)
+            }
+        };
+        let finished = transition(&ch, &record.tenant_id, &record.job_id, status, |r| {
+            r.rows = rows;
+            r.meta = meta.to_string();
+            r.error = error;
+        })
+        .await;
+        if let Err(e) = finished {
+            tracing::error!(job_id = %record.job_id, error = %e, "recording query job status failed");
+        }
+    }
+
+    /// Cancel a running job: record it as cancelled, stop its task if it runs
+    /// here and kill its queries wherever they run. Returns `None` if the job
+    /// had already finished.
+    pub async fn cancel(&self, ch: &Client, tenant_id: &str, id: &str) -> anyhow::Result<Option<JobInfo>> {
+        let Some(record) = transition(ch, tenant_id, id, JobStatus::Cancelled, |_| {}).await? else {
+            return Ok(None);
+        };
+        if let Some((_, abort)) = self.local.remove(id) {
+            abort.abort();
+        }
+        ch.query("KILL QUERY WHERE startsWith(query_id, ?) ASYNC")
+            .bind(format!("{id}-"))
+            .execute()
+            .await?;
+        Ok(Some(record.info(None)))
+    }
+}
+
+/// Finished longer than `RESULT_TTL` ago: the rows are gone too.
+fn expired(record: &JobRecord) -> bool {
+    record.finished_at > 0 && now_ms() - record.finished_at > RESULT_TTL.as_millis() as i64
+}
+
+/// Progress of a running job's queries, from `system.processes`.
+pub async fn progress(ch: &Client, job_id: &str) -> anyhow::Result<JobProgress> {
+    Ok(ch
+        .query(
+            "SELECT toUInt64(sum(read_rows)) AS read_rows, toUInt64(sum(read_bytes)) AS read_bytes, \
+             toUInt64(sum(total_rows_approx)) AS total_rows_approx, toFloat64(max(elapsed)) AS elapsed_secs \
+             FROM system.processes WHERE startsWith(query_id, ?)",
+        )
+        .bind(format!("{job_id}-"))
+        .fetch_one::<JobProgress>()
+        .await?)
+}
+
+/// One page of a finished job's rows.
+pub async fn fetch_rows(ch: &Client, tenant_id: &str, job_id: &str, offset: u64, limit: u64) -> anyhow::Result<Vec<serde_json::Value>> {
+    let rows = crate::tenant_query(
+        ch,
+        "SELECT row FROM query_job_results WHERE tenant_id = ? AND job_id = ? AND seq >= ? ORDER BY seq LIMIT ?",
+        tenant_id,
+    )
+    .bind(tenant_id)
+    .bind(job_id)
+    .bind(offset)
+    .bind(limit)
+    .fetch_all::<String>()
+    .await?;
+    Ok(rows.into_iter().map(|r| serde_json::from_str(&r).unwrap_or(serde_json::Value::String(r))).collect())
+}
+
+/// Stream a handler response into `query_job_results`. Returns the row count
+/// and metadata, or the error to show for the job.
+async fn store_response(
+    ch: &Client,
+    tenant_id: &str,
+    job_id: &str,
+    response: axum::response::Response,
+    max_bytes: usize,
+) -> Result<(u64, serde_json::Value), String> {
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if !status.is_success() {
+        let body = axum::body::to_bytes(response.into_body(), MAX_ERROR_BYTES).await.unwrap_or_default();
+        let msg = String::from_utf8_lossy(&body);
+        return Err(if msg.is_empty() { format!("query failed ({status})") } else { msg.into_owned() });
+    }
+
+    let mut body = response.into_body().into_data_stream();
+    let mut splitter = RowSplitter::new(&content_type);
+    let mut pending = Vec::new();
+    let (mut read, mut stored) = (0usize, 0u32);
+    let store = async |pending: &mut Vec<String>, stored: &mut u32| {
+        store_rows(ch, tenant_id, job_id, *stored, pending).await.map_err(|e| {
+            tracing::error!(job_id = %job_id, error = %e, "storing query job result failed");
+            "storing result failed".to_string()
+        })?;
+        *stored += pending.len() as u32;
+        pending.clear();
+        Ok::<_, String>(())
+    };
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| format!("reading result failed: {e}"))?;
+        read += chunk.len();
+        if read > max_bytes {
+            return Err(format!("result larger than {max_bytes} bytes"));
+        }
+        splitter.push(&chunk, &mut pending)?;
+        if pending.len() >= INSERT_CHUNK {
+            store(&mut pending, &mut stored).await?;
+        }
+    }
+    let meta = splitter.finish(&mut pending)?;
+    store(&mut pending, &mut stored).await?;
+    Ok((stored as u64, meta))
+}
+
+/// Insert `rows` numbered from `first_seq`.
+async fn store_rows(ch: &Client, tenant_id: &str, job_id: &str, first_seq: u32, rows: &[String]) -> anyhow::Result<()> {
+    for (chunk_idx, chunk) in rows.chunks(INSERT_CHUNK).enumerate() {
+        let mut insert = ch.insert("query_job_results")?;
+        for (i, row) in chunk.iter().enumerate() {
+            insert
+                .write(&ResultRow {
+                    tenant_id,
+                    job_id,
+                    seq: first_seq + (chunk_idx * INSERT_CHUNK + i) as u32,
+                    row: row.clone(),
+                })
+                .await?;
+        }
+        insert.end().await?;
+    }
+    Ok(())
+}
+
+// ── Splitting a response into rows ──
+
+/// Splits a handler response into rows (as JSON text) while it streams in.
+///
+/// A JSON array is one row per element. For a JSON object the rows are the
+/// elements of its main array field and the other fields are metadata: the
+/// first array to reach `STREAM_AFTER` elements is streamed from then on,
+/// and if none does the largest is picked when the body ends. Anything else
+/// (CSV, NDJSON, a JSON scalar) is one row per line.
+struct RowSplitter {
+    state: Splitting,
+}
+
+enum Splitting {
+    /// A JSON body whose first byte hasn't arrived yet.
+    JsonStart,
+    Lines(Vec<u8>),
+    Json(JsonScan),
+}
+
+impl RowSplitter {
+    fn new(content_type: &str) -> Self {
+        let state = if content_type.starts_with("application/json") {
+            Splitting::JsonStart
+        } else {
+            Splitting::Lines(Vec::new())
+        };
+        RowSplitter { state }
+    }
+
+    fn push(&mut self, mut data: &[u8], rows: &mut Vec<String>) -> Result<(), String> {
+        if let Splitting::JsonStart = self.state {
+            let Some(i) = data.iter().position(|b| !b.is_ascii_whitespace()) else { return Ok(()) };
+            self.state = match data[i] {
+                b'[' | b'{' => Splitting::Json(JsonScan::default()),
+                _ => Splitting::Lines(Vec::new()),
+            };
+            data = &data[i..];
+        }
+        match &mut self.state {
+            Splitting::JsonStart => {}
+            Splitting::Lines(partial) => {
+                partial.extend_from_slice(data);
+                let mut start = 0;
+                while let Some(n) = partial[start..].iter().position(|&b| b == b'\n') {
+                    push_line(&partial[start..start + n], rows);
+                    start += n + 1;
+                }
+                partial.drain(..start);
+            }
+            Splitting::Json(scan) => {
+                for &b in data {
+                    scan.byte(b, rows)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush what's left; returns the metadata.
+    fn finish(self, rows: &mut Vec<String>) -> Result<serde_json::Value, String> {
+        match self.state {
+            Splitting::JsonStart => Ok(serde_json::Value::Null),
+            Splitting::Lines(partial) => {
+                push_line(&partial, rows);
+                Ok(serde_json::Value::Null)
+            }
+            Splitting::Json(scan) => scan.finish(rows),
+        }
+    }
+}
+
+fn push_line(line: &[u8], rows: &mut Vec<String>) {
+    let line = String::from_utf8_lossy(line);
+    let line = line.strip_suffix('\r').unwrap_or(&line);
+    if !line.is_empty() {
+        let value = serde_json::from_str(line).unwrap_or_else(|_| serde_json::Value::String(line.to_string()));
+        rows.push(value.to_string());
+    }
+}
+
+/// Byte-level scanner for a JSON array or object body. Only structure is
+/// tracked here; each element (or non-array member) is parsed on its own.
+#[derive(Default)]
+struct JsonScan {
+    /// Containers open at the current byte.
+    depth: usize,
+    in_str: bool,
+    escaped: bool,
+    /// `[` or `{`.
+    top: u8,
+    done: bool,
+    /// The element being read, or for an object a member that isn't an array.
+    buf: Vec<u8>,
+    /// Depth inside the array being split into elements, if any.
+    array_depth: Option<usize>,
+    /// Arrays not picked as the rows yet: (field, elements).
+    held: Vec<(String, Vec<String>)>,
+    /// The field whose elements are the rows (`""` for a top-level array).
+    rows_field: Option<String>,
+    meta: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JsonScan {
+    fn byte(&mut self, b: u8, rows: &mut Vec<String>) -> Result<(), String> {
+        if self.done {
+            return if b.is_ascii_whitespace() { Ok(()) } else { Err("unexpected data after JSON result".into()) };
+        }
+        if self.in_str {
+            self.buf.push(b);
+            if self.escaped {
+                self.escaped = false;
+            } else if b == b'\\' {
+                self.escaped = true;
+            } else if b == b'"' {
+                self.in_str = false;
+            }
+            return Ok(());
+        }
+        match b {
+            b'"' => {
+                self.in_str = true;
+                self.buf.push(b);
+            }
+            b'[' | b'{' if self.depth == 0 => {
+                self.top = b;
+                self.depth = 1;
+                if b == b'[' {
+                    self.array_depth = Some(1);
+                    self.rows_field = Some(String::new());
+                }
+            }
+            // `"field": [` — split the member's array instead of buffering it.
+            b'[' if self.top == b'{' && self.depth == 1 => {
+                let key = String::from_utf8_lossy(&self.buf);
+                let key = key.trim().strip_suffix(':').map(str::trim).unwrap_or_default();
+                let key: String = serde_json::from_str(key).map_err(|_| "malformed JSON result".to_string())?;
+                self.buf.clear();
+                self.depth = 2;
+                self.array_depth = Some(2);
+                self.held.push((key, Vec::new()));
+            }
+            b'[' | b'{' => {
+                self.depth += 1;
+                self.buf.push(b);
+            }
+            b']' | b'}' if self.array_depth == Some(self.depth) => {
+                self.element(rows)?;
+                self.array_depth = None;
+                self.depth -= 1;
+                self.done = self.depth == 0;
+            }
+            b'}' if self.top == b'{' && self.depth == 1 => {
+                self.member()?;
+                self.depth = 0;
+                self.done = true;
+            }
+            b']' | b'}' => {
+                self.depth = self.depth.checked_sub(1).ok_or("malformed JSON result")?;
+                self.buf.push(b);
+            }
+            b',' if self.array_depth == Some(self.depth) => self.element(rows)?,
+            b',' if self.top == b'{' && self.depth == 1 => self.member()?,
+            _ => self.buf.push(b),
+        }
+        Ok(())
+    }
+
+    fn take_buf(&mut self) -> Option<Vec<u8>> {
+        let text = self.buf.trim_ascii().to_vec();
+        self.buf.clear();
+        (!text.is_empty()).then_some(text)
+    }
+
+    fn element(&mut self, rows: &mut Vec<String>) -> Result<(), String> {
+        let Some(text) = self.take_buf() else { return Ok(()) };
+        let value: serde_json::Value =
+            serde_json::from_slice(&text).map_err(|e| format!("malformed JSON result: {e}"))?;
+        let row = value.to_string();
+        if self.rows_field.is_some() && self.array_depth == Some(1) {
+            rows.push(row);
+            return Ok(());
+        }
+        let Some((field, elements)) = self.held.last_mut() else { return Ok(()) };
+        if self.rows_field.as_ref() == Some(field) {
+            rows.push(row);
+        } else {
+            elements.push(row);
+            if self.rows_field.is_none() && elements.len() >= STREAM_AFTER {
+                self.rows_field = Some(field.clone());
+                rows.append(elements);
+            }
+        }
+        Ok(())
+    }
+
+    fn member(&mut self) -> Result<(), String> {
+        let Some(text) = self.take_buf() else { return Ok(()) };
+        let mut object = b"{".to_vec();
+        object.extend_from_slice(&text);
+        object.push(b'}');
+        let member: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&object).map_err(|e| format!("malformed JSON result: {e}"))?;
+        self.meta.extend(member);
+        Ok(())
+    }
+
+    fn finish(mut self, rows: &mut Vec<String>) -> Result<serde_json::Value, String> {
+        if !self.done {
+            return Err("truncated JSON result".into());
+        }
+        if self.top == b'[' {
+            return Ok(serde_json::Value::Null);
+        }
+        if self.rows_field.is_none()
+            && let Some(i) = (0..self.held.len()).max_by_key(|&i| self.held[i].1.len())
+        {
+            let (field, elements) = self.held.remove(i);
+            rows.extend(elements);
+            self.rows_field = Some(field);
+        }
+        for (field, elements) in self.held {
+            if Some(&field) == self.rows_field.as_ref() {
+                continue;
+            }
+            let values = elements.iter().filter_map(|e| serde_json::from_str(e).ok()).collect();
+            self.meta.insert(field, serde_json::Value::Array(values));
+        }
+        if let Some(field) = self.rows_field {
+            self.meta.insert("rows_field".into(), serde_json::Value::String(field));
+        }
+        Ok(serde_json::Value::Object(self.meta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `body` in `step`-byte pieces.
+    fn split(content_type: &str, body: &[u8], step: usize) -> (Vec<serde_json::Value>, serde_json::Value) {
+        let mut splitter = RowSplitter::new(content_type);
+        let mut rows = Vec::new();
+        for piece in body.chunks(step) {
+            splitter.push(piece, &mut rows).unwrap();
+        }
+        let meta = splitter.finish(&mut rows).unwrap();
+        (rows.iter().map(|r| serde_json::from_str(r).unwrap()).collect(), meta)
+    }
+
+    #[test]
+    fn largest_array_is_the_rows() {
+        let body = br#"{"groups":[{"k":"a,]"},{"k":"b\"}"}],"total":2,"warnings":[]}"#;
+        for step in [1, 7, body.len()] {
+            let (rows, meta) = split("application/json", body, step);
+            assert_eq!(rows, vec![serde_json::json!({"k": "a,]"}), serde_json::json!({"k": "b\"}"})]);
+            assert_eq!(meta["total"], 2);
+            assert_eq!(meta["rows_field"], "groups");
+            assert_eq!(meta["warnings"], serde_json::json!([]));
+            assert!(meta.get("groups").is_none());
+        }
+
+        let (rows, meta) = split("application/json", b" [1, [2, 3], {\"a\": []}] ", 3);
+        assert_eq!(rows, vec![serde_json::json!(1), serde_json::json!([2, 3]), serde_json::json!({"a": []})]);
+        assert!(meta.is_null());
+
+        let (rows, _) = split("text/csv; charset=utf-8", b"a,b\n1,2\n\n3,4\n", 3);
+        assert_eq!(rows, vec![serde_json::json!("a,b"), serde_json::json!("1,2"), serde_json::json!("3,4")]);
+
+        let (rows, _) = split("application/x-ndjson", b"{\"a\":1}\r\n{\"a\":2}", 5);
+        assert_eq!(rows[1]["a"], 2);
+    }
+
+    #[test]
+    fn a_long_array_streams_before_the_body_ends() {
+        let mut splitter = RowSplitter::new("application/json");
+        let mut rows = Vec::new();
+        splitter.push(br#"{"columns":["a"],"rows":["#, &mut rows).unwrap();
+        for i in 0..STREAM_AFTER + 5 {
+            splitter.push(format!("{{\"a\":{i}}},").as_bytes(), &mut rows).unwrap();
+        }
+        // Past `STREAM_AFTER` the rows go out as they are read.
+        assert_eq!(rows.len(), STREAM_AFTER + 5);
+        splitter.push(br#"{"a":-1}],"truncated":false}"#, &mut rows).unwrap();
+        let meta = splitter.finish(&mut rows).unwrap();
+        assert_eq!(rows.len(), STREAM_AFTER + 6);
+        assert_eq!(meta["rows_field"], "rows");
+        assert_eq!(meta["columns"], serde_json::json!(["a"]));
+        assert_eq!(meta["truncated"], false);
+    }
+
+    #[test]
+    fn truncated_or_malformed_json_fails() {
+        let mut splitter = RowSplitter::new("application/json");
+        let mut rows = Vec::new();
+        splitter.push(br#"{"rows":[1,2"#, &mut rows).unwrap();
+        assert!(splitter.finish(&mut rows).is_err());
+
+        let mut splitter = RowSplitter::new("application/json");
+        assert!(splitter.push(b"[1, nope]", &mut rows).is_err());
+    }
+
+    #[test]
+    fn running_jobs_past_their_deadline_read_as_lost() {
+        let now = now_ms();
+        let mut record = JobRecord {
+            tenant_id: "t".into(),
+            job_id: "j".into(),
+            kind: "query".into(),
+            status: "running".into(),
+            error: String::new(),
+            rows: 0,
+            meta: "null".into(),
+            submitted_at: now - 10_000,
+            finished_at: 0,
+            deadline: now + 10_000,
+            version: 1,
+        };
+        assert_eq!(record.info(None).status, JobStatus::Running);
+        record.deadline = now - LOST_GRACE_MS - 1_000;
+        let info = record.info(None);
+        assert_eq!(info.status, JobStatus::Failed);
+        assert!(info.error.unwrap().contains("lost"));
+        record.status = "done".into();
+        assert_eq!(record.info(None).status, JobStatus::Done);
+    }
+
+    #[tokio::test]
+    async fn queries_inside_a_job_get_prefixed_ids() {
+        assert!(current_query_settings().is_none());
+        let scope = Arc::new(JobScope {
+            id: "job1".into(),
+            next_query: AtomicU32::new(0),
+            max_execution_secs: 60,
+            max_result_rows: 10,
+        });
+        let ids = JOB_SCOPE
+            .scope(scope, async {
+                let a = current_query_settings().unwrap();
+                let b = current_query_settings().unwrap();
+                (a.query_id, b.query_id, a.max_result_rows)
+            })
+            .await;
+        assert_eq!(ids, ("job1-0".to_string(), "job1-1".to_string(), "10".to_string()));
+    }
+}
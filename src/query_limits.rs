@@ -0,0 +1,503 @@
+//! Per-tenant query budgets.
+//!
+//! Each tenant has a `QueryLimits`: the process-wide defaults from
+//! `RUSH_QUERY_*`, or an override set through
+//! `PUT /api/v1/tenants/{id}/query-limits`. Overrides are cached here (loaded
+//! at startup and refreshed periodically, like the metric naming modes) so
+//! the read path never waits on the config store.
+//!
+//! Two kinds of limit:
+//!
+//! - Admission (`admit`): concurrent query requests and query requests per
+//!   minute, checked before the handler runs by the `enforce` route
+//!   middleware. Rejections are 429. Async query jobs keep the slot their
+//!   submit request took (`HeldSlot`) until the job finishes.
+//! - Scan (`settings`): `max_execution_time`, `max_bytes_to_read` and
+//!   `max_result_rows`, attached to every statement by `tenant_query` (so
+//!   they also cover the PromQL evaluator and background engines) and
+//!   enforced by ClickHouse. `query_error` turns the resulting ClickHouse
+//!   errors into 422s that name the limit.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::TenantContext;
+use crate::config::env_or;
+
+/// Interactive result row cap when nothing else is configured.
+const DEFAULT_MAX_RESULT_ROWS: u64 = 500_000;
+
+/// Window for `queries_per_minute`.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// One tenant's budget. 0 means unlimited for every field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryLimits {
+    /// Query requests running at once.
+    pub max_concurrent_queries: u32,
+    /// Query requests started per minute.
+    pub queries_per_minute: u32,
+    /// ClickHouse `max_execution_time` per statement, in seconds.
+    pub max_execution_secs: u32,
+    /// ClickHouse `max_bytes_to_read` per statement.
+    pub max_bytes_to_read: u64,
+    /// ClickHouse `max_result_rows` per statement. Interactive results are
+    /// truncated at the cap unless `enforce_result_rows`. Query jobs use their
+    /// own cap.
+    pub max_result_rows: u64,
+    /// Set once `max_result_rows` was set for the tenant itself: results over
+    /// the cap then fail the query (422) instead of being truncated.
+    #[serde(default)]
+    pub enforce_result_rows: bool,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        QueryLimits {
+            max_concurrent_queries: 0,
+            queries_per_minute: 0,
+            max_execution_secs: 0,
+            max_bytes_to_read: 0,
+            max_result_rows: DEFAULT_MAX_RESULT_ROWS,
+            enforce_result_rows: false,
+        }
+    }
+}
+
+impl QueryLimits {
+    pub fn from_env() -> Self {
+        let d = QueryLimits::default();
+        QueryLimits {
+            max_concurrent_queries: env_or("RUSH_QUERY_MAX_CONCURRENT", d.max_concurrent_queries),
+            queries_per_minute: env_or("RUSH_QUERY_PER_MINUTE", d.queries_per_minute),
+            max_execution_secs: env_or("RUSH_QUERY_MAX_EXECUTION_SECS", d.max_execution_secs),
+            max_bytes_to_read: env_or("RUSH_QUERY_MAX_BYTES_TO_READ", d.max_bytes_to_read),
+            max_result_rows: env_or("RUSH_QUERY_MAX_RESULT_ROWS", d.max_result_rows),
+            enforce_result_rows: false,
+        }
+    }
+}
+
+/// Partial update for `PUT /api/v1/tenants/{id}/query-limits`; omitted
+/// fields keep their current value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QueryLimitsUpdate {
+    pub max_concurrent_queries: Option<u32>,
+    pub queries_per_minute: Option<u32>,
+    pub max_execution_secs: Option<u32>,
+    pub max_bytes_to_read: Option<u64>,
+    pub max_result_rows: Option<u64>,
+}
+
+impl QueryLimitsUpdate {
+    pub fn apply(&self, base: QueryLimits) -> QueryLimits {
+        QueryLimits {
+            max_concurrent_queries: self.max_concurrent_queries.unwrap_or(base.max_concurrent_queries),
+            queries_per_minute: self.queries_per_minute.unwrap_or(base.queries_per_minute),
+            max_execution_secs: self.max_execution_secs.unwrap_or(base.max_execution_secs),
+            max_bytes_to_read: self.max_bytes_to_read.unwrap_or(base.max_bytes_to_read),
+            max_result_rows: self.max_result_rows.unwrap_or(base.max_result_rows),
+            enforce_result_rows: base.enforce_result_rows || self.max_result_rows.is_some(),
+        }
+    }
+}
+
+// ── Limits cache ──
+
+static DEFAULTS: LazyLock<QueryLimits> = LazyLock::new(QueryLimits::from_env);
+
+/// Tenants with an override.
+static OVERRIDES: LazyLock<RwLock<Arc<HashMap<String, QueryLimits>>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(HashMap::new())));
+
+/// The process-wide defaults.
+pub fn defaults() -> QueryLimits {
+    *DEFAULTS
+}
+
+/// The override for a tenant, if any.
+pub fn override_for(tenant_id: &str) -> Option<QueryLimits> {
+    OVERRIDES.read().ok().and_then(|g| g.get(tenant_id).copied())
+}
+
+/// The limits in effect for a tenant.
+pub fn for_tenant(tenant_id: &str) -> QueryLimits {
+    override_for(tenant_id).unwrap_or_else(defaults)
+}
+
+/// Replace the cached overrides with rows from the config store.
+pub fn set_overrides(rows: Vec<(String, QueryLimits)>) {
+    if let Ok(mut g) = OVERRIDES.write() {
+        *g = Arc::new(rows.into_iter().collect());
+    }
+}
+
+/// Update one tenant's cached override (after a config change); `None`
+/// returns it to the defaults.
+pub fn set_tenant_override(tenant_id: &str, limits: Option<QueryLimits>) {
+    if let Ok(mut g) = OVERRIDES.write() {
+        let mut map = (**g).clone();
+        match limits {
+            Some(l) => map.insert(tenant_id.to_string(), l),
+            None => map.remove(tenant_id),
+        };
+        *g = Arc::new(map);
+    }
+}
+
+/// `(name, value)` ClickHouse settings for a tenant's scan limits. Unlimited
+/// fields are left out so the server profile applies.
+pub fn settings(tenant_id: &str) -> Vec<(&'static str, String)> {
+    let l = for_tenant(tenant_id);
+    let mut out = Vec::with_capacity(3);
+    if l.max_execution_secs > 0 {
+        out.push(("max_execution_time", l.max_execution_secs.to_string()));
+    }
+    if l.max_bytes_to_read > 0 {
+        out.push(("max_bytes_to_read", l.max_bytes_to_read.to_string()));
+    }
+    if l.max_result_rows > 0 {
+        out.push(("max_result_rows", l.max_result_rows.to_string()));
+    }
+    out
+}
+
+/// `result_overflow_mode` for a tenant's interactive reads: a row limit set
+/// for the tenant is enforced (`throw`, reported as a 422), while the process
+/// default only truncates (`break`).
+pub fn result_overflow_mode(tenant_id: &str) -> &'static str {
+    let enforced = for_tenant(tenant_id);
+    if enforced.enforce_result_rows && enforced.max_result_rows > 0 { "throw" } else { "break" }
+}
+
+/// The server profile's own `max_execution_time` in seconds (0 = none or
+/// unknown), read once at startup by `probe_profile_timeout`.
+static PROFILE_MAX_EXECUTION_SECS: AtomicU64 = AtomicU64::new(0);
+
+/// Read the ClickHouse profile's `max_execution_time`, so a timeout can be
+/// told apart from the tenant's own limit.
+pub async fn probe_profile_timeout(ch: &clickhouse::Client) {
+    #[derive(clickhouse::Row, Deserialize)]
+    struct Row {
+        secs: f64,
+    }
+    match ch.query("SELECT toFloat64(getSetting('max_execution_time')) AS secs").fetch_one::<Row>().await {
+        Ok(row) => PROFILE_MAX_EXECUTION_SECS.store(row.secs.ceil() as u64, Ordering::Relaxed),
+        Err(e) => tracing::warn!(error = %e, "could not read the ClickHouse profile's max_execution_time"),
+    }
+}
+
+// ── Violations ──
+
+/// A limit a query ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Concurrency(u32),
+    Rate(u32),
+    ExecutionTime(u32),
+    BytesToRead(u64),
+    ResultRows(u64),
+}
+
+impl LimitExceeded {
+    /// 429 for admission limits (retry later), 422 for scan limits (the
+    /// query itself is too expensive).
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitExceeded::Concurrency(_) | LimitExceeded::Rate(_) => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    /// The limit's field name in `QueryLimits`.
+    pub fn limit(&self) -> &'static str {
+        match self {
+            LimitExceeded::Concurrency(_) => "max_concurrent_queries",
+            LimitExceeded::Rate(_) => "queries_per_minute",
+            LimitExceeded::ExecutionTime(_) => "max_execution_secs",
+            LimitExceeded::BytesToRead(_) => "max_bytes_to_read",
+            LimitExceeded::ResultRows(_) => "max_result_rows",
+        }
+    }
+}
+
+/// Message prefix that marks a limit violation inside a string error (the
+/// PromQL evaluator reports errors as strings).
+const MESSAGE_PREFIX: &str = "query limit exceeded";
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            LimitExceeded::Concurrency(n) | LimitExceeded::Rate(n) | LimitExceeded::ExecutionTime(n) => n.to_string(),
+            LimitExceeded::BytesToRead(n) | LimitExceeded::ResultRows(n) => n.to_string(),
+        };
+        write!(f, "{MESSAGE_PREFIX}: {}={value}", self.limit())?;
+        match self {
+            LimitExceeded::Concurrency(_) => write!(f, " (too many queries running for this tenant; retry shortly)"),
+            LimitExceeded::Rate(_) => write!(f, " (too many queries in the last minute; retry shortly)"),
+            _ => write!(f, " (narrow the time range or add filters)"),
+        }
+    }
+}
+
+impl IntoResponse for LimitExceeded {
+    fn into_response(self) -> Response {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+/// ClickHouse exception code in an error message (`Code: 307. DB::Exception…`).
+fn exception_code(message: &str) -> Option<u32> {
+    let rest = &message[message.find("Code: ")? + "Code: ".len()..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// The tenant limit behind a ClickHouse error, if that's what it was.
+pub fn exceeded(tenant_id: &str, e: &clickhouse::error::Error) -> Option<LimitExceeded> {
+    let profile_secs = PROFILE_MAX_EXECUTION_SECS.load(Ordering::Relaxed);
+    limit_for_code(for_tenant(tenant_id), profile_secs, exception_code(&e.to_string())?)
+}
+
+fn limit_for_code(l: QueryLimits, profile_secs: u64, code: u32) -> Option<LimitExceeded> {
+    // A timeout is only the tenant's when its limit is the tighter one.
+    let tenant_timeout =
+        l.max_execution_secs > 0 && (profile_secs == 0 || u64::from(l.max_execution_secs) < profile_secs);
+    match code {
+        // TIMEOUT_EXCEEDED
+        159 if tenant_timeout => Some(LimitExceeded::ExecutionTime(l.max_execution_secs)),
+        // TOO_MANY_BYTES
+        307 if l.max_bytes_to_read > 0 => Some(LimitExceeded::BytesToRead(l.max_bytes_to_read)),
+        // TOO_MANY_ROWS_OR_BYTES (result overflow with mode=throw)
+        396 if l.max_result_rows > 0 => Some(LimitExceeded::ResultRows(l.max_result_rows)),
+        _ => None,
+    }
+}
+
+/// Handler error for a failed ClickHouse read: the 422 naming the limit when
+/// a tenant limit stopped it, otherwise a 500 with `message`.
+pub fn query_error(tenant_id: &str, e: &clickhouse::error::Error, message: &str) -> (StatusCode, String) {
+    match exceeded(tenant_id, e) {
+        Some(l) => (l.status(), l.to_string()),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, message.to_string()),
+    }
+}
+
+/// Status for a PromQL evaluation error: 422 for a limit violation, 400
+/// otherwise.
+pub fn promql_error_status(message: &str) -> StatusCode {
+    if message.starts_with(MESSAGE_PREFIX) {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+// ── Admission ──
+
+#[derive(Default)]
+struct TenantUsage {
+    running: u32,
+    window_start: Option<Instant>,
+    window_count: u32,
+}
+
+static USAGE: LazyLock<DashMap<String, Mutex<TenantUsage>>> = LazyLock::new(DashMap::new);
+
+/// A running query request; frees its concurrency slot on drop.
+pub struct QuerySlot {
+    tenant_id: String,
+}
+
+impl Drop for QuerySlot {
+    fn drop(&mut self) {
+        if let Some(u) = USAGE.get(&self.tenant_id) {
+            let mut u = u.lock().unwrap();
+            u.running = u.running.saturating_sub(1);
+        }
+    }
+}
+
+/// Take a concurrency slot and count one request against the per-minute
+/// rate, or say which limit is in the way.
+pub fn admit(tenant_id: &str) -> Result<QuerySlot, LimitExceeded> {
+    admit_with(tenant_id, for_tenant(tenant_id), Instant::now())
+}
+
+fn admit_with(tenant_id: &str, limits: QueryLimits, now: Instant) -> Result<QuerySlot, LimitExceeded> {
+    let entry = USAGE.entry(tenant_id.to_string()).or_default();
+    let mut u = entry.lock().unwrap();
+    if limits.max_concurrent_queries > 0 && u.running >= limits.max_concurrent_queries {
+        return Err(LimitExceeded::Concurrency(limits.max_concurrent_queries));
+    }
+    if limits.queries_per_minute > 0 {
+        match u.window_start {
+            Some(start) if now.duration_since(start) < RATE_WINDOW => {
+                if u.window_count >= limits.queries_per_minute {
+                    return Err(LimitExceeded::Rate(limits.queries_per_minute));
+                }
+                u.window_count += 1;
+            }
+            _ => {
+                u.window_start = Some(now);
+                u.window_count = 1;
+            }
+        }
+    }
+    u.running += 1;
+    Ok(QuerySlot { tenant_id: tenant_id.to_string() })
+}
+
+/// An admitted request's slot, put in the request extensions by `enforce`.
+/// A handler that hands work off (`POST /api/v1/jobs`) keeps a clone so the
+/// slot stays taken until that work is done.
+#[derive(Clone)]
+pub struct HeldSlot {
+    _slot: Arc<QuerySlot>,
+}
+
+/// Route middleware for query endpoints: admit the request under the
+/// tenant's concurrency and rate limits and hold the slot while the handler
+/// runs (a streamed export body is not counted once its headers are sent).
+pub async fn enforce(mut req: Request, next: Next) -> Response {
+    let Some(tenant_id) = req.extensions().get::<TenantContext>().map(|t| t.tenant_id.clone()) else {
+        return next.run(req).await;
+    };
+    let slot = match admit(&tenant_id) {
+        Ok(slot) => HeldSlot { _slot: Arc::new(slot) },
+        Err(l) => {
+            tracing::warn!(tenant_id = %tenant_id, limit = l.limit(), "query rejected by tenant limit");
+            return l.into_response();
+        }
+    };
+    req.extensions_mut().insert(slot.clone());
+    let resp = next.run(req).await;
+    drop(slot);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admission_enforces_concurrency_and_rate() {
+        let limits = QueryLimits { max_concurrent_queries: 2, queries_per_minute: 3, ..QueryLimits::default() };
+        let t0 = Instant::now();
+        let a = admit_with("t-admit", limits, t0).unwrap();
+        let _b = admit_with("t-admit", limits, t0).unwrap();
+        assert_eq!(admit_with("t-admit", limits, t0).err(), Some(LimitExceeded::Concurrency(2)));
+        drop(a);
+        let _c = admit_with("t-admit", limits, t0).unwrap();
+        drop(_c);
+        // Three admitted in this window; the fourth is over the rate.
+        assert_eq!(admit_with("t-admit", limits, t0).err(), Some(LimitExceeded::Rate(3)));
+        assert!(admit_with("t-admit", limits, t0 + RATE_WINDOW).is_ok());
+    }
+
+    #[tokio::test]
+    async fn submitted_jobs_hold_their_slot() {
+        use axum::{Extension, Router, routing::post};
+
+        let tenant = "t-jobs-budget";
+        set_tenant_override(tenant, Some(QueryLimits { max_concurrent_queries: 1, ..QueryLimits::default() }));
+        let (finish, finished) = tokio::sync::watch::channel(false);
+        // Stands in for `submit_job`: the job keeps the request's slot and
+        // outlives the 202.
+        let submit = move |Extension(slot): Extension<HeldSlot>| {
+            let mut finished = finished.clone();
+            async move {
+                tokio::spawn(async move {
+                    let _slot = slot;
+                    let _ = finished.wait_for(|done| *done).await;
+                });
+                StatusCode::ACCEPTED
+            }
+        };
+        let app = Router::new()
+            .route("/api/v1/jobs", post(submit).route_layer(axum::middleware::from_fn(enforce)))
+            .layer(Extension(TenantContext { tenant_id: tenant.to_string() }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/v1/jobs", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let status = |url: String| {
+            let client = client.clone();
+            async move { client.post(url).send().await.unwrap().status() }
+        };
+        assert_eq!(status(url.clone()).await, StatusCode::ACCEPTED);
+        // The first job is still running, so the tenant is at its limit.
+        assert_eq!(status(url.clone()).await, StatusCode::TOO_MANY_REQUESTS);
+        finish.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while USAGE.get(tenant).is_some_and(|u| u.lock().unwrap().running > 0) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(status(url).await, StatusCode::ACCEPTED);
+        set_tenant_override(tenant, None);
+    }
+
+    #[test]
+    fn clickhouse_limit_errors_are_recognised() {
+        assert_eq!(
+            exception_code("Code: 307. DB::Exception: Limit for (uncompressed) bytes to read exceeded"),
+            Some(307)
+        );
+        assert_eq!(exception_code("bad response: Code: 159. DB::Exception: Timeout exceeded"), Some(159));
+        assert_eq!(exception_code("connection refused"), None);
+
+        let l = LimitExceeded::BytesToRead(1 << 30);
+        assert_eq!(l.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(promql_error_status(&l.to_string()), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(l.to_string().contains("max_bytes_to_read=1073741824"));
+        assert_eq!(LimitExceeded::Rate(10).status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn timeouts_map_to_the_tighter_limit() {
+        let l = QueryLimits { max_execution_secs: 30, ..QueryLimits::default() };
+        assert_eq!(limit_for_code(l, 0, 159), Some(LimitExceeded::ExecutionTime(30)));
+        assert_eq!(limit_for_code(l, 60, 159), Some(LimitExceeded::ExecutionTime(30)));
+        // The server profile's own timeout fired, not the tenant's.
+        assert_eq!(limit_for_code(l, 10, 159), None);
+        assert_eq!(limit_for_code(l, 30, 159), None);
+        assert_eq!(limit_for_code(QueryLimits::default(), 0, 159), None);
+        assert_eq!(limit_for_code(l, 0, 396), Some(LimitExceeded::ResultRows(DEFAULT_MAX_RESULT_ROWS)));
+    }
+
+    #[test]
+    fn only_tenant_row_limits_throw() {
+        let put = |update: QueryLimitsUpdate| {
+            let limits = update.apply(for_tenant("t-overflow"));
+            set_tenant_override("t-overflow", Some(limits));
+        };
+        assert_eq!(result_overflow_mode("t-overflow"), "break");
+        // An override that only sets concurrency keeps the default truncation.
+        put(QueryLimitsUpdate { max_concurrent_queries: Some(4), ..Default::default() });
+        assert_eq!(for_tenant("t-overflow").max_result_rows, DEFAULT_MAX_RESULT_ROWS);
+        assert_eq!(result_overflow_mode("t-overflow"), "break");
+        put(QueryLimitsUpdate { max_result_rows: Some(1000), ..Default::default() });
+        assert_eq!(result_overflow_mode("t-overflow"), "throw");
+        // Later updates of other fields keep the row limit enforced.
+        put(QueryLimitsUpdate { queries_per_minute: Some(60), ..Default::default() });
+        assert_eq!(result_overflow_mode("t-overflow"), "throw");
+        put(QueryLimitsUpdate { max_result_rows: Some(0), ..Default::default() });
+        assert_eq!(result_overflow_mode("t-overflow"), "break");
+        set_tenant_override("t-overflow", None);
+    }
+}
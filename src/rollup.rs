@@ -19,6 +19,25 @@
 //! windows `[t-step/2, t+step/2]` on an arbitrary (not necessarily wall-clock) step grid.
 //! Those reads stay on raw (see `promql::eval`), where we push the centered-window
 //! bucketing into SQL but read raw samples.
+//!
+//! PromQL range functions are the one opt-in exception: in approximate mode (see
+//! `promql::rollup`) `rate`, `increase` and the `*_over_time` family are served from a
+//! rollup when every window `[t - range, t]` is a whole number of rollup buckets
+//! (`select_range_source`). Bucket states are merged per window instead of re-reading
+//! raw samples, with these error bounds against the raw evaluation:
+//!   - `count/sum/avg/min/max/last/present_over_time`: exact, except on the window
+//!     edges. A sample stamped exactly on the window end `t` belongs to the next bucket
+//!     and is left out. One stamped exactly on the window start `t - range` opens the
+//!     first bucket and is counted, as the raw evaluation here does, whereas Prometheus
+//!     windows `(t - range, t]` leave it out.
+//!   - `increase`: the window runs from the last sample before `t - range` to the last
+//!     sample before `t` instead of from the first to the last sample inside
+//!     `[t - range, t]`, so each end can move by up to one scrape interval of counter
+//!     growth. A counter reset inside a bucket is bridged through the bucket's max; a
+//!     second reset in the same bucket loses the growth between the two, and a series
+//!     that starts inside the window loses the growth within its first bucket.
+//!   - `rate`: the same increase over exactly `range` seconds. For a steadily growing
+//!     counter both agree; in general the relative error is about scrape/range.
 
 /// Span (seconds) at/under which a read always uses raw, regardless of step. Recent,
 /// short windows are cheap on raw and avoid any rollup-freshness edge (the live MV may
@@ -105,6 +124,24 @@ pub fn select_interval_source(start_secs: f64, end_secs: f64, bucket_secs: i64)
     }
 }
 
+/// Choose a source for a PromQL range function over `[t - range, t]` at every step
+/// `t = first_step + k * step`. A rollup qualifies only when its buckets tile every
+/// window exactly: the range, the step and the first step are all multiples of its
+/// interval (so in particular step >= interval). The coarsest qualifying rollup wins.
+pub fn select_range_source(range_secs: f64, step_secs: f64, first_step_secs: f64) -> Source {
+    let tiles = |interval: i64| {
+        let i = interval as f64;
+        range_secs >= i && [range_secs, step_secs, first_step_secs].iter().all(|v| v % i == 0.0)
+    };
+    if tiles(HOUR_INTERVAL_SECS) {
+        Source::Rollup1h
+    } else if tiles(MIN_INTERVAL_SECS) {
+        Source::Rollup1m
+    } else {
+        Source::Raw
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -162,6 +199,23 @@ mod tests {
         assert_eq!(select_interval_source(t0, t0 + 600.0, 3600), Source::Raw);
     }
 
+    #[test]
+    fn range_source_requires_windows_to_tile() {
+        let t0 = 1_699_999_200.0; // on the hour
+        // 1h range at a 1h step → 1h rollup
+        assert_eq!(select_range_source(3600.0, 3600.0, t0), Source::Rollup1h);
+        // 1h range at a 5m step → only the 1m rollup lines up with every step
+        assert_eq!(select_range_source(3600.0, 300.0, t0), Source::Rollup1m);
+        // 5m range at a 1h step → the window is smaller than an hour bucket
+        assert_eq!(select_range_source(300.0, 3600.0, t0), Source::Rollup1m);
+        // step below the smallest rollup interval → raw
+        assert_eq!(select_range_source(3600.0, 30.0, t0), Source::Raw);
+        // grid not on a minute boundary → raw
+        assert_eq!(select_range_source(3600.0, 3600.0, t0 + 15.0), Source::Raw);
+        // 90s range → raw
+        assert_eq!(select_range_source(90.0, 60.0, t0), Source::Raw);
+    }
+
     #[test]
     fn suffix_and_interval() {
         assert_eq!(Source::Raw.suffix(), "");
@@ -1,20 +1,58 @@
 /// Crash-safe segmented disk spool for ClickHouse write backpressure.
 ///
-/// Each segment is a flat file of framed records:
+/// Segment format v2 (written by this version) — an 8-byte header
+/// `SEGMENT_MAGIC` followed by records:
+///   [u32 LE: REC_MAGIC] [u32 LE: body len] [u32 LE: CRC32C of body] [body]
+///   body = [u8: codec] [u32 LE: table name len] [table name bytes] [payload]
+/// where codec 1 means the payload is zstd-compressed (0 = stored as-is, used
+/// when compression doesn't help). A record whose CRC or framing doesn't check
+/// out is skipped and counted; the reader resyncs on the next `REC_MAGIC`.
+///
+/// Legacy (v1) segments have no header and are a flat run of
 ///   [u32 LE: table name len] [table name bytes] [u32 LE: payload len] [payload bytes]
+/// and are still read, so a spool written by an older build drains after upgrade.
 ///
-/// Segments are named `seg-<unix_millis>-<seq>.spool`.
-/// The current (open) segment is rotated once it reaches SEGMENT_MAX_BYTES (32 MiB).
+/// Segments are named `seg-<unix_millis>-<seq>-<hex tenant id>.spool`; each
+/// tenant appends to its own open segment, so every segment belongs to exactly
+/// one tenant (legacy names without the suffix are attributed to tenant "").
+/// An open segment is rotated once it reaches SEGMENT_MAX_BYTES (32 MiB).
 /// On open, existing `*.spool` files are scanned to restore total_bytes.
+///
+/// Replay is fair across tenants: `next_batch` picks the tenant with a
+/// weighted round-robin (`FairScheduler`) and drains that tenant's oldest
+/// segment, so one tenant's large backlog can't starve everyone else after an
+/// outage. A `TenantPolicy` also caps how much of the spool one tenant may
+/// hold (half by default, while another tenant has data spooled); past that, only
+/// that tenant's appends fail with `SpoolFull` (→ 429).
+///
+/// Records the replayer gives up on (see `ChWriter::spawn_replayer`) are moved
+/// to a `deadletter/` subdirectory as `dl-<unix_millis>-<seq>.dead` files, one
+/// record per file (see `encode_dead_letter`). They do not count against the
+/// spool cap and are never replayed automatically.
 
+use std::collections::{BTreeSet, HashMap};
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const SEGMENT_MAX_BYTES: u64 = 32 * 1024 * 1024; // 32 MiB per segment
+const DEAD_LETTER_DIR: &str = "deadletter";
+/// v2 segment header. A v1 segment starts with a table-name length, which can
+/// never look like this.
+pub const SEGMENT_MAGIC: [u8; 8] = *b"RSPOOL\x00\x02";
+/// Start-of-record marker, used to resync after a corrupt record.
+const REC_MAGIC: u32 = 0x5253_5243; // "CRSR" LE
+const REC_HEADER_LEN: usize = 12;
+const CODEC_NONE: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+const ZSTD_LEVEL: i32 = 3;
+
+/// A segment's readable `(table, payload)` records plus the number of records
+/// skipped as corrupt.
+pub type SegmentRead = (Vec<(String, Vec<u8>)>, u64);
 
 /// Returned by `Spool::append` when the byte cap has been reached.
 #[derive(Debug)]
@@ -33,18 +71,33 @@ struct SpoolInner {
     total_bytes: u64,
     /// Sequence counter to disambiguate segments created in the same millisecond.
     seq: u64,
-    /// Currently open segment file + its current size.
-    current: Option<(File, u64, PathBuf)>,
+    /// Currently open segment file + its current size, per tenant.
+    current: HashMap<String, (File, u64, PathBuf)>,
     /// All segment paths (open + sealed), kept sorted. Zero-padded `seg-<ms>-<seq>`
     /// names sort chronologically, so `first()` is the oldest. Maintained on
     /// open/rotate/remove so stats and the replayer never have to read_dir.
-    segments: std::collections::BTreeSet<PathBuf>,
+    segments: BTreeSet<PathBuf>,
+    /// The same index split by owning tenant, plus each tenant's byte total.
+    tenants: HashMap<String, TenantSegments>,
+    policy: TenantPolicy,
+    scheduler: FairScheduler,
+    /// Quarantined (dead-letter) entries: count + total file size.
+    dead_count: usize,
+    dead_bytes: u64,
+}
+
+#[derive(Default)]
+struct TenantSegments {
+    bytes: u64,
+    segments: BTreeSet<PathBuf>,
 }
 
 pub struct Spool {
     inner: Mutex<SpoolInner>,
     /// Monotonic count of segments successfully drained (for drain-rate metric).
     committed: AtomicU64,
+    /// Monotonic count of records skipped as corrupt/torn on read.
+    corrupt: AtomicU64,
 }
 
 impl Spool {
@@ -56,7 +109,8 @@ impl Spool {
 
         let mut total_bytes: u64 = 0;
         let mut max_seq: u64 = 0;
-        let mut segments = std::collections::BTreeSet::new();
+        let mut segments = BTreeSet::new();
+        let mut tenants: HashMap<String, TenantSegments> = HashMap::new();
 
         // Scan existing segments once at startup to restore byte count, max
         // sequence, and the in-memory segment index.
@@ -68,6 +122,9 @@ impl Spool {
                 let size = entry.metadata()?.len();
                 total_bytes += size;
                 segments.insert(entry.path());
+                let t = tenants.entry(segment_tenant(&name_str)).or_default();
+                t.bytes += size;
+                t.segments.insert(entry.path());
 
                 // Parse seq from seg-<ms>-<seq>.spool
                 if let Some(seq) = parse_seq(&name_str) {
@@ -78,16 +135,43 @@ impl Spool {
             }
         }
 
+        // Same for the dead-letter area.
+        let dead_dir = dir.join(DEAD_LETTER_DIR);
+        fs::create_dir_all(&dead_dir)?;
+        let mut dead_count = 0usize;
+        let mut dead_bytes = 0u64;
+        for entry in fs::read_dir(&dead_dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name_str = name.to_string_lossy();
+            if name_str.ends_with(".dead.tmp") {
+                // Interrupted quarantine: the record was never acknowledged.
+                let _ = fs::remove_file(entry.path());
+            } else if name_str.ends_with(".dead") {
+                dead_count += 1;
+                dead_bytes += entry.metadata()?.len();
+                if let Some(seq) = parse_seq(name_str.trim_end_matches(".dead")) {
+                    max_seq = max_seq.max(seq);
+                }
+            }
+        }
+
         Ok(Spool {
             inner: Mutex::new(SpoolInner {
                 dir,
                 max_bytes,
                 total_bytes,
                 seq: max_seq,
-                current: None,
+                current: HashMap::new(),
                 segments,
+                tenants,
+                policy: TenantPolicy::default(),
+                scheduler: FairScheduler::default(),
+                dead_count,
+                dead_bytes,
             }),
             committed: AtomicU64::new(0),
+            corrupt: AtomicU64::new(0),
         })
     }
 
@@ -95,6 +179,10 @@ impl Spool {
         self.committed.load(Ordering::Relaxed)
     }
 
+    pub fn corrupt_records_total(&self) -> u64 {
+        self.corrupt.load(Ordering::Relaxed)
+    }
+
     /// Age (seconds) of the oldest pending segment, parsed from its `seg-<ms>-`
     /// filename. `None` when empty. Served from the in-memory index — no I/O.
     pub fn oldest_age_secs(&self) -> Option<u64> {
@@ -109,120 +197,145 @@ impl Spool {
         Some(now.saturating_sub(ms) / 1000)
     }
 
-    /// Append a record to the spool.  Returns `Err(SpoolFull)` when
-    /// `total_bytes + record_len > max_bytes`.  Thread-safe.
-    pub fn append(&self, table: &str, payload: &[u8]) -> Result<(), SpoolFull> {
-        let mut g = self.inner.lock().unwrap();
+    /// Replace the per-tenant cap / replay weights.
+    pub fn set_tenant_policy(&self, policy: TenantPolicy) {
+        self.inner.lock().unwrap().policy = policy;
+    }
 
-        // Frame: 4 bytes table-len + table + 4 bytes payload-len + payload
-        let table_bytes = table.as_bytes();
-        let frame_len = 4 + table_bytes.len() + 4 + payload.len();
+    /// Append a record to `tenant`'s open segment.  Returns `Err(SpoolFull)`
+    /// when `total_bytes + record_len > max_bytes`, or when the tenant would
+    /// exceed its own cap.  Thread-safe. The payload is compressed before
+    /// taking the lock, so the caps apply to on-disk bytes.
+    pub fn append(&self, tenant: &str, table: &str, payload: &[u8]) -> Result<(), SpoolFull> {
+        let frame = encode_record(table, payload);
+        let frame_len = frame.len() as u64;
 
-        if g.total_bytes + frame_len as u64 > g.max_bytes {
-            return Err(SpoolFull);
-        }
+        let mut guard = self.inner.lock().unwrap();
+        let g = &mut *guard;
 
-        // Rotate if the current segment would exceed SEGMENT_MAX_BYTES.
-        let needs_rotate = match &g.current {
-            Some((_, size, _)) => *size + frame_len as u64 > SEGMENT_MAX_BYTES,
+        // Rotate if the tenant's open segment would exceed SEGMENT_MAX_BYTES.
+        // A new segment also pays for its header.
+        let needs_rotate = match g.current.get(tenant) {
+            Some((_, size, _)) => *size + frame_len > SEGMENT_MAX_BYTES,
             None => true,
         };
+        let header_len = if needs_rotate { SEGMENT_MAGIC.len() as u64 } else { 0 };
+        let needed = frame_len + header_len;
+
+        if g.total_bytes + needed > g.max_bytes {
+            return Err(SpoolFull);
+        }
+        let active = active_tenants(g.tenants.keys().map(String::as_str), tenant);
+        let tenant_bytes = g.tenants.get(tenant).map_or(0, |t| t.bytes);
+        if tenant_bytes + needed > g.policy.tenant_max_bytes(g.max_bytes, active) {
+            return Err(SpoolFull);
+        }
 
         if needs_rotate {
-            if let Some((mut f, _, _)) = g.current.take() {
+            if let Some((mut f, _, _)) = g.current.remove(tenant) {
                 let _ = f.flush();
                 let _ = sync_file(&mut f);
             }
             g.seq += 1;
-            let path = g.dir.join(seg_name(g.seq));
-            let file = OpenOptions::new()
+            let path = g.dir.join(seg_name(g.seq, tenant));
+            let mut file = OpenOptions::new()
                 .create(true)
                 .append(true)
                 .open(&path)
                 .map_err(|_| SpoolFull)?;
+            file.write_all(&SEGMENT_MAGIC).map_err(|_| SpoolFull)?;
             g.segments.insert(path.clone());
-            g.current = Some((file, 0, path));
+            let t = g.tenants.entry(tenant.to_string()).or_default();
+            t.segments.insert(path.clone());
+            t.bytes += header_len;
+            g.current.insert(tenant.to_string(), (file, header_len, path));
+            g.total_bytes += header_len;
         }
 
-        if let Some((file, size, _)) = g.current.as_mut() {
-            // Write frame
-            let tbl_len = table_bytes.len() as u32;
-            let pay_len = payload.len() as u32;
-            file.write_all(&tbl_len.to_le_bytes()).map_err(|_| SpoolFull)?;
-            file.write_all(table_bytes).map_err(|_| SpoolFull)?;
-            file.write_all(&pay_len.to_le_bytes()).map_err(|_| SpoolFull)?;
-            file.write_all(payload).map_err(|_| SpoolFull)?;
-            *size += frame_len as u64;
-            g.total_bytes += frame_len as u64;
+        if let Some((file, size, _)) = g.current.get_mut(tenant) {
+            file.write_all(&frame).map_err(|_| SpoolFull)?;
+            *size += frame_len;
+            g.total_bytes += frame_len;
+            if let Some(t) = g.tenants.get_mut(tenant) {
+                t.bytes += frame_len;
+            }
         }
 
         Ok(())
     }
 
-    /// Take the path of the oldest closed (completed) segment, for replay.
-    /// Does NOT include the currently open segment. Served from the in-memory
-    /// index — no directory scan.
-    pub fn take_oldest_segment(&self) -> Option<PathBuf> {
+    /// Pending bytes and segment count per tenant.
+    pub fn tenant_usage(&self) -> Vec<TenantUsage> {
         let g = self.inner.lock().unwrap();
-        let current_path = g.current.as_ref().map(|(_, _, p)| p.clone());
-        g.segments
+        let mut out: Vec<TenantUsage> = g
+            .tenants
             .iter()
-            .find(|seg| current_path.as_ref() != Some(seg))
-            .cloned()
+            .map(|(t, s)| TenantUsage { tenant: t.clone(), pending_bytes: s.bytes, pending_count: s.segments.len() })
+            .collect();
+        out.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        out
     }
 
-    /// Read all framed records from a segment file.
-    ///
-    /// Records are not fsync'd per-append (only on rotation), so an unclean
-    /// shutdown can leave a torn trailing record. We tolerate that: any EOF
-    /// encountered partway through a frame ends parsing and returns the records
-    /// read so far, rather than discarding the whole (otherwise-valid) segment.
-    pub fn read_segment(path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
-        let mut file = File::open(path)?;
-        let mut records = Vec::new();
-
-        loop {
-            // Read table name length. A clean EOF here is the normal end.
-            let mut buf4 = [0u8; 4];
-            match file.read_exact(&mut buf4) {
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e),
-            }
-            let tbl_len = u32::from_le_bytes(buf4) as usize;
-            let mut tbl_buf = vec![0u8; tbl_len];
-            if read_exact_or_eof(&mut file, &mut tbl_buf)?.is_none() {
-                break; // torn record — stop here
-            }
-            let table = match String::from_utf8(tbl_buf) {
-                Ok(t) => t,
-                Err(_) => break, // garbage tail — stop
-            };
-
-            // Read payload length + payload.
-            if read_exact_or_eof(&mut file, &mut buf4)?.is_none() {
-                break;
+    /// Take the next closed (completed) segment to replay, with its tenant: the
+    /// tenant is chosen by weighted round-robin among tenants that have one,
+    /// and within a tenant the oldest goes first. A tenant whose only data is
+    /// in its open segment has that segment sealed first, so a small trickle
+    /// from one tenant isn't stuck behind another tenant's closed backlog.
+    /// Served from the in-memory index — no directory scan.
+    pub fn take_next_segment(&self) -> Option<(String, PathBuf)> {
+        let mut guard = self.inner.lock().unwrap();
+        let g = &mut *guard;
+        let idle: Vec<String> = g
+            .current
+            .iter()
+            .filter(|(tenant, (_, _, open))| {
+                g.tenants.get(*tenant).is_none_or(|t| t.segments.iter().all(|seg| seg == open))
+            })
+            .map(|(tenant, _)| tenant.clone())
+            .collect();
+        for tenant in idle {
+            if let Some((mut f, _, _)) = g.current.remove(&tenant) {
+                let _ = f.flush();
+                let _ = sync_file(&mut f);
             }
-            let pay_len = u32::from_le_bytes(buf4) as usize;
-            let mut payload = vec![0u8; pay_len];
-            if read_exact_or_eof(&mut file, &mut payload)?.is_none() {
-                break;
+        }
+        let mut oldest: Vec<(&str, &PathBuf)> = Vec::new();
+        for (tenant, t) in &g.tenants {
+            let open = g.current.get(tenant).map(|(_, _, p)| p);
+            if let Some(seg) = t.segments.iter().find(|seg| open != Some(*seg)) {
+                oldest.push((tenant.as_str(), seg));
             }
-
-            records.push((table, payload));
         }
+        oldest.sort();
+        let ready: Vec<&str> = oldest.iter().map(|(t, _)| *t).collect();
+        let tenant = g.scheduler.pick(&ready, &g.policy)?;
+        let (_, seg) = oldest.iter().find(|(t, _)| *t == tenant)?;
+        Some((tenant.to_string(), (*seg).clone()))
+    }
 
-        Ok(records)
+    /// Read all records from a segment file (v2, or legacy v1), returning them
+    /// with the number of records that were skipped as corrupt.
+    ///
+    /// Records are not fsync'd per-append (only on rotation), so an unclean
+    /// shutdown can leave a torn trailing record. We tolerate that: a torn or
+    /// corrupt record is skipped (and counted) rather than discarding the
+    /// whole (otherwise-valid) segment.
+    pub fn read_segment(path: &Path) -> std::io::Result<SegmentRead> {
+        let data = fs::read(path)?;
+        match data.strip_prefix(&SEGMENT_MAGIC[..]) {
+            Some(body) => Ok(decode_records(body)),
+            None => Ok(decode_legacy_records(&data)),
+        }
     }
 
-    /// Seal (close + fsync) the currently open segment so it becomes eligible
+    /// Seal (close + fsync) every tenant's open segment so it becomes eligible
     /// for replay. No-op if there is no open segment. The replayer calls this
     /// when data remains in the spool but no closed segment is available, so a
     /// partial final batch is drained promptly after ClickHouse recovers rather
     /// than waiting for the segment to reach the 32 MiB rotation threshold.
     pub fn seal_current(&self) {
         let mut g = self.inner.lock().unwrap();
-        if let Some((mut f, _, _)) = g.current.take() {
+        for (_, (mut f, _, _)) in g.current.drain() {
             let _ = f.flush();
             let _ = sync_file(&mut f);
         }
@@ -235,6 +348,14 @@ impl Spool {
         let mut g = self.inner.lock().unwrap();
         g.total_bytes = g.total_bytes.saturating_sub(size);
         g.segments.remove(path);
+        let tenant = path.file_name().map(|n| segment_tenant(&n.to_string_lossy())).unwrap_or_default();
+        if let Some(t) = g.tenants.get_mut(&tenant) {
+            t.bytes = t.bytes.saturating_sub(size);
+            t.segments.remove(path);
+            if t.segments.is_empty() {
+                g.tenants.remove(&tenant);
+            }
+        }
         self.committed.fetch_add(1, Ordering::Relaxed);
     }
 
@@ -250,18 +371,394 @@ impl Spool {
     pub fn segment_count(&self) -> usize {
         self.inner.lock().unwrap().segments.len()
     }
+
+    /// Write one record to the dead-letter area (fsync'd before returning).
+    ///
+    /// Only the sequence number is allocated under the lock; the write, fsync
+    /// and rename into place happen after it is released so appends and drains
+    /// don't stall on the disk flush. The entry is written as `*.dead.tmp` and
+    /// renamed once durable, so readers never see a partial record.
+    pub fn quarantine(&self, table: &str, payload: &[u8], meta: &DeadLetterMeta) -> std::io::Result<()> {
+        let body = encode_dead_letter(meta, table, payload);
+        let path = {
+            let mut g = self.inner.lock().unwrap();
+            g.seq += 1;
+            let ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+            g.dir.join(DEAD_LETTER_DIR).join(format!("dl-{ms:020}-{:016}.dead", g.seq))
+        };
+        let tmp = path.with_extension("dead.tmp");
+        let written = (|| {
+            let mut f = OpenOptions::new().create_new(true).write(true).open(&tmp)?;
+            f.write_all(&body)?;
+            sync_file(&mut f)?;
+            fs::rename(&tmp, &path)
+        })();
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        let mut g = self.inner.lock().unwrap();
+        g.dead_count += 1;
+        g.dead_bytes += body.len() as u64;
+        Ok(())
+    }
+
+    /// All dead-letter entries, oldest first.
+    pub fn dead_letters(&self) -> std::io::Result<Vec<DeadLetterEntry>> {
+        let dead_dir = self.inner.lock().unwrap().dir.join(DEAD_LETTER_DIR);
+        let mut names: Vec<String> = fs::read_dir(&dead_dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|n| n.ends_with(".dead"))
+            .collect();
+        names.sort();
+        let mut out = Vec::with_capacity(names.len());
+        for id in names {
+            let Ok(data) = fs::read(dead_dir.join(&id)) else { continue };
+            out.push(DeadLetterEntry::from_body(id, &data));
+        }
+        Ok(out)
+    }
+
+    /// Read a dead-letter entry by id. `Ok(None)` when it doesn't exist.
+    pub fn read_dead_letter(&self, id: &str) -> std::io::Result<Option<Vec<u8>>> {
+        if !valid_dead_letter_id(id) {
+            return Ok(None);
+        }
+        let path = self.inner.lock().unwrap().dir.join(DEAD_LETTER_DIR).join(id);
+        match fs::read(path) {
+            Ok(b) => Ok(Some(b)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete a dead-letter entry. Returns false when it doesn't exist.
+    pub fn delete_dead_letter(&self, id: &str) -> std::io::Result<bool> {
+        if !valid_dead_letter_id(id) {
+            return Ok(false);
+        }
+        let mut g = self.inner.lock().unwrap();
+        let path = g.dir.join(DEAD_LETTER_DIR).join(id);
+        let size = match fs::metadata(&path) {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        fs::remove_file(&path)?;
+        g.dead_count = g.dead_count.saturating_sub(1);
+        g.dead_bytes = g.dead_bytes.saturating_sub(size);
+        Ok(true)
+    }
+
+    pub fn dead_letter_count(&self) -> usize {
+        self.inner.lock().unwrap().dead_count
+    }
+
+    pub fn dead_letter_bytes(&self) -> u64 {
+        self.inner.lock().unwrap().dead_bytes
+    }
+}
+
+// ─── Dead-letter entries ─────────────────────────────────────────────────────
+//
+// Both backends store a quarantined record as `{meta json}\n{table}\n{payload}`.
+// The metadata is compact single-line JSON and table names have no newline, so
+// the two leading lines split unambiguously; the payload is the original
+// serde_json batch bytes, untouched, so a re-drive appends it back verbatim.
+
+/// Why and when a record was quarantined.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeadLetterMeta {
+    /// Tenant that owns the record (re-drives go back to its share).
+    #[serde(default)]
+    pub tenant: String,
+    /// The last error that caused the record to be quarantined.
+    pub reason: String,
+    /// Non-transient insert failures seen before it was quarantined.
+    pub attempts: u32,
+    /// The spool segment / object the record was drained from.
+    pub source: String,
+    /// Unix seconds.
+    pub quarantined_at: u64,
+}
+
+/// A dead-letter listing row (payload omitted).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeadLetterEntry {
+    pub id: String,
+    pub table: String,
+    pub bytes: u64,
+    #[serde(flatten)]
+    pub meta: Option<DeadLetterMeta>,
+}
+
+impl DeadLetterEntry {
+    pub fn from_body(id: String, data: &[u8]) -> Self {
+        let decoded = decode_dead_letter(data);
+        DeadLetterEntry {
+            id,
+            table: decoded.as_ref().map(|d| d.1.clone()).unwrap_or_default(),
+            bytes: data.len() as u64,
+            meta: decoded.map(|d| d.0),
+        }
+    }
+}
+
+pub fn encode_dead_letter(meta: &DeadLetterMeta, table: &str, payload: &[u8]) -> Vec<u8> {
+    let meta = serde_json::to_vec(meta).unwrap_or_else(|_| b"{}".to_vec());
+    let mut body = Vec::with_capacity(meta.len() + table.len() + payload.len() + 2);
+    body.extend_from_slice(&meta);
+    body.push(b'\n');
+    body.extend_from_slice(table.as_bytes());
+    body.push(b'\n');
+    body.extend_from_slice(payload);
+    body
+}
+
+/// Split a dead-letter body into (meta, table, payload). `None` if malformed.
+pub fn decode_dead_letter(data: &[u8]) -> Option<(DeadLetterMeta, String, Vec<u8>)> {
+    let nl = data.iter().position(|&b| b == b'\n')?;
+    let meta: DeadLetterMeta = serde_json::from_slice(&data[..nl]).ok()?;
+    let rest = &data[nl + 1..];
+    let nl = rest.iter().position(|&b| b == b'\n')?;
+    let table = std::str::from_utf8(&rest[..nl]).ok()?.to_string();
+    Some((meta, table, rest[nl + 1..].to_vec()))
+}
+
+/// Dead-letter ids come from URLs — accept bare file names only.
+pub fn valid_dead_letter_id(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('.')
+        && id.ends_with(".dead")
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Encode one v2 record frame (header + body).
+pub fn encode_record(table: &str, payload: &[u8]) -> Vec<u8> {
+    let (codec, data) = match zstd::bulk::compress(payload, ZSTD_LEVEL) {
+        Ok(c) if c.len() < payload.len() => (CODEC_ZSTD, std::borrow::Cow::Owned(c)),
+        _ => (CODEC_NONE, std::borrow::Cow::Borrowed(payload)),
+    };
+    let body_len = 1 + 4 + table.len() + data.len();
+    let mut frame = Vec::with_capacity(REC_HEADER_LEN + body_len);
+    frame.extend_from_slice(&REC_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&(body_len as u32).to_le_bytes());
+    frame.extend_from_slice(&[0u8; 4]); // CRC, filled below
+    frame.push(codec);
+    frame.extend_from_slice(&(table.len() as u32).to_le_bytes());
+    frame.extend_from_slice(table.as_bytes());
+    frame.extend_from_slice(&data);
+    let crc = crc32c(&frame[REC_HEADER_LEN..]);
+    frame[8..12].copy_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// Decode a v2 record body (after its CRC has been verified).
+fn decode_record_body(body: &[u8]) -> Option<(String, Vec<u8>)> {
+    let (&codec, rest) = body.split_first()?;
+    let tbl_len = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+    let table = std::str::from_utf8(rest.get(4..4 + tbl_len)?).ok()?.to_string();
+    let data = &rest[4 + tbl_len..];
+    let payload = match codec {
+        CODEC_NONE => data.to_vec(),
+        CODEC_ZSTD => zstd::stream::decode_all(data).ok()?,
+        _ => return None,
+    };
+    Some((table, payload))
+}
+
+/// Decode the v2 record at the start of `rest`, returning it with its frame length.
+fn decode_frame(rest: &[u8]) -> Option<((String, Vec<u8>), usize)> {
+    if rest.get(..4)? != REC_MAGIC.to_le_bytes() {
+        return None;
+    }
+    let body_len = u32::from_le_bytes(rest.get(4..8)?.try_into().ok()?) as usize;
+    let crc = u32::from_le_bytes(rest.get(8..12)?.try_into().ok()?);
+    let body = rest.get(REC_HEADER_LEN..REC_HEADER_LEN.checked_add(body_len)?)?;
+    if crc32c(body) != crc {
+        return None;
+    }
+    decode_record_body(body).map(|r| (r, REC_HEADER_LEN + body_len))
+}
+
+/// Parse v2 records (segment header already stripped). On a bad frame, scan
+/// forward to the next `REC_MAGIC` and count the skipped record.
+fn decode_records(data: &[u8]) -> SegmentRead {
+    let magic = REC_MAGIC.to_le_bytes();
+    let mut records = Vec::new();
+    let mut corrupt = 0u64;
+    let mut pos = 0usize;
+    while pos < data.len() {
+        let rest = &data[pos..];
+        match decode_frame(rest) {
+            Some((record, len)) => {
+                records.push(record);
+                pos += len;
+            }
+            None => {
+                corrupt += 1;
+                // Resync on the next marker strictly after this position.
+                match rest[1..].windows(4).position(|w| w == magic) {
+                    Some(off) => pos += 1 + off,
+                    None => break,
+                }
+            }
+        }
+    }
+    (records, corrupt)
+}
+
+/// Parse a legacy (v1) segment. There's no checksum or marker to resync on, so
+/// parsing stops at the first frame that doesn't fit (a torn tail), counting it.
+fn decode_legacy_records(data: &[u8]) -> SegmentRead {
+    fn take<'a>(data: &'a [u8], pos: &mut usize, n: usize) -> Option<&'a [u8]> {
+        let s = data.get(*pos..pos.checked_add(n)?)?;
+        *pos += n;
+        Some(s)
+    }
+    fn frame(data: &[u8], pos: &mut usize) -> Option<(String, Vec<u8>)> {
+        let tbl_len = u32::from_le_bytes(take(data, pos, 4)?.try_into().ok()?) as usize;
+        let table = String::from_utf8(take(data, pos, tbl_len)?.to_vec()).ok()?;
+        let pay_len = u32::from_le_bytes(take(data, pos, 4)?.try_into().ok()?) as usize;
+        Some((table, take(data, pos, pay_len)?.to_vec()))
+    }
+    let mut records = Vec::new();
+    let mut pos = 0usize;
+    while pos < data.len() {
+        match frame(data, &mut pos) {
+            Some(r) => records.push(r),
+            None => return (records, 1),
+        }
+    }
+    (records, 0)
+}
+
+/// CRC-32C (Castagnoli), reflected — the variant used by iSCSI, ext4 and RocksDB.
+fn crc32c(data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut t = [0u32; 256];
+        for (i, slot) in t.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { (c >> 1) ^ 0x82F6_3B78 } else { c >> 1 };
+            }
+            *slot = c;
+        }
+        t
+    });
+    !data.iter().fold(!0u32, |crc, &b| table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+// ─── Tenant fairness ─────────────────────────────────────────────────────────
+
+/// Default share of the spool one tenant may hold, in percent.
+pub const DEFAULT_TENANT_MAX_PCT: u8 = 50;
+
+/// Per-tenant spool limits and replay weights, shared by both backends.
+#[derive(Debug, Clone)]
+pub struct TenantPolicy {
+    /// Share of the global cap a single tenant may hold, in percent (0 or 100
+    /// = no per-tenant cap).
+    pub max_pct: u8,
+    /// Replay weight per tenant; tenants not listed get 1.
+    pub weights: HashMap<String, u32>,
+}
+
+impl Default for TenantPolicy {
+    fn default() -> Self {
+        TenantPolicy { max_pct: DEFAULT_TENANT_MAX_PCT, weights: HashMap::new() }
+    }
+}
+
+impl TenantPolicy {
+    /// `RUSH_SPOOL_TENANT_MAX_PCT` (default 50; 100 disables the per-tenant
+    /// cap) and `RUSH_SPOOL_TENANT_WEIGHTS` (`tenant=weight,...`).
+    pub fn from_env() -> Self {
+        let max_pct = std::env::var("RUSH_SPOOL_TENANT_MAX_PCT")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(DEFAULT_TENANT_MAX_PCT)
+            .min(100);
+        let weights = std::env::var("RUSH_SPOOL_TENANT_WEIGHTS")
+            .map(|s| parse_weights(&s))
+            .unwrap_or_default();
+        TenantPolicy { max_pct, weights }
+    }
+
+    /// Byte cap for one tenant given the global cap and the number of active
+    /// tenants (see `active_tenants`). A lone tenant (single-tenant installs)
+    /// may use the whole spool; while another has data spooled, each is held
+    /// to its share.
+    pub fn tenant_max_bytes(&self, global_max: u64, active_tenants: usize) -> u64 {
+        if self.max_pct == 0 || self.max_pct >= 100 || active_tenants <= 1 {
+            return global_max;
+        }
+        global_max / 100 * self.max_pct as u64
+    }
+
+    pub fn weight(&self, tenant: &str) -> u32 {
+        self.weights.get(tenant).copied().unwrap_or(1).max(1)
+    }
+}
+
+/// Tenants sharing the spool: those with data spooled (`holding`) plus the
+/// one appending. Legacy tenant-less data (`""`) doesn't count, so an
+/// upgraded single-tenant install isn't capped by its own old segments.
+pub(crate) fn active_tenants<'a>(holding: impl Iterator<Item = &'a str>, appending: &str) -> usize {
+    let others = holding.filter(|t| !t.is_empty() && *t != appending).count();
+    others + usize::from(!appending.is_empty())
 }
 
-/// Like `read_exact`, but returns `Ok(None)` on EOF (clean or partial) instead
-/// of erroring, so a torn trailing record terminates parsing gracefully.
-fn read_exact_or_eof(file: &mut File, buf: &mut [u8]) -> std::io::Result<Option<()>> {
-    match file.read_exact(buf) {
-        Ok(_) => Ok(Some(())),
-        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
-        Err(e) => Err(e),
+fn parse_weights(s: &str) -> HashMap<String, u32> {
+    s.split(',')
+        .filter_map(|kv| {
+            let (k, v) = kv.split_once('=')?;
+            Some((k.trim().to_string(), v.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Smooth weighted round-robin (as in nginx upstreams): each pick, every ready
+/// tenant earns its weight in credit, the richest is chosen and pays the total.
+/// Over any window a tenant with weight 3 is picked three times as often as
+/// one with weight 1, interleaved rather than in bursts.
+#[derive(Debug, Default)]
+pub struct FairScheduler {
+    credit: HashMap<String, i64>,
+}
+
+impl FairScheduler {
+    /// Pick one of `ready` (should be in a stable order; ties go to the first).
+    pub fn pick<'a>(&mut self, ready: &[&'a str], policy: &TenantPolicy) -> Option<&'a str> {
+        // Tenants with nothing pending don't bank credit while idle.
+        self.credit.retain(|t, _| ready.contains(&t.as_str()));
+        let total: i64 = ready.iter().map(|t| policy.weight(t) as i64).sum();
+        let mut best: Option<(&'a str, i64)> = None;
+        for &tenant in ready {
+            let c = self.credit.entry(tenant.to_string()).or_insert(0);
+            *c += policy.weight(tenant) as i64;
+            if best.is_none_or(|(_, b)| *c > b) {
+                best = Some((tenant, *c));
+            }
+        }
+        let (tenant, _) = best?;
+        if let Some(c) = self.credit.get_mut(tenant) {
+            *c -= total;
+        }
+        Some(tenant)
     }
 }
 
+/// One tenant's share of the buffer (for the status endpoint).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TenantUsage {
+    pub tenant: String,
+    pub pending_bytes: u64,
+    pub pending_count: usize,
+}
+
 // ─── Backend seam ────────────────────────────────────────────────────────────
 //
 // `IngestBuffer` is the backend the durable write path (`ChWriter`) writes
@@ -274,13 +771,27 @@ fn read_exact_or_eof(file: &mut File, buf: &mut [u8]) -> std::io::Result<Option<
 /// A unit of spooled work for the replayer: the records to insert plus an opaque
 /// handle used to `commit` (remove/ack) them once successfully written to CH.
 pub struct DrainBatch {
+    /// Tenant that owns every record in this batch.
+    pub tenant: String,
     pub records: Vec<(String, Vec<u8>)>,
     handle: BatchHandle,
 }
 
+impl DrainBatch {
+    /// Stable identifier of the underlying segment/object (for retry accounting
+    /// and dead-letter provenance).
+    pub fn id(&self) -> String {
+        match &self.handle {
+            BatchHandle::Disk(p) => p.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            BatchHandle::ObjectStore(k, _) => k.to_string(),
+        }
+    }
+}
+
 enum BatchHandle {
     Disk(PathBuf),
-    ObjectStore(object_store::path::Path),
+    /// Object key + size (for byte accounting on commit).
+    ObjectStore(object_store::path::Path, u64),
 }
 
 pub enum IngestBuffer {
@@ -293,19 +804,34 @@ impl IngestBuffer {
     /// Disk appends are blocking file writes serialized by the spool mutex, so
     /// they run on the blocking pool — otherwise, exactly when ClickHouse is
     /// down, every ingest request would do blocking I/O on a tokio worker.
-    pub async fn append(self: &Arc<Self>, table: &str, payload: Vec<u8>) -> Result<(), SpoolFull> {
+    pub async fn append(self: &Arc<Self>, tenant: &str, table: &str, payload: Vec<u8>) -> Result<(), SpoolFull> {
         match &**self {
             IngestBuffer::Disk(_) => {
                 let me = Arc::clone(self);
+                let tenant = tenant.to_string();
                 let table = table.to_string();
                 tokio::task::spawn_blocking(move || match &*me {
-                    IngestBuffer::Disk(s) => s.append(&table, &payload),
+                    IngestBuffer::Disk(s) => s.append(&tenant, &table, &payload),
                     IngestBuffer::ObjectStore(_) => unreachable!(),
                 })
                 .await
                 .map_err(|_| SpoolFull)?
             }
-            IngestBuffer::ObjectStore(s) => s.append(table, &payload).await,
+            IngestBuffer::ObjectStore(s) => s.append(tenant, table, &payload).await,
+        }
+    }
+
+    pub fn set_tenant_policy(&self, policy: TenantPolicy) {
+        match self {
+            IngestBuffer::Disk(s) => s.set_tenant_policy(policy),
+            IngestBuffer::ObjectStore(s) => s.set_tenant_policy(policy),
+        }
+    }
+
+    pub fn tenant_usage(&self) -> Vec<TenantUsage> {
+        match self {
+            IngestBuffer::Disk(s) => s.tenant_usage(),
+            IngestBuffer::ObjectStore(s) => s.tenant_usage(),
         }
     }
 
@@ -345,6 +871,15 @@ impl IngestBuffer {
         }
     }
 
+    /// Monotonic count of spooled records skipped as corrupt on replay (disk
+    /// only; the object-store backend discards corrupt objects whole).
+    pub fn corrupt_records_total(&self) -> u64 {
+        match self {
+            IngestBuffer::Disk(s) => s.corrupt_records_total(),
+            IngestBuffer::ObjectStore(_) => 0,
+        }
+    }
+
     /// Age (seconds) of the oldest pending batch — the replay lag. `None`/0 when empty.
     pub async fn oldest_age_secs(&self) -> Option<u64> {
         match self {
@@ -353,11 +888,12 @@ impl IngestBuffer {
         }
     }
 
-    /// The next unit of work to replay, or `None` when empty. For the disk
-    /// backend this seals the open segment when no closed segment is available
-    /// (so a partial trailing batch still drains), and discards an unreadable
-    /// segment before trying the next. Disk reads (up to a 32 MiB segment) and
-    /// the seal fsync run on the blocking pool.
+    /// The next unit of work to replay, or `None` when empty. Tenants take
+    /// turns by weighted round-robin (see `FairScheduler`). For the disk
+    /// backend a tenant's open segment is sealed when it has no closed one (so
+    /// a partial trailing batch still drains), and an unreadable segment is
+    /// discarded before trying the next. Disk reads (up to a 32 MiB segment)
+    /// and the seal fsync run on the blocking pool.
     pub async fn next_batch(self: &Arc<Self>) -> Option<DrainBatch> {
         match &**self {
             IngestBuffer::Disk(_) => {
@@ -366,22 +902,15 @@ impl IngestBuffer {
                     let IngestBuffer::Disk(s) = &*me else { unreachable!() };
                     // Loop (not recursion) to skip unreadable segments.
                     loop {
-                        let path = match s.take_oldest_segment() {
-                            Some(p) => p,
-                            None => {
-                                if s.total_bytes() > 0 {
-                                    s.seal_current();
-                                    match s.take_oldest_segment() {
-                                        Some(p) => p,
-                                        None => return None,
-                                    }
-                                } else {
-                                    return None;
+                        let (tenant, path) = s.take_next_segment()?;
+                        match Spool::read_segment(&path) {
+                            Ok((records, corrupt)) => {
+                                if corrupt > 0 {
+                                    tracing::error!(corrupt, segment = %path.display(), "ingest buffer: skipped corrupt records in segment");
+                                    s.corrupt.fetch_add(corrupt, Ordering::Relaxed);
                                 }
+                                return Some(DrainBatch { tenant, records, handle: BatchHandle::Disk(path) });
                             }
-                        };
-                        match Spool::read_segment(&path) {
-                            Ok(records) => return Some(DrainBatch { records, handle: BatchHandle::Disk(path) }),
                             Err(e) => {
                                 tracing::error!(error = %e, segment = %path.display(), "ingest buffer: failed to read segment — discarding");
                                 s.remove_segment(&path);
@@ -395,9 +924,10 @@ impl IngestBuffer {
                 .flatten()
             }
             IngestBuffer::ObjectStore(s) => {
-                s.next_batch().await.map(|(key, records)| DrainBatch {
+                s.next_batch().await.map(|(tenant, key, size, records)| DrainBatch {
+                    tenant,
                     records,
-                    handle: BatchHandle::ObjectStore(key),
+                    handle: BatchHandle::ObjectStore(key, size),
                 })
             }
         }
@@ -407,34 +937,328 @@ impl IngestBuffer {
     pub async fn commit(&self, batch: DrainBatch) {
         match (self, batch.handle) {
             (IngestBuffer::Disk(s), BatchHandle::Disk(path)) => s.remove_segment(&path),
-            (IngestBuffer::ObjectStore(s), BatchHandle::ObjectStore(key)) => s.commit(&key).await,
+            (IngestBuffer::ObjectStore(s), BatchHandle::ObjectStore(key, size)) => {
+                s.commit(&batch.tenant, &key, size).await
+            }
             // Mismatched handle/backend can't happen (handles are minted by the same backend).
             _ => tracing::error!("ingest buffer: commit handle/backend mismatch"),
         }
     }
+
+    /// Move one record to the dead-letter area. An error means it was NOT
+    /// quarantined, so the caller must not commit the batch it came from.
+    pub async fn quarantine(self: &Arc<Self>, table: &str, payload: &[u8], meta: DeadLetterMeta) -> anyhow::Result<()> {
+        match &**self {
+            IngestBuffer::Disk(_) => {
+                let me = Arc::clone(self);
+                let table = table.to_string();
+                let payload = payload.to_vec();
+                tokio::task::spawn_blocking(move || {
+                    let IngestBuffer::Disk(s) = &*me else { unreachable!() };
+                    s.quarantine(&table, &payload, &meta)
+                })
+                .await??;
+                Ok(())
+            }
+            IngestBuffer::ObjectStore(s) => s.quarantine(table, payload, &meta).await,
+        }
+    }
+
+    /// All dead-letter entries, oldest first.
+    pub async fn dead_letters(self: &Arc<Self>) -> anyhow::Result<Vec<DeadLetterEntry>> {
+        match &**self {
+            IngestBuffer::Disk(_) => {
+                let me = Arc::clone(self);
+                let entries = tokio::task::spawn_blocking(move || {
+                    let IngestBuffer::Disk(s) = &*me else { unreachable!() };
+                    s.dead_letters()
+                })
+                .await??;
+                Ok(entries)
+            }
+            IngestBuffer::ObjectStore(s) => s.dead_letters().await,
+        }
+    }
+
+    /// A decoded dead-letter entry: (meta, table, payload). `None` when the id
+    /// doesn't exist; an undecodable entry is an error.
+    pub async fn read_dead_letter(&self, id: &str) -> anyhow::Result<Option<(DeadLetterMeta, String, Vec<u8>)>> {
+        let raw = match self {
+            IngestBuffer::Disk(s) => s.read_dead_letter(id)?,
+            IngestBuffer::ObjectStore(s) => s.read_dead_letter(id).await?,
+        };
+        match raw {
+            Some(data) => decode_dead_letter(&data)
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("malformed dead-letter entry {id}")),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete a dead-letter entry. Returns false when it doesn't exist.
+    pub async fn delete_dead_letter(&self, id: &str) -> anyhow::Result<bool> {
+        match self {
+            IngestBuffer::Disk(s) => Ok(s.delete_dead_letter(id)?),
+            IngestBuffer::ObjectStore(s) => s.delete_dead_letter(id).await,
+        }
+    }
+
+    pub fn dead_letter_count(&self) -> usize {
+        match self {
+            IngestBuffer::Disk(s) => s.dead_letter_count(),
+            IngestBuffer::ObjectStore(s) => s.dead_letter_count(),
+        }
+    }
+
+    pub fn dead_letter_bytes(&self) -> u64 {
+        match self {
+            IngestBuffer::Disk(s) => s.dead_letter_bytes(),
+            IngestBuffer::ObjectStore(s) => s.dead_letter_bytes(),
+        }
+    }
 }
 
-fn seg_name(seq: u64) -> String {
+fn seg_name(seq: u64, tenant: &str) -> String {
     let ms = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_millis() as u64;
-    format!("seg-{ms:020}-{seq:016}.spool")
+    if tenant.is_empty() {
+        format!("seg-{ms:020}-{seq:016}.spool")
+    } else {
+        // Hex keeps arbitrary tenant ids filename-safe.
+        format!("seg-{ms:020}-{seq:016}-{}.spool", hex::encode(tenant))
+    }
 }
 
 fn parse_seq(name: &str) -> Option<u64> {
-    // seg-<ms>-<seq>.spool
-    let stripped = name.strip_suffix(".spool")?;
-    let parts: Vec<&str> = stripped.splitn(3, '-').collect();
-    if parts.len() == 3 {
+    // seg-<ms>-<seq>[-<tenant>].spool (or dl-<ms>-<seq> for dead-letter entries)
+    let stripped = name.strip_suffix(".spool").unwrap_or(name);
+    let parts: Vec<&str> = stripped.splitn(4, '-').collect();
+    if parts.len() >= 3 {
         parts[2].parse().ok()
     } else {
         None
     }
 }
 
+/// Owning tenant of a segment file name ("" for legacy, tenant-less names).
+fn segment_tenant(name: &str) -> String {
+    name.strip_suffix(".spool")
+        .and_then(|n| n.splitn(4, '-').nth(3))
+        .and_then(|h| hex::decode(h).ok())
+        .map(|b| String::from_utf8_lossy(&b).into_owned())
+        .unwrap_or_default()
+}
+
 /// Flush a sealed segment's data + metadata to disk before it is considered
 /// durable for replay.
 fn sync_file(f: &mut File) -> std::io::Result<()> {
     f.sync_all()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        std::env::temp_dir().join(format!("rush-spool-{name}-{}-{ms}", std::process::id()))
+    }
+
+    #[test]
+    fn dead_letter_roundtrip_and_restart() {
+        let dir = temp_dir("dl");
+        let meta = DeadLetterMeta { tenant: "t".into(), reason: "Code: 53".into(), attempts: 3, source: "seg".into(), quarantined_at: 7 };
+        {
+            let s = Spool::open(&dir, 1_000_000).unwrap();
+            s.append("t", "logs", b"[1]").unwrap();
+            s.quarantine("logs", b"[2]", &meta).unwrap();
+            // Dead letters don't count as pending spool data.
+            assert_eq!(s.segment_count(), 1);
+            assert_eq!(s.dead_letter_count(), 1);
+        }
+        // A quarantine interrupted before its rename leaves only a temp file.
+        let stray = dir.join(DEAD_LETTER_DIR).join("dl-00000000000000000009-0000000000000099.dead.tmp");
+        fs::write(&stray, b"partial").unwrap();
+        let s = Spool::open(&dir, 1_000_000).unwrap();
+        assert_eq!(s.segment_count(), 1);
+        assert_eq!(s.dead_letter_count(), 1);
+        assert!(!stray.exists());
+        let list = s.dead_letters().unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].table, "logs");
+        assert_eq!(list[0].meta.as_ref(), Some(&meta));
+        assert_eq!(s.dead_letter_bytes(), list[0].bytes);
+
+        let raw = s.read_dead_letter(&list[0].id).unwrap().unwrap();
+        assert_eq!(decode_dead_letter(&raw), Some((meta, "logs".to_string(), b"[2]".to_vec())));
+        assert!(s.read_dead_letter("../seg.dead").unwrap().is_none());
+        assert!(s.delete_dead_letter(&list[0].id).unwrap());
+        assert!(!s.delete_dead_letter(&list[0].id).unwrap());
+        assert_eq!((s.dead_letter_count(), s.dead_letter_bytes()), (0, 0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn v2_segment_roundtrip_compresses() {
+        let dir = temp_dir("v2");
+        let s = Spool::open(&dir, 1_000_000).unwrap();
+        let big = br#"{"Logs":[{"body":"hello hello hello hello hello hello hello"}]}"#.repeat(50);
+        s.append("t", "logs", &big).unwrap();
+        s.append("t", "spans", b"x").unwrap();
+        assert!(s.total_bytes() < big.len() as u64, "payload should be stored compressed");
+        let (tenant, path) = s.take_next_segment().unwrap();
+        assert_eq!(tenant, "t");
+        let (records, corrupt) = Spool::read_segment(&path).unwrap();
+        assert_eq!(corrupt, 0);
+        assert_eq!(records, vec![("logs".to_string(), big), ("spans".to_string(), b"x".to_vec())]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupt_and_torn_records_are_skipped_and_counted() {
+        let mut data = Vec::new();
+        for p in [&b"one"[..], b"two", b"three"] {
+            data.extend_from_slice(&encode_record("logs", p));
+        }
+        // Flip a payload byte of the second record, and tear the fourth.
+        let first_len = encode_record("logs", b"one").len();
+        data[first_len + REC_HEADER_LEN + 6] ^= 0xff;
+        data.extend_from_slice(&encode_record("logs", b"four")[..10]);
+        let (records, corrupt) = decode_records(&data);
+        let payloads: Vec<&[u8]> = records.iter().map(|r| r.1.as_slice()).collect();
+        assert_eq!(payloads, vec![&b"one"[..], b"three"]);
+        assert_eq!(corrupt, 2);
+    }
+
+    #[test]
+    fn legacy_segments_still_read() {
+        let dir = temp_dir("v1");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("seg-00000000000000000001-0000000000000001.spool");
+        let mut data = Vec::new();
+        for (t, p) in [("logs", &b"alpha"[..]), ("spans", b"beta")] {
+            data.extend_from_slice(&(t.len() as u32).to_le_bytes());
+            data.extend_from_slice(t.as_bytes());
+            data.extend_from_slice(&(p.len() as u32).to_le_bytes());
+            data.extend_from_slice(p);
+        }
+        data.extend_from_slice(&[9, 0]); // torn tail
+        fs::write(&path, &data).unwrap();
+        let (records, corrupt) = Spool::read_segment(&path).unwrap();
+        assert_eq!(records, vec![("logs".to_string(), b"alpha".to_vec()), ("spans".to_string(), b"beta".to_vec())]);
+        assert_eq!(corrupt, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn active_tenants_skips_legacy_data_and_counts_the_appender_once() {
+        assert_eq!(active_tenants(["", "a"].into_iter(), "a"), 1);
+        assert_eq!(active_tenants(["a"].into_iter(), "b"), 2);
+        assert_eq!(active_tenants(std::iter::empty(), ""), 0);
+    }
+
+    #[test]
+    fn weighted_round_robin_is_smooth() {
+        let policy = TenantPolicy { max_pct: 0, weights: parse_weights("a=3, b=1") };
+        let mut sched = FairScheduler::default();
+        let picks: Vec<&str> = (0..8).map(|_| sched.pick(&["a", "b"], &policy).unwrap()).collect();
+        assert_eq!(picks, ["a", "a", "b", "a", "a", "a", "b", "a"]);
+        assert_eq!(sched.pick(&[], &policy), None);
+    }
+
+    #[test]
+    fn default_policy_caps_a_tenant_only_while_another_is_active() {
+        let dir = temp_dir("default-cap");
+        // Tenant-less segment left by a pre-upgrade version.
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("seg-00000000000000000001-0000000000000001.spool"), b"legacy").unwrap();
+        let s = Spool::open(&dir, 10_000).unwrap();
+        let payload = |i: u32| -> Vec<u8> {
+            let mut x = 0x9E37_79B9_7F4A_7C15u64 ^ i as u64;
+            (0..1_000)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    x as u8
+                })
+                .collect()
+        };
+        // Alone (legacy data doesn't count), a tenant may go past half the spool.
+        for i in 0..6 {
+            s.append("solo", "logs", &payload(i)).unwrap();
+        }
+        assert!(s.tenant_usage().iter().any(|u| u.tenant == "solo" && u.pending_bytes > 5_000));
+        let _ = fs::remove_dir_all(&dir);
+
+        let dir = temp_dir("default-cap2");
+        let s = Spool::open(&dir, 10_000).unwrap();
+        s.append("quiet", "logs", b"hello").unwrap();
+        let mut i = 0;
+        while s.append("noisy", "logs", &payload(i)).is_ok() {
+            i += 1;
+        }
+        let noisy = s.tenant_usage().into_iter().find(|u| u.tenant == "noisy").unwrap();
+        assert!(noisy.pending_bytes <= 5_000, "noisy is held to half the spool");
+        assert!(s.total_bytes() + 1_100 < s.max_bytes(), "the spool itself is not full");
+        s.append("quiet", "logs", &payload(99)).unwrap();
+
+        // Once quiet's data has drained, noisy is alone again.
+        s.seal_current();
+        let quiet: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| segment_tenant(&p.file_name().unwrap().to_string_lossy()) == "quiet")
+            .collect();
+        quiet.iter().for_each(|p| s.remove_segment(p));
+        s.append("noisy", "logs", &payload(i)).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tenant_cap_and_fair_replay_on_disk() {
+        let dir = temp_dir("fair");
+        let s = Spool::open(&dir, 10_000).unwrap();
+        s.set_tenant_policy(TenantPolicy { max_pct: 50, weights: HashMap::new() });
+        // Incompressible payloads so the cap math is predictable.
+        let mut x = 0x2545_F491_4F6C_DD1Du64;
+        let noise: Vec<u8> = (0..1_500)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+        s.append("small", "logs", b"fresh").unwrap();
+        for _ in 0..3 {
+            s.append("big", "logs", &noise).unwrap();
+            s.seal_current(); // one segment per batch
+        }
+        assert!(s.append("big", "logs", &noise).is_err(), "big is over its 5000-byte share");
+
+        // small's open segment is sealed and served right after big's first.
+        let mut order = Vec::new();
+        while let Some((tenant, path)) = s.take_next_segment() {
+            s.remove_segment(&path);
+            order.push(tenant);
+        }
+        assert_eq!(order, ["big", "small", "big", "big"]);
+        assert_eq!(s.total_bytes(), 0);
+        assert!(s.tenant_usage().is_empty());
+
+        // Tenant ownership survives a restart via the segment name.
+        s.append("tenant/with spaces", "logs", b"x").unwrap();
+        drop(s);
+        let s = Spool::open(&dir, 10_000).unwrap();
+        assert_eq!(s.take_next_segment().unwrap().0, "tenant/with spaces");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,6 @@
 use clickhouse::Client;
 use std::sync::Arc;
+use crate::k8s_enrich::K8sEnricher;
 use crate::spool::IngestBuffer;
 
 #[derive(clickhouse::Row, serde::Deserialize)]
@@ -12,19 +13,19 @@ struct BytesRow {
     total: u64,
 }
 
-pub fn spawn_stats_engine(ch: Client, buffer: Arc<IngestBuffer>) {
+pub fn spawn_stats_engine(ch: Client, buffer: Arc<IngestBuffer>, k8s: Option<Arc<K8sEnricher>>) {
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
         loop {
             interval.tick().await;
-            if let Err(e) = collect_and_write(&ch, &buffer).await {
+            if let Err(e) = collect_and_write(&ch, &buffer, k8s.as_deref()).await {
                 tracing::error!("stats engine error: {e}");
             }
         }
     });
 }
 
-async fn collect_and_write(ch: &Client, buffer: &IngestBuffer) -> anyhow::Result<()> {
+async fn collect_and_write(ch: &Client, buffer: &IngestBuffer, k8s: Option<&K8sEnricher>) -> anyhow::Result<()> {
     let now = chrono::Utc::now();
     let now_nanos = now.timestamp_nanos_opt().unwrap_or(0);
     let one_hour_ago = (now - chrono::Duration::hours(1))
@@ -109,12 +110,15 @@ async fn collect_and_write(ch: &Client, buffer: &IngestBuffer) -> anyhow::Result
     let buf_oldest = buf_oldest.unwrap_or(0);
 
     // ── Write all metrics ──
-    let metrics: Vec<(&str, f64)> = vec![
+    let mut metrics: Vec<(&str, f64)> = vec![
         ("rush_stats_ingest_buffer_pending_bytes", buffer.total_bytes() as f64),
         ("rush_stats_ingest_buffer_pending_count", buffer.segment_count() as f64),
         ("rush_stats_ingest_buffer_oldest_age_secs", buf_oldest as f64),
         // Cumulative counter — drain rate = rate(rush_stats_ingest_buffer_committed_total).
         ("rush_stats_ingest_buffer_committed_total", buffer.committed_total() as f64),
+        ("rush_stats_ingest_buffer_corrupt_records_total", buffer.corrupt_records_total() as f64),
+        ("rush_stats_ingest_buffer_quarantine_count", buffer.dead_letter_count() as f64),
+        ("rush_stats_ingest_buffer_quarantine_bytes", buffer.dead_letter_bytes() as f64),
         ("rush_stats_span_events_total", span_total as f64),
         ("rush_stats_span_events_bytes", span_bytes as f64),
         ("rush_stats_logs_total", log_total as f64),
@@ -129,6 +133,15 @@ async fn collect_and_write(ch: &Client, buffer: &IngestBuffer) -> anyhow::Result
         ("rush_stats_disk_local_free_bytes", disk_local_free_bytes as f64),
         ("rush_stats_disk_local_total_bytes", disk_local_total_bytes as f64),
     ];
+    // Kubernetes enrichment cache: hit ratio = hits / (hits + misses).
+    if let Some(k8s) = k8s {
+        let st = k8s.stats();
+        metrics.extend([
+            ("rush_stats_k8s_enrich_pods", st.pods as f64),
+            ("rush_stats_k8s_enrich_hits_total", st.hits as f64),
+            ("rush_stats_k8s_enrich_misses_total", st.misses as f64),
+        ]);
+    }
 
     let values: Vec<String> = metrics.iter().map(|(name, val)| {
         format!(
@@ -0,0 +1,577 @@
+//! Ingest-time delta → cumulative temporality normalizer for OTLP metrics.
+//!
+//! PromQL `rate()` / `increase()` (`promql::compute`) assume cumulative
+//! counters, so sums and histograms sent with DELTA temporality (.NET, some
+//! Java and Go SDK configs, serverless exporters) would otherwise produce
+//! nonsense rates. `ChWriter::write` runs every Sum / Histogram /
+//! ExpHistogram batch through here before the firewall: each delta point is
+//! added to a running total kept per series and stored as a CUMULATIVE point
+//! whose start time is the start of the first delta seen for that series.
+//! Cumulative points pass through untouched.
+//!
+//! Series identity is tenant + metric + service + resource attributes + scope
+//! + point attributes (attribute order doesn't matter), plus the metric kind.
+//!
+//! Start-time handling follows the OTel `deltatocumulative` processor:
+//!   - a point whose `TimeUnix` is not after the last one accumulated is a
+//!     duplicate or out of order and is dropped;
+//!   - a point whose `StartTimeUnix` is before the last accumulated time
+//!     overlaps a window already counted and is dropped;
+//!   - a gap (start after the last time) is accumulated — the total just
+//!     doesn't grow over the gap;
+//!   - a histogram whose bucket layout changes (explicit bounds, or scale for
+//!     exponential histograms) starts a new cumulative series at that point.
+//!
+//! Off by default (`RUSH_OTLP_DELTA_TO_CUMULATIVE=true` turns it on). State
+//! lives in this process only, so it is only correct with a single ingest
+//! replica or with routing that sends every point of a series to the same
+//! replica: otherwise each replica accumulates its own share and the stored
+//! counters are not monotonic. Series idle for `state_ttl_secs` are
+//! forgotten (the next point restarts the total with a fresh start time,
+//! which `rate()` treats as a counter reset). Past `max_series` tracked
+//! series, new delta series are stored unconverted.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+
+use crate::config::env_or;
+use crate::models::ingest::{ExpHistogramRow, HistogramRow, SumRow};
+
+/// OTLP `AggregationTemporality` values as stored in `AggregationTemporality`.
+pub const TEMPORALITY_DELTA: i32 = 1;
+pub const TEMPORALITY_CUMULATIVE: i32 = 2;
+
+const DEFAULT_STATE_TTL_SECS: u64 = 3600;
+const DEFAULT_MAX_SERIES: usize = 500_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaConfig {
+    pub enabled: bool,
+    /// Forget a series not seen for this long.
+    pub state_ttl_secs: u64,
+    /// Hard bound on tracked series (memory).
+    pub max_series: usize,
+}
+
+impl Default for DeltaConfig {
+    fn default() -> Self {
+        DeltaConfig { enabled: false, state_ttl_secs: DEFAULT_STATE_TTL_SECS, max_series: DEFAULT_MAX_SERIES }
+    }
+}
+
+impl DeltaConfig {
+    /// `RUSH_OTLP_DELTA_TO_CUMULATIVE` (default off, deltas are stored as
+    /// received; `true`/`1`/`on` converts), `RUSH_OTLP_DELTA_STATE_TTL_SECS`
+    /// (default 3600) and `RUSH_OTLP_DELTA_MAX_SERIES` (default 500000).
+    pub fn from_env() -> Self {
+        let d = DeltaConfig::default();
+        DeltaConfig {
+            enabled: matches!(env_or("RUSH_OTLP_DELTA_TO_CUMULATIVE", String::new()).as_str(), "true" | "1" | "on"),
+            state_ttl_secs: env_or("RUSH_OTLP_DELTA_STATE_TTL_SECS", d.state_ttl_secs),
+            max_series: env_or("RUSH_OTLP_DELTA_MAX_SERIES", d.max_series),
+        }
+    }
+}
+
+/// Running totals for one series.
+pub enum Total {
+    Sum(f64),
+    Histogram { count: u64, sum: f64, buckets: Vec<u64>, bounds: Vec<f64>, min: f64, max: f64 },
+    ExpHistogram {
+        count: u64,
+        sum: f64,
+        scale: i32,
+        zero_count: u64,
+        positive: (i32, Vec<u64>),
+        negative: (i32, Vec<u64>),
+        min: f64,
+        max: f64,
+    },
+}
+
+struct SeriesState {
+    start: i64,
+    last: i64,
+    seen: u64,
+    total: Total,
+}
+
+/// What happened to one delta point.
+enum Step {
+    /// Now carries the running total from this cumulative start time.
+    Emit(i64),
+    /// Duplicate, out of order or overlapping.
+    Drop,
+    /// Over `max_series`: stored as received.
+    Untracked,
+}
+
+/// Per-batch outcome (for logging).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DeltaOutcome {
+    pub converted: usize,
+    pub dropped: usize,
+}
+
+pub struct DeltaToCumulative {
+    cfg: DeltaConfig,
+    series: DashMap<u64, SeriesState>,
+    dropped_total: AtomicU64,
+    untracked_total: AtomicU64,
+}
+
+impl Default for DeltaToCumulative {
+    fn default() -> Self {
+        Self::new(DeltaConfig::default())
+    }
+}
+
+impl DeltaToCumulative {
+    pub fn new(cfg: DeltaConfig) -> Self {
+        DeltaToCumulative {
+            cfg,
+            series: DashMap::new(),
+            dropped_total: AtomicU64::new(0),
+            untracked_total: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.cfg.enabled
+    }
+
+    /// Number of delta series currently accumulated.
+    pub fn tracked_series(&self) -> usize {
+        self.series.len()
+    }
+
+    /// Delta points dropped as duplicate / out of order / overlapping.
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_total.load(Ordering::Relaxed)
+    }
+
+    /// Delta points stored unconverted because `max_series` was reached.
+    pub fn untracked_total(&self) -> u64 {
+        self.untracked_total.load(Ordering::Relaxed)
+    }
+
+    /// Forget series idle for longer than the TTL. Called periodically.
+    pub fn gc(&self, now: u64) {
+        let ttl = self.cfg.state_ttl_secs;
+        self.series.retain(|_, s| now.saturating_sub(s.seen) < ttl);
+    }
+
+    /// Convert the delta points of a batch in place: accumulated points become
+    /// cumulative, duplicates / out-of-order points are removed. Cumulative
+    /// points are left alone.
+    pub fn apply<R: DeltaPoint>(&self, rows: &mut Vec<R>, now: u64) -> DeltaOutcome {
+        let mut out = DeltaOutcome::default();
+        if !self.cfg.enabled {
+            return out;
+        }
+        rows.retain_mut(|r| {
+            if r.temporality() != TEMPORALITY_DELTA {
+                return true;
+            }
+            match self.step(r, now) {
+                Step::Emit(start) => {
+                    r.set_cumulative(start);
+                    out.converted += 1;
+                    true
+                }
+                Step::Drop => {
+                    out.dropped += 1;
+                    self.dropped_total.fetch_add(1, Ordering::Relaxed);
+                    false
+                }
+                Step::Untracked => {
+                    self.untracked_total.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+            }
+        });
+        if out.dropped > 0 {
+            tracing::debug!(
+                dropped = out.dropped,
+                converted = out.converted,
+                "delta→cumulative dropped out-of-order delta points"
+            );
+        }
+        out
+    }
+
+    /// Fold one delta point into its series and write the running total back
+    /// into the row.
+    fn step<R: DeltaPoint>(&self, r: &mut R, now: u64) -> Step {
+        let (start, time) = r.window();
+        let start = if start > 0 { start } else { time };
+        let key = r.series_key();
+        if self.series.len() >= self.cfg.max_series && !self.series.contains_key(&key) {
+            return Step::Untracked;
+        }
+        // The entry lock serialises concurrent requests for the same series.
+        let mut s = self.series.entry(key).or_insert_with(|| SeriesState {
+            start,
+            last: i64::MIN,
+            seen: now,
+            total: r.empty_total(),
+        });
+        if time <= s.last || start < s.last {
+            return Step::Drop;
+        }
+        if !r.add_to(&mut s.total) {
+            // Bucket layout changed: restart the series from this point.
+            s.total = r.empty_total();
+            s.start = start;
+            r.add_to(&mut s.total);
+        }
+        s.last = time;
+        s.seen = now;
+        r.emit(&s.total);
+        Step::Emit(s.start)
+    }
+}
+
+/// A metric row that may carry DELTA temporality.
+pub trait DeltaPoint {
+    fn temporality(&self) -> i32;
+    /// `(StartTimeUnix, TimeUnix)`.
+    fn window(&self) -> (i64, i64);
+    fn series_key(&self) -> u64;
+    fn empty_total(&self) -> Total;
+    /// Merge this point into `total`; false if the layouts are incompatible.
+    fn add_to(&self, total: &mut Total) -> bool;
+    /// Replace this point's values with `total`.
+    fn emit(&mut self, total: &Total);
+    fn set_cumulative(&mut self, start: i64);
+}
+
+impl DeltaPoint for SumRow {
+    fn temporality(&self) -> i32 {
+        self.aggregation_temporality
+    }
+    fn window(&self) -> (i64, i64) {
+        (self.start_time_unix, self.time_unix)
+    }
+    fn series_key(&self) -> u64 {
+        series_key(SeriesIds {
+            kind: if self.is_monotonic { 0 } else { 1 },
+            tenant: &self.tenant_id,
+            metric: &self.metric_name,
+            service: &self.service_name,
+            resource: &self.resource_attributes,
+            scope: (&self.scope_name, &self.scope_version),
+            attrs: &self.attributes,
+        })
+    }
+    fn empty_total(&self) -> Total {
+        Total::Sum(0.0)
+    }
+    fn add_to(&self, total: &mut Total) -> bool {
+        let Total::Sum(v) = total else { return false };
+        *v += self.value;
+        true
+    }
+    fn emit(&mut self, total: &Total) {
+        if let Total::Sum(v) = total {
+            self.value = *v;
+        }
+    }
+    fn set_cumulative(&mut self, start: i64) {
+        self.start_time_unix = start;
+        self.aggregation_temporality = TEMPORALITY_CUMULATIVE;
+    }
+}
+
+impl DeltaPoint for HistogramRow {
+    fn temporality(&self) -> i32 {
+        self.aggregation_temporality
+    }
+    fn window(&self) -> (i64, i64) {
+        (self.start_time_unix, self.time_unix)
+    }
+    fn series_key(&self) -> u64 {
+        series_key(SeriesIds {
+            kind: 2,
+            tenant: &self.tenant_id,
+            metric: &self.metric_name,
+            service: &self.service_name,
+            resource: &self.resource_attributes,
+            scope: (&self.scope_name, &self.scope_version),
+            attrs: &self.attributes,
+        })
+    }
+    fn empty_total(&self) -> Total {
+        Total::Histogram {
+            count: 0,
+            sum: 0.0,
+            buckets: vec![0; self.bucket_counts.len()],
+            bounds: self.explicit_bounds.clone(),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+    fn add_to(&self, total: &mut Total) -> bool {
+        let Total::Histogram { count, sum, buckets, bounds, min, max } = total else { return false };
+        if *bounds != self.explicit_bounds || buckets.len() != self.bucket_counts.len() {
+            return false;
+        }
+        *count += self.count;
+        *sum += self.sum;
+        add_counts(buckets, &self.bucket_counts);
+        if self.count > 0 {
+            *min = min.min(self.min);
+            *max = max.max(self.max);
+        }
+        true
+    }
+    fn emit(&mut self, total: &Total) {
+        if let Total::Histogram { count, sum, buckets, min, max, .. } = total {
+            self.count = *count;
+            self.sum = *sum;
+            self.bucket_counts.clone_from(buckets);
+            if *count > 0 {
+                self.min = *min;
+                self.max = *max;
+            }
+        }
+    }
+    fn set_cumulative(&mut self, start: i64) {
+        self.start_time_unix = start;
+        self.aggregation_temporality = TEMPORALITY_CUMULATIVE;
+    }
+}
+
+impl DeltaPoint for ExpHistogramRow {
+    fn temporality(&self) -> i32 {
+        self.aggregation_temporality
+    }
+    fn window(&self) -> (i64, i64) {
+        (self.start_time_unix, self.time_unix)
+    }
+    fn series_key(&self) -> u64 {
+        series_key(SeriesIds {
+            kind: 3,
+            tenant: &self.tenant_id,
+            metric: &self.metric_name,
+            service: &self.service_name,
+            resource: &self.resource_attributes,
+            scope: (&self.scope_name, &self.scope_version),
+            attrs: &self.attributes,
+        })
+    }
+    fn empty_total(&self) -> Total {
+        Total::ExpHistogram {
+            count: 0,
+            sum: 0.0,
+            scale: self.scale,
+            zero_count: 0,
+            positive: (self.positive_offset, Vec::new()),
+            negative: (self.negative_offset, Vec::new()),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+    fn add_to(&self, total: &mut Total) -> bool {
+        let Total::ExpHistogram { count, sum, scale, zero_count, positive, negative, min, max } = total else {
+            return false;
+        };
+        if *scale != self.scale {
+            return false;
+        }
+        *count += self.count;
+        *sum += self.sum;
+        *zero_count += self.zero_count;
+        add_offset_counts(positive, self.positive_offset, &self.positive_bucket_counts);
+        add_offset_counts(negative, self.negative_offset, &self.negative_bucket_counts);
+        if self.count > 0 {
+            *min = min.min(self.min);
+            *max = max.max(self.max);
+        }
+        true
+    }
+    fn emit(&mut self, total: &Total) {
+        if let Total::ExpHistogram { count, sum, zero_count, positive, negative, min, max, .. } = total {
+            self.count = *count;
+            self.sum = *sum;
+            self.zero_count = *zero_count;
+            self.positive_offset = positive.0;
+            self.positive_bucket_counts.clone_from(&positive.1);
+            self.negative_offset = negative.0;
+            self.negative_bucket_counts.clone_from(&negative.1);
+            if *count > 0 {
+                self.min = *min;
+                self.max = *max;
+            }
+        }
+    }
+    fn set_cumulative(&mut self, start: i64) {
+        self.start_time_unix = start;
+        self.aggregation_temporality = TEMPORALITY_CUMULATIVE;
+    }
+}
+
+struct SeriesIds<'a> {
+    kind: u8,
+    tenant: &'a str,
+    metric: &'a str,
+    service: &'a str,
+    resource: &'a [(String, String)],
+    scope: (&'a str, &'a str),
+    attrs: &'a [(String, String)],
+}
+
+fn hash_one<T: Hash + ?Sized>(v: &T) -> u64 {
+    let mut h = DefaultHasher::new();
+    v.hash(&mut h);
+    h.finish()
+}
+
+/// Order-independent within each attribute set (sorted pairs are hashed, as
+/// in the cardinality limiter), but resource and point attributes are kept
+/// apart.
+fn series_key(ids: SeriesIds<'_>) -> u64 {
+    let set = |attrs: &[(String, String)]| {
+        let mut pairs: Vec<&(String, String)> = attrs.iter().collect();
+        pairs.sort_unstable();
+        hash_one(&pairs)
+    };
+    hash_one(&(
+        ids.kind,
+        ids.tenant,
+        ids.metric,
+        ids.service,
+        ids.scope,
+        set(ids.resource),
+        set(ids.attrs),
+    ))
+}
+
+fn add_counts(total: &mut [u64], delta: &[u64]) {
+    for (t, d) in total.iter_mut().zip(delta) {
+        *t += d;
+    }
+}
+
+/// Add an offset-indexed bucket run (exponential histogram) into a total,
+/// widening the total's range as needed.
+fn add_offset_counts(total: &mut (i32, Vec<u64>), offset: i32, delta: &[u64]) {
+    if delta.is_empty() {
+        return;
+    }
+    let (t_off, counts) = total;
+    if counts.is_empty() {
+        *t_off = offset;
+    }
+    if offset < *t_off {
+        let grow = (*t_off - offset) as usize;
+        counts.splice(0..0, std::iter::repeat_n(0, grow));
+        *t_off = offset;
+    }
+    let base = (offset - *t_off) as usize;
+    if counts.len() < base + delta.len() {
+        counts.resize(base + delta.len(), 0);
+    }
+    add_counts(&mut counts[base..], delta);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ingest::GaugeRow;
+    use std::sync::Arc;
+
+    fn sum(attrs: &[(&str, &str)], start: i64, time: i64, value: f64, temporality: i32) -> SumRow {
+        let g = GaugeRow {
+            tenant_id: "t".into(),
+            resource_attributes: Arc::new(vec![("host.name".into(), "h1".into())]),
+            resource_schema_url: "".into(),
+            scope_name: "".into(),
+            scope_version: "".into(),
+            scope_attributes: Arc::new(Vec::new()),
+            scope_dropped_attr_count: 0,
+            scope_schema_url: "".into(),
+            service_name: "svc".into(),
+            metric_name: "requests".into(),
+            metric_description: "".into(),
+            metric_unit: "".into(),
+            attributes: attrs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            start_time_unix: start,
+            time_unix: time,
+            value,
+            flags: 0,
+            exemplars_filtered_attributes: Vec::new(),
+            exemplars_time_unix: Vec::new(),
+            exemplars_value: Vec::new(),
+            exemplars_span_id: Vec::new(),
+            exemplars_trace_id: Vec::new(),
+        };
+        let mut s = SumRow::from_gauge(&g, true);
+        s.aggregation_temporality = temporality;
+        s
+    }
+
+    #[test]
+    fn delta_sums_accumulate_per_series() {
+        let d = DeltaToCumulative::new(DeltaConfig { enabled: true, ..DeltaConfig::default() });
+        let mut rows = vec![
+            sum(&[("a", "1")], 100, 110, 5.0, TEMPORALITY_DELTA),
+            sum(&[("a", "2")], 100, 110, 1.0, TEMPORALITY_DELTA),
+            sum(&[("a", "1")], 110, 120, 3.0, TEMPORALITY_DELTA),
+            // Gap: still accumulated.
+            sum(&[("a", "1")], 130, 140, 2.0, TEMPORALITY_DELTA),
+            sum(&[("a", "1")], 0, 999, 7.0, TEMPORALITY_CUMULATIVE),
+        ];
+        let out = d.apply(&mut rows, 1);
+        assert_eq!(out, DeltaOutcome { converted: 4, dropped: 0 });
+        let got: Vec<(i64, i64, f64, i32)> =
+            rows.iter().map(|r| (r.start_time_unix, r.time_unix, r.value, r.aggregation_temporality)).collect();
+        assert_eq!(
+            got,
+            vec![(100, 110, 5.0, 2), (100, 110, 1.0, 2), (100, 120, 8.0, 2), (100, 140, 10.0, 2), (0, 999, 7.0, 2)]
+        );
+        assert_eq!(d.tracked_series(), 2);
+    }
+
+    #[test]
+    fn out_of_order_and_overlapping_deltas_are_dropped() {
+        let d = DeltaToCumulative::new(DeltaConfig { enabled: true, ..DeltaConfig::default() });
+        let mut rows = vec![
+            sum(&[], 100, 110, 5.0, TEMPORALITY_DELTA),
+            sum(&[], 100, 110, 5.0, TEMPORALITY_DELTA), // duplicate
+            sum(&[], 105, 115, 1.0, TEMPORALITY_DELTA), // overlaps [100,110]
+            sum(&[], 110, 120, 1.0, TEMPORALITY_DELTA),
+        ];
+        let out = d.apply(&mut rows, 1);
+        assert_eq!(out, DeltaOutcome { converted: 2, dropped: 2 });
+        assert_eq!(rows.iter().map(|r| r.value).collect::<Vec<_>>(), vec![5.0, 6.0]);
+        assert_eq!(d.dropped_total(), 2);
+    }
+
+    #[test]
+    fn idle_series_restart_and_max_series_passes_through() {
+        let d = DeltaToCumulative::new(DeltaConfig { enabled: true, state_ttl_secs: 60, max_series: 1 });
+        let mut rows = vec![sum(&[("a", "1")], 100, 110, 5.0, TEMPORALITY_DELTA)];
+        d.apply(&mut rows, 1_000);
+        // Over max_series: stored as received.
+        let mut rows = vec![sum(&[("a", "2")], 100, 110, 4.0, TEMPORALITY_DELTA)];
+        d.apply(&mut rows, 1_000);
+        assert_eq!((rows[0].value, rows[0].aggregation_temporality), (4.0, TEMPORALITY_DELTA));
+        assert_eq!(d.untracked_total(), 1);
+        // After the TTL the total restarts with a fresh start time.
+        d.gc(1_100);
+        let mut rows = vec![sum(&[("a", "1")], 200, 210, 2.0, TEMPORALITY_DELTA)];
+        d.apply(&mut rows, 1_100);
+        assert_eq!((rows[0].start_time_unix, rows[0].value), (200, 2.0));
+    }
+
+    #[test]
+    fn exp_histogram_buckets_merge_across_offsets() {
+        let mut total = (0, Vec::new());
+        add_offset_counts(&mut total, 3, &[1, 1]);
+        add_offset_counts(&mut total, 1, &[2, 0, 0, 0, 5]);
+        assert_eq!(total, (1, vec![2, 0, 1, 1, 5]));
+    }
+}
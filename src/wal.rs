@@ -0,0 +1,304 @@
+//! Local write-ahead log for durable acknowledged ingest
+//! (`RUSH_INGEST_DURABILITY=wal`).
+//!
+//! With plain batching, `ChWriter::write` acks once rows sit in the in-memory
+//! `BatchAccumulator`, so a hard crash loses them. In WAL mode each request's
+//! batch is first appended here and fsync'd, and only then acked; batching to
+//! ClickHouse carries on asynchronously.
+//!
+//! Group commit: appends are queued to a single committer task, which waits up
+//! to `commit_interval` for more appends, writes the whole group and fsyncs
+//! once. Every waiter in the group is acked together, so the fsync cost is
+//! shared and the added request latency is bounded by the interval.
+//!
+//! Segments (`wal-<seq>.log`) use the spool's v2 framing (`SEGMENT_MAGIC` +
+//! CRC'd, zstd records) so `Spool::read_segment` reads them back. Each acked
+//! append returns a `WalTicket` pinning its segment; the ticket rides along
+//! with the rows in the batcher and is dropped once they are inserted or
+//! spooled. A segment is deleted when it has been rotated out and no ticket
+//! pins it. Segments left over at startup (crash, or rows the spool refused)
+//! are replayed by `ChWriter::replay_wal` — at-least-once, like spool replay.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::spool::{SEGMENT_MAGIC, encode_record};
+
+/// Rotate the open segment once it is this old (so fully-flushed segments are
+/// reclaimed promptly) or this large.
+const SEGMENT_MAX_AGE: Duration = Duration::from_secs(1);
+const SEGMENT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub dir: PathBuf,
+    /// How long the committer waits to gather a group before one fsync.
+    pub commit_interval: Duration,
+}
+
+impl WalConfig {
+    /// `Some` when `RUSH_INGEST_DURABILITY=wal`. `RUSH_INGEST_WAL_DIR`
+    /// (default `./data/wal`) and `RUSH_INGEST_WAL_COMMIT_MS` (default 2).
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("RUSH_INGEST_DURABILITY").ok()?.trim() != "wal" {
+            return None;
+        }
+        let dir = std::env::var("RUSH_INGEST_WAL_DIR").unwrap_or_else(|_| "./data/wal".to_string());
+        let commit_ms = std::env::var("RUSH_INGEST_WAL_COMMIT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2u64);
+        Some(WalConfig { dir: dir.into(), commit_interval: Duration::from_millis(commit_ms) })
+    }
+}
+
+struct WalState {
+    dir: PathBuf,
+    /// Open segment: seq, file, size, when it was opened.
+    current: Option<(u64, File, u64, Instant)>,
+    next_seq: u64,
+    /// Outstanding tickets per segment.
+    refs: BTreeMap<u64, usize>,
+}
+
+impl WalState {
+    /// Append a group of frames to the open segment (rotating first if due)
+    /// and fsync. Returns the segment the group landed in.
+    fn commit(&mut self, frames: &[Vec<u8>]) -> std::io::Result<u64> {
+        let rotate = match &self.current {
+            Some((_, _, size, opened)) => *size >= SEGMENT_MAX_BYTES || opened.elapsed() >= SEGMENT_MAX_AGE,
+            None => true,
+        };
+        if rotate {
+            self.rotate()?;
+        }
+        let (seq, file, size, _) = self.current.as_mut().expect("rotate opened a segment");
+        let mut buf = Vec::with_capacity(frames.iter().map(Vec::len).sum());
+        for f in frames {
+            buf.extend_from_slice(f);
+        }
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        *size += buf.len() as u64;
+        let seq = *seq;
+        *self.refs.entry(seq).or_insert(0) += frames.len();
+        Ok(seq)
+    }
+
+    /// Close the open segment (deleting it if nothing pins it) and start a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.close_current();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut file = OpenOptions::new().create(true).append(true).open(self.path(seq))?;
+        file.write_all(&SEGMENT_MAGIC)?;
+        file.sync_data()?;
+        self.current = Some((seq, file, SEGMENT_MAGIC.len() as u64, Instant::now()));
+        Ok(())
+    }
+
+    fn close_current(&mut self) {
+        if let Some((seq, _, _, _)) = self.current.take()
+            && self.refs.get(&seq).copied().unwrap_or(0) == 0
+        {
+            self.refs.remove(&seq);
+            let _ = fs::remove_file(self.path(seq));
+        }
+    }
+
+    fn release(&mut self, seq: u64) {
+        let Some(n) = self.refs.get_mut(&seq) else { return };
+        *n = n.saturating_sub(1);
+        let open = self.current.as_ref().is_some_and(|c| c.0 == seq);
+        if *n == 0 && !open {
+            self.refs.remove(&seq);
+            if let Err(e) = fs::remove_file(self.path(seq)) {
+                tracing::warn!(error = %e, segment = seq, "wal: failed to remove drained segment");
+            }
+        }
+    }
+
+    fn path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("wal-{seq:016}.log"))
+    }
+}
+
+struct PendingAppend {
+    frame: Vec<u8>,
+    done: oneshot::Sender<Result<u64, String>>,
+}
+
+pub struct Wal {
+    state: Arc<Mutex<WalState>>,
+    tx: mpsc::UnboundedSender<PendingAppend>,
+}
+
+/// Pins one acked append's segment until the rows are durable elsewhere.
+/// Dropping it releases the pin; `keep` leaves the rows in the WAL for replay
+/// at the next startup.
+pub struct WalTicket {
+    state: Arc<Mutex<WalState>>,
+    seq: u64,
+}
+
+impl WalTicket {
+    pub fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for WalTicket {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().release(self.seq);
+    }
+}
+
+impl Wal {
+    /// Open the WAL directory and start the committer. Returns the segments
+    /// left over from a previous run, oldest first, for the caller to replay
+    /// (and delete). Must be called from within a tokio runtime.
+    pub fn open(cfg: &WalConfig) -> std::io::Result<(Arc<Self>, Vec<PathBuf>)> {
+        fs::create_dir_all(&cfg.dir)?;
+        let mut leftovers: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(&cfg.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(seq) = name.strip_prefix("wal-").and_then(|n| n.strip_suffix(".log")).and_then(|n| n.parse().ok()) {
+                leftovers.push((seq, entry.path()));
+            }
+        }
+        leftovers.sort();
+        let next_seq = leftovers.last().map_or(0, |(s, _)| s + 1);
+        let state = Arc::new(Mutex::new(WalState {
+            dir: cfg.dir.clone(),
+            current: None,
+            next_seq,
+            refs: BTreeMap::new(),
+        }));
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_committer(state.clone(), rx, cfg.commit_interval));
+        Ok((Arc::new(Wal { state, tx }), leftovers.into_iter().map(|(_, p)| p).collect()))
+    }
+
+    /// Append one record and wait until it is fsync'd (group-committed).
+    pub async fn append(&self, table: &str, payload: &[u8]) -> std::io::Result<WalTicket> {
+        let (done, rx) = oneshot::channel();
+        self.tx
+            .send(PendingAppend { frame: encode_record(table, payload), done })
+            .map_err(|_| std::io::Error::other("wal committer stopped"))?;
+        let seq = rx
+            .await
+            .map_err(|_| std::io::Error::other("wal committer stopped"))?
+            .map_err(std::io::Error::other)?;
+        Ok(WalTicket { state: self.state.clone(), seq })
+    }
+
+    /// Close the open segment, deleting it if every ticket has been released.
+    /// Called on graceful shutdown after the batcher has been flushed.
+    pub fn close(&self) {
+        self.state.lock().unwrap().close_current();
+    }
+
+    /// Number of segments still pinned by unflushed rows.
+    pub fn pinned_segments(&self) -> usize {
+        self.state.lock().unwrap().refs.values().filter(|n| **n > 0).count()
+    }
+}
+
+async fn run_committer(
+    state: Arc<Mutex<WalState>>,
+    mut rx: mpsc::UnboundedReceiver<PendingAppend>,
+    interval: Duration,
+) {
+    while let Some(first) = rx.recv().await {
+        if !interval.is_zero() {
+            tokio::time::sleep(interval).await;
+        }
+        let mut group = vec![first];
+        while let Ok(p) = rx.try_recv() {
+            group.push(p);
+        }
+        let (frames, dones): (Vec<Vec<u8>>, Vec<_>) = group.into_iter().map(|p| (p.frame, p.done)).unzip();
+        let st = state.clone();
+        let result = tokio::task::spawn_blocking(move || st.lock().unwrap().commit(&frames))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.map_err(|e| e.to_string()));
+        if let Err(e) = &result {
+            tracing::error!(error = %e, "wal: group commit failed");
+        }
+        for done in dones {
+            // A waiter that went away (request cancelled) still holds a ref.
+            if done.send(result.clone()).is_err()
+                && let Ok(seq) = result
+            {
+                state.lock().unwrap().release(seq);
+            }
+        }
+    }
+}
+
+/// Delete a replayed leftover segment.
+pub fn remove_segment(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        tracing::warn!(error = %e, segment = %path.display(), "wal: failed to remove replayed segment");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spool::Spool;
+
+    fn cfg(name: &str) -> WalConfig {
+        let ns = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let dir = std::env::temp_dir().join(format!("rush-wal-{name}-{}-{ns}", std::process::id()));
+        WalConfig { dir, commit_interval: Duration::from_millis(1) }
+    }
+
+    fn segments(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn released_segments_are_deleted_once_closed() {
+        let cfg = cfg("release");
+        let (wal, leftovers) = Wal::open(&cfg).unwrap();
+        assert!(leftovers.is_empty());
+        let (a, b) = tokio::join!(wal.append("logs", b"a"), wal.append("logs", b"b"));
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(wal.pinned_segments(), 1);
+        drop(a);
+        drop(b);
+        // Still the open segment: kept until closed.
+        assert_eq!(segments(&cfg.dir), 1);
+        wal.close();
+        assert_eq!(segments(&cfg.dir), 0);
+        let _ = fs::remove_dir_all(&cfg.dir);
+    }
+
+    #[tokio::test]
+    async fn kept_rows_are_replayed_after_restart() {
+        let cfg = cfg("replay");
+        {
+            let (wal, _) = Wal::open(&cfg).unwrap();
+            wal.append("logs", b"durable").await.unwrap().keep();
+            wal.append("logs", b"flushed").await.unwrap(); // dropped → released
+            wal.close();
+        }
+        let (_wal, leftovers) = Wal::open(&cfg).unwrap();
+        assert_eq!(leftovers.len(), 1);
+        let (records, corrupt) = Spool::read_segment(&leftovers[0]).unwrap();
+        assert_eq!(corrupt, 0);
+        // At-least-once: the whole pinned segment is replayed.
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], ("logs".to_string(), b"durable".to_vec()));
+        let _ = fs::remove_dir_all(&cfg.dir);
+    }
+}